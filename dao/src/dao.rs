@@ -27,6 +27,7 @@ mod dao {
             put_tokens => PUBLIC;
             send_tokens => restrict_to: [OWNER];
             take_tokens => restrict_to: [OWNER];
+            mint_tokens => restrict_to: [OWNER];
             employ => restrict_to: [OWNER];
             fire => restrict_to: [OWNER];
             airdrop_tokens => restrict_to: [OWNER];
@@ -340,6 +341,31 @@ mod dao {
             receiver.call_raw::<()>("put_tokens", scrypto_args!(payment));
         }
 
+        /// Mints new mother tokens and sends them to a receiver, for instance to sell them in a debt auction of the STAB module
+        ///
+        /// # Input
+        /// - `amount`: Amount of mother tokens to mint
+        /// - `receiver_address`: Component address to send the tokens to
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Mint the mother tokens using the controller badge
+        /// - Send the tokens to the receiver using the `put_tokens` method of the receiver component
+        pub fn mint_tokens(&mut self, amount: Decimal, receiver_address: ComponentAddress) {
+            let mother_token_manager: ResourceManager =
+                ResourceManager::from_address(self.mother_token_address);
+            let payment: Bucket = self
+                .vaults
+                .get_mut(&self.controller_badge_address)
+                .unwrap()
+                .as_fungible()
+                .authorize_with_amount(dec!("0.75"), || mother_token_manager.mint(amount));
+            let receiver: Global<AnyComponent> = Global::from(receiver_address);
+            receiver.call_raw::<()>("put_tokens", scrypto_args!(payment));
+        }
+
         /// Takes tokens from the DAO treasury
         ///
         /// # Input
@@ -2,6 +2,7 @@
 //!
 //! This blueprint allows users to borrow STAB tokens from the Stabilis component. The user must pay back the borrowed amount plus interest in the same transaction.
 //! This works by the user receiving a transient token loan receipt, that only the FlashLoan component can burn. They have to pay this back in the same transaction to make the transaction succeed.
+//! The interest paid is deposited into the surplus buffer of the Stabilis component, where it is used to cancel bad debt.
 
 use crate::stabilis_component::stabilis_component::*;
 use scrypto::prelude::*;
@@ -21,7 +22,6 @@ mod flash_loans {
             borrow => restrict_to: [OWNER];
             settings => restrict_to: [OWNER];
             pay_back => restrict_to: [OWNER];
-            retrieve_interest => restrict_to: [OWNER];
         }
     }
 
@@ -30,8 +30,6 @@ mod flash_loans {
         badge_vault: FungibleVault,
         /// The resource manager for the loan receipts
         loan_receipt_manager: ResourceManager,
-        /// The vault for the interest
-        interest_vault: Option<Vault>,
         /// The counter for the loan receipts
         loan_receipt_counter: u64,
         /// The interest rate for the flash loans (starts at 0.00, so example: 0.05 is 5% interest)
@@ -103,7 +101,6 @@ mod flash_loans {
                 badge_vault: FungibleVault::with_bucket(controller_badge.as_fungible()),
                 loan_receipt_manager,
                 interest: dec!(0),
-                interest_vault: None,
                 stabilis,
                 loan_receipt_counter: 0,
                 enabled: true,
@@ -173,7 +170,7 @@ mod flash_loans {
         /// - Checks if the payment is enough to pay back the loan
         /// - Burns the receipt
         /// - Burns the STAB tokens borrowed
-        /// - If there is interest, it is put into the interest vault
+        /// - If there is interest, it is deposited into the surplus buffer of the Stabilis component
         /// - Returns the remaining STAB tokens
         pub fn pay_back(&mut self, receipt_bucket: Bucket, mut payment: Bucket) -> Bucket {
             assert!(
@@ -196,26 +193,15 @@ mod flash_loans {
             });
 
             if receipt.interest > dec!(0) {
-                if self.interest_vault.is_none() {
-                    self.interest_vault = Some(Vault::with_bucket(
-                        payment.take(receipt.interest * receipt.borrowed_amount),
-                    ));
-                } else {
-                    self.interest_vault
-                        .as_mut()
-                        .unwrap()
-                        .put(payment.take(receipt.interest * receipt.borrowed_amount));
-                }
+                let interest: Bucket = payment.take(receipt.interest * receipt.borrowed_amount);
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.deposit_surplus(interest)
+                });
             }
 
             receipt_bucket.burn();
 
             payment
         }
-
-        /// Method called to empty the interest vault
-        pub fn retrieve_interest(&mut self) -> Bucket {
-            self.interest_vault.as_mut().unwrap().take_all()
-        }
     }
 }
@@ -0,0 +1,251 @@
+//! # Keeper Rewards Blueprint
+//!
+//! The protocol depends on keepers: people (or bots) that mark undercollateralized loans, save loans that turn out to be healthy, and update the internal price.
+//! Liquidators are paid by the liquidation fine, but marking and updating costs fees without any direct return.
+//! The keeper rewards component pays a bounty for this work:
+//! - Governance sets the reward token (STAB or ILIS) and a bounty per mark, per save and per internal price update
+//! - The rewards are funded from the protocol surplus: collected stability fees, or ILIS sent by the DAO
+//! - A marker receipt can be used to claim a bounty once (`reward_marker`), the bounty depends on whether the loan was marked or saved
+//!     - A mark is only rewarded once the marker is used and the loan is liquidated, so marking loans that end up saved earns nothing
+//!     - A save is only rewarded if the loan was marked by someone else, not when marking a healthy loan saved it right away
+//! - An internal price update is rewarded at most once per update delay (`reward_update`), so spamming updates earns nothing
+//! - If the reward vault runs dry, whatever is left is paid out
+//!
+//! All methods are called through the Proxy component.
+
+use crate::shared_structs::*;
+use scrypto::prelude::*;
+
+/// Reward configuration of the keeper rewards, set by governance
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct KeeperRewardConfig {
+    /// token the rewards are paid in (STAB or ILIS)
+    pub reward_token: ResourceAddress,
+    /// reward for marking a loan for liquidation
+    pub mark_reward: Decimal,
+    /// reward for saving a loan (marking a loan that turned out to be healthy)
+    pub save_reward: Decimal,
+    /// reward for updating the internal price
+    pub update_reward: Decimal,
+}
+
+/// Rewarded keeper actions
+#[derive(ScryptoSbor, PartialEq, Clone, Copy, Debug)]
+pub enum KeeperAction {
+    Mark,
+    Save,
+    Update,
+}
+
+/// Event emitted when a keeper is rewarded
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct KeeperRewardPaidEvent {
+    pub action: KeeperAction,
+    pub reward_token: ResourceAddress,
+    pub reward: Decimal,
+    pub marker_id: Option<NonFungibleLocalId>,
+}
+
+#[blueprint]
+#[events(KeeperRewardPaidEvent)]
+mod keeper_rewards {
+    enable_method_auth! {
+        methods {
+            reward_marker => restrict_to: [OWNER];
+            reward_update => restrict_to: [OWNER];
+            fund => restrict_to: [OWNER];
+            withdraw => restrict_to: [OWNER];
+            set_config => restrict_to: [OWNER];
+            get_config => PUBLIC;
+            get_reward_funds => PUBLIC;
+        }
+    }
+
+    struct KeeperRewards {
+        /// The reward configuration (None if no rewards are paid)
+        config: Option<KeeperRewardConfig>,
+        /// KVS storing the vaults the rewards are paid from
+        reward_vaults: KeyValueStore<ResourceAddress, Vault>,
+        /// The resource manager for the CDP receipts
+        cdp_receipt_manager: ResourceManager,
+        /// The resource manager for the CDP markers
+        cdp_marker_manager: ResourceManager,
+        /// KVS storing the markers that have already been rewarded
+        rewarded_markers: KeyValueStore<NonFungibleLocalId, ()>,
+        /// The last time an internal price update was rewarded
+        last_update_reward: Instant,
+    }
+
+    impl KeeperRewards {
+        /// Instantiates the KeeperRewards component
+        ///
+        /// # Input
+        /// - `controller_address`: The resource address of the controller badge
+        /// - `cdp_receipt_address`: The resource address of the CDP receipts
+        /// - `cdp_marker_address`: The resource address of the CDP markers
+        ///
+        /// # Output
+        /// - The global instance of the KeeperRewards component
+        pub fn instantiate(
+            controller_address: ResourceAddress,
+            cdp_receipt_address: ResourceAddress,
+            cdp_marker_address: ResourceAddress,
+        ) -> Global<KeeperRewards> {
+            Self {
+                config: None,
+                reward_vaults: KeyValueStore::new(),
+                cdp_receipt_manager: ResourceManager::from_address(cdp_receipt_address),
+                cdp_marker_manager: ResourceManager::from_address(cdp_marker_address),
+                rewarded_markers: KeyValueStore::new(),
+                last_update_reward: Clock::current_time_rounded_to_minutes(),
+            }
+            .instantiate()
+            .prepare_to_globalize(OwnerRole::Fixed(rule!(require(controller_address))))
+            .globalize()
+        }
+
+        /// Reward the keeper holding a marker receipt
+        ///
+        /// # Input
+        /// - `marker_id`: The id of the marker receipt (the proof is checked by the Proxy component)
+        ///
+        /// # Output
+        /// - The reward
+        ///
+        /// # Logic
+        /// - Check the marker hasn't been rewarded before
+        /// - For a marked loan, check the marker is used and the loan is liquidated
+        /// - For a saved loan, check the save wasn't caused by the marking itself
+        /// - Remember the marker, and pay the mark or save reward
+        pub fn reward_marker(&mut self, marker_id: NonFungibleLocalId) -> Bucket {
+            assert!(
+                self.rewarded_markers.get(&marker_id).is_none(),
+                "Marker already rewarded."
+            );
+
+            let marker: CdpMarker = self.cdp_marker_manager.get_non_fungible_data(&marker_id);
+            let action: KeeperAction = match marker.mark_type {
+                CdpUpdate::Marked => {
+                    let cdp: Cdp = self
+                        .cdp_receipt_manager
+                        .get_non_fungible_data(&marker.marked_id);
+                    assert!(
+                        marker.used && cdp.status == CdpStatus::Liquidated,
+                        "Marked loan not liquidated yet."
+                    );
+                    KeeperAction::Mark
+                }
+                CdpUpdate::Saved => {
+                    assert!(
+                        !marker.saved_by_own_mark,
+                        "Saving a loan by marking it isn't rewarded."
+                    );
+                    KeeperAction::Save
+                }
+            };
+
+            self.rewarded_markers.insert(marker_id.clone(), ());
+
+            self.pay(action, Some(marker_id))
+        }
+
+        /// Reward the keeper that updated the internal price
+        ///
+        /// # Input
+        /// - `update_delay`: The delay between internal price updates (minutes)
+        ///
+        /// # Output
+        /// - The reward, or None if no rewards are configured or the last rewarded update was too recent
+        ///
+        /// # Logic
+        /// - Check at least the update delay (and at least one minute) has passed since the last rewarded update
+        /// - Pay the update reward
+        pub fn reward_update(&mut self, update_delay: i64) -> Option<Bucket> {
+            let passed_minutes: i64 = (Clock::current_time_rounded_to_minutes()
+                .seconds_since_unix_epoch
+                - self.last_update_reward.seconds_since_unix_epoch)
+                / 60;
+
+            if self.config.is_none() || passed_minutes < update_delay.max(1) {
+                return None;
+            }
+
+            self.last_update_reward = Clock::current_time_rounded_to_minutes();
+
+            Some(self.pay(KeeperAction::Update, None))
+        }
+
+        /// Deposit reward tokens
+        pub fn fund(&mut self, tokens: Bucket) {
+            let address: ResourceAddress = tokens.resource_address();
+            if self.reward_vaults.get(&address).is_some() {
+                self.reward_vaults.get_mut(&address).unwrap().put(tokens);
+            } else {
+                self.reward_vaults.insert(address, Vault::with_bucket(tokens));
+            }
+        }
+
+        /// Withdraw reward tokens
+        pub fn withdraw(&mut self, token: ResourceAddress, amount: Decimal) -> Bucket {
+            self.reward_vaults
+                .get_mut(&token)
+                .expect("No rewards of this type.")
+                .take(amount)
+        }
+
+        /// Set the reward configuration
+        pub fn set_config(&mut self, config: KeeperRewardConfig) {
+            assert!(
+                config.mark_reward >= dec!(0)
+                    && config.save_reward >= dec!(0)
+                    && config.update_reward >= dec!(0),
+                "Rewards can't be negative."
+            );
+
+            if self.reward_vaults.get(&config.reward_token).is_none() {
+                self.reward_vaults
+                    .insert(config.reward_token, Vault::new(config.reward_token));
+            }
+
+            self.config = Some(config);
+        }
+
+        /// Gets the reward configuration
+        pub fn get_config(&self) -> Option<KeeperRewardConfig> {
+            self.config.clone()
+        }
+
+        /// Gets the amount of reward tokens available
+        pub fn get_reward_funds(&self, token: ResourceAddress) -> Decimal {
+            match self.reward_vaults.get(&token) {
+                Some(vault) => vault.amount(),
+                None => dec!(0),
+            }
+        }
+
+        /// Pay the reward for an action, or whatever is left in the reward vault
+        fn pay(&mut self, action: KeeperAction, marker_id: Option<NonFungibleLocalId>) -> Bucket {
+            let config: KeeperRewardConfig =
+                self.config.clone().expect("No keeper rewards configured.");
+
+            let reward_amount: Decimal = match action {
+                KeeperAction::Mark => config.mark_reward,
+                KeeperAction::Save => config.save_reward,
+                KeeperAction::Update => config.update_reward,
+            };
+
+            let mut vault = self.reward_vaults.get_mut(&config.reward_token).unwrap();
+            let available: Decimal = vault.amount();
+            let reward: Bucket = vault.take(reward_amount.min(available));
+
+            Runtime::emit_event(KeeperRewardPaidEvent {
+                action,
+                reward_token: config.reward_token,
+                reward: reward.amount(),
+                marker_id,
+            });
+
+            reward
+        }
+    }
+}
@@ -13,13 +13,23 @@
 //!     - Ensure potential upgrades to the Stabilis component can be done without disrupting the rest of the system.
 //! - `flash_loans`: The flash loans component, which allows users to borrow STAB tokens from the Stabilis component.
 //! - `stabilis_liquidity_pool`: The liquidity pool component, which is a STAB/XRD liquidity pool native to the Stabilis protocol. It is used to determine the price of STAB tokens.
+//! - `stability_pool`: The stability pool component, in which users deposit STAB that is used to liquidate marked loans when no external liquidator steps in. The seized collateral is shared among the depositors.
+//! - `peg_stability_module`: The peg stability module, which lets users swap between STAB and governance-approved stable assets at STAB's internal price, with fees and a debt ceiling per asset.
+//! - `surplus_auction`: The surplus auction component, which sells collateral treasuries above a governance-set threshold in Dutch auctions for STAB or ILIS, burning the proceeds or sending them to the DAO.
+//! - `keeper_rewards`: The keeper rewards component, which pays keepers a STAB or ILIS bounty, funded by the protocol surplus, for marking and saving loans and for updating the internal price.
+//! - `price_aggregator`: An oracle component that aggregates the prices of several sources per resource into a median, rejecting stale and deviating sources, with a status the Proxy Component acts on.
 //! - `oracle`: A component that aggregates oracle data and casts it into a form the Proxy Component is able to process.
 //!
 //! More information on each component can be found in their respective modules.
 
 pub mod flash_loans;
+pub mod keeper_rewards;
+pub mod peg_stability_module;
+pub mod price_aggregator;
 pub mod proxy;
 pub mod shared_structs;
 pub mod stabilis_component;
 pub mod stabilis_liquidity_pool;
+pub mod stability_pool;
+pub mod surplus_auction;
 pub mod oracle;
\ No newline at end of file
@@ -0,0 +1,349 @@
+//! # Peg Stability Module Blueprint
+//!
+//! The peg stability module (PSM) lets users swap between STAB and governance-approved stable assets (assumed to be worth 1 USD) at STAB's internal price.
+//! - Depositing a stable asset mints STAB: 1 stable asset = 1 / internal price STAB, minus an in fee
+//! - Redeeming STAB returns the stable asset: 1 STAB = internal price stable assets, minus an out fee
+//!
+//! This gives STAB a hard arbitrage band around its internal price, next to the interest rate loop in the Proxy component and the StabilisPool.
+//! Every stable asset has its own fees and debt ceiling (the maximum amount of STAB that can be minted against it).
+//! STAB minted by the PSM counts toward the circulating STAB of the Stabilis component, so collateral share limits keep working.
+//! Fees are collected in STAB, and both fees and reserves can be withdrawn by the owner (the DAO).
+//! After an emergency shutdown, the reserves are handed over to the Stabilis component (`settle_reserves`), so STAB holders can redeem them pro rata.
+//! All methods are called through the Proxy component.
+
+use crate::stabilis_component::stabilis_component::*;
+use scrypto::prelude::*;
+
+/// All info about a stable asset accepted by the PSM
+#[derive(ScryptoSbor)]
+pub struct PsmAsset {
+    /// the reserve of the stable asset, backing the STAB minted against it
+    pub reserve: Vault,
+    /// fee charged when minting STAB (0.001 is 0.1%)
+    pub fee_in: Decimal,
+    /// fee charged when redeeming STAB (0.001 is 0.1%)
+    pub fee_out: Decimal,
+    /// maximum amount of STAB that can be minted against this asset
+    pub debt_ceiling: Decimal,
+    /// STAB minted against this asset
+    pub minted_stab: Decimal,
+    /// whether STAB can be minted against this asset
+    pub accepted: bool,
+}
+
+/// Read-only view of a stable asset's information
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct PsmAssetView {
+    /// address of the stable asset
+    pub address: ResourceAddress,
+    /// amount of the stable asset in the reserve
+    pub reserve: Decimal,
+    /// fee charged when minting STAB
+    pub fee_in: Decimal,
+    /// fee charged when redeeming STAB
+    pub fee_out: Decimal,
+    /// maximum amount of STAB that can be minted against this asset
+    pub debt_ceiling: Decimal,
+    /// STAB minted against this asset
+    pub minted_stab: Decimal,
+    /// whether STAB can be minted against this asset
+    pub accepted: bool,
+}
+
+/// Event emitted when STAB is minted by depositing a stable asset
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PsmMintEvent {
+    pub asset: ResourceAddress,
+    pub asset_deposited: Decimal,
+    pub stab_minted: Decimal,
+    pub fee: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when STAB is redeemed for a stable asset
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PsmRedeemEvent {
+    pub asset: ResourceAddress,
+    pub stab_redeemed: Decimal,
+    pub asset_returned: Decimal,
+    pub fee: Decimal,
+    pub internal_price: Decimal,
+}
+
+#[blueprint]
+#[events(PsmMintEvent, PsmRedeemEvent)]
+mod peg_stability_module {
+    enable_method_auth! {
+        methods {
+            mint => restrict_to: [OWNER];
+            redeem => restrict_to: [OWNER];
+            add_asset => restrict_to: [OWNER];
+            edit_asset => restrict_to: [OWNER];
+            withdraw_fees => restrict_to: [OWNER];
+            withdraw_reserve => restrict_to: [OWNER];
+            settle_reserves => restrict_to: [OWNER];
+            get_asset_info => PUBLIC;
+            get_assets => PUBLIC;
+            get_fees => PUBLIC;
+        }
+    }
+
+    struct PegStabilityModule {
+        /// The vault for the controller badge, used to authorize minting and burning STAB
+        badge_vault: FungibleVault,
+        /// The global instance of the Stabilis component
+        stabilis: Global<Stabilis>,
+        /// KVS storing all accepted stable assets and their information
+        assets: KeyValueStore<ResourceAddress, PsmAsset>,
+        /// Addresses of all stable assets ever added, to be able to query them
+        asset_addresses: Vec<ResourceAddress>,
+        /// The vault for the collected fees (in STAB)
+        fees: Vault,
+    }
+
+    impl PegStabilityModule {
+        /// Instantiates the PegStabilityModule component
+        ///
+        /// # Input
+        /// - `controller_badge`: The controller badge of the Stabilis component
+        /// - `stabilis_address`: The address of the Stabilis component
+        /// - `stab_address`: The resource address of STAB
+        ///
+        /// # Output
+        /// - The global instance of the PegStabilityModule component
+        pub fn instantiate(
+            controller_badge: Bucket,
+            stabilis_address: ComponentAddress,
+            stab_address: ResourceAddress,
+        ) -> Global<PegStabilityModule> {
+            let controller_address: ResourceAddress = controller_badge.resource_address();
+
+            Self {
+                badge_vault: FungibleVault::with_bucket(controller_badge.as_fungible()),
+                stabilis: Global::from(stabilis_address),
+                assets: KeyValueStore::new(),
+                asset_addresses: Vec::new(),
+                fees: Vault::new(stab_address),
+            }
+            .instantiate()
+            .prepare_to_globalize(OwnerRole::Fixed(rule!(require(controller_address))))
+            .globalize()
+        }
+
+        /// Mint STAB by depositing a stable asset
+        ///
+        /// # Input
+        /// - `asset`: The stable asset to deposit
+        ///
+        /// # Output
+        /// - The minted STAB, minus the in fee
+        ///
+        /// # Logic
+        /// - Check whether the asset is accepted
+        /// - Calculate the STAB to mint at the internal price, and check the debt ceiling
+        /// - Mint the STAB through the Stabilis component (counting toward circulating STAB)
+        /// - Put the fee in the fee vault and the asset in the reserve
+        /// - Return the rest of the STAB
+        pub fn mint(&mut self, asset: Bucket) -> Bucket {
+            let address: ResourceAddress = asset.resource_address();
+            let internal_price: Decimal = self.stabilis.return_internal_price();
+            let stab_amount: Decimal = asset.amount() / internal_price;
+
+            let fee: Decimal = {
+                let mut info = self
+                    .assets
+                    .get_mut(&address)
+                    .expect("This asset is not accepted");
+                assert!(info.accepted, "This asset is not accepted");
+                assert!(
+                    info.minted_stab + stab_amount <= info.debt_ceiling,
+                    "Debt ceiling of this asset reached."
+                );
+                info.minted_stab += stab_amount;
+                stab_amount * info.fee_in
+            };
+
+            let mut stab: Bucket = self
+                .badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.psm_mint(stab_amount));
+
+            self.fees.put(stab.take(fee));
+
+            Runtime::emit_event(PsmMintEvent {
+                asset: address,
+                asset_deposited: asset.amount(),
+                stab_minted: stab_amount,
+                fee,
+                internal_price,
+            });
+
+            self.assets.get_mut(&address).unwrap().reserve.put(asset);
+
+            stab
+        }
+
+        /// Redeem STAB for a stable asset
+        ///
+        /// # Input
+        /// - `stab`: The STAB to redeem
+        /// - `asset`: The stable asset to receive
+        ///
+        /// # Output
+        /// - The stable asset
+        ///
+        /// # Logic
+        /// - Take the out fee from the STAB and put it in the fee vault
+        /// - Check the redeemed STAB isn't more than was minted against the asset, so a depegged asset can't be swapped for another one
+        /// - Calculate the stable assets to return at the internal price, and check the reserve is big enough
+        /// - Burn the rest of the STAB through the Stabilis component (reducing circulating STAB)
+        /// - Return the stable asset
+        pub fn redeem(&mut self, mut stab: Bucket, asset: ResourceAddress) -> Bucket {
+            let internal_price: Decimal = self.stabilis.return_internal_price();
+
+            let fee: Decimal = stab.amount() * self.assets.get(&asset).expect("Unknown asset").fee_out;
+            self.fees.put(stab.take(fee));
+
+            let stab_redeemed: Decimal = stab.amount();
+            let asset_amount: Decimal = stab_redeemed * internal_price;
+
+            let asset_bucket: Bucket = {
+                let mut info = self.assets.get_mut(&asset).expect("Unknown asset");
+                assert!(
+                    stab_redeemed <= info.minted_stab,
+                    "Can't redeem more STAB than was minted against this asset."
+                );
+                assert!(
+                    info.reserve.amount() >= asset_amount,
+                    "Not enough of this asset in the reserve."
+                );
+                info.minted_stab -= stab_redeemed;
+                info.reserve
+                    .take_advanced(asset_amount, WithdrawStrategy::Rounded(RoundingMode::ToZero))
+            };
+
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.psm_burn(stab));
+
+            Runtime::emit_event(PsmRedeemEvent {
+                asset,
+                stab_redeemed,
+                asset_returned: asset_bucket.amount(),
+                fee,
+                internal_price,
+            });
+
+            asset_bucket
+        }
+
+        /// Add a stable asset that can be used to mint STAB
+        pub fn add_asset(
+            &mut self,
+            address: ResourceAddress,
+            fee_in: Decimal,
+            fee_out: Decimal,
+            debt_ceiling: Decimal,
+        ) {
+            assert!(self.assets.get(&address).is_none(), "Asset already added.");
+            Self::check_fees(fee_in, fee_out);
+
+            self.assets.insert(
+                address,
+                PsmAsset {
+                    reserve: Vault::new(address),
+                    fee_in,
+                    fee_out,
+                    debt_ceiling,
+                    minted_stab: dec!(0),
+                    accepted: true,
+                },
+            );
+            self.asset_addresses.push(address);
+        }
+
+        /// Edit the fees, debt ceiling and acceptance of a stable asset
+        pub fn edit_asset(
+            &mut self,
+            address: ResourceAddress,
+            fee_in: Decimal,
+            fee_out: Decimal,
+            debt_ceiling: Decimal,
+            accepted: bool,
+        ) {
+            Self::check_fees(fee_in, fee_out);
+            let mut info = self.assets.get_mut(&address).expect("Unknown asset");
+            info.fee_in = fee_in;
+            info.fee_out = fee_out;
+            info.debt_ceiling = debt_ceiling;
+            info.accepted = accepted;
+        }
+
+        /// Withdraw collected fees (in STAB)
+        pub fn withdraw_fees(&mut self, amount: Decimal) -> Bucket {
+            self.fees.take(amount)
+        }
+
+        /// Withdraw stable assets from a reserve
+        pub fn withdraw_reserve(&mut self, address: ResourceAddress, amount: Decimal) -> Bucket {
+            self.assets
+                .get_mut(&address)
+                .expect("Unknown asset")
+                .reserve
+                .take(amount)
+        }
+
+        /// Hand all reserves over to the Stabilis component after an emergency shutdown, and stop accepting the assets
+        pub fn settle_reserves(&mut self) {
+            for address in self.asset_addresses.clone() {
+                let reserve: Bucket = {
+                    let mut info = self.assets.get_mut(&address).unwrap();
+                    info.accepted = false;
+                    info.reserve.take_all()
+                };
+
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis.settle_psm_reserve(reserve)
+                });
+            }
+        }
+
+        /// Gets the information of a stable asset
+        pub fn get_asset_info(&self, address: ResourceAddress) -> PsmAssetView {
+            let info = self.assets.get(&address).expect("Unknown asset");
+
+            PsmAssetView {
+                address,
+                reserve: info.reserve.amount(),
+                fee_in: info.fee_in,
+                fee_out: info.fee_out,
+                debt_ceiling: info.debt_ceiling,
+                minted_stab: info.minted_stab,
+                accepted: info.accepted,
+            }
+        }
+
+        /// Gets the information of all stable assets
+        pub fn get_assets(&self) -> Vec<PsmAssetView> {
+            self.asset_addresses
+                .iter()
+                .map(|address| self.get_asset_info(*address))
+                .collect()
+        }
+
+        /// Gets the amount of collected fees (in STAB)
+        pub fn get_fees(&self) -> Decimal {
+            self.fees.amount()
+        }
+
+        /// Check whether the fees of a stable asset are valid (at least 0, and below 1)
+        fn check_fees(fee_in: Decimal, fee_out: Decimal) {
+            assert!(
+                fee_in >= dec!(0) && fee_in < dec!(1),
+                "In fee must be at least 0 and below 1."
+            );
+            assert!(
+                fee_out >= dec!(0) && fee_out < dec!(1),
+                "Out fee must be at least 0 and below 1."
+            );
+        }
+    }
+}
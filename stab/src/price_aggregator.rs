@@ -0,0 +1,574 @@
+//! # Price Aggregator Blueprint
+//!
+//! Oracle component aggregating the prices of several sources per resource, so no single feed or key can move the collateral prices of the protocol.
+//! - Governance registers price sources per resource:
+//!     - Component sources: another component with a method returning the price and the time it was last updated as a `(Decimal, Instant)`
+//!         - Pulled one by one by anyone (`pull_component_source`), so a component that reverts only fails its own pull, not the aggregation of every resource
+//!         - The time returned by the component is used as the update time, so a feed that stopped updating is rejected as stale
+//!     - Reporter sources: a badge, the holder of which can push prices (`report_price`)
+//!     - Signed report sources: prices signed off-ledger by a governance-managed set of reporter keys, submitted by anyone (`submit_signed_report`)
+//!         - A report is accepted if a quorum of distinct reporter keys signed it, and its timestamp is newer than the last accepted report (and not in the future)
+//!         - Reporter keys are Secp256k1 or Ed25519 keys, signing the Blake2b-256 hash of the report message (verified on-ledger through `CryptoUtils`)
+//! - Every source stores its latest price and the time it was updated
+//! - The aggregated price is the median of the accepted sources:
+//!     - Sources that weren't updated within the heartbeat of the resource are rejected as stale
+//!     - Sources deviating more than the maximum deviation from the median of the fresh sources are rejected
+//!     - If fewer sources than the minimum are left, no price is returned, but a status telling why
+//! - The Proxy component uses `get_aggregated_prices`, and skips collaterals of which the price wasn't accepted
+
+use scrypto::prelude::*;
+
+/// Kind of a price source
+#[derive(ScryptoSbor, Clone, Debug)]
+pub enum PriceSourceKind {
+    /// A component with a method returning the price and the time it was last updated as a `(Decimal, Instant)`
+    Component(ComponentAddress, String),
+    /// A badge, the holder of which can report prices
+    Reporter(ResourceAddress),
+    /// Reports signed by a quorum of the reporter keys
+    SignedReports,
+}
+
+/// Signature of a price report by a reporter key, using the scheme of that key
+#[derive(ScryptoSbor, Clone, Debug)]
+pub enum ReportSignature {
+    Secp256k1(Secp256k1Signature),
+    Ed25519(Ed25519Signature),
+}
+
+/// A price source of a resource, with its latest price
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct PriceSource {
+    /// where the price comes from
+    pub kind: PriceSourceKind,
+    /// latest price of the source (None if it never reported)
+    pub price: Option<Decimal>,
+    /// time of the latest price
+    pub last_update: Instant,
+}
+
+/// Aggregation settings and sources of a resource
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct AggregatedResource {
+    /// the price sources of the resource
+    pub sources: Vec<PriceSource>,
+    /// minutes after which a source's price is stale
+    pub heartbeat: i64,
+    /// maximum relative deviation of a source from the median (0.05 is 5%)
+    pub max_deviation: Decimal,
+    /// minimum amount of accepted sources to return a price
+    pub min_sources: u64,
+}
+
+/// Status of an aggregated price
+#[derive(ScryptoSbor, PartialEq, Clone, Copy, Debug)]
+pub enum PriceStatus {
+    /// Enough sources agree, the price can be used
+    Valid,
+    /// Not enough sources were updated within the heartbeat
+    Stale,
+    /// Not enough fresh sources are within the maximum deviation of the median
+    Deviating,
+}
+
+/// Aggregated price of a resource
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct AggregatedPrice {
+    /// the median price of the accepted sources (None if the status isn't valid)
+    pub price: Option<Decimal>,
+    /// whether the price can be used, or why not
+    pub status: PriceStatus,
+    /// amount of fresh sources
+    pub fresh_sources: u64,
+    /// amount of fresh sources within the maximum deviation of the median
+    pub accepted_sources: u64,
+}
+
+/// Event emitted when a reporter reports a price
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PriceReportedEvent {
+    pub resource: ResourceAddress,
+    pub reporter: ResourceAddress,
+    pub price: Decimal,
+}
+
+/// Event emitted when a signed price report is accepted
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SignedPriceReportedEvent {
+    pub resource: ResourceAddress,
+    pub price: Decimal,
+    pub timestamp: i64,
+    pub signers: u64,
+}
+
+/// Event emitted when the price of a resource can't be aggregated
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PriceRejectedEvent {
+    pub resource: ResourceAddress,
+    pub status: PriceStatus,
+    pub fresh_sources: u64,
+    pub accepted_sources: u64,
+}
+
+#[blueprint]
+#[events(PriceReportedEvent, SignedPriceReportedEvent, PriceRejectedEvent)]
+mod price_aggregator {
+    enable_method_auth! {
+        methods {
+            report_price => PUBLIC;
+            submit_signed_report => PUBLIC;
+            get_report_message => PUBLIC;
+            get_reporter_keys => PUBLIC;
+            pull_component_source => PUBLIC;
+            get_aggregated_prices => PUBLIC;
+            get_prices => PUBLIC;
+            get_price => PUBLIC;
+            get_resource => PUBLIC;
+            add_resource => restrict_to: [OWNER];
+            set_resource_parameters => restrict_to: [OWNER];
+            add_component_source => restrict_to: [OWNER];
+            add_reporter_source => restrict_to: [OWNER];
+            add_signed_report_source => restrict_to: [OWNER];
+            set_reporter_keys => restrict_to: [OWNER];
+            remove_source => restrict_to: [OWNER];
+        }
+    }
+
+    struct PriceAggregator {
+        /// KVS storing the aggregation settings and sources of every resource
+        resources: KeyValueStore<ResourceAddress, AggregatedResource>,
+        /// The resources with an aggregated price
+        resource_addresses: Vec<ResourceAddress>,
+        /// The public keys allowed to sign price reports
+        reporter_keys: Vec<PublicKey>,
+        /// The amount of distinct reporter keys that have to sign a price report
+        quorum: u64,
+    }
+
+    impl PriceAggregator {
+        /// Instantiates the PriceAggregator component
+        ///
+        /// # Input
+        /// - `controller`: The owner badge address (the DAO's controller badge)
+        ///
+        /// # Output
+        /// - The global instance of the PriceAggregator component
+        pub fn instantiate(controller: ResourceAddress) -> Global<PriceAggregator> {
+            Self {
+                resources: KeyValueStore::new(),
+                resource_addresses: vec![],
+                reporter_keys: vec![],
+                quorum: 1,
+            }
+            .instantiate()
+            .prepare_to_globalize(OwnerRole::Fixed(rule!(require(controller))))
+            .globalize()
+        }
+
+        /// Report a price as a reporter source
+        ///
+        /// # Input
+        /// - `reporter_proof`: Proof of the reporter badge
+        /// - `resource`: The resource to report the price of
+        /// - `price`: The price
+        ///
+        /// # Logic
+        /// - Find the reporter source of the resource with the badge of the proof
+        /// - Store the price and the current time
+        pub fn report_price(&mut self, reporter_proof: Proof, resource: ResourceAddress, price: Decimal) {
+            assert!(price > dec!(0), "Price must be positive.");
+            let reporter: ResourceAddress = reporter_proof.resource_address();
+            reporter_proof.check_with_message(reporter, "Invalid reporter proof.");
+
+            let mut info = self
+                .resources
+                .get_mut(&resource)
+                .expect("No price sources for this resource.");
+            let source: &mut PriceSource = info
+                .sources
+                .iter_mut()
+                .find(|source| {
+                    matches!(source.kind, PriceSourceKind::Reporter(badge) if badge == reporter)
+                })
+                .expect("Not a reporter of this resource.");
+
+            source.price = Some(price);
+            source.last_update = Clock::current_time_rounded_to_minutes();
+
+            Runtime::emit_event(PriceReportedEvent {
+                resource,
+                reporter,
+                price,
+            });
+        }
+
+        /// Submit a price report signed off-ledger by the reporter keys (anyone can relay a report)
+        ///
+        /// # Input
+        /// - `resource`: The resource the price is reported for
+        /// - `price`: The price
+        /// - `timestamp`: The time of the report (seconds since unix epoch)
+        /// - `signatures`: Signatures of the Blake2b-256 hash of the report message (see `get_report_message`), with the reporter key that signed it
+        ///
+        /// # Logic
+        /// - Check the timestamp is newer than the last accepted report, and not in the future
+        /// - Verify every signature with the scheme of its reporter key, each reporter key may only be counted once
+        /// - Check the amount of valid signatures reaches the quorum
+        /// - Store the price as the price of the signed report source of the resource, updated at the report's timestamp
+        pub fn submit_signed_report(
+            &mut self,
+            resource: ResourceAddress,
+            price: Decimal,
+            timestamp: i64,
+            signatures: Vec<(PublicKey, ReportSignature)>,
+        ) {
+            assert!(price > dec!(0), "Price must be positive.");
+            let report_time: Instant = Instant::new(timestamp);
+            assert!(
+                Clock::current_time_is_at_or_after(report_time, TimePrecision::Minute),
+                "Report is from the future."
+            );
+
+            let message_hash: Hash =
+                CryptoUtils::blake2b_256_hash(&self.get_report_message(resource, price, timestamp));
+            let mut signers: Vec<PublicKey> = Vec::new();
+
+            for (public_key, signature) in signatures.iter() {
+                assert!(
+                    self.reporter_keys.contains(public_key),
+                    "Signed by an unknown reporter key."
+                );
+                assert!(!signers.contains(public_key), "Reporter key signed twice.");
+
+                let valid: bool = match (public_key, signature) {
+                    (PublicKey::Secp256k1(key), ReportSignature::Secp256k1(signature)) => {
+                        CryptoUtils::secp256k1_ecdsa_verify(message_hash, key, signature)
+                    }
+                    (PublicKey::Ed25519(key), ReportSignature::Ed25519(signature)) => {
+                        CryptoUtils::ed25519_verify(message_hash.as_ref(), key, signature)
+                    }
+                    _ => false,
+                };
+                assert!(valid, "Invalid report signature.");
+
+                signers.push(*public_key);
+            }
+
+            assert!(
+                signers.len() as u64 >= self.quorum,
+                "Not enough reporters signed the report."
+            );
+
+            let mut info = self
+                .resources
+                .get_mut(&resource)
+                .expect("No price sources for this resource.");
+            let source: &mut PriceSource = info
+                .sources
+                .iter_mut()
+                .find(|source| matches!(source.kind, PriceSourceKind::SignedReports))
+                .expect("No signed report source for this resource.");
+
+            assert!(
+                source.price.is_none()
+                    || report_time.seconds_since_unix_epoch > source.last_update.seconds_since_unix_epoch,
+                "Report is not newer than the last accepted report."
+            );
+
+            source.price = Some(price);
+            source.last_update = report_time;
+
+            Runtime::emit_event(SignedPriceReportedEvent {
+                resource,
+                price,
+                timestamp,
+                signers: signers.len() as u64,
+            });
+        }
+
+        /// Get the message reporters sign for a price report (binding the report to this component)
+        pub fn get_report_message(
+            &self,
+            resource: ResourceAddress,
+            price: Decimal,
+            timestamp: i64,
+        ) -> Vec<u8> {
+            scrypto_encode(&(Runtime::global_address(), resource, price, timestamp)).unwrap()
+        }
+
+        /// Get the reporter keys and the quorum of signed price reports
+        pub fn get_reporter_keys(&self) -> (Vec<PublicKey>, u64) {
+            (self.reporter_keys.clone(), self.quorum)
+        }
+
+        /// Pull the price of a component source (anyone can pull)
+        ///
+        /// # Input
+        /// - `resource`: The resource the component source prices
+        /// - `index`: The index of the component source in the sources of the resource
+        ///
+        /// # Logic
+        /// - Call the method of the component, returning the price and the time it was last updated
+        /// - Check the update time isn't in the future, and isn't older than the stored update time
+        /// - Store the price, updated at the time returned by the component
+        pub fn pull_component_source(&mut self, resource: ResourceAddress, index: u64) {
+            let source: PriceSource = self
+                .resources
+                .get(&resource)
+                .expect("No price sources for this resource.")
+                .sources
+                .get(index as usize)
+                .expect("Price source not found.")
+                .clone();
+
+            let (component, method_name): (ComponentAddress, String) = match source.kind {
+                PriceSourceKind::Component(component, method_name) => (component, method_name),
+                _ => panic!("Not a component source."),
+            };
+
+            let (price, update_time): (Decimal, Instant) =
+                Global::<AnyComponent>::from(component).call(&method_name, &());
+
+            assert!(price > dec!(0), "Price must be positive.");
+            assert!(
+                Clock::current_time_is_at_or_after(update_time, TimePrecision::Minute),
+                "Price is from the future."
+            );
+            assert!(
+                source.price.is_none()
+                    || update_time.seconds_since_unix_epoch >= source.last_update.seconds_since_unix_epoch,
+                "Price is older than the stored price."
+            );
+
+            let mut info = self.resources.get_mut(&resource).unwrap();
+            info.sources[index as usize].price = Some(price);
+            info.sources[index as usize].last_update = update_time;
+        }
+
+        /// Aggregate the price of every resource, using the stored prices of its sources (component sources are pulled separately, see `pull_component_source`)
+        ///
+        /// # Output
+        /// - The aggregated price of every resource, with its status
+        ///
+        /// # Logic
+        /// - Aggregate the price of every resource (see `get_price`)
+        /// - Emit an event for every resource of which the price was rejected
+        pub fn get_aggregated_prices(&mut self) -> Vec<(ResourceAddress, AggregatedPrice)> {
+            let mut prices: Vec<(ResourceAddress, AggregatedPrice)> = Vec::new();
+
+            for resource in self.resource_addresses.clone() {
+                let price: AggregatedPrice = self.get_price(resource);
+
+                if price.status != PriceStatus::Valid {
+                    Runtime::emit_event(PriceRejectedEvent {
+                        resource,
+                        status: price.status,
+                        fresh_sources: price.fresh_sources,
+                        accepted_sources: price.accepted_sources,
+                    });
+                }
+
+                prices.push((resource, price));
+            }
+
+            prices
+        }
+
+        /// Get the valid aggregated prices, in the format of the basic Oracle component
+        pub fn get_prices(&mut self) -> Vec<(ResourceAddress, Decimal)> {
+            self.get_aggregated_prices()
+                .into_iter()
+                .filter_map(|(resource, price)| price.price.map(|price| (resource, price)))
+                .collect()
+        }
+
+        /// Aggregate the price of a resource, using the stored prices of its sources
+        ///
+        /// # Logic
+        /// - Reject sources without a price, or not updated within the heartbeat
+        /// - Take the median of the fresh sources
+        /// - Reject sources deviating more than the maximum deviation from this median
+        /// - Return the median of the accepted sources, or the reason there aren't enough
+        pub fn get_price(&self, resource: ResourceAddress) -> AggregatedPrice {
+            let info = self
+                .resources
+                .get(&resource)
+                .expect("No price sources for this resource.");
+
+            let fresh_prices: Vec<Decimal> = info
+                .sources
+                .iter()
+                .filter(|source| {
+                    Clock::current_time_is_strictly_before(
+                        source.last_update.add_minutes(info.heartbeat).unwrap(),
+                        TimePrecision::Minute,
+                    )
+                })
+                .filter_map(|source| source.price)
+                .collect();
+
+            if (fresh_prices.len() as u64) < info.min_sources || fresh_prices.is_empty() {
+                return AggregatedPrice {
+                    price: None,
+                    status: PriceStatus::Stale,
+                    fresh_sources: fresh_prices.len() as u64,
+                    accepted_sources: 0,
+                };
+            }
+
+            let fresh_median: Decimal = median(fresh_prices.clone());
+            let accepted_prices: Vec<Decimal> = fresh_prices
+                .iter()
+                .copied()
+                .filter(|price| {
+                    (*price - fresh_median).checked_abs().unwrap() / fresh_median
+                        <= info.max_deviation
+                })
+                .collect();
+
+            if (accepted_prices.len() as u64) < info.min_sources || accepted_prices.is_empty() {
+                return AggregatedPrice {
+                    price: None,
+                    status: PriceStatus::Deviating,
+                    fresh_sources: fresh_prices.len() as u64,
+                    accepted_sources: accepted_prices.len() as u64,
+                };
+            }
+
+            AggregatedPrice {
+                price: Some(median(accepted_prices.clone())),
+                status: PriceStatus::Valid,
+                fresh_sources: fresh_prices.len() as u64,
+                accepted_sources: accepted_prices.len() as u64,
+            }
+        }
+
+        /// Get the aggregation settings and sources of a resource
+        pub fn get_resource(&self, resource: ResourceAddress) -> AggregatedResource {
+            self.resources
+                .get(&resource)
+                .expect("No price sources for this resource.")
+                .clone()
+        }
+
+        //==================================================================
+        //                         ADMIN METHODS
+        //==================================================================
+
+        /// Add a resource to aggregate the price of
+        pub fn add_resource(
+            &mut self,
+            resource: ResourceAddress,
+            heartbeat: i64,
+            max_deviation: Decimal,
+            min_sources: u64,
+        ) {
+            assert!(
+                self.resources.get(&resource).is_none(),
+                "Resource already added."
+            );
+            self.resources.insert(
+                resource,
+                AggregatedResource {
+                    sources: vec![],
+                    heartbeat,
+                    max_deviation,
+                    min_sources,
+                },
+            );
+            self.resource_addresses.push(resource);
+        }
+
+        /// Set the heartbeat (minutes), maximum deviation and minimum amount of sources of a resource
+        pub fn set_resource_parameters(
+            &mut self,
+            resource: ResourceAddress,
+            heartbeat: i64,
+            max_deviation: Decimal,
+            min_sources: u64,
+        ) {
+            let mut info = self.resources.get_mut(&resource).unwrap();
+            info.heartbeat = heartbeat;
+            info.max_deviation = max_deviation;
+            info.min_sources = min_sources;
+        }
+
+        /// Add a component source, with a method returning the price of the resource and the time it was last updated as a `(Decimal, Instant)`
+        pub fn add_component_source(
+            &mut self,
+            resource: ResourceAddress,
+            component: ComponentAddress,
+            method_name: String,
+        ) {
+            self.add_source(resource, PriceSourceKind::Component(component, method_name));
+        }
+
+        /// Add a reporter source, the holder of the badge can report prices of the resource
+        pub fn add_reporter_source(&mut self, resource: ResourceAddress, badge: ResourceAddress) {
+            self.add_source(resource, PriceSourceKind::Reporter(badge));
+        }
+
+        /// Add a signed report source, accepting reports signed by a quorum of the reporter keys
+        pub fn add_signed_report_source(&mut self, resource: ResourceAddress) {
+            assert!(
+                !self
+                    .resources
+                    .get(&resource)
+                    .expect("Resource not added.")
+                    .sources
+                    .iter()
+                    .any(|source| matches!(source.kind, PriceSourceKind::SignedReports)),
+                "Signed report source already added."
+            );
+            self.add_source(resource, PriceSourceKind::SignedReports);
+        }
+
+        /// Set the reporter keys allowed to sign price reports, and how many distinct keys have to sign a report
+        pub fn set_reporter_keys(&mut self, reporter_keys: Vec<PublicKey>, quorum: u64) {
+            assert!(quorum > 0, "Quorum must be at least 1.");
+            assert!(
+                quorum <= reporter_keys.len() as u64,
+                "Quorum can't be more than the amount of reporter keys."
+            );
+            self.reporter_keys = reporter_keys;
+            self.quorum = quorum;
+        }
+
+        /// Remove a price source of a resource
+        pub fn remove_source(&mut self, resource: ResourceAddress, index: u64) {
+            self.resources
+                .get_mut(&resource)
+                .unwrap()
+                .sources
+                .remove(index as usize);
+        }
+
+        //==================================================================
+        //                         HELPER METHODS
+        //==================================================================
+
+        /// Add a price source to a resource
+        fn add_source(&mut self, resource: ResourceAddress, kind: PriceSourceKind) {
+            self.resources
+                .get_mut(&resource)
+                .expect("Resource not added.")
+                .sources
+                .push(PriceSource {
+                    kind,
+                    price: None,
+                    last_update: Clock::current_time_rounded_to_minutes(),
+                });
+        }
+    }
+}
+
+/// Median of a non-empty list of prices (the average of the middle two for an even amount)
+fn median(mut prices: Vec<Decimal>) -> Decimal {
+    prices.sort();
+    let middle: usize = prices.len() / 2;
+    if prices.len() % 2 == 0 {
+        (prices[middle - 1] + prices[middle]) / dec!(2)
+    } else {
+        prices[middle]
+    }
+}
@@ -5,19 +5,38 @@
 //!    - STAB's internal price
 //!        - The internal price is calculated by the interest rate within this component
 //!             - The interest rate is calculated using a PID controller (with the price error as the input), to ensure demand and supply for STAB meets at the price: STAB trading above its peg will decrease the interest rate (to incentivize borrowing), and vice versa.
+//!             - Governance can switch to another interest rate strategy: a PID controller with a derivative term and anti-windup, a fixed interest rate, or a schedule mapping price errors to interest rates.
 //!    - Collateral prices
 //! - Ensure that the Stabilis component is only interacted with by authorized callers.
 //! - Ensure potential upgrades to the Stabilis component can be done without disrupting the rest of the system.
 //!
+//! The proxy also combines components for a better user experience:
+//! - Opening a leveraged XRD loan in one call (`open_leveraged_cdp`): flash borrowing STAB, swapping it for XRD through the StabilisPool, and using all XRD as collateral for a loan that pays back the flash loan.
+//! - Repaying the debt of an XRD loan with its own collateral (`deleverage_cdp`): flash borrowing STAB to repay the debt, and swapping collateral for STAB to pay back the flash loan.
+//!
+//! Keepers are rewarded through the proxy as well: a marker receipt can be exchanged for a bounty once (`claim_keeper_reward`), and updating the internal price through `rewarded_update` pays a bounty at most once per update delay.
+//!
 //! Interest rate calculation is done within the proxy component, and collateral prices are gathered from an oracle and sent to the main component through here as well.
 //!
 //! Methods used to call other components only are explained in their respective modules.
 //! Sometimes, a proof is checked within this component, as they cannot be passed along to other components. The ID for this proof is then passed along, for the other component to check the proofs data.
 
 use crate::flash_loans::flash_loans::*;
+use crate::flash_loans::LoanReceipt;
+use crate::keeper_rewards::keeper_rewards::*;
+use crate::keeper_rewards::KeeperRewardConfig;
+use crate::peg_stability_module::peg_stability_module::*;
+use crate::peg_stability_module::PsmAssetView;
+use crate::price_aggregator::{AggregatedPrice, PriceStatus};
 use crate::shared_structs::*;
 use crate::stabilis_component::stabilis_component::*;
+use crate::stabilis_component::{
+    CircuitBreaker, DebtAuctionView, ProtocolParameters, ShutdownState,
+};
 use crate::stabilis_liquidity_pool::stabilis_liquidity_pool::*;
+use crate::stability_pool::stability_pool::*;
+use crate::surplus_auction::surplus_auction::*;
+use crate::surplus_auction::{SurplusAuctionConfig, SurplusAuctionView};
 use scrypto::prelude::*;
 use scrypto_math::*;
 
@@ -31,34 +50,129 @@ mod proxy {
             remove_collateral => PUBLIC;
             close_cdp => PUBLIC;
             partial_close_cdp => PUBLIC;
+            swap_cdp_collateral => PUBLIC;
             retrieve_leftover_collateral => PUBLIC;
             mark_for_liquidation => PUBLIC;
+            mark_cdp => PUBLIC;
+            mark_for_liquidation_batch => PUBLIC;
+            retrieve_marker_deposit => PUBLIC;
+            liquidate_batch => PUBLIC;
+            refresh_pool_unit_crs => PUBLIC;
             liquidate_position_with_marker => PUBLIC;
             liquidate_position_without_marker => PUBLIC;
+            partial_liquidate_position_with_marker => PUBLIC;
+            partial_liquidate_position_without_marker => PUBLIC;
+            fill_auction => PUBLIC;
+            open_basket_cdp => PUBLIC;
+            top_up_basket_cdp => PUBLIC;
+            remove_basket_collateral => PUBLIC;
+            borrow_more_basket => PUBLIC;
+            close_basket_cdp => PUBLIC;
+            retrieve_leftover_basket_collateral => PUBLIC;
+            mark_basket_for_liquidation => PUBLIC;
+            liquidate_basket => PUBLIC;
+            settle_cdp => PUBLIC;
+            settle_basket_cdp => PUBLIC;
+            redeem_settlement => PUBLIC;
             update => PUBLIC;
+            rewarded_update => PUBLIC;
+            claim_keeper_reward => PUBLIC;
+            get_keeper_reward_config => PUBLIC;
+            get_keeper_reward_funds => PUBLIC;
             get_internal_price => PUBLIC;
+            get_interest_rate_state => PUBLIC;
+            get_collateral_info => PUBLIC;
+            get_pool_unit_info => PUBLIC;
+            get_lp_unit_info => PUBLIC;
+            get_circuit_breaker => PUBLIC;
+            get_protocol_parameters => PUBLIC;
+            get_price_statuses => PUBLIC;
+            get_lowest_crs => PUBLIC;
+            get_marked_cdps => PUBLIC;
+            get_cdp_health => PUBLIC;
+            get_auction_discount => PUBLIC;
+            get_stab_surplus => PUBLIC;
+            get_basket_health => PUBLIC;
+            get_lowest_basket_healths => PUBLIC;
+            get_shutdown_state => PUBLIC;
+            get_bad_debt => PUBLIC;
+            get_surplus_buffer => PUBLIC;
+            get_debt_auction => PUBLIC;
+            deposit_surplus => PUBLIC;
+            bid_debt_auction => PUBLIC;
+            put_tokens => PUBLIC;
+            start_surplus_auction => PUBLIC;
+            bid_surplus_auction => PUBLIC;
+            get_surplus_auction => PUBLIC;
             flash_borrow => PUBLIC;
             flash_pay_back => PUBLIC;
+            open_leveraged_cdp => PUBLIC;
+            deleverage_cdp => PUBLIC;
             burn_marker => PUBLIC;
             burn_loan_receipt => PUBLIC;
             force_mint => PUBLIC;
             force_liquidate => PUBLIC;
+            redeem => PUBLIC;
             receive_badges => PUBLIC;
+            stability_pool_deposit => PUBLIC;
+            stability_pool_withdraw => PUBLIC;
+            stability_pool_claim => PUBLIC;
+            liquidate_with_stability_pool => PUBLIC;
+            psm_mint => PUBLIC;
+            psm_redeem => PUBLIC;
+            get_psm_asset_info => PUBLIC;
+            get_psm_assets => PUBLIC;
+            get_psm_fees => PUBLIC;
             change_collateral_price => restrict_to: [OWNER];
+            set_circuit_breaker => restrict_to: [OWNER];
+            confirm_pending_price => restrict_to: [OWNER];
+            reject_pending_price => restrict_to: [OWNER];
             set_max_vector_length => restrict_to: [OWNER];
+            set_pool_unit_refresh_count => restrict_to: [OWNER];
             set_price_error => restrict_to: [OWNER];
             set_minmax_interest => restrict_to: [OWNER];
+            set_twap_window => restrict_to: [OWNER];
             set_update_delays => restrict_to: [OWNER];
             set_ks => restrict_to: [OWNER];
+            set_interest_strategy => restrict_to: [OWNER];
+            set_pid_parameters => restrict_to: [OWNER];
+            set_fixed_interest_rate => restrict_to: [OWNER];
+            set_interest_schedule => restrict_to: [OWNER];
             set_allowed_deviation => restrict_to: [OWNER];
             add_collateral => restrict_to: [OWNER];
             add_pool_collateral => restrict_to: [OWNER];
+            add_lp_collateral => restrict_to: [OWNER];
+            update_lp_price => restrict_to: [OWNER];
             change_internal_price => restrict_to: [OWNER];
             set_oracle => restrict_to: [OWNER];
+            set_aggregated_oracle => restrict_to: [OWNER];
             send_badges => restrict_to: [OWNER];
-            flash_retrieve_interest => restrict_to: [OWNER];
+            cancel_bad_debt => restrict_to: [OWNER];
+            fund_surplus_buffer_from_treasury => restrict_to: [OWNER];
+            start_debt_auction => restrict_to: [OWNER];
+            end_debt_auction => restrict_to: [OWNER];
+            set_surplus_auction_config => restrict_to: [OWNER];
+            set_surplus_auction_dao => restrict_to: [OWNER];
+            cancel_surplus_auction => restrict_to: [OWNER];
+            set_keeper_reward_config => restrict_to: [OWNER];
+            fund_keeper_rewards_from_surplus => restrict_to: [OWNER];
+            fund_keeper_rewards_from_dao_tokens => restrict_to: [OWNER];
+            withdraw_keeper_rewards => restrict_to: [OWNER];
             set_force_mint_liq_percentage => restrict_to: [OWNER];
             set_number_of_prices_cached => restrict_to: [OWNER];
+            set_redemption_parameters => restrict_to: [OWNER];
+            set_partial_liquidation_buffer => restrict_to: [OWNER];
+            set_marker_deposit => restrict_to: [OWNER];
+            set_auction_parameters => restrict_to: [OWNER];
+            set_liquidation_mode => restrict_to: [OWNER];
+            set_stability_fee => restrict_to: [OWNER];
+            retrieve_stab_surplus => restrict_to: [OWNER];
+            emergency_shutdown => restrict_to: [OWNER];
+            finalize_shutdown => restrict_to: [OWNER];
+            psm_add_asset => restrict_to: [OWNER];
+            psm_edit_asset => restrict_to: [OWNER];
+            psm_withdraw_fees => restrict_to: [OWNER];
+            psm_withdraw_reserve => restrict_to: [OWNER];
         }
     }
 
@@ -73,8 +187,22 @@ mod proxy {
         oracle: Global<AnyComponent>,
         /// The name of the method to call on the oracle component
         oracle_method_name: String,
+        /// Whether the oracle component is a PriceAggregator, returning a status with every price
+        aggregated_oracle: bool,
+        /// The status of the latest aggregated price of every resource (only used with a PriceAggregator oracle)
+        price_statuses: KeyValueStore<ResourceAddress, PriceStatus>,
         /// The global instance of the flash loans component
         flash_loans: Global<FlashLoans>,
+        /// The global instance of the stability pool component
+        stability_pool: Global<StabilityPool>,
+        /// The resource manager for the stability pool receipts
+        stability_pool_receipt_manager: ResourceManager,
+        /// The global instance of the peg stability module component
+        peg_stability_module: Global<PegStabilityModule>,
+        /// The global instance of the surplus auctions component
+        surplus_auctions: Global<SurplusAuctions>,
+        /// The global instance of the keeper rewards component
+        keeper_rewards: Global<KeeperRewards>,
         /// The delay between updates (minutes)
         update_delay: i64,
         /// The number of cached prices to use for the interest rate calculation
@@ -83,10 +211,16 @@ mod proxy {
         cdp_receipt_manager: ResourceManager,
         /// The resource manager for the CDP markers
         cdp_marker_manager: ResourceManager,
+        /// The resource manager for the basket loan receipts
+        basket_receipt_manager: ResourceManager,
         /// The price of the XRD token
         xrd_price: Decimal,
         /// The collaterals accepted by the Stabilis component
         accepted_collaterals: Vec<ResourceAddress>,
+        /// The pool unit collaterals accepted by the Stabilis component
+        pool_unit_collaterals: Vec<ResourceAddress>,
+        /// The maximum amount of loans per pool unit to re-price during an update (0 by default, so updates only re-price once governance opts in)
+        pool_unit_refresh_count: u64,
         /// The percentage of the collateral to supply when force minting
         percentage_to_supply: Decimal,
         /// The percentage of the collateral to take when force liquidating
@@ -95,10 +229,14 @@ mod proxy {
         parameters: InterestParameters,
         /// Data about STAB's price
         stab_price_data: StabPriceData,
+        /// The selected interest rate strategy and the parameters / state of the alternative strategies
+        controllers: InterestRateControllers,
+        /// Vaults storing tokens sent by the DAO (like ILIS to sell in debt auctions)
+        dao_tokens: KeyValueStore<ResourceAddress, Vault>,
     }
 
     impl Proxy {
-        /// Instantiates the Proxy component, a StabilisPool component and a FlashLoans component for the Stabilis protocol
+        /// Instantiates the Proxy component, a StabilisPool component, a FlashLoans component, a StabilityPool component, a PegStabilityModule component, a SurplusAuctions component and a KeeperRewards component for the Stabilis protocol
         ///
         /// # Input
         /// - `xrd_bucket`: The bucket for the XRD token
@@ -119,6 +257,10 @@ mod proxy {
         ///     - Adds liquidity to the STAB/XRD pool
         /// - Gets the internal price of the STAB token
         /// - Instantiates the FlashLoans component
+        /// - Instantiates the StabilityPool component
+        /// - Instantiates the PegStabilityModule component
+        /// - Instantiates the SurplusAuctions component
+        /// - Instantiates the KeeperRewards component
         /// - Instantiates the Proxy component
         pub fn new(
             xrd_bucket: Bucket,
@@ -140,12 +282,34 @@ mod proxy {
                 dec!(0.001),
             );
 
+            let stab_pool_stab_address: ResourceAddress = stab_bucket.resource_address();
+
             let (lp_tokens, optional_return_bucket): (Bucket, Option<Bucket>) =
                 stab_pool.add_liquidity(stab_bucket, xrd_bucket);
 
             let internal_price: Decimal =
                 controller_badge.authorize_with_all(|| stabilis.return_internal_price());
 
+            let basket_receipt_manager: ResourceManager =
+                ResourceManager::from_address(stabilis.get_basket_receipt_address());
+
+            let stability_pool: Global<StabilityPool> =
+                StabilityPool::instantiate(controller_address, stab_pool_stab_address);
+            let stability_pool_receipt_manager: ResourceManager =
+                ResourceManager::from_address(stability_pool.get_receipt_address());
+
+            let peg_stability_module: Global<PegStabilityModule> = PegStabilityModule::instantiate(
+                controller_badge.take(1),
+                stabilis_address,
+                stab_pool_stab_address,
+            );
+
+            let surplus_auctions: Global<SurplusAuctions> = SurplusAuctions::instantiate(
+                controller_badge.take(1),
+                stabilis_address,
+                stab_pool_stab_address,
+            );
+
             let proxy = Self {
                 flash_loans: FlashLoans::instantiate(
                     controller_badge.take(1),
@@ -154,14 +318,28 @@ mod proxy {
                 badge_vault: FungibleVault::with_bucket(controller_badge.as_fungible()),
                 stab_pool,
                 stabilis,
+                stability_pool,
+                stability_pool_receipt_manager,
+                peg_stability_module,
+                surplus_auctions,
+                keeper_rewards: KeeperRewards::instantiate(
+                    controller_address,
+                    cdp_receipt_address,
+                    cdp_marker_address,
+                ),
                 oracle: Global::from(oracle_address),
                 oracle_method_name: "get_prices".to_string(),
+                aggregated_oracle: false,
+                price_statuses: KeyValueStore::new(),
                 update_delay: 0,
                 number_of_cached_prices: 50,
                 cdp_receipt_manager: ResourceManager::from_address(cdp_receipt_address),
                 cdp_marker_manager: ResourceManager::from_address(cdp_marker_address),
+                basket_receipt_manager,
                 xrd_price: dec!("0.041"),
                 accepted_collaterals: vec![XRD],
+                pool_unit_collaterals: vec![],
+                pool_unit_refresh_count: 0,
                 percentage_to_supply: dec!("1.05"),
                 percentage_to_take: dec!("0.95"),
                 stab_price_data: StabPriceData {
@@ -173,6 +351,20 @@ mod proxy {
                     full_cache: false,
                     interest_rate: dec!(1),
                 },
+                dao_tokens: KeyValueStore::new(),
+                controllers: InterestRateControllers {
+                    strategy: InterestRateStrategy::ProportionalIntegral,
+                    pid: PidController {
+                        kp: dec!("0.00005"),
+                        ki: dec!("0.0000001"),
+                        kd: dec!("0.0001"),
+                        integral_limit: dec!(5),
+                        integral: dec!(0),
+                        last_error: None,
+                    },
+                    fixed_rate: dec!(1),
+                    schedule: vec![],
+                },
                 parameters: InterestParameters {
                     kp: dec!("0.00000000076517857"),
                     ki: dec!("0.00000000076517857"),
@@ -181,6 +373,7 @@ mod proxy {
                     allowed_deviation: dec!("0.005"),
                     price_error_offset: dec!(1),
                     max_price_error: dec!(0.5),
+                    twap_window: None,
                 },
             }
             .instantiate()
@@ -201,20 +394,35 @@ mod proxy {
         /// - None
         ///
         /// # Logic
+        /// - Does nothing after an emergency shutdown, as prices are frozen then
         /// - Updates the collateral prices
+        /// - Re-prices a batch of loans for every pool unit collateral
         /// - Checks if the internal price needs to be updated
         /// - Updates the internal price if needed
         pub fn update(&mut self) {
-            let passed_minutes: Decimal = (Clock::current_time_rounded_to_minutes()
-                .seconds_since_unix_epoch
-                - self.stab_price_data.last_update.seconds_since_unix_epoch)
-                / dec!(60);
-
-            self.update_collateral_prices();
+            self.update_prices();
+        }
 
-            if passed_minutes >= Decimal::from(self.update_delay) {
-                self.update_internal_price();
+        /// Updates the Stabilis component with new data, rewarding the caller if the internal price was updated
+        ///
+        /// # Input
+        /// - None
+        ///
+        /// # Output
+        /// - The keeper reward, or None if the internal price wasn't updated or the last rewarded update was too recent
+        ///
+        /// # Logic
+        /// - Updates the Stabilis component (see `update`)
+        /// - Rewards the caller if the internal price was updated, at most once per update delay
+        pub fn rewarded_update(&mut self) -> Option<Bucket> {
+            if !self.update_prices() {
+                return None;
             }
+
+            let update_delay: i64 = self.update_delay;
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.keeper_rewards.reward_update(update_delay)
+            })
         }
 
         /// Receives controller badges
@@ -255,6 +463,75 @@ mod proxy {
             self.percentage_to_take = percentage_to_take;
         }
 
+        /// Sets the redemption parameters of the Stabilis component
+        pub fn set_redemption_parameters(
+            &mut self,
+            stop_redemptions: bool,
+            redemption_fee: Decimal,
+            max_redemption_cdps: u64,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_redemption_parameters(
+                    stop_redemptions,
+                    redemption_fee,
+                    max_redemption_cdps,
+                )
+            });
+        }
+
+        /// Sets the buffer above the liquidation collateral ratio partially liquidated loans are restored to
+        pub fn set_partial_liquidation_buffer(&mut self, new_buffer: Decimal) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_partial_liquidation_buffer(new_buffer)
+            });
+        }
+
+        /// Sets the deposit required to mark a loan (None to mark loans for free)
+        pub fn set_marker_deposit(&mut self, config: Option<MarkerDepositConfig>) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_marker_deposit(config)
+            });
+        }
+
+        /// Sets the auction parameters of the Stabilis component
+        pub fn set_auction_parameters(
+            &mut self,
+            start_discount: Decimal,
+            discount_increase: Decimal,
+            max_discount: Decimal,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .set_auction_parameters(start_discount, discount_increase, max_discount)
+            });
+        }
+
+        /// Sets whether a collateral is liquidated through fixed fines or auctions
+        pub fn set_liquidation_mode(&mut self, collateral: ResourceAddress, mode: LiquidationMode) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_liquidation_mode(collateral, mode)
+            });
+        }
+
+        /// Sets the stability fee of a collateral (interest per minute)
+        pub fn set_stability_fee(&mut self, collateral: ResourceAddress, new_fee: Decimal) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_stability_fee(collateral, new_fee)
+            });
+        }
+
+        /// Retrieves collected stability fees from the Stabilis component
+        pub fn retrieve_stab_surplus(&mut self, amount: Decimal) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.retrieve_stab_surplus(amount)
+            })
+        }
+
+        /// Sets the window (in minutes) of the StabilisPool TWAP used as market price (None to use the spot price)
+        pub fn set_twap_window(&mut self, window_minutes: Option<i64>) {
+            self.parameters.twap_window = window_minutes;
+        }
+
         /// Sets the min/max interest rate parameters
         pub fn set_minmax_interest(&mut self, min_interest: Decimal, max_interest: Decimal) {
             self.parameters.max_interest_rate = max_interest;
@@ -272,10 +549,89 @@ mod proxy {
             self.parameters.kp = new_kp;
         }
 
+        /// Selects the strategy used to calculate the interest rate
+        ///
+        /// # Input
+        /// - `strategy`: The interest rate strategy to use from the next internal price update on
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Checks whether a schedule is set if the schedule strategy is selected
+        /// - Resets the state of the PID controller if it is selected, so it doesn't act on an error from before the switch
+        /// - Leaves the cached price errors untouched, as they're kept up to date under every strategy
+        pub fn set_interest_strategy(&mut self, strategy: InterestRateStrategy) {
+            if strategy == InterestRateStrategy::Schedule {
+                assert!(
+                    !self.controllers.schedule.is_empty(),
+                    "No interest rate schedule set."
+                );
+            }
+            if strategy == InterestRateStrategy::Pid
+                && self.controllers.strategy != InterestRateStrategy::Pid
+            {
+                self.controllers.pid.integral = dec!(0);
+                self.controllers.pid.last_error = None;
+            }
+            self.controllers.strategy = strategy;
+        }
+
+        /// Sets the parameters of the PID controller strategy
+        pub fn set_pid_parameters(
+            &mut self,
+            kp: Decimal,
+            ki: Decimal,
+            kd: Decimal,
+            integral_limit: Decimal,
+        ) {
+            assert!(
+                integral_limit >= dec!(0),
+                "Integral limit can't be negative."
+            );
+            self.controllers.pid.kp = kp;
+            self.controllers.pid.ki = ki;
+            self.controllers.pid.kd = kd;
+            self.controllers.pid.integral_limit = integral_limit;
+            self.controllers.pid.integral = self
+                .controllers
+                .pid
+                .integral
+                .clamp(-integral_limit, integral_limit);
+        }
+
+        /// Sets the interest rate used by the fixed rate strategy (interest per minute)
+        pub fn set_fixed_interest_rate(&mut self, interest_rate: Decimal) {
+            assert!(interest_rate > dec!(0), "Interest rate must be positive.");
+            self.controllers.fixed_rate = interest_rate;
+        }
+
+        /// Sets the schedule used by the schedule strategy: (relative price error threshold, interest rate) pairs, ordered by ascending threshold
+        pub fn set_interest_schedule(&mut self, schedule: Vec<(Decimal, Decimal)>) {
+            assert!(!schedule.is_empty(), "Schedule can't be empty.");
+            assert!(
+                schedule.windows(2).all(|steps| steps[0].0 < steps[1].0),
+                "Schedule thresholds must be ascending."
+            );
+            assert!(
+                schedule.iter().all(|(_, rate)| *rate > dec!(0)),
+                "Interest rates must be positive."
+            );
+            self.controllers.schedule = schedule;
+        }
+
         /// Sets the oracle component and method to call
         pub fn set_oracle(&mut self, oracle_address: ComponentAddress, method_name: String) {
             self.oracle = Global::from(oracle_address);
             self.oracle_method_name = method_name;
+            self.aggregated_oracle = false;
+        }
+
+        /// Sets a PriceAggregator component as oracle, so prices that weren't accepted by the aggregator are skipped
+        pub fn set_aggregated_oracle(&mut self, oracle_address: ComponentAddress) {
+            self.oracle = Global::from(oracle_address);
+            self.oracle_method_name = "get_aggregated_prices".to_string();
+            self.aggregated_oracle = true;
         }
 
         /// Sends badges to another component
@@ -289,6 +645,28 @@ mod proxy {
         //                         HELPER METHODS
         //==================================================================
 
+        /// Updates collateral prices and, if the update delay has passed, the internal price. Returns whether the internal price was updated.
+        fn update_prices(&mut self) -> bool {
+            if self.stabilis.get_shutdown_state().is_some() {
+                return false;
+            }
+
+            let passed_minutes: Decimal = (Clock::current_time_rounded_to_minutes()
+                .seconds_since_unix_epoch
+                - self.stab_price_data.last_update.seconds_since_unix_epoch)
+                / dec!(60);
+
+            self.update_collateral_prices();
+            self.refresh_pool_units();
+
+            if passed_minutes >= Decimal::from(self.update_delay) {
+                self.update_internal_price();
+                true
+            } else {
+                false
+            }
+        }
+
         /// Updates the collateral prices of the Stabilis component
         ///
         /// # Input
@@ -299,10 +677,23 @@ mod proxy {
         ///
         /// # Logic
         /// - Calls the oracle component to get the latest prices
+        ///     - If the oracle is a PriceAggregator, stores the status of every price and skips the prices that weren't accepted
         /// - Iterates over them and updates the collateral prices in the Stabilis component
         fn update_collateral_prices(&mut self) {
-            let prices: Vec<(ResourceAddress, Decimal)> =
-                self.oracle.call(&self.oracle_method_name, &());
+            let prices: Vec<(ResourceAddress, Decimal)> = if self.aggregated_oracle {
+                let aggregated_prices: Vec<(ResourceAddress, AggregatedPrice)> =
+                    self.oracle.call(&self.oracle_method_name, &());
+                aggregated_prices
+                    .into_iter()
+                    .filter_map(|(address, aggregated_price)| {
+                        self.price_statuses.insert(address, aggregated_price.status);
+                        aggregated_price.price.map(|price| (address, price))
+                    })
+                    .collect()
+            } else {
+                self.oracle.call(&self.oracle_method_name, &())
+            };
+
             for (address, price) in prices {
                 if address == XRD {
                     self.xrd_price = price;
@@ -315,6 +706,20 @@ mod proxy {
             }
         }
 
+        /// Re-prices a batch of loans for every pool unit collateral (see `Stabilis::refresh_pool_unit_crs`)
+        fn refresh_pool_units(&mut self) {
+            if self.pool_unit_refresh_count == 0 {
+                return;
+            }
+
+            for pool_unit in self.pool_unit_collaterals.clone() {
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    self.stabilis
+                        .refresh_pool_unit_crs(pool_unit, self.pool_unit_refresh_count)
+                });
+            }
+        }
+
         /// Updates the internal price of the STAB token
         ///
         /// # Input
@@ -324,22 +729,39 @@ mod proxy {
         /// - None
         ///
         /// # Logic
-        /// - Calculates the price error
-        /// - Updates the latest price errors
+        /// - Calculates the price error, using the TWAP of the StabilisPool if a TWAP window is set (otherwise the spot price)
+        /// - Updates the latest price errors (under every strategy, so switching strategies doesn't leave gaps in the cache)
         ///   - System keeps track of the latest n (by default 50) price errors and their totals
         ///     - If this cache is full, it replaces the oldest price error with the new one
-        /// - Calculates the new interest rate using the PID controller
+        /// - Calculates the new interest rate using the selected strategy
+        ///     - PI controller: changes the interest rate based on the price error and the cached price errors
+        ///     - PID controller: calculates the interest rate from the relative price error, its bounded integral and its derivative
+        ///     - Fixed rate: uses the interest rate set by governance
+        ///     - Schedule: uses the interest rate of the highest schedule threshold below the relative price error
         /// - Updates the internal price using the new interest rate
         fn update_internal_price(&mut self) {
-            let mut price_error: Decimal = self.stab_pool.get_stab_price()
+            let market_price: Decimal = match self.parameters.twap_window {
+                Some(window_minutes) => self.stab_pool.get_twap(window_minutes),
+                None => self.stab_pool.get_stab_price(),
+            };
+
+            let market_error: Decimal = market_price
                 * self.xrd_price
                 * self.parameters.price_error_offset
                 - self.stab_price_data.internal_price;
 
+            let mut price_error: Decimal = market_error;
+
             if price_error > self.parameters.allowed_deviation {
                 price_error = self.parameters.allowed_deviation;
             }
 
+            let relative_error: Decimal = (market_error / self.stab_price_data.internal_price)
+                .clamp(
+                    -self.parameters.max_price_error,
+                    self.parameters.max_price_error,
+                );
+
             let passed_minutes: Decimal = (Clock::current_time_rounded_to_minutes()
                 .seconds_since_unix_epoch
                 - self.stab_price_data.last_update.seconds_since_unix_epoch)
@@ -370,21 +792,47 @@ mod proxy {
                 .latest_stab_price_errors
                 .insert(to_change_id, price_error);
 
-            if price_error.checked_abs().unwrap()
-                > self.parameters.allowed_deviation * self.stab_price_data.internal_price
-            {
-                self.stab_price_data.interest_rate -= (self.parameters.kp
-                    * (price_error / self.stab_price_data.internal_price)
-                    + self.parameters.ki
-                        * (self.stab_price_data.latest_stab_price_errors_total
-                            / (self.stab_price_data.internal_price
-                                * Decimal::from(self.number_of_cached_prices))))
-                    * passed_minutes;
-
-                if self.stab_price_data.interest_rate > self.parameters.max_interest_rate {
-                    self.stab_price_data.interest_rate = self.parameters.max_interest_rate;
-                } else if self.stab_price_data.interest_rate < self.parameters.min_interest_rate {
-                    self.stab_price_data.interest_rate = self.parameters.min_interest_rate;
+            match self.controllers.strategy {
+                InterestRateStrategy::ProportionalIntegral => {
+                    if price_error.checked_abs().unwrap()
+                        > self.parameters.allowed_deviation * self.stab_price_data.internal_price
+                    {
+                        self.stab_price_data.interest_rate -= (self.parameters.kp
+                            * (price_error / self.stab_price_data.internal_price)
+                            + self.parameters.ki
+                                * (self.stab_price_data.latest_stab_price_errors_total
+                                    / (self.stab_price_data.internal_price
+                                        * Decimal::from(self.number_of_cached_prices))))
+                            * passed_minutes;
+
+                        self.stab_price_data.interest_rate =
+                            self.bound_interest_rate(self.stab_price_data.interest_rate);
+                    }
+                }
+                InterestRateStrategy::Pid => {
+                    let controller_error: Decimal = if relative_error.checked_abs().unwrap()
+                        > self.parameters.allowed_deviation
+                    {
+                        relative_error
+                    } else {
+                        dec!(0)
+                    };
+
+                    self.stab_price_data.interest_rate = self.controllers.pid.next_interest_rate(
+                        controller_error,
+                        passed_minutes,
+                        self.parameters.min_interest_rate,
+                        self.parameters.max_interest_rate,
+                    );
+                }
+                InterestRateStrategy::FixedRate => {
+                    self.stab_price_data.interest_rate =
+                        self.bound_interest_rate(self.controllers.fixed_rate);
+                }
+                InterestRateStrategy::Schedule => {
+                    self.stab_price_data.interest_rate = self.bound_interest_rate(
+                        self.controllers.scheduled_interest_rate(relative_error),
+                    );
                 }
             }
 
@@ -399,6 +847,14 @@ mod proxy {
             self.change_internal_price(calculated_price);
         }
 
+        /// Bounds an interest rate by the min/max interest rate parameters
+        fn bound_interest_rate(&self, interest_rate: Decimal) -> Decimal {
+            interest_rate.clamp(
+                self.parameters.min_interest_rate,
+                self.parameters.max_interest_rate,
+            )
+        }
+
         //==================================================================
         //    PROXY FUNCTIONALITY FROM HERE (CONTROL OTHER COMPONENTS)
         //==================================================================
@@ -477,6 +933,23 @@ mod proxy {
             })
         }
 
+        pub fn swap_cdp_collateral(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            new_collateral: Bucket,
+        ) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.swap_cdp_collateral(receipt_id, new_collateral)
+            })
+        }
+
         pub fn partial_close_cdp(&mut self, receipt_proof: NonFungibleProof, stab_payment: Bucket) -> (Option<Bucket>, Option<Bucket>) {
             let receipt_proof = receipt_proof.check_with_message(
                 self.cdp_receipt_manager.address(),
@@ -516,9 +989,60 @@ mod proxy {
             });
         }
 
-        pub fn mark_for_liquidation(&mut self, collateral: ResourceAddress) -> Bucket {
+        pub fn mark_for_liquidation(
+            &mut self,
+            collateral: ResourceAddress,
+            deposit: Option<Bucket>,
+        ) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.mark_for_liquidation(collateral, deposit)
+            })
+        }
+
+        pub fn mark_cdp(&mut self, cdp_id: NonFungibleLocalId, deposit: Option<Bucket>) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.mark_cdp(cdp_id, deposit)
+            })
+        }
+
+        pub fn mark_for_liquidation_batch(
+            &mut self,
+            collateral: ResourceAddress,
+            max_count: u64,
+            deposit: Option<Bucket>,
+        ) -> (Vec<Bucket>, Option<Bucket>) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .mark_for_liquidation_batch(collateral, max_count, deposit)
+            })
+        }
+
+        pub fn retrieve_marker_deposit(&mut self, marker_proof: NonFungibleProof) -> Bucket {
+            let marker_proof = marker_proof.check_with_message(
+                self.cdp_marker_manager.address(),
+                "Incorrect proof! Are you sure this is a correct marker?",
+            );
+            let marker_id: NonFungibleLocalId =
+                marker_proof.non_fungible::<CdpMarker>().local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.retrieve_marker_deposit(marker_id)
+            })
+        }
+
+        pub fn refresh_pool_unit_crs(&mut self, pool_unit: ResourceAddress, max_count: u64) -> u64 {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.refresh_pool_unit_crs(pool_unit, max_count)
+            })
+        }
+
+        pub fn liquidate_batch(
+            &mut self,
+            payment: Bucket,
+            max_count: u64,
+        ) -> (Vec<Bucket>, Bucket, Vec<Bucket>) {
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.mark_for_liquidation(collateral)
+                self.stabilis.liquidate_batch(payment, max_count)
             })
         }
 
@@ -550,6 +1074,34 @@ mod proxy {
             })
         }
 
+        pub fn partial_liquidate_position_with_marker(
+            &mut self,
+            marker_proof: NonFungibleProof,
+            payment: Bucket,
+        ) -> (Bucket, Option<Bucket>, Bucket) {
+            let marker_proof = marker_proof.check_with_message(
+                self.cdp_marker_manager.address(),
+                "Incorrect proof! Are you sure this is a correct marker?",
+            );
+            let marker = marker_proof.non_fungible::<CdpMarker>();
+            let marker_id: NonFungibleLocalId = marker.local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .partial_liquidate_position_with_marker(marker_id, payment)
+            })
+        }
+
+        pub fn fill_auction(
+            &mut self,
+            cdp_id: NonFungibleLocalId,
+            payment: Bucket,
+        ) -> (Bucket, Bucket, Bucket) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.fill_auction(cdp_id, payment)
+            })
+        }
+
         pub fn force_liquidate(
             &mut self,
             collateral: ResourceAddress,
@@ -561,6 +1113,12 @@ mod proxy {
             })
         }
 
+        pub fn redeem(&mut self, collateral: ResourceAddress, payment: Bucket) -> (Bucket, Bucket) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.redeem(collateral, payment)
+            })
+        }
+
         pub fn force_mint(
             &mut self,
             collateral: ResourceAddress,
@@ -584,49 +1142,686 @@ mod proxy {
             })
         }
 
-        pub fn change_collateral_price(&self, collateral: ResourceAddress, new_price: Decimal) {
+        pub fn partial_liquidate_position_without_marker(
+            &mut self,
+            payment: Bucket,
+            skip: Option<i64>,
+            cdp_id: NonFungibleLocalId,
+        ) -> (Bucket, Option<Bucket>, Bucket) {
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.change_collateral_price(collateral, new_price)
-            });
+                self.stabilis
+                    .partial_liquidate_position_without_marker(payment, skip, cdp_id)
+            })
         }
 
-        pub fn add_pool_collateral(
-            &self,
-            address: ResourceAddress,
-            parent_address: ResourceAddress,
-            validator: ComponentAddress,
-            lsu: bool,
-            initial_acceptance: bool,
-        ) {
+        pub fn open_basket_cdp(
+            &mut self,
+            collaterals: Vec<Bucket>,
+            stab_to_mint: Decimal,
+        ) -> (Bucket, Bucket) {
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.add_pool_collateral(
-                    address,
-                    parent_address,
-                    validator,
-                    lsu,
-                    initial_acceptance,
-                )
-            });
+                self.stabilis.open_basket_cdp(collaterals, stab_to_mint)
+            })
         }
 
-        pub fn change_internal_price(&mut self, new_price: Decimal) {
-            self.stab_price_data.internal_price = new_price;
+        pub fn top_up_basket_cdp(&mut self, receipt_proof: NonFungibleProof, collaterals: Vec<Bucket>) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.basket_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt_id: NonFungibleLocalId = receipt_proof.non_fungible_local_id();
+
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.change_internal_price(new_price)
+                self.stabilis.top_up_basket_cdp(receipt_id, collaterals)
             });
         }
 
-        pub fn set_max_vector_length(&mut self, new_stabilis_length: u64) {
+        pub fn remove_basket_collateral(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            collateral: ResourceAddress,
+            amount: Decimal,
+        ) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.basket_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt_id: NonFungibleLocalId = receipt_proof.non_fungible_local_id();
+
             self.badge_vault.authorize_with_amount(dec!("0.75"), || {
-                self.stabilis.set_max_vector_length(new_stabilis_length)
-            });
+                self.stabilis
+                    .remove_basket_collateral(receipt_id, collateral, amount)
+            })
         }
 
-        pub fn get_internal_price(&self) -> Decimal {
-            self.stab_price_data.internal_price
-        }
+        pub fn borrow_more_basket(&mut self, receipt_proof: NonFungibleProof, amount: Decimal) -> Bucket {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.basket_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt_id: NonFungibleLocalId = receipt_proof.non_fungible_local_id();
 
-        //==================================================================
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.borrow_more_basket(receipt_id, amount)
+            })
+        }
+
+        pub fn close_basket_cdp(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            stab_payment: Bucket,
+        ) -> (Vec<Bucket>, Bucket) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.basket_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt_id: NonFungibleLocalId = receipt_proof.non_fungible_local_id();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.close_basket_cdp(receipt_id, stab_payment)
+            })
+        }
+
+        pub fn retrieve_leftover_basket_collateral(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+        ) -> Vec<Bucket> {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.basket_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt_id: NonFungibleLocalId = receipt_proof.non_fungible_local_id();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.retrieve_leftover_basket_collateral(receipt_id)
+            })
+        }
+
+        pub fn mark_basket_for_liquidation(&mut self, basket_id: Option<NonFungibleLocalId>) -> bool {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.mark_basket_for_liquidation(basket_id)
+            })
+        }
+
+        pub fn liquidate_basket(
+            &mut self,
+            basket_id: NonFungibleLocalId,
+            payment: Bucket,
+        ) -> (Vec<Bucket>, Bucket) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.liquidate_basket(basket_id, payment)
+            })
+        }
+
+        pub fn settle_cdp(&mut self, cdp_id: NonFungibleLocalId) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.settle_cdp(cdp_id)
+            });
+        }
+
+        pub fn settle_basket_cdp(&mut self, basket_id: NonFungibleLocalId) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.settle_basket_cdp(basket_id)
+            });
+        }
+
+        pub fn redeem_settlement(&mut self, payment: Bucket) -> Vec<Bucket> {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.redeem_settlement(payment)
+            })
+        }
+
+        /// Shuts the protocol down (only callable through the DAO, which holds the controller badges)
+        pub fn emergency_shutdown(&mut self) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.emergency_shutdown()
+            });
+        }
+
+        /// Finalizes an emergency shutdown, after handing the PSM reserves over to the Stabilis component
+        pub fn finalize_shutdown(&mut self) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.peg_stability_module.settle_reserves();
+                self.stabilis.finalize_shutdown()
+            });
+        }
+
+        pub fn deposit_surplus(&mut self, stab: Bucket) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.deposit_surplus(stab)
+            });
+        }
+
+        pub fn cancel_bad_debt(&mut self, amount: Decimal) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.cancel_bad_debt(amount)
+            });
+        }
+
+        /// Funds the surplus buffer by selling XRD liquidation fines from the XRD treasury for STAB
+        ///
+        /// # Input
+        /// - `amount`: The amount of XRD to sell
+        /// - `min_stab_out`: The minimum amount of STAB the swap has to return (a pool price read in the same transaction can be manipulated, so the caller has to supply this)
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Take the XRD from the treasury
+        /// - Swap it for STAB in the StabilisPool, checking the minimum output
+        /// - Deposit the STAB into the surplus buffer
+        pub fn fund_surplus_buffer_from_treasury(&mut self, amount: Decimal, min_stab_out: Decimal) {
+            let xrd: Bucket = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.empty_collateral_treasury(amount, XRD, false)
+            });
+
+            let stab: Bucket = self.stab_pool.swap(xrd);
+            assert!(
+                stab.amount() >= min_stab_out,
+                "Slippage too high. Received less STAB than the minimum output."
+            );
+
+            self.deposit_surplus(stab);
+        }
+
+        /// Receives tokens sent by the DAO (through its `send_tokens` method), like ILIS to sell in debt auctions
+        pub fn put_tokens(&mut self, tokens: Bucket) {
+            let address: ResourceAddress = tokens.resource_address();
+            if self.dao_tokens.get(&address).is_some() {
+                self.dao_tokens.get_mut(&address).unwrap().put(tokens);
+            } else {
+                self.dao_tokens.insert(address, Vault::with_bucket(tokens));
+            }
+        }
+
+        pub fn start_debt_auction(
+            &mut self,
+            token: ResourceAddress,
+            amount: Decimal,
+            starting_price: Decimal,
+            price_decay: Decimal,
+            minimum_price: Decimal,
+        ) {
+            let tokens: Bucket = self
+                .dao_tokens
+                .get_mut(&token)
+                .expect("No tokens of this type received from the DAO.")
+                .take(amount);
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .start_debt_auction(tokens, starting_price, price_decay, minimum_price)
+            });
+        }
+
+        pub fn bid_debt_auction(&mut self, payment: Bucket) -> (Bucket, Bucket) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.bid_debt_auction(payment)
+            })
+        }
+
+        pub fn end_debt_auction(&mut self) -> Bucket {
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.stabilis.end_debt_auction())
+        }
+
+        pub fn change_collateral_price(&self, collateral: ResourceAddress, new_price: Decimal) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.change_collateral_price(collateral, new_price)
+            });
+        }
+
+        /// Sets the circuit breaker of a collateral: the maximum relative price move per update and per hour (None to remove it)
+        pub fn set_circuit_breaker(
+            &self,
+            collateral: ResourceAddress,
+            limits: Option<(Decimal, Decimal)>,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_circuit_breaker(collateral, limits)
+            });
+        }
+
+        /// Confirms the pending price of a collateral held by its circuit breaker
+        pub fn confirm_pending_price(&self, collateral: ResourceAddress) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.confirm_pending_price(collateral)
+            });
+        }
+
+        /// Rejects the pending price of a collateral held by its circuit breaker
+        pub fn reject_pending_price(&self, collateral: ResourceAddress) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.reject_pending_price(collateral)
+            });
+        }
+
+        pub fn add_pool_collateral(
+            &mut self,
+            address: ResourceAddress,
+            parent_address: ResourceAddress,
+            validator: ComponentAddress,
+            lsu: bool,
+            initial_acceptance: bool,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.add_pool_collateral(
+                    address,
+                    parent_address,
+                    validator,
+                    lsu,
+                    initial_acceptance,
+                )
+            });
+            self.pool_unit_collaterals.push(address);
+        }
+
+        /// Sets the maximum amount of loans per pool unit to re-price during an update (0 to stop re-pricing during updates)
+        pub fn set_pool_unit_refresh_count(&mut self, new_count: u64) {
+            self.pool_unit_refresh_count = new_count;
+        }
+
+        pub fn add_lp_collateral(
+            &self,
+            address: ResourceAddress,
+            pool_address: ComponentAddress,
+            multi_resource: bool,
+            chosen_mcr: Decimal,
+            max_stab_share: Decimal,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.add_lp_collateral(
+                    address,
+                    pool_address,
+                    multi_resource,
+                    chosen_mcr,
+                    max_stab_share,
+                )
+            });
+        }
+
+        pub fn update_lp_price(&self, lp_address: ResourceAddress) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.update_lp_price(lp_address)
+            });
+        }
+
+        pub fn change_internal_price(&mut self, new_price: Decimal) {
+            self.stab_price_data.internal_price = new_price;
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.change_internal_price(new_price)
+            });
+        }
+
+        pub fn set_max_vector_length(&mut self, new_stabilis_length: u64) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.set_max_vector_length(new_stabilis_length)
+            });
+        }
+
+        pub fn get_internal_price(&self) -> Decimal {
+            self.stab_price_data.internal_price
+        }
+
+        pub fn get_interest_rate_state(&self) -> InterestRateView {
+            InterestRateView {
+                interest_rate: self.stab_price_data.interest_rate,
+                cached_price_errors: match self.stab_price_data.full_cache {
+                    true => self.number_of_cached_prices,
+                    false => self.stab_price_data.last_changed_price,
+                },
+                price_errors_total: self.stab_price_data.latest_stab_price_errors_total,
+                controllers: self.controllers.clone(),
+            }
+        }
+
+        pub fn get_collateral_info(&self, collateral: ResourceAddress) -> CollateralInfoView {
+            self.stabilis.get_collateral_info(collateral)
+        }
+
+        pub fn get_pool_unit_info(&self, pool_unit: ResourceAddress) -> PoolUnitInfoView {
+            self.stabilis.get_pool_unit_info(pool_unit)
+        }
+
+        pub fn get_lp_unit_info(&self, lp_address: ResourceAddress) -> LpUnitInfoView {
+            self.stabilis.get_lp_unit_info(lp_address)
+        }
+
+        pub fn get_circuit_breaker(&self, collateral: ResourceAddress) -> Option<CircuitBreaker> {
+            self.stabilis.get_circuit_breaker(collateral)
+        }
+
+        /// Gets the status of the latest aggregated price of resources (None if the resource has no aggregated price)
+        pub fn get_price_statuses(
+            &self,
+            resources: Vec<ResourceAddress>,
+        ) -> Vec<(ResourceAddress, Option<PriceStatus>)> {
+            resources
+                .into_iter()
+                .map(|address| {
+                    let status: Option<PriceStatus> =
+                        self.price_statuses.get(&address).map(|status| *status);
+                    (address, status)
+                })
+                .collect()
+        }
+
+        pub fn get_protocol_parameters(&self) -> ProtocolParameters {
+            self.stabilis.get_protocol_parameters()
+        }
+
+        pub fn get_lowest_crs(
+            &self,
+            collateral: ResourceAddress,
+            amount: u64,
+        ) -> Vec<(Decimal, NonFungibleLocalId)> {
+            self.stabilis.get_lowest_crs(collateral, amount)
+        }
+
+        pub fn get_marked_cdps(&self, amount: u64) -> (u64, Vec<(Decimal, NonFungibleLocalId)>) {
+            self.stabilis.get_marked_cdps(amount)
+        }
+
+        pub fn get_cdp_health(&self, cdp_id: NonFungibleLocalId) -> CdpHealth {
+            self.stabilis.get_cdp_health(cdp_id)
+        }
+
+        pub fn get_auction_discount(&self, cdp_id: NonFungibleLocalId) -> Decimal {
+            self.stabilis.get_auction_discount(cdp_id)
+        }
+
+        pub fn get_stab_surplus(&self) -> Decimal {
+            self.stabilis.get_stab_surplus()
+        }
+
+        pub fn get_basket_health(&self, basket_id: NonFungibleLocalId) -> BasketCdpHealth {
+            self.stabilis.get_basket_health(basket_id)
+        }
+
+        pub fn get_lowest_basket_healths(&self, amount: u64) -> Vec<(Decimal, NonFungibleLocalId)> {
+            self.stabilis.get_lowest_basket_healths(amount)
+        }
+
+        pub fn get_shutdown_state(&self) -> Option<ShutdownState> {
+            self.stabilis.get_shutdown_state()
+        }
+
+        pub fn get_bad_debt(&self) -> Decimal {
+            self.stabilis.get_bad_debt()
+        }
+
+        pub fn get_surplus_buffer(&self) -> Decimal {
+            self.stabilis.get_surplus_buffer()
+        }
+
+        pub fn get_debt_auction(&self) -> Option<DebtAuctionView> {
+            self.stabilis.get_debt_auction()
+        }
+
+        //==================================================================
+        //                     STABILITY POOL COMPONENT
+        //==================================================================
+
+        pub fn stability_pool_deposit(&mut self, stab: Bucket) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stability_pool.deposit(stab)
+            })
+        }
+
+        pub fn stability_pool_withdraw(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            amount: Decimal,
+        ) -> (Bucket, Vec<Bucket>) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.stability_pool_receipt_manager.address(),
+                "Incorrect proof! Are you sure this deposit is yours?",
+            );
+            let receipt_id: NonFungibleLocalId = receipt_proof.non_fungible_local_id();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stability_pool.withdraw(receipt_id, amount)
+            })
+        }
+
+        pub fn stability_pool_claim(&mut self, receipt_proof: NonFungibleProof) -> Vec<Bucket> {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.stability_pool_receipt_manager.address(),
+                "Incorrect proof! Are you sure this deposit is yours?",
+            );
+            let receipt_id: NonFungibleLocalId = receipt_proof.non_fungible_local_id();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stability_pool.claim_collateral(receipt_id)
+            })
+        }
+
+        /// Liquidates a marked loan / CDP using the STAB in the stability pool
+        ///
+        /// # Input
+        /// - `cdp_id`: The id of the marked loan
+        ///
+        /// # Output
+        /// - The liquidation receipt, or a receipt with saved status if the loan was saved
+        ///
+        /// # Logic
+        /// - Take the STAB needed to liquidate from the stability pool (the debt including the stability fee that's added when liquidating)
+        /// - If the collateral is liquidated through auctions and the loan is still liquidatable, fill its complete auction at the current discount
+        /// - Otherwise, liquidate the loan without a marker, so this is only possible when no external liquidator has stepped in during the liquidation and unmarked delays
+        /// - If the loan was liquidated, the collateral and leftover STAB are absorbed by the stability pool
+        /// - If the loan was saved, the STAB is returned to the stability pool
+        pub fn liquidate_with_stability_pool(&mut self, cdp_id: NonFungibleLocalId) -> Bucket {
+            let health: CdpHealth = self.stabilis.get_cdp_health(cdp_id.clone());
+            let debt: Decimal = health.minted_stab + health.pending_fee;
+            let fill_auction: bool = self
+                .stabilis
+                .get_collateral_info(health.parent_address)
+                .liquidation_mode
+                == LiquidationMode::Auction
+                && health.current_cr < health.liquidation_collateral_ratio;
+
+            let (collateral_or_stab, leftover_stab, receipt): (Bucket, Option<Bucket>, Bucket) =
+                self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                    let stab: Bucket = self.stability_pool.provide_stab(debt);
+                    if fill_auction {
+                        let (collateral, leftover_stab, receipt): (Bucket, Bucket, Bucket) =
+                            self.stabilis.fill_auction(cdp_id, stab);
+                        (collateral, Some(leftover_stab), receipt)
+                    } else {
+                        self.stabilis
+                            .liquidate_position_without_marker(stab, None, cdp_id)
+                    }
+                });
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                if let Some(leftover_stab) = leftover_stab {
+                    self.stability_pool
+                        .absorb_liquidation(collateral_or_stab, leftover_stab);
+                } else {
+                    self.stability_pool.return_stab(collateral_or_stab);
+                }
+            });
+
+            receipt
+        }
+
+        //==================================================================
+        //                   PEG STABILITY MODULE COMPONENT
+        //==================================================================
+
+        pub fn psm_mint(&mut self, asset: Bucket) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.peg_stability_module.mint(asset)
+            })
+        }
+
+        pub fn psm_redeem(&mut self, stab: Bucket, asset: ResourceAddress) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.peg_stability_module.redeem(stab, asset)
+            })
+        }
+
+        pub fn psm_add_asset(
+            &mut self,
+            address: ResourceAddress,
+            fee_in: Decimal,
+            fee_out: Decimal,
+            debt_ceiling: Decimal,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.peg_stability_module
+                    .add_asset(address, fee_in, fee_out, debt_ceiling)
+            });
+        }
+
+        pub fn psm_edit_asset(
+            &mut self,
+            address: ResourceAddress,
+            fee_in: Decimal,
+            fee_out: Decimal,
+            debt_ceiling: Decimal,
+            accepted: bool,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.peg_stability_module
+                    .edit_asset(address, fee_in, fee_out, debt_ceiling, accepted)
+            });
+        }
+
+        pub fn psm_withdraw_fees(&mut self, amount: Decimal) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.peg_stability_module.withdraw_fees(amount)
+            })
+        }
+
+        pub fn psm_withdraw_reserve(&mut self, address: ResourceAddress, amount: Decimal) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.peg_stability_module.withdraw_reserve(address, amount)
+            })
+        }
+
+        pub fn get_psm_asset_info(&self, address: ResourceAddress) -> PsmAssetView {
+            self.peg_stability_module.get_asset_info(address)
+        }
+
+        pub fn get_psm_assets(&self) -> Vec<PsmAssetView> {
+            self.peg_stability_module.get_assets()
+        }
+
+        pub fn get_psm_fees(&self) -> Decimal {
+            self.peg_stability_module.get_fees()
+        }
+
+        //==================================================================
+        //                   SURPLUS AUCTIONS COMPONENT
+        //==================================================================
+
+        pub fn start_surplus_auction(&mut self, collateral: ResourceAddress) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.surplus_auctions.start_auction(collateral)
+            });
+        }
+
+        pub fn bid_surplus_auction(
+            &mut self,
+            collateral: ResourceAddress,
+            payment: Bucket,
+        ) -> (Bucket, Bucket) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.surplus_auctions.bid(collateral, payment)
+            })
+        }
+
+        pub fn set_surplus_auction_config(
+            &mut self,
+            collateral: ResourceAddress,
+            config: SurplusAuctionConfig,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.surplus_auctions.set_config(collateral, config)
+            });
+        }
+
+        pub fn set_surplus_auction_dao(
+            &mut self,
+            dao_address: ComponentAddress,
+            ilis_address: ResourceAddress,
+        ) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.surplus_auctions.set_dao(dao_address, ilis_address)
+            });
+        }
+
+        pub fn cancel_surplus_auction(&mut self, collateral: ResourceAddress) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.surplus_auctions.cancel_auction(collateral)
+            });
+        }
+
+        pub fn get_surplus_auction(&self, collateral: ResourceAddress) -> SurplusAuctionView {
+            self.surplus_auctions.get_auction(collateral)
+        }
+
+        //==================================================================
+        //                    KEEPER REWARDS COMPONENT
+        //==================================================================
+
+        /// Claims the keeper reward for marking (or saving) a loan, once per marker receipt
+        pub fn claim_keeper_reward(&mut self, marker_proof: NonFungibleProof) -> Bucket {
+            let marker_proof = marker_proof.check_with_message(
+                self.cdp_marker_manager.address(),
+                "Incorrect proof! Are you sure this is a correct marker?",
+            );
+            let marker_id: NonFungibleLocalId =
+                marker_proof.non_fungible::<CdpMarker>().local_id().clone();
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.keeper_rewards.reward_marker(marker_id)
+            })
+        }
+
+        pub fn set_keeper_reward_config(&mut self, config: KeeperRewardConfig) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.keeper_rewards.set_config(config)
+            });
+        }
+
+        /// Funds the keeper rewards with collected stability fees (STAB)
+        pub fn fund_keeper_rewards_from_surplus(&mut self, amount: Decimal) {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                let stab: Bucket = self.stabilis.retrieve_stab_surplus(amount);
+                self.keeper_rewards.fund(stab)
+            });
+        }
+
+        /// Funds the keeper rewards with tokens sent by the DAO (like ILIS)
+        pub fn fund_keeper_rewards_from_dao_tokens(&mut self, token: ResourceAddress, amount: Decimal) {
+            let tokens: Bucket = self
+                .dao_tokens
+                .get_mut(&token)
+                .expect("No tokens of this type received from the DAO.")
+                .take(amount);
+
+            self.badge_vault
+                .authorize_with_amount(dec!("0.75"), || self.keeper_rewards.fund(tokens));
+        }
+
+        pub fn withdraw_keeper_rewards(&mut self, token: ResourceAddress, amount: Decimal) -> Bucket {
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.keeper_rewards.withdraw(token, amount)
+            })
+        }
+
+        pub fn get_keeper_reward_config(&self) -> Option<KeeperRewardConfig> {
+            self.keeper_rewards.get_config()
+        }
+
+        pub fn get_keeper_reward_funds(&self, token: ResourceAddress) -> Decimal {
+            self.keeper_rewards.get_reward_funds(token)
+        }
+
+        //==================================================================
         //                      FLASH LOANS COMPONENT
         //==================================================================
 
@@ -641,9 +1836,169 @@ mod proxy {
             })
         }
 
-        pub fn flash_retrieve_interest(&mut self) -> Bucket {
-            self.badge_vault
-                .authorize_with_amount(dec!("0.75"), || self.flash_loans.retrieve_interest())
+        //==================================================================
+        //          LEVERAGE (FLASH LOANS + STABILIS POOL COMPONENTS)
+        //==================================================================
+
+        /// Opens a leveraged XRD loan, by flash borrowing STAB and swapping it for extra XRD collateral
+        ///
+        /// # Input
+        /// - `collateral`: The XRD to use as (initial) collateral
+        /// - `target_cr`: The targeted collateral ratio (collateral value / debt value, so 1.5 is 150%)
+        /// - `max_slippage`: The maximum slippage accepted for the swap, compared to the internal STAB price (0.01 is 1%)
+        ///
+        /// # Output
+        /// - The CDP receipt
+        /// - The leftover STAB (if any)
+        ///
+        /// # Logic
+        /// - Calculate the debt needed for the target collateral ratio, taking into account the XRD bought with the debt at the internal price
+        ///     - debt = collateral value / (internal price * (target_cr - 1))
+        /// - Flash borrow the debt and swap it for XRD in the StabilisPool, checking the slippage
+        ///     - The internal price is used instead of the StabilisPool spot price, which can be moved within the same transaction
+        /// - Open a loan with all XRD, minting enough STAB to pay back the flash loan (including its interest)
+        /// - Pay back the flash loan
+        pub fn open_leveraged_cdp(
+            &mut self,
+            mut collateral: Bucket,
+            target_cr: Decimal,
+            max_slippage: Decimal,
+        ) -> (Bucket, Bucket) {
+            assert!(
+                collateral.resource_address() == XRD,
+                "Only XRD can be used as collateral for leveraged loans."
+            );
+            assert!(
+                max_slippage >= dec!(0) && max_slippage < dec!(1),
+                "Invalid maximum slippage."
+            );
+
+            assert!(
+                target_cr > dec!(1),
+                "Target collateral ratio too low to reach through leverage."
+            );
+
+            let xrd_price: Decimal = self.stabilis.get_collateral_info(XRD).usd_price;
+            let internal_price: Decimal = self.stabilis.return_internal_price();
+            let xrd_per_stab: Decimal = internal_price / xrd_price;
+
+            let debt: Decimal =
+                (collateral.amount() * xrd_price) / (internal_price * (target_cr - dec!(1)));
+
+            let (stab_loan, flash_receipt): (Bucket, Bucket) = self.flash_borrow(debt);
+            let flash_interest: Decimal = self.flash_loan_interest(&flash_receipt);
+
+            let bought_xrd: Bucket = self.stab_pool.swap(stab_loan);
+            assert!(
+                bought_xrd.amount() >= debt * xrd_per_stab * (dec!(1) - max_slippage),
+                "Slippage too high. Try again with a higher maximum slippage."
+            );
+            collateral.put(bought_xrd);
+
+            let (minted_stab, cdp_receipt): (Bucket, Bucket) =
+                self.open_cdp(collateral, debt * (dec!(1) + flash_interest));
+
+            let leftover_stab: Bucket = self.flash_pay_back(flash_receipt, minted_stab);
+
+            (cdp_receipt, leftover_stab)
+        }
+
+        /// Repays (part of) the debt of an XRD loan using its own collateral
+        ///
+        /// # Input
+        /// - `receipt_proof`: The proof of the CDP receipt
+        /// - `stab_to_repay`: The amount of debt to repay (if higher than the debt, the loan is closed)
+        /// - `max_slippage`: The maximum slippage accepted for the swap, compared to the internal STAB price (0.01 is 1%)
+        ///
+        /// # Output
+        /// - The leftover collateral, if the loan was closed
+        /// - The leftover STAB
+        ///
+        /// # Logic
+        /// - Flash borrow the STAB to repay and repay it
+        ///     - If all debt is repaid, the loan is closed and the collateral returned, otherwise the loan is partially closed
+        /// - Take enough collateral to buy back the flash loan (plus interest) within the maximum slippage
+        ///     - The internal price is used instead of the StabilisPool spot price, which can be moved within the same transaction
+        ///     - If the loan is still open, this collateral is removed from the loan, which checks the MCR
+        /// - Swap the collateral for STAB in the StabilisPool, checking the slippage
+        /// - Pay back the flash loan
+        pub fn deleverage_cdp(
+            &mut self,
+            receipt_proof: NonFungibleProof,
+            stab_to_repay: Decimal,
+            max_slippage: Decimal,
+        ) -> (Option<Bucket>, Bucket) {
+            let receipt_proof = receipt_proof.check_with_message(
+                self.cdp_receipt_manager.address(),
+                "Incorrect proof! Are you sure this loan is yours?",
+            );
+            let receipt = receipt_proof.non_fungible::<Cdp>();
+            let receipt_id: NonFungibleLocalId = receipt.local_id().clone();
+
+            assert!(
+                max_slippage >= dec!(0) && max_slippage < dec!(1),
+                "Invalid maximum slippage."
+            );
+
+            let health: CdpHealth = self.stabilis.get_cdp_health(receipt_id.clone());
+            assert!(
+                health.collateral == XRD,
+                "Only loans using XRD as collateral can be deleveraged."
+            );
+
+            let debt: Decimal = health.minted_stab + health.pending_fee;
+            let repayment: Decimal = stab_to_repay.min(debt);
+            let close_loan: bool = repayment == debt;
+
+            let (stab_loan, flash_receipt): (Bucket, Bucket) = self.flash_borrow(repayment);
+            let stab_needed: Decimal =
+                repayment * (dec!(1) + self.flash_loan_interest(&flash_receipt));
+            let xrd_per_stab: Decimal =
+                self.stabilis.return_internal_price() / self.stabilis.get_collateral_info(XRD).usd_price;
+            let xrd_to_sell: Decimal = stab_needed * xrd_per_stab / (dec!(1) - max_slippage);
+
+            let (collateral, xrd, leftover_payment): (Option<Bucket>, Bucket, Option<Bucket>) =
+                if close_loan {
+                    let (mut collateral, leftover_payment): (Bucket, Bucket) = self
+                        .badge_vault
+                        .authorize_with_amount(dec!("0.75"), || {
+                            self.stabilis.close_cdp(receipt_id, stab_loan)
+                        });
+                    assert!(
+                        collateral.amount() >= xrd_to_sell,
+                        "Not enough collateral to repay the debt."
+                    );
+                    let xrd: Bucket = collateral.take(xrd_to_sell);
+                    (Some(collateral), xrd, Some(leftover_payment))
+                } else {
+                    let xrd: Bucket = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                        self.stabilis.partial_close_cdp(receipt_id.clone(), stab_loan);
+                        self.stabilis.remove_collateral(receipt_id, xrd_to_sell)
+                    });
+                    (None, xrd, None)
+                };
+
+            let mut bought_stab: Bucket = self.stab_pool.swap(xrd);
+            assert!(
+                bought_stab.amount() >= stab_needed,
+                "Slippage too high. Try again with a higher maximum slippage."
+            );
+            if let Some(leftover_payment) = leftover_payment {
+                bought_stab.put(leftover_payment);
+            }
+
+            let leftover_stab: Bucket = self.flash_pay_back(flash_receipt, bought_stab);
+
+            (collateral, leftover_stab)
+        }
+
+        /// Gets the interest of a flash loan from its receipt
+        fn flash_loan_interest(&self, flash_receipt: &Bucket) -> Decimal {
+            flash_receipt
+                .as_non_fungible()
+                .non_fungible::<LoanReceipt>()
+                .data()
+                .interest
         }
     }
 }
@@ -682,4 +2037,109 @@ pub struct InterestParameters {
     pub max_price_error: Decimal,
     /// The offset for the price error
     pub price_error_offset: Decimal,
+    /// The window (in minutes) of the StabilisPool TWAP used as market price (None to use the spot price)
+    pub twap_window: Option<i64>,
+}
+
+/// The strategy used to calculate the interest rate of STAB
+#[derive(ScryptoSbor, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InterestRateStrategy {
+    /// The PI controller, changing the interest rate based on the latest price error and the cached price errors (using `InterestParameters`)
+    ProportionalIntegral,
+    /// A PID controller with a bounded integral, calculating the interest rate from the relative price error (using `PidController`)
+    Pid,
+    /// A fixed interest rate
+    FixedRate,
+    /// An interest rate looked up in a schedule of relative price errors
+    Schedule,
+}
+
+/// A PID controller calculating the interest rate from the relative price error (market price / internal price - 1)
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct PidController {
+    /// The proportional gain
+    pub kp: Decimal,
+    /// The integral gain
+    pub ki: Decimal,
+    /// The derivative gain
+    pub kd: Decimal,
+    /// The maximum absolute value of the integral, preventing it from winding up while STAB is off peg for long
+    pub integral_limit: Decimal,
+    /// The relative price error integrated over time (minutes)
+    pub integral: Decimal,
+    /// The relative price error of the last update
+    pub last_error: Option<Decimal>,
+}
+
+impl PidController {
+    /// Calculates the next interest rate (bounded by the min / max interest rate) and updates the controller state
+    ///
+    /// The integral is only updated if it doesn't push an already saturated interest rate further past its bounds (anti-windup).
+    pub fn next_interest_rate(
+        &mut self,
+        error: Decimal,
+        passed_minutes: Decimal,
+        min_interest_rate: Decimal,
+        max_interest_rate: Decimal,
+    ) -> Decimal {
+        let derivative: Decimal = match self.last_error {
+            Some(last_error) if passed_minutes > dec!(0) => (error - last_error) / passed_minutes,
+            _ => dec!(0),
+        };
+
+        let integral: Decimal = (self.integral + error * passed_minutes)
+            .clamp(-self.integral_limit, self.integral_limit);
+
+        let interest_rate: Decimal =
+            dec!(1) - (self.kp * error + self.ki * integral + self.kd * derivative);
+
+        let winding_up: bool = (interest_rate > max_interest_rate && integral < self.integral)
+            || (interest_rate < min_interest_rate && integral > self.integral);
+
+        if !winding_up {
+            self.integral = integral;
+        }
+        self.last_error = Some(error);
+
+        interest_rate.clamp(min_interest_rate, max_interest_rate)
+    }
+}
+
+/// The selected interest rate strategy, and the parameters / state of the strategies next to the PI controller
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct InterestRateControllers {
+    /// The strategy currently used to calculate the interest rate
+    pub strategy: InterestRateStrategy,
+    /// The PID controller
+    pub pid: PidController,
+    /// The interest rate used by the fixed rate strategy
+    pub fixed_rate: Decimal,
+    /// The (relative price error threshold, interest rate) pairs used by the schedule strategy, ordered by ascending threshold
+    pub schedule: Vec<(Decimal, Decimal)>,
+}
+
+impl InterestRateControllers {
+    /// Gets the interest rate of the highest schedule threshold at or below the relative price error (the first one if the error is below all thresholds)
+    pub fn scheduled_interest_rate(&self, error: Decimal) -> Decimal {
+        self.schedule
+            .iter()
+            .rev()
+            .find(|(threshold, _)| *threshold <= error)
+            .or(self.schedule.first())
+            .map(|(_, interest_rate)| *interest_rate)
+            .unwrap_or(dec!(1))
+    }
+}
+
+/// The current interest rate and the state of the interest rate strategies
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct InterestRateView {
+    /// The current interest rate (per minute)
+    pub interest_rate: Decimal,
+    /// The number of price errors in the cache
+    pub cached_price_errors: u64,
+    /// The total of the cached price errors
+    pub price_errors_total: Decimal,
+    /// The selected strategy and the parameters / state of the strategies
+    pub controllers: InterestRateControllers,
 }
@@ -6,11 +6,14 @@ use scrypto::prelude::*;
 /// Data struct of a loan receipt / CDP receipt, gained when opening a CDP / loan
 #[derive(ScryptoSbor, NonFungibleData)]
 pub struct Cdp {
-    /// collateral used for this loan / cdp
+    /// collateral used for this loan / cdp (can change by swapping the collateral)
+    #[mutable]
     pub collateral: ResourceAddress,
     /// parent address of this collateral (only differs from collateral in the case of a pool unit)
+    #[mutable]
     pub parent_address: ResourceAddress,
     /// whether collateral is a pool unit
+    #[mutable]
     pub is_pool_unit_collateral: bool,
 
     /// amount of collateral used
@@ -31,6 +34,36 @@ pub struct Cdp {
     /// id of the marker that last marked this loan
     #[mutable]
     pub marker_id: u64,
+    /// debt index of the collateral at the last time stability fees were added to this loan
+    #[mutable]
+    pub debt_index: Decimal,
+}
+
+/// Data struct of a basket loan receipt, gained when opening a loan / CDP backed by several collaterals
+#[derive(ScryptoSbor, NonFungibleData)]
+pub struct BasketCdp {
+    /// amount of each collateral used (pool units are stored as pool units, not converted to their parent)
+    #[mutable]
+    pub collaterals: IndexMap<ResourceAddress, Decimal>,
+    /// amount of stab minted
+    #[mutable]
+    pub minted_stab: Decimal,
+    /// health of the basket at its last interaction, which is its key in the basket AvlTree
+    /// health = sum of (collateral value / MCR) / minted STAB value, so a basket can be liquidated if its health < 1
+    #[mutable]
+    pub health: Decimal,
+    /// status of the basket loan
+    #[mutable]
+    pub status: CdpStatus,
+    /// time the basket was marked for liquidation (None if it isn't marked)
+    #[mutable]
+    pub time_marked: Option<Instant>,
+    /// debt index of each (parent) collateral at the last time stability fees were added to this basket
+    #[mutable]
+    pub debt_indexes: IndexMap<ResourceAddress, Decimal>,
+    /// debt and (real) collateral amount attributed to each collateral at the last interaction, counted in that collateral's minted STAB and collateral amount
+    #[mutable]
+    pub debt_attribution: IndexMap<ResourceAddress, (Decimal, Decimal)>,
 }
 
 /// Data struct of a CDP Marker, gained when marking a loan / CDP for liquidation
@@ -44,10 +77,39 @@ pub struct CdpMarker {
     pub marked_id: NonFungibleLocalId,
     /// Marker ID in AvlTree storing markers
     pub marker_placing: Decimal,
+    /// whether the loan was saved by this marking itself (a saved marker returned by marking a healthy loan)
+    pub saved_by_own_mark: bool,
 
     ///whether the marker has been used
     #[mutable]
     pub used: bool,
+    /// deposit paid when marking the loan (None if no deposit was required)
+    #[mutable]
+    pub deposit: Option<MarkerDeposit>,
+}
+
+/// Deposit paid when marking a loan, refunded when the mark turns out justified
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct MarkerDeposit {
+    /// token of the deposit (STAB or an accepted collateral, like XRD)
+    pub token: ResourceAddress,
+    /// amount deposited
+    pub amount: Decimal,
+    /// amount forfeited to the treasury, because the marked loan turned out to be saved
+    pub forfeited: Decimal,
+    /// whether the (unforfeited) deposit has been refunded
+    pub refunded: bool,
+}
+
+/// Marker deposit required when marking a loan, set by governance
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct MarkerDepositConfig {
+    /// token of the deposit (STAB or an accepted collateral, like XRD)
+    pub token: ResourceAddress,
+    /// amount to deposit per marked loan
+    pub amount: Decimal,
+    /// share of the deposit forfeited to the treasury if the marked loan turns out to be saved (0.5 is 50%)
+    pub forfeit_share: Decimal,
 }
 
 ///Data of Liquidation Receipt, gained when liquidating a loan
@@ -65,6 +127,10 @@ pub struct LiquidationReceipt {
     pub cdp_liquidated: NonFungibleLocalId,
     /// time of liquidation
     pub date_liquidated: Instant,
+    /// whether only part of the loan was liquidated (restoring it to a healthy collateral ratio)
+    pub partial: bool,
+    /// stab debt left in the loan after liquidation (0 if the loan was fully liquidated)
+    pub remaining_stab: Decimal,
 }
 
 /// Status of a CDP
@@ -75,6 +141,17 @@ pub enum CdpStatus {
     Liquidated,
     ForceLiquidated,
     Closed,
+    Redeemed,
+    Settled,
+}
+
+/// How marked loans using a collateral are liquidated
+#[derive(ScryptoSbor, PartialEq, Clone, Copy, Debug)]
+pub enum LiquidationMode {
+    /// The liquidator repays the complete debt and receives the collateral plus fixed fines
+    FixedFine,
+    /// The collateral is auctioned, with a discount increasing over time since the loan was marked
+    Auction,
 }
 
 /// The kind of update that the action has executed.
@@ -119,3 +196,140 @@ pub struct InterestParameters {
     /// The offset for the price error
     pub price_error_offset: Decimal,
 }
+
+/// Read-only view of a collateral's information (the vaults of `CollateralInfo` can't be returned, so their amounts are returned instead)
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct CollateralInfoView {
+    /// address of the collateral
+    pub resource_address: ResourceAddress,
+    /// USD price of the collateral
+    pub usd_price: Decimal,
+    /// minimum collateral ratio
+    pub mcr: Decimal,
+    /// collateral ratio (collateral amount / minted STAB) under which loans using this collateral can be liquidated
+    pub liquidation_collateral_ratio: Decimal,
+    /// whether the collateral is accepted for new loans
+    pub accepted: bool,
+    /// how marked loans using this collateral are liquidated
+    pub liquidation_mode: LiquidationMode,
+    /// stability fee, as interest per minute (so 1.000001 is 0.0001% per minute)
+    pub stability_fee: Decimal,
+    /// cumulative debt index, by which the debt of loans using this collateral has grown through stability fees
+    pub debt_index: Decimal,
+    /// STAB minted using this collateral (including pool units that have it as parent)
+    pub minted_stab: Decimal,
+    /// share of the circulating STAB minted using this collateral
+    pub stab_share: Decimal,
+    /// maximum share of the circulating STAB that can be minted using this collateral
+    pub max_stab_share: Decimal,
+    /// total real amount of collateral backing loans (including pool units, converted to the underlying asset)
+    pub collateral_amount: Decimal,
+    /// amount of collateral in the collateral vault
+    pub vault_amount: Decimal,
+    /// amount of collateral in the treasury
+    pub treasury_amount: Decimal,
+    /// highest collateral ratio ever stored for this collateral
+    pub highest_cr: Decimal,
+    /// total bad debt ever caused by loans using this collateral
+    pub bad_debt: Decimal,
+}
+
+/// Read-only view of a pool unit collateral's information
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct PoolUnitInfoView {
+    /// address of the pool unit
+    pub address: ResourceAddress,
+    /// address of the underlying collateral
+    pub parent_address: ResourceAddress,
+    /// whether the pool unit is an LSU
+    pub lsu: bool,
+    /// address of the validator or one resource pool of the pool unit
+    pub pool_address: Option<ComponentAddress>,
+    /// whether the pool unit is accepted for new loans
+    pub accepted: bool,
+    /// STAB minted using this pool unit
+    pub minted_stab: Decimal,
+    /// share of the parent collateral's minted STAB minted using this pool unit
+    pub pool_share: Decimal,
+    /// maximum share of the parent collateral's minted STAB that can be minted using this pool unit
+    pub max_pool_share: Decimal,
+    /// amount of pool units in the collateral vault
+    pub vault_amount: Decimal,
+    /// amount of pool units in the treasury
+    pub treasury_amount: Decimal,
+    /// current amount of the underlying collateral one pool unit can be redeemed for
+    pub redemption_value_per_unit: Decimal,
+    /// amount of loans indexed for re-pricing (see `refresh_pool_unit_crs`)
+    pub indexed_loans: u64,
+}
+
+/// Read-only view of an LP collateral's information (its loans and share cap are in its `CollateralInfoView`)
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct LpUnitInfoView {
+    /// address of the LP unit
+    pub address: ResourceAddress,
+    /// address of the TwoResourcePool or MultiResourcePool of the LP unit
+    pub pool_address: ComponentAddress,
+    /// whether the pool is a MultiResourcePool
+    pub multi_resource: bool,
+    /// current amount of every leg one LP unit can be redeemed for
+    pub redemption_value_per_unit: IndexMap<ResourceAddress, Decimal>,
+    /// price of one LP unit, valued from its legs
+    pub usd_price: Decimal,
+}
+
+/// Health summary of a loan / CDP, using the latest pool unit redemption value
+#[derive(ScryptoSbor)]
+pub struct CdpHealth {
+    /// id of the cdp / loan
+    pub cdp_id: NonFungibleLocalId,
+    /// status of the cdp / loan
+    pub status: CdpStatus,
+    /// collateral used for this loan / cdp
+    pub collateral: ResourceAddress,
+    /// parent address of this collateral
+    pub parent_address: ResourceAddress,
+    /// amount of collateral used
+    pub collateral_amount: Decimal,
+    /// amount of collateral used, converted to the parent collateral
+    pub real_collateral_amount: Decimal,
+    /// amount of stab minted
+    pub minted_stab: Decimal,
+    /// stability fees accrued since the loan was last interacted with (added to the debt on the next interaction)
+    pub pending_fee: Decimal,
+    /// collateral ratio stored in the AvlTree / receipt
+    pub stored_cr: Decimal,
+    /// collateral ratio using the latest pool unit redemption value
+    pub current_cr: Decimal,
+    /// collateral ratio under which the loan can be liquidated
+    pub liquidation_collateral_ratio: Decimal,
+    /// collateral value / minted STAB value
+    pub cr_percentage: Decimal,
+    /// USD price of the parent collateral at which the loan can be liquidated
+    pub liquidation_price: Decimal,
+    /// whether the loan can be marked (if healthy) or liquidated (if marked) right now
+    pub liquidatable: bool,
+}
+
+/// Health summary of a basket loan / CDP, using the latest prices and pool unit redemption values
+#[derive(ScryptoSbor)]
+pub struct BasketCdpHealth {
+    /// id of the basket loan
+    pub basket_id: NonFungibleLocalId,
+    /// status of the basket loan
+    pub status: CdpStatus,
+    /// amount of each collateral used
+    pub collaterals: IndexMap<ResourceAddress, Decimal>,
+    /// amount of stab minted
+    pub minted_stab: Decimal,
+    /// summed USD value of all collaterals
+    pub collateral_value: Decimal,
+    /// MCR of the basket, weighted by collateral value (collateral value / weighted MCR = sum of each collateral's value / MCR)
+    pub weighted_mcr: Decimal,
+    /// health stored in the AvlTree / receipt
+    pub stored_health: Decimal,
+    /// health using the latest prices (collateral value / (minted STAB value * weighted MCR))
+    pub current_health: Decimal,
+    /// whether the basket can be marked (if healthy) or liquidated (if marked) right now
+    pub liquidatable: bool,
+}
@@ -22,31 +22,128 @@
 //! - Add collateral to a loan: `top_up_cdp`
 //! - Borrow more: `borrow_more`
 //! - Partially close a loan: `partial_close_cdp`
+//! - Swap the collateral of a loan for another collateral: `swap_cdp_collateral`
 //! - Force liquidate a loan (liquidate a loan immediately without it being undercollateralized): `force_liquidate`
 //! - Force mint STAB tokens (force a borrower to mint more STAB tokens in return for collateral added to their CDP): `force_mint`
-//! - Mark a loan to liquidate it: `mark_for_liquidation`
+//! - Mark a loan to liquidate it: `mark_for_liquidation` (the loan with the lowest collateral ratio) or `mark_cdp` (any undercollateralized loan)
 //! - Liquidate a loan: `liquidate_position_with_marker` or `liquidate_position_without_marker`
+//! - Mark or liquidate many loans at once (during a crash): `mark_for_liquidation_batch` and `liquidate_batch`
+//! - Retrieve the marker deposit (if governance requires one to mark a loan): `retrieve_marker_deposit`, part of it is forfeited if the marked loan is saved
 //! - Retrieve leftover collateral after being liquidated: `retrieve_leftover_collateral`
+//!
+//! If an oracle or collateral fails, the protocol can be wound down through an emergency shutdown (`emergency_shutdown`):
+//! - Prices and stability fees are frozen, and everything but closing loans is stopped
+//! - Every loan is settled (`settle_cdp`, `settle_basket_cdp`): collateral worth its debt is reserved for STAB holders, the borrower can retrieve the excess
+//! - The peg stability module hands its reserves over (`settle_psm_reserve`), as STAB minted by it is redeemed as well
+//! - When all loans are settled, the shutdown is finalized (`finalize_shutdown`) and STAB can be redeemed pro rata for the reserved collateral and PSM reserves (`redeem_settlement`)
+//!
+//! Every one of these actions emits a typed event (the `Cdp...Event` structs at the bottom of this module), so indexers don't have to diff NFT data to follow a loan.
+//!
+//! If a loan is liquidated while its collateral is worth less than its debt, the liquidator only pays the collateral value minus the liquidation fine, and the unpaid STAB that stays in circulation is recorded as bad debt (per collateral and in total).
+//! Bad debt is cancelled by burning STAB from the surplus buffer (`cancel_bad_debt`), which is funded by flash loan interest and sold liquidation fines (`deposit_surplus`).
+//! If the buffer is empty, a debt auction (`start_debt_auction`) sells newly minted ILIS for STAB at a decaying price, burning the STAB to cancel the bad debt.
+//!
+//! Besides single-collateral loans, a basket loan can be backed by several collaterals at once (`open_basket_cdp`).
+//! Its health is the sum of each collateral's value / MCR, divided by the minted STAB value, so the basket uses a collateral value weighted MCR.
+//! Baskets are ranked in their own AvlTree by health, are marked with `mark_basket_for_liquidation` and liquidated with `liquidate_basket`, which seizes the same share of every collateral.
+//!
+//! LP units of a TwoResourcePool or MultiResourcePool (like the STAB/XRD LP units of the StabilisPool) can be added as a collateral of their own (`add_lp_collateral`).
+//! Their price follows the prices of their legs, and liquidating a loan seizes the LP units themselves.
 
 use crate::shared_structs::*;
 use scrypto::prelude::*;
 use scrypto_avltree::AvlTree;
+use scrypto_math::*;
 
 #[blueprint]
+#[events(
+    CdpOpenedEvent,
+    CdpClosedEvent,
+    CdpPartiallyClosedEvent,
+    CdpBorrowedMoreEvent,
+    CdpToppedUpEvent,
+    CdpCollateralRemovedEvent,
+    CdpCollateralSwappedEvent,
+    CdpMarkedEvent,
+    CdpLiquidatedEvent,
+    CdpSavedEvent,
+    CdpForceLiquidatedEvent,
+    CdpForceMintedEvent,
+    CdpRedeemedEvent,
+    RedemptionEvent,
+    AuctionFilledEvent,
+    StabilityFeeAccruedEvent,
+    BasketCdpOpenedEvent,
+    BasketCdpClosedEvent,
+    BasketCdpToppedUpEvent,
+    BasketCdpCollateralRemovedEvent,
+    BasketCdpBorrowedMoreEvent,
+    BasketCdpMarkedEvent,
+    BasketCdpLiquidatedEvent,
+    BasketStabilityFeeAccruedEvent,
+    EmergencyShutdownEvent,
+    CdpSettledEvent,
+    BasketCdpSettledEvent,
+    ShutdownFinalizedEvent,
+    SettlementRedemptionEvent,
+    BadDebtRecordedEvent,
+    SurplusDepositedEvent,
+    BadDebtCancelledEvent,
+    DebtAuctionStartedEvent,
+    DebtAuctionBidEvent,
+    DebtAuctionEndedEvent,
+    MarkerDepositForfeitedEvent,
+    MarkerDepositRefundedEvent,
+    PoolUnitCrsRefreshedEvent,
+    CircuitBreakerTrippedEvent,
+    PendingPriceResolvedEvent
+)]
 mod stabilis_component {
     enable_method_auth! {
         methods {
             return_internal_price => PUBLIC;
+            get_collateral_info => PUBLIC;
+            get_pool_unit_info => PUBLIC;
+            is_pool_unit => PUBLIC;
+            get_lp_unit_info => PUBLIC;
+            get_circuit_breaker => PUBLIC;
+            get_protocol_parameters => PUBLIC;
+            get_lowest_crs => PUBLIC;
+            get_marked_cdps => PUBLIC;
+            get_cdp_health => PUBLIC;
+            get_auction_discount => PUBLIC;
+            get_stab_surplus => PUBLIC;
+            get_basket_health => PUBLIC;
+            get_lowest_basket_healths => PUBLIC;
+            get_basket_receipt_address => PUBLIC;
+            get_shutdown_state => PUBLIC;
+            get_bad_debt => PUBLIC;
+            get_surplus_buffer => PUBLIC;
+            get_debt_auction => PUBLIC;
             add_pool_collateral => restrict_to: [OWNER];
+            add_lp_collateral => restrict_to: [OWNER];
+            update_lp_price => restrict_to: [OWNER];
+            set_circuit_breaker => restrict_to: [OWNER];
+            confirm_pending_price => restrict_to: [OWNER];
+            reject_pending_price => restrict_to: [OWNER];
             open_cdp => restrict_to: [OWNER];
             top_up_cdp => restrict_to: [OWNER];
             close_cdp => restrict_to: [OWNER];
             retrieve_leftover_collateral => restrict_to: [OWNER];
             mark_for_liquidation => restrict_to: [OWNER];
+            mark_cdp => restrict_to: [OWNER];
+            retrieve_marker_deposit => restrict_to: [OWNER];
+            mark_for_liquidation_batch => restrict_to: [OWNER];
+            liquidate_batch => restrict_to: [OWNER];
+            refresh_pool_unit_crs => restrict_to: [OWNER];
             liquidate_position_with_marker => restrict_to: [OWNER];
             liquidate_position_without_marker => restrict_to: [OWNER];
+            partial_liquidate_position_with_marker => restrict_to: [OWNER];
+            partial_liquidate_position_without_marker => restrict_to: [OWNER];
+            fill_auction => restrict_to: [OWNER];
             change_collateral_price => restrict_to: [OWNER];
             empty_collateral_treasury => restrict_to: [OWNER];
+            return_collateral_to_treasury => restrict_to: [OWNER];
             edit_collateral => restrict_to: [OWNER];
             edit_pool_collateral => restrict_to: [OWNER];
             mint_controller_badge => restrict_to: [OWNER];
@@ -54,8 +151,16 @@ mod stabilis_component {
             set_unmarked_delay => restrict_to: [OWNER];
             set_stops => restrict_to: [OWNER];
             set_max_vector_length => restrict_to: [OWNER];
+            set_marker_deposit => restrict_to: [OWNER];
             set_minimum_mint => restrict_to: [OWNER];
             set_fines => restrict_to: [OWNER];
+            set_partial_liquidation_buffer => restrict_to: [OWNER];
+            set_auction_parameters => restrict_to: [OWNER];
+            set_liquidation_mode => restrict_to: [OWNER];
+            set_stability_fee => restrict_to: [OWNER];
+            retrieve_stab_surplus => restrict_to: [OWNER];
+            set_redemption_parameters => restrict_to: [OWNER];
+            redeem => restrict_to: [OWNER];
             add_collateral => restrict_to: [OWNER];
             change_internal_price => restrict_to: [OWNER];
             remove_collateral => restrict_to: [OWNER];
@@ -63,11 +168,33 @@ mod stabilis_component {
             force_mint => restrict_to: [OWNER];
             set_force_mint_multiplier => restrict_to: [OWNER];
             free_stab => restrict_to: [OWNER];
+            psm_mint => restrict_to: [OWNER];
+            psm_burn => restrict_to: [OWNER];
+            settle_psm_reserve => restrict_to: [OWNER];
             burn_stab => restrict_to: [OWNER];
             burn_marker => restrict_to: [OWNER];
             burn_loan_receipt => restrict_to: [OWNER];
             borrow_more => restrict_to: [OWNER];
             partial_close_cdp => restrict_to: [OWNER];
+            swap_cdp_collateral => restrict_to: [OWNER];
+            open_basket_cdp => restrict_to: [OWNER];
+            top_up_basket_cdp => restrict_to: [OWNER];
+            remove_basket_collateral => restrict_to: [OWNER];
+            borrow_more_basket => restrict_to: [OWNER];
+            close_basket_cdp => restrict_to: [OWNER];
+            mark_basket_for_liquidation => restrict_to: [OWNER];
+            liquidate_basket => restrict_to: [OWNER];
+            retrieve_leftover_basket_collateral => restrict_to: [OWNER];
+            emergency_shutdown => restrict_to: [OWNER];
+            settle_cdp => restrict_to: [OWNER];
+            settle_basket_cdp => restrict_to: [OWNER];
+            finalize_shutdown => restrict_to: [OWNER];
+            redeem_settlement => restrict_to: [OWNER];
+            deposit_surplus => restrict_to: [OWNER];
+            cancel_bad_debt => restrict_to: [OWNER];
+            start_debt_auction => restrict_to: [OWNER];
+            bid_debt_auction => restrict_to: [OWNER];
+            end_debt_auction => restrict_to: [OWNER];
         }
     }
     struct Stabilis {
@@ -75,6 +202,12 @@ mod stabilis_component {
         collaterals: KeyValueStore<ResourceAddress, CollateralInfo>,
         /// KVS storing all accepted pool units and their information
         pool_units: KeyValueStore<ResourceAddress, PoolUnitInfo>,
+        /// KVS storing all accepted LP collaterals and their pool
+        lp_units: KeyValueStore<ResourceAddress, LpUnitInfo>,
+        /// KVS storing the LP collaterals that have a resource as one of their legs
+        lp_units_by_leg: KeyValueStore<ResourceAddress, Vec<ResourceAddress>>,
+        /// KVS storing the circuit breakers on collateral price updates
+        circuit_breakers: KeyValueStore<ResourceAddress, CircuitBreaker>,
         /// KVS storing all active collateral ratios for each collateral
         collateral_ratios:
             KeyValueStore<ResourceAddress, AvlTree<Decimal, Vec<NonFungibleLocalId>>>,
@@ -106,6 +239,30 @@ mod stabilis_component {
         liquidation_counter: u64,
         /// The protocol parameters
         parameters: ProtocolParameters,
+        /// Vault storing STAB minted as stability fees (protocol revenue)
+        stab_surplus: Vault,
+        /// AVL tree storing the health of all healthy basket loans (a basket mixes collaterals, so it can't be ranked in the per-collateral AVL trees)
+        basket_healths: AvlTree<Decimal, Vec<NonFungibleLocalId>>,
+        /// Counter for the basket loans
+        basket_counter: u64,
+        /// The resource manager for the basket loan receipts
+        basket_manager: ResourceManager,
+        /// State of the emergency shutdown (None if the protocol is running normally)
+        shutdown: Option<ShutdownState>,
+        /// STAB minted by the peg stability module (included in the circulating STAB)
+        psm_stab: Decimal,
+        /// Vault storing STAB reserved to cancel bad debt (funded by flash loan interest and sold liquidation fines)
+        surplus_buffer: Vault,
+        /// Bad debt that isn't cancelled yet: STAB left in circulation (and included in the circulating STAB) by liquidations of loans that weren't fully backed by collateral
+        bad_debt: Decimal,
+        /// The running debt auction (None if no auction is running)
+        debt_auction: Option<DebtAuction>,
+        /// Vault storing the tokens (ILIS) sold in debt auctions
+        debt_auction_tokens: Option<Vault>,
+        /// KVS storing the deposits paid when marking loans, per deposit token
+        marker_deposits: KeyValueStore<ResourceAddress, Vault>,
+        /// KVS storing the peg stability module reserves handed over during an emergency shutdown, per stable asset
+        psm_reserves: KeyValueStore<ResourceAddress, Vault>,
     }
 
     impl Stabilis {
@@ -123,6 +280,7 @@ mod stabilis_component {
         /// - Creates the CDP manager
         /// - Creates the CDP marker manager
         /// - Creates the liquidation receipt manager
+        /// - Creates the basket loan receipt manager
         /// - Creates the Stabilis component
         pub fn instantiate() -> (Global<Stabilis>, Bucket) {
             let parameters = ProtocolParameters {
@@ -138,6 +296,14 @@ mod stabilis_component {
                 stop_force_mint: false,
                 stop_force_liquidate: false,
                 force_mint_cr_multiplier: dec!(3),
+                stop_redemptions: false,
+                redemption_fee: dec!("0.005"),
+                max_redemption_cdps: 10,
+                partial_liquidation_buffer: dec!("0.05"),
+                auction_start_discount: dec!(0),
+                auction_discount_increase: dec!("0.001"),
+                auction_max_discount: dec!("0.2"),
+                marker_deposit: None,
             };
 
             let (address_reservation, component_address) =
@@ -336,9 +502,62 @@ mod stabilis_component {
                 ))
                 .create_with_no_initial_supply();
 
+            let basket_manager: ResourceManager =
+                ResourceBuilder::new_integer_non_fungible::<BasketCdp>(OwnerRole::Fixed(rule!(
+                    require_amount(dec!("0.75"), controller_role.resource_address())
+                )))
+                .metadata(metadata!(
+                    init {
+                        "name" => "Stabilis Basket Loan Receipt", locked;
+                        "symbol" => "stabBASKET", locked;
+                        "description" => "A receipt for your Stabilis loan backed by multiple collaterals", locked;
+                        "info_url" => "https://stabilis.finance", updatable;
+                        "icon_url" => Url::of("https://i.imgur.com/pUFclTo.png"), updatable;
+                    }
+                ))
+                .non_fungible_data_update_roles(non_fungible_data_update_roles!(
+                    non_fungible_data_updater => rule!(require(global_caller(component_address))
+                        || require_amount(
+                            dec!("0.75"),
+                            controller_role.resource_address()
+                        ));
+                    non_fungible_data_updater_updater => rule!(require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    ));
+                ))
+                .mint_roles(mint_roles!(
+                    minter => rule!(require(global_caller(component_address))
+                    || require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    ));
+                    minter_updater => rule!(require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    ));
+                ))
+                .burn_roles(burn_roles!(
+                    burner => rule!(require(global_caller(component_address))
+                    || require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    ));
+                    burner_updater => rule!(require_amount(
+                        dec!("0.75"),
+                        controller_role.resource_address()
+                    ));
+                ))
+                .create_with_no_initial_supply();
+
+            let stab_address: ResourceAddress = stab_manager.address();
+
             let stabilis = Self {
                 collaterals: KeyValueStore::<ResourceAddress, CollateralInfo>::new(),
                 pool_units: KeyValueStore::<ResourceAddress, PoolUnitInfo>::new(),
+                lp_units: KeyValueStore::new(),
+                lp_units_by_leg: KeyValueStore::new(),
+                circuit_breakers: KeyValueStore::new(),
                 collateral_ratios: KeyValueStore::<
                     ResourceAddress,
                     AvlTree<Decimal, Vec<NonFungibleLocalId>>,
@@ -357,6 +576,18 @@ mod stabilis_component {
                 liquidation_receipt_manager,
                 liquidation_counter: 0,
                 parameters,
+                stab_surplus: Vault::new(stab_address),
+                basket_healths: AvlTree::new(),
+                basket_counter: 0,
+                basket_manager,
+                shutdown: None,
+                psm_stab: dec!(0),
+                surplus_buffer: Vault::new(stab_address),
+                bad_debt: dec!(0),
+                debt_auction: None,
+                debt_auction_tokens: None,
+                marker_deposits: KeyValueStore::new(),
+                psm_reserves: KeyValueStore::new(),
             }
             .instantiate()
             .prepare_to_globalize(OwnerRole::Fixed(rule!(require_amount(
@@ -444,6 +675,8 @@ mod stabilis_component {
                 }
             };
 
+            self.assert_price_not_paused(parent_collateral_address);
+
             self.collaterals
                 .get_mut(&parent_collateral_address)
                 .unwrap()
@@ -469,33 +702,13 @@ mod stabilis_component {
 
             let cr: Decimal = collateral_amount / stab_tokens.amount();
 
-            if self
-                .collaterals
-                .get(&parent_collateral_address)
-                .unwrap()
-                .initialized
-            {
-                self.insert_cr(
-                    parent_collateral_address,
-                    cr,
-                    NonFungibleLocalId::integer(self.cdp_counter),
-                );
-            } else {
-                let mut avl_tree: AvlTree<Decimal, Vec<NonFungibleLocalId>> = AvlTree::new();
-                let cdp_ids: Vec<NonFungibleLocalId> =
-                    vec![NonFungibleLocalId::integer(self.cdp_counter)];
-                avl_tree.insert(cr, cdp_ids);
-                self.collateral_ratios
-                    .insert(parent_collateral_address, avl_tree);
-                self.collaterals
-                    .get_mut(&parent_collateral_address)
-                    .unwrap()
-                    .initialized = true;
-                self.collaterals
-                    .get_mut(&parent_collateral_address)
-                    .unwrap()
-                    .highest_cr = cr;
-            }
+            self.initialize_or_insert_cr(
+                parent_collateral_address,
+                cr,
+                NonFungibleLocalId::integer(self.cdp_counter),
+            );
+
+            self.update_debt_index(parent_collateral_address);
 
             let cdp = Cdp {
                 collateral: collateral.resource_address(),
@@ -506,6 +719,11 @@ mod stabilis_component {
                 collateral_stab_ratio: cr,
                 status: CdpStatus::Healthy,
                 marker_id: 0u64,
+                debt_index: self
+                    .collaterals
+                    .get(&parent_collateral_address)
+                    .unwrap()
+                    .debt_index,
             };
 
             self.update_minted_stab(
@@ -522,6 +740,27 @@ mod stabilis_component {
                 .mint_non_fungible(&NonFungibleLocalId::integer(self.cdp_counter), cdp)
                 .as_non_fungible();
 
+            if is_pool_unit_collateral {
+                self.index_pool_unit_loan(
+                    collateral.resource_address(),
+                    NonFungibleLocalId::integer(self.cdp_counter),
+                );
+            }
+
+            Runtime::emit_event(CdpOpenedEvent {
+                cdp_id: NonFungibleLocalId::integer(self.cdp_counter),
+                collateral: collateral.resource_address(),
+                collateral_amount: collateral.amount(),
+                minted_stab: stab_tokens.amount(),
+                new_cr: cr,
+                collateral_price: self
+                    .collaterals
+                    .get(&parent_collateral_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
             self.put_collateral(
                 collateral.resource_address(),
                 is_pool_unit_collateral,
@@ -556,7 +795,7 @@ mod stabilis_component {
             receipt_id: NonFungibleLocalId,
             mut stab_payment: Bucket,
         ) -> (Bucket, Bucket) {
-            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&receipt_id);
+            let receipt_data: Cdp = self.accrue_stability_fee(&receipt_id);
 
             assert!(
                 stab_payment.amount() >= receipt_data.minted_stab,
@@ -609,10 +848,24 @@ mod stabilis_component {
             self.cdp_manager
                 .update_non_fungible_data(&receipt_id, "collateral_amount", dec!(0));
 
+            Runtime::emit_event(CdpClosedEvent {
+                cdp_id: receipt_id,
+                collateral: receipt_data.collateral,
+                collateral_returned: collateral.amount(),
+                stab_repaid: receipt_data.minted_stab,
+                old_cr: receipt_data.collateral_stab_ratio,
+                collateral_price: self
+                    .collaterals
+                    .get(&receipt_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
             (collateral, stab_payment)
         }
 
-        /// Retrieve leftover collateral from a liquidated loan / cdp
+        /// Retrieve leftover collateral from a liquidated, fully redeemed or settled loan / cdp
         ///
         /// # Input
         /// - `receipt_id`: The CDP receipt
@@ -621,7 +874,7 @@ mod stabilis_component {
         /// - The leftover collateral
         ///
         /// # Logic
-        /// - Check if the loan is liquidated
+        /// - Check if the loan is liquidated, fully redeemed or settled
         /// - Check if there is leftover collateral
         /// - Check if it is allowed to close loans right now
         /// - Update CDP receipt to 0 collateral
@@ -631,15 +884,17 @@ mod stabilis_component {
 
             assert!(
                 receipt_data.status == CdpStatus::Liquidated
-                    || receipt_data.status == CdpStatus::ForceLiquidated,
-                "Loan not liquidated"
+                    || receipt_data.status == CdpStatus::ForceLiquidated
+                    || receipt_data.status == CdpStatus::Redeemed
+                    || receipt_data.status == CdpStatus::Settled,
+                "Loan not liquidated, redeemed or settled"
             );
             assert!(
                 receipt_data.collateral_amount > dec!(0),
                 "No collateral leftover"
             );
             assert!(
-                !self.parameters.stop_closings,
+                !self.parameters.stop_closings || receipt_data.status == CdpStatus::Settled,
                 "Not allowed to close loans right now."
             );
 
@@ -674,7 +929,7 @@ mod stabilis_component {
         /// - Update the CDP receipt
         /// - If the loan was marked, update the marker receipt
         pub fn top_up_cdp(&mut self, collateral_id: NonFungibleLocalId, collateral: Bucket) {
-            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let receipt_data: Cdp = self.accrue_stability_fee(&collateral_id);
             let new_collateral_amount = receipt_data.collateral_amount + collateral.amount();
 
             assert!(
@@ -718,6 +973,22 @@ mod stabilis_component {
 
             self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
 
+            Runtime::emit_event(CdpToppedUpEvent {
+                cdp_id: collateral_id.clone(),
+                collateral: receipt_data.collateral,
+                collateral_added: collateral.amount(),
+                collateral_amount: new_collateral_amount,
+                old_cr: receipt_data.collateral_stab_ratio,
+                new_cr: cr,
+                was_marked: receipt_data.status == CdpStatus::Marked,
+                collateral_price: self
+                    .collaterals
+                    .get(&receipt_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
             self.put_collateral(
                 receipt_data.collateral,
                 receipt_data.is_pool_unit_collateral,
@@ -774,7 +1045,7 @@ mod stabilis_component {
             collateral_id: NonFungibleLocalId,
             amount: Decimal,
         ) -> Bucket {
-            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let receipt_data: Cdp = self.accrue_stability_fee(&collateral_id);
             let new_collateral_amount = receipt_data.collateral_amount - amount;
 
             assert!(
@@ -825,9 +1096,196 @@ mod stabilis_component {
                 new_collateral_amount,
             );
 
+            Runtime::emit_event(CdpCollateralRemovedEvent {
+                cdp_id: collateral_id,
+                collateral: receipt_data.collateral,
+                collateral_removed: removed_collateral.amount(),
+                collateral_amount: new_collateral_amount,
+                old_cr: receipt_data.collateral_stab_ratio,
+                new_cr: cr,
+                collateral_price: self
+                    .collaterals
+                    .get(&receipt_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
             removed_collateral
         }
 
+        /// Swap the collateral of a loan / CDP for another accepted collateral, without repaying the debt
+        ///
+        /// # Input
+        /// - `collateral_id`: The CDP receipt
+        /// - `new_collateral`: The new collateral, of a different type than the current collateral
+        ///
+        /// # Output
+        /// - The old collateral
+        ///
+        /// # Logic
+        /// - Add accrued stability fees to the debt
+        /// - Check if the loan is healthy, and opening and closing loans is allowed right now
+        /// - Check whether the new collateral is accepted and if it is a pool unit
+        /// - Check whether the new collateral value is high enough for the new collateral's MCR
+        /// - Move the debt: remove the loan from the old collateral's AvlTree and minted STAB, and add it to the new collateral's
+        ///     - This checks the share of the new (pool unit) collateral
+        /// - Take the old collateral and store the new collateral in the correct vaults
+        /// - Update the CDP receipt, using the debt index of the new collateral from now on
+        /// - Return the old collateral
+        pub fn swap_cdp_collateral(
+            &mut self,
+            collateral_id: NonFungibleLocalId,
+            new_collateral: Bucket,
+        ) -> Bucket {
+            let receipt_data: Cdp = self.accrue_stability_fee(&collateral_id);
+            let new_address: ResourceAddress = new_collateral.resource_address();
+
+            assert!(
+                receipt_data.status == CdpStatus::Healthy,
+                "Loan not healthy. Save it first."
+            );
+            assert!(
+                !self.parameters.stop_openings && !self.parameters.stop_closings,
+                "Not allowed to swap collateral right now."
+            );
+            assert!(
+                new_address != receipt_data.collateral,
+                "New collateral is the same as the current collateral."
+            );
+
+            let is_pool_unit_collateral: bool = match self.pool_units.get(&new_address) {
+                Some(pool_unit) => {
+                    assert!(pool_unit.accepted, "This collateral is not accepted");
+                    true
+                }
+                None => {
+                    assert!(
+                        self.collaterals
+                            .get(&new_address)
+                            .map(|c| c.accepted)
+                            .unwrap_or(false),
+                        "This collateral is not accepted"
+                    );
+                    false
+                }
+            };
+
+            let parent_collateral_address: ResourceAddress = match is_pool_unit_collateral {
+                false => new_address,
+                true => self.pool_units.get(&new_address).unwrap().parent_address,
+            };
+
+            let collateral_amount: Decimal = self.pool_to_real(
+                new_collateral.amount(),
+                new_address,
+                is_pool_unit_collateral,
+            );
+
+            self.assert_price_not_paused(parent_collateral_address);
+
+            let (new_price, new_mcr): (Decimal, Decimal) = {
+                let info = self.collaterals.get(&parent_collateral_address).unwrap();
+                (info.usd_price, info.mcr)
+            };
+
+            assert!(
+                new_price * collateral_amount
+                    >= self.internal_stab_price * receipt_data.minted_stab * new_mcr,
+                "Collateral value too low."
+            );
+
+            self.remove_cr(
+                receipt_data.parent_address,
+                receipt_data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
+            self.collaterals
+                .get_mut(&receipt_data.parent_address)
+                .unwrap()
+                .collateral_amount -= receipt_data.collateral_stab_ratio * receipt_data.minted_stab;
+            self.update_minted_stab(
+                false,
+                receipt_data.is_pool_unit_collateral,
+                false,
+                receipt_data.minted_stab,
+                receipt_data.parent_address,
+                receipt_data.collateral,
+            );
+
+            let cr: Decimal = collateral_amount / receipt_data.minted_stab;
+
+            self.initialize_or_insert_cr(parent_collateral_address, cr, collateral_id.clone());
+            self.collaterals
+                .get_mut(&parent_collateral_address)
+                .unwrap()
+                .collateral_amount += collateral_amount;
+            self.update_minted_stab(
+                true,
+                is_pool_unit_collateral,
+                true,
+                receipt_data.minted_stab,
+                parent_collateral_address,
+                new_address,
+            );
+
+            self.update_debt_index(parent_collateral_address);
+            let debt_index: Decimal = self
+                .collaterals
+                .get(&parent_collateral_address)
+                .unwrap()
+                .debt_index;
+
+            let old_collateral: Bucket = self.take_collateral(
+                receipt_data.collateral,
+                receipt_data.is_pool_unit_collateral,
+                receipt_data.collateral_amount,
+            );
+
+            if is_pool_unit_collateral {
+                self.index_pool_unit_loan(new_address, collateral_id.clone());
+            }
+
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "collateral", new_address);
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "parent_address",
+                parent_collateral_address,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "is_pool_unit_collateral",
+                is_pool_unit_collateral,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "collateral_amount",
+                new_collateral.amount(),
+            );
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "collateral_stab_ratio", cr);
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "debt_index", debt_index);
+
+            Runtime::emit_event(CdpCollateralSwappedEvent {
+                cdp_id: collateral_id,
+                old_collateral: receipt_data.collateral,
+                new_collateral: new_address,
+                collateral_returned: old_collateral.amount(),
+                collateral_added: new_collateral.amount(),
+                minted_stab: receipt_data.minted_stab,
+                old_cr: receipt_data.collateral_stab_ratio,
+                new_cr: cr,
+                collateral_price: new_price,
+                internal_price: self.internal_stab_price,
+            });
+
+            self.put_collateral(new_address, is_pool_unit_collateral, new_collateral);
+
+            old_collateral
+        }
+
         /// Partially close a loan / CDP (pay off part of the debt)
         ///
         /// # Input
@@ -857,7 +1315,7 @@ mod stabilis_component {
                 repayment.resource_address() == self.stab_manager.address(),
                 "Invalid STAB payment."
             );
-            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let receipt_data: Cdp = self.accrue_stability_fee(&collateral_id);
             let new_stab_amount = receipt_data.minted_stab - repayment.amount();
 
             if new_stab_amount < dec!(0) {
@@ -897,6 +1355,7 @@ mod stabilis_component {
                 receipt_data.collateral,
             );
 
+            let stab_repaid: Decimal = repayment.amount();
             repayment.burn();
 
             self.insert_cr(receipt_data.parent_address, cr, collateral_id.clone());
@@ -918,6 +1377,21 @@ mod stabilis_component {
                 new_stab_amount,
             );
 
+            Runtime::emit_event(CdpPartiallyClosedEvent {
+                cdp_id: collateral_id,
+                collateral: receipt_data.collateral,
+                stab_repaid,
+                minted_stab: new_stab_amount,
+                old_cr: receipt_data.collateral_stab_ratio,
+                new_cr: cr,
+                collateral_price: self
+                    .collaterals
+                    .get(&receipt_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
             (None, None)
         }
 
@@ -944,7 +1418,7 @@ mod stabilis_component {
             collateral_id: NonFungibleLocalId,
             amount: Decimal,
         ) -> Bucket {
-            let receipt_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let receipt_data: Cdp = self.accrue_stability_fee(&collateral_id);
             let new_stab_amount = receipt_data.minted_stab + amount;
 
             assert!(
@@ -992,6 +1466,21 @@ mod stabilis_component {
                 new_stab_amount,
             );
 
+            Runtime::emit_event(CdpBorrowedMoreEvent {
+                cdp_id: collateral_id,
+                collateral: receipt_data.collateral,
+                stab_borrowed: amount,
+                minted_stab: new_stab_amount,
+                old_cr: receipt_data.collateral_stab_ratio,
+                new_cr: cr,
+                collateral_price: self
+                    .collaterals
+                    .get(&receipt_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
             self.stab_manager.mint(amount)
         }
 
@@ -999,6 +1488,7 @@ mod stabilis_component {
         ///
         /// # Input
         /// - `collateral`: The collateral for which to look for undercollateralized loans to be liquidated
+        /// - `deposit`: The marker deposit, if governance requires one (refunded when the loan is liquidated, partly forfeited when it is saved)
         ///
         /// # Output
         /// - The marker receipt in a `Bucket`
@@ -1006,7 +1496,7 @@ mod stabilis_component {
         /// # Logic
         /// - Get the CDP with the lowest collateral ratio for the chosen collateral
         /// - Calculate new collateral ratio (as pool unit aren't always up to date)
-        /// - Create the marker receipt struct
+        /// - Take the marker deposit and create the marker receipt struct
         /// - Insert the CDP into the marked CDPs AvlTree
         /// - Remove the collateral ratio from the AvlTree
         /// - Mint marker receipt, which will be returned if the marking is a success
@@ -1015,7 +1505,11 @@ mod stabilis_component {
         /// - Save CDP if CR is high enough after pool_to_real conversion, unless the collateral_ids vector is full
         ///     - Return the initial marker receipt if saving wasn't possible
         ///     - Or return a new marker receipt if saving was possible
-        pub fn mark_for_liquidation(&mut self, collateral: ResourceAddress) -> Bucket {
+        pub fn mark_for_liquidation(
+            &mut self,
+            collateral: ResourceAddress,
+            deposit: Option<Bucket>,
+        ) -> Bucket {
             let (_first_cr, collateral_ids, _next_key) = self
                 .collateral_ratios
                 .get_mut(&collateral)
@@ -1025,7 +1519,7 @@ mod stabilis_component {
                 .unwrap();
             let collateral_id: NonFungibleLocalId = collateral_ids[0].clone();
 
-            let data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let data: Cdp = self.accrue_stability_fee(&collateral_id);
 
             assert!(
                 data.collateral_stab_ratio
@@ -1043,81 +1537,292 @@ mod stabilis_component {
                 data.is_pool_unit_collateral,
             ) / data.minted_stab;
 
-            self.collaterals
-                .get_mut(&data.parent_address)
-                .unwrap()
-                .collateral_amount += (cr - data.collateral_stab_ratio) * data.minted_stab;
+            self.mark(collateral_id, data, cr, deposit)
+        }
 
-            self.marker_placing_counter += dec!(1);
-            self.cdp_marker_counter += 1;
-            let id: Decimal = self.marker_placing_counter;
+        /// Mark a specific loan for liquidation
+        ///
+        /// # Input
+        /// - `cdp_id`: The id of the loan to mark
+        /// - `deposit`: The marker deposit, if governance requires one
+        ///
+        /// # Output
+        /// - The marker receipt in a `Bucket`
+        ///
+        /// # Logic
+        /// - Check whether the loan is healthy (so not already marked, liquidated or closed)
+        /// - Calculate new collateral ratio (as pool unit aren't always up to date)
+        /// - Check whether this collateral ratio is below the liquidation collateral ratio
+        /// - Mark the loan (see `mark_for_liquidation`)
+        pub fn mark_cdp(&mut self, cdp_id: NonFungibleLocalId, deposit: Option<Bucket>) -> Bucket {
+            let data: Cdp = self.accrue_stability_fee(&cdp_id);
 
-            let marker = CdpMarker {
-                mark_type: CdpUpdate::Marked,
-                time_marked: Clock::current_time_rounded_to_minutes(),
-                marked_id: collateral_id.clone(),
-                marker_placing: self.marker_placing_counter,
-                used: false,
-            };
+            assert!(
+                data.status == CdpStatus::Healthy,
+                "Loan not available for marking."
+            );
 
-            self.marked_cdps.insert(id, collateral_id.clone());
-            self.marked_cdps_active += 1;
+            let cr: Decimal = self.pool_to_real(
+                data.collateral_amount,
+                data.collateral,
+                data.is_pool_unit_collateral,
+            ) / data.minted_stab;
 
-            self.remove_cr(
-                data.parent_address,
-                data.collateral_stab_ratio,
-                collateral_id.clone(),
+            assert!(
+                cr < self
+                    .collaterals
+                    .get(&data.parent_address)
+                    .unwrap()
+                    .liquidation_collateral_ratio,
+                "No possible liquidations."
             );
 
-            let marker_receipt_success: NonFungibleBucket = self
-                .cdp_marker_manager
-                .mint_non_fungible(
-                    &NonFungibleLocalId::integer(self.cdp_marker_counter),
-                    marker,
-                )
-                .as_non_fungible();
+            self.mark(cdp_id, data, cr, deposit)
+        }
 
-            self.cdp_manager.update_non_fungible_data(
-                &collateral_id,
-                "marker_id",
-                self.cdp_marker_counter,
+        /// Mark multiple loans for liquidation at once
+        ///
+        /// # Input
+        /// - `collateral`: The collateral for which to look for undercollateralized loans to be liquidated
+        /// - `max_count`: The maximum amount of loans to mark (the cost budget, as every marking costs about the same)
+        /// - `deposit`: The marker deposits for all loans, if governance requires one
+        ///
+        /// # Output
+        /// - The marker receipts (a loan using pool units might be saved instead, returning a saved marker receipt)
+        /// - The leftover deposit
+        ///
+        /// # Logic
+        /// - Walk the AvlTree of the collateral from the lowest collateral ratio up
+        /// - Mark loans (see `mark_for_liquidation`) until the lowest collateral ratio isn't liquidatable, the deposit runs out, or `max_count` loans are marked
+        pub fn mark_for_liquidation_batch(
+            &mut self,
+            collateral: ResourceAddress,
+            max_count: u64,
+            mut deposit: Option<Bucket>,
+        ) -> (Vec<Bucket>, Option<Bucket>) {
+            let mut markers: Vec<Bucket> = Vec::new();
+            let deposit_amount: Option<Decimal> = self
+                .parameters
+                .marker_deposit
+                .as_ref()
+                .map(|config| config.amount);
+
+            for _ in 0..max_count {
+                let liquidation_collateral_ratio: Decimal = self
+                    .collaterals
+                    .get(&collateral)
+                    .unwrap()
+                    .liquidation_collateral_ratio;
+
+                match self.lowest_cr(collateral) {
+                    Some(cr) if cr < liquidation_collateral_ratio => {}
+                    _ => break,
+                }
+
+                let marker_deposit: Option<Bucket> = match (deposit.as_mut(), deposit_amount) {
+                    (Some(deposit), Some(amount)) => {
+                        if deposit.amount() < amount {
+                            break;
+                        }
+                        Some(deposit.take(amount))
+                    }
+                    _ => None,
+                };
+
+                markers.push(self.mark_for_liquidation(collateral, marker_deposit));
+            }
+
+            assert!(!markers.is_empty(), "No possible liquidations.");
+
+            (markers, deposit)
+        }
+
+        /// Liquidate multiple marked loans at once, without marker receipts
+        ///
+        /// # Input
+        /// - `payment`: The STAB tokens to pay back the loans with
+        /// - `max_count`: The maximum amount of loans to liquidate (the cost budget, as every liquidation costs about the same)
+        ///
+        /// # Output
+        /// - The collateral rewards
+        /// - The leftover STAB
+        /// - The liquidation receipts (or saved marker receipts, for loans that turned out to be healthy again)
+        ///
+        /// # Logic
+        /// - Walk the marked loans in the order they were marked
+        /// - Stop at the first loan that can't be liquidated yet (delay not passed, auction mode, or not enough STAB left)
+        /// - Liquidate every other loan completely (see `liquidate_position_without_marker`), saving loans that are healthy again
+        pub fn liquidate_batch(
+            &mut self,
+            mut payment: Bucket,
+            max_count: u64,
+        ) -> (Vec<Bucket>, Bucket, Vec<Bucket>) {
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+            assert!(
+                !self.parameters.stop_liquidations,
+                "Not allowed to liquidate loans right now."
             );
-            self.cdp_manager
-                .update_non_fungible_data(&collateral_id, "status", CdpStatus::Marked);
 
-            let mut cdp_ids: Vec<NonFungibleLocalId> = Vec::new();
+            let cdp_ids: Vec<NonFungibleLocalId> = self
+                .marked_cdps
+                .range(dec!(0)..)
+                .take(max_count as usize)
+                .map(|(_marker_placing, cdp_id, _next_key)| cdp_id.clone())
+                .collect();
 
-            if self
-                .collateral_ratios
-                .get_mut(&collateral)
-                .unwrap()
-                .get_mut(&cr)
-                .is_some()
-            {
-                cdp_ids = self
+            let mut rewards: Vec<Bucket> = Vec::new();
+            let mut receipts: Vec<Bucket> = Vec::new();
+
+            for cdp_id in cdp_ids {
+                let health: CdpHealth = self.get_cdp_health(cdp_id.clone());
+                let data: Cdp = self.cdp_manager.get_non_fungible_data(&cdp_id);
+                let marker_data: CdpMarker = self
+                    .cdp_marker_manager
+                    .get_non_fungible_data(&NonFungibleLocalId::integer(data.marker_id));
+                let delay: i64 =
+                    self.parameters.liquidation_delay + self.parameters.unmarked_delay;
+
+                let liquidatable: bool = Clock::current_time_is_at_or_after(
+                    marker_data.time_marked.add_minutes(delay).unwrap(),
+                    TimePrecision::Minute,
+                ) && self
+                    .collaterals
+                    .get(&data.parent_address)
+                    .unwrap()
+                    .liquidation_mode
+                    == LiquidationMode::FixedFine
+                    && payment.amount() >= health.minted_stab + health.pending_fee
+                    && !self.price_paused(data.parent_address);
+
+                if !liquidatable {
+                    break;
+                }
+
+                let (reward, remainder, receipt): (Bucket, Option<Bucket>, Bucket) =
+                    self.liquidate_without_marker(payment, None, cdp_id, false);
+
+                match remainder {
+                    Some(remainder) => {
+                        rewards.push(reward);
+                        payment = remainder;
+                    }
+                    None => payment = reward,
+                }
+                receipts.push(receipt);
+            }
+
+            assert!(!receipts.is_empty(), "No loans available to liquidate.");
+
+            (rewards, payment, receipts)
+        }
+
+        /// Re-price loans using a pool unit collateral, so their collateral ratios follow the pool unit's redemption value
+        ///
+        /// # Input
+        /// - `pool_unit`: The pool unit collateral (LSU or OneResourcePool unit) of which to refresh the loans
+        /// - `max_count`: The maximum amount of loans to check (the cost budget)
+        ///
+        /// # Output
+        /// - The amount of loans that were re-priced
+        ///
+        /// # Logic
+        /// - Walk the loans indexed for this pool unit, starting after the position the previous refresh stopped at
+        /// - Remove loans that are no longer open, or no longer use the pool unit, from the index
+        ///     - The last indexed loan takes the removed loan's position, so it is checked next
+        /// - For every healthy loan using the pool unit:
+        ///     - Accrue the stability fee
+        ///     - Calculate the new collateral ratio using `pool_to_real`
+        ///     - Move the loan in the AvlTree (unless the collateral ratio vector is full) and update the CDP receipt
+        ///     - Update the collateral amount of the parent collateral
+        /// - Store where the walk stopped, starting over from the first loan once the last one is reached
+        pub fn refresh_pool_unit_crs(&mut self, pool_unit: ResourceAddress, max_count: u64) -> u64 {
+            assert!(
+                self.shutdown.is_none(),
+                "Prices are frozen after an emergency shutdown."
+            );
+            let mut cursor: u64 = self
+                .pool_units
+                .get(&pool_unit)
+                .expect("Pool unit not found.")
+                .refresh_cursor;
+
+            let mut refreshed: u64 = 0;
+
+            for _ in 0..max_count {
+                if cursor >= self.pool_units.get(&pool_unit).unwrap().loan_count {
+                    cursor = 0;
+                    break;
+                }
+                cursor += 1;
+
+                let cdp_id: NonFungibleLocalId = self
+                    .pool_units
+                    .get(&pool_unit)
+                    .unwrap()
+                    .loans
+                    .get(&cursor)
+                    .unwrap()
+                    .clone();
+                let data: Cdp = self.cdp_manager.get_non_fungible_data(&cdp_id);
+
+                if data.collateral != pool_unit
+                    || !data.is_pool_unit_collateral
+                    || (data.status != CdpStatus::Healthy && data.status != CdpStatus::Marked)
+                {
+                    self.unindex_pool_unit_loan(pool_unit, cdp_id);
+                    cursor -= 1;
+                    continue;
+                }
+
+                if data.status != CdpStatus::Healthy {
+                    continue;
+                }
+
+                let data: Cdp = self.accrue_stability_fee(&cdp_id);
+                let cr: Decimal =
+                    self.pool_to_real(data.collateral_amount, pool_unit, true) / data.minted_stab;
+
+                if cr == data.collateral_stab_ratio {
+                    continue;
+                }
+
+                let vector_length: usize = self
                     .collateral_ratios
-                    .get_mut(&collateral)
+                    .get_mut(&data.parent_address)
                     .unwrap()
                     .get_mut(&cr)
-                    .unwrap()
-                    .to_vec();
-            }
+                    .map_or(0, |cdp_ids| cdp_ids.len());
 
-            if (cr
-                > self
-                    .collaterals
-                    .get(&collateral)
+                if vector_length >= self.parameters.max_vector_length.try_into().unwrap() {
+                    continue;
+                }
+
+                self.remove_cr(data.parent_address, data.collateral_stab_ratio, cdp_id.clone());
+                self.insert_cr(data.parent_address, cr, cdp_id.clone());
+
+                self.collaterals
+                    .get_mut(&data.parent_address)
                     .unwrap()
-                    .liquidation_collateral_ratio)
-                && cdp_ids.len() < self.parameters.max_vector_length.try_into().unwrap()
-            {
-                let marker_data: CdpMarker = marker_receipt_success.non_fungible().data();
-                marker_receipt_success.burn();
-                let cdp_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
-                self.save(marker_data, cdp_data, cr)
-            } else {
-                marker_receipt_success.into()
+                    .collateral_amount += (cr - data.collateral_stab_ratio) * data.minted_stab;
+
+                self.cdp_manager
+                    .update_non_fungible_data(&cdp_id, "collateral_stab_ratio", cr);
+
+                refreshed += 1;
             }
+
+            self.pool_units.get_mut(&pool_unit).unwrap().refresh_cursor = cursor;
+
+            Runtime::emit_event(PoolUnitCrsRefreshedEvent {
+                pool_unit,
+                refreshed,
+                cursor,
+            });
+
+            refreshed
         }
 
         /// Force liquidate a loan / CDP (liquidating without the loan being undercollateralized, but with a fee that should be beneficial for the borrower)
@@ -1179,7 +1884,7 @@ mod stabilis_component {
                 .next()
                 .unwrap();
             let collateral_id: NonFungibleLocalId = collateral_ids[0].clone();
-            let data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+            let data: Cdp = self.accrue_stability_fee(&collateral_id);
 
             self.remove_cr(
                 data.parent_address,
@@ -1264,8 +1969,10 @@ mod stabilis_component {
                 new_stab_amount,
             );
 
+            let mut new_cr: Decimal = dec!(0);
+
             if percentage_to_liquidate < dec!(1) {
-                let new_cr: Decimal = self.pool_to_real(
+                new_cr = self.pool_to_real(
                     new_collateral_amount,
                     data.collateral,
                     data.is_pool_unit_collateral,
@@ -1296,6 +2003,211 @@ mod stabilis_component {
                     .collateral_amount -= data.collateral_stab_ratio * data.minted_stab;
             }
 
+            Runtime::emit_event(CdpForceLiquidatedEvent {
+                cdp_id: collateral_id,
+                collateral: data.collateral,
+                stab_paid: payment_amount,
+                collateral_taken: collateral_payment.amount(),
+                old_cr: data.collateral_stab_ratio,
+                new_cr,
+                fully_liquidated: percentage_to_liquidate == dec!(1),
+                collateral_price: self.collaterals.get(&collateral).unwrap().usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
+            (collateral_payment, payment)
+        }
+
+        /// Redeem STAB for collateral, at the internal price of STAB, against the loans / CDPs with the lowest collateral ratio
+        ///
+        /// # Input
+        /// - `collateral`: The collateral to receive
+        /// - `payment`: The STAB tokens to redeem
+        ///
+        /// # Output
+        /// - The collateral received (minus the redemption fee)
+        /// - The leftover STAB
+        ///
+        /// # Logic
+        /// - Check if redemptions are allowed right now and the payment is STAB
+        /// - Calculate the amount of collateral 1 STAB can be redeemed for (internal STAB price / collateral price)
+        /// - Walk the AvlTree from the liquidation collateral ratio upwards (loans below it should be liquidated instead), selecting loans until the payment is covered or the maximum amount of loans is reached
+        ///    - Pool unit loans are skipped, as their collateral is not the requested collateral
+        /// - For every selected loan:
+        ///    - Calculate the STAB to redeem against it, making sure a partially redeemed loan keeps at least the minimum mint
+        ///    - Remove the collateral ratio from the AvlTree
+        ///    - Reduce the debt and collateral of the loan
+        ///    - If the debt is fully redeemed, set the status to redeemed (leftover collateral can be retrieved by the owner)
+        ///    - Otherwise insert the new collateral ratio into the AvlTree
+        ///    - Update the CDP receipt and the minted STAB
+        /// - Burn the redeemed STAB
+        /// - Take the collateral, and put the redemption fee in the collateral treasury
+        /// - Return the collateral and the leftover STAB
+        pub fn redeem(&mut self, collateral: ResourceAddress, mut payment: Bucket) -> (Bucket, Bucket) {
+            assert!(
+                !self.parameters.stop_redemptions,
+                "Not allowed to redeem STAB right now."
+            );
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+            assert!(
+                self.collaterals.get(&collateral).is_some()
+                    && self.collaterals.get(&collateral).unwrap().initialized,
+                "Collateral not available for redemptions."
+            );
+
+            let lcr: Decimal = self
+                .collaterals
+                .get(&collateral)
+                .unwrap()
+                .liquidation_collateral_ratio;
+            let collateral_per_stab: Decimal =
+                self.internal_stab_price / self.collaterals.get(&collateral).unwrap().usd_price;
+
+            let mut selected_cdps: Vec<NonFungibleLocalId> = Vec::new();
+            let mut selected_stab: Decimal = dec!(0);
+
+            'outer_loop: for (_cr, collateral_ids, _next_key) in self
+                .collateral_ratios
+                .get_mut(&collateral)
+                .unwrap()
+                .range(lcr..)
+            {
+                for collateral_id in collateral_ids {
+                    if selected_stab >= payment.amount()
+                        || selected_cdps.len() as u64 >= self.parameters.max_redemption_cdps
+                    {
+                        break 'outer_loop;
+                    }
+                    let data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+                    if !data.is_pool_unit_collateral {
+                        selected_stab += data.minted_stab;
+                        selected_cdps.push(collateral_id);
+                    }
+                }
+            }
+
+            let mut stab_redeemed: Decimal = dec!(0);
+            let mut collateral_redeemed: Decimal = dec!(0);
+
+            for collateral_id in selected_cdps {
+                let data: Cdp = self.accrue_stability_fee(&collateral_id);
+
+                let mut stab_to_redeem: Decimal = payment.amount() - stab_redeemed;
+                if stab_to_redeem >= data.minted_stab {
+                    stab_to_redeem = data.minted_stab;
+                } else if data.minted_stab - stab_to_redeem < self.parameters.minimum_mint {
+                    stab_to_redeem = data.minted_stab - self.parameters.minimum_mint;
+                }
+
+                if stab_to_redeem <= dec!(0) {
+                    continue;
+                }
+
+                let collateral_to_take: Decimal = stab_to_redeem * collateral_per_stab;
+                let new_collateral_amount: Decimal = data.collateral_amount - collateral_to_take;
+                let new_stab_amount: Decimal = data.minted_stab - stab_to_redeem;
+
+                self.remove_cr(
+                    data.parent_address,
+                    data.collateral_stab_ratio,
+                    collateral_id.clone(),
+                );
+
+                self.collaterals
+                    .get_mut(&data.parent_address)
+                    .unwrap()
+                    .collateral_amount -= data.collateral_stab_ratio * data.minted_stab;
+
+                let mut new_cr: Decimal = dec!(0);
+
+                if new_stab_amount == dec!(0) {
+                    self.cdp_manager.update_non_fungible_data(
+                        &collateral_id,
+                        "status",
+                        CdpStatus::Redeemed,
+                    );
+                } else {
+                    new_cr = new_collateral_amount / new_stab_amount;
+
+                    self.collaterals
+                        .get_mut(&data.parent_address)
+                        .unwrap()
+                        .collateral_amount += new_cr * new_stab_amount;
+
+                    self.insert_cr(data.parent_address, new_cr, collateral_id.clone());
+
+                    self.cdp_manager.update_non_fungible_data(
+                        &collateral_id,
+                        "collateral_stab_ratio",
+                        new_cr,
+                    );
+                }
+
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "collateral_amount",
+                    new_collateral_amount,
+                );
+                self.cdp_manager.update_non_fungible_data(
+                    &collateral_id,
+                    "minted_stab",
+                    new_stab_amount,
+                );
+
+                self.update_minted_stab(
+                    false,
+                    false,
+                    false,
+                    stab_to_redeem,
+                    data.parent_address,
+                    data.collateral,
+                );
+
+                stab_redeemed += stab_to_redeem;
+                collateral_redeemed += collateral_to_take;
+
+                Runtime::emit_event(CdpRedeemedEvent {
+                    cdp_id: collateral_id,
+                    collateral: data.collateral,
+                    stab_redeemed: stab_to_redeem,
+                    collateral_taken: collateral_to_take,
+                    old_cr: data.collateral_stab_ratio,
+                    new_cr,
+                    fully_redeemed: new_stab_amount == dec!(0),
+                    collateral_price: self.collaterals.get(&collateral).unwrap().usd_price,
+                    internal_price: self.internal_stab_price,
+                });
+            }
+
+            assert!(
+                stab_redeemed > dec!(0),
+                "No loans available to redeem against."
+            );
+
+            payment.take(stab_redeemed).burn();
+
+            let mut collateral_payment: Bucket =
+                self.take_collateral(collateral, false, collateral_redeemed);
+
+            let fee: Bucket = collateral_payment.take_advanced(
+                collateral_payment.amount() * self.parameters.redemption_fee,
+                WithdrawStrategy::Rounded(RoundingMode::ToZero),
+            );
+            let fee_amount: Decimal = fee.amount();
+            self.put_collateral_in_treasury(collateral, false, fee);
+
+            Runtime::emit_event(RedemptionEvent {
+                collateral,
+                stab_redeemed,
+                collateral_returned: collateral_payment.amount(),
+                fee: fee_amount,
+                collateral_price: self.collaterals.get(&collateral).unwrap().usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
             (collateral_payment, payment)
         }
 
@@ -1363,6 +2275,7 @@ mod stabilis_component {
                 data.collateral == payment.resource_address(),
                 "Can only force mint other collaterals right now."
             );
+            let data: Cdp = self.accrue_stability_fee(&collateral_id);
 
             let pool_to_real: Decimal =
                 self.pool_to_real(dec!(1), data.collateral, data.is_pool_unit_collateral);
@@ -1448,6 +2361,17 @@ mod stabilis_component {
                 data.collateral,
             );
 
+            Runtime::emit_event(CdpForceMintedEvent {
+                cdp_id: collateral_id,
+                collateral: data.collateral,
+                collateral_added: payment.amount(),
+                stab_minted: stab_tokens.amount(),
+                old_cr: data.collateral_stab_ratio,
+                new_cr,
+                collateral_price,
+                internal_price: self.internal_stab_price,
+            });
+
             self.put_collateral(data.collateral, data.is_pool_unit_collateral, payment);
 
             (stab_tokens, return_bucket)
@@ -1482,19 +2406,27 @@ mod stabilis_component {
                 payment.resource_address() == self.stab_manager.address(),
                 "Invalid STAB payment."
             );
-            let marker_data: CdpMarker = self.cdp_marker_manager.get_non_fungible_data(&marker_id);
-
-            let cdp_data: Cdp = self
-                .cdp_manager
-                .get_non_fungible_data(&marker_data.marked_id);
+            self.liquidate_with_marker(marker_id, payment, false)
+        }
 
-            self.try_liquidate(
-                payment,
-                cdp_data,
-                marker_data,
-                marker_id,
-                self.parameters.liquidation_delay,
-            )
+        /// Partially liquidate a marked loan / CDP, using a marker receipt
+        ///
+        /// # Input
+        /// - `marker_id`: The marker receipt id
+        /// - `payment`: The STAB tokens to pay back
+        ///
+        /// # Output
+        /// - Same as `liquidate_position_with_marker`
+        ///
+        /// # Logic
+        /// - Same as `liquidate_position_with_marker`, but only the debt needed to restore the loan to the liquidation collateral ratio plus a buffer is repaid
+        ///    - If a partial liquidation isn't possible, the loan is liquidated completely
+        pub fn partial_liquidate_position_with_marker(
+            &mut self,
+            marker_id: NonFungibleLocalId,
+            payment: Bucket,
+        ) -> (Bucket, Option<Bucket>, Bucket) {
+            self.liquidate_with_marker(marker_id, payment, true)
         }
 
         /// Liquidate a marked loan / CDP, without a marker receipt
@@ -1527,59 +2459,377 @@ mod stabilis_component {
             skip: Option<i64>,
             cdp_id: NonFungibleLocalId,
         ) -> (Bucket, Option<Bucket>, Bucket) {
-            assert!(
-                payment.resource_address() == self.stab_manager.address(),
-                "Invalid STAB payment."
-            );
-            let mut collateral_id: NonFungibleLocalId = cdp_id;
-            let mut skip_counter: i64 = 0;
-            let mut found: bool = false;
+            self.liquidate_without_marker(payment, skip, cdp_id, false)
+        }
 
-            if let Some(skip) = skip {
-                for (_identifier, found_collateral_id, _next_key) in
-                    self.marked_cdps.range(dec!(0)..)
-                {
-                    collateral_id = found_collateral_id.clone();
-                    skip_counter += 1;
-                    if (skip_counter - 1) == skip {
-                        found = true;
-                        break;
-                    }
-                }
-                if skip_counter == 0 {
-                    panic!("No loans available to liquidate.");
-                } else if !found {
-                    panic!(
-                        "Too many skipped. Skip a maximum of {} loans.",
-                        skip_counter - 1
-                    );
-                }
-            }
+        /// Partially liquidate a marked loan / CDP, without a marker receipt
+        ///
+        /// # Input
+        /// - `payment`: The STAB tokens to pay back
+        /// - `skip`: The amount of marked loans to skip, if None, the `cdp_id` is used
+        /// - `cdp_id`: The CDP receipt id
+        ///
+        /// # Output
+        /// - Same as `liquidate_position_without_marker`
+        ///
+        /// # Logic
+        /// - Same as `liquidate_position_without_marker`, but only the debt needed to restore the loan to the liquidation collateral ratio plus a buffer is repaid
+        ///    - If a partial liquidation isn't possible, the loan is liquidated completely
+        pub fn partial_liquidate_position_without_marker(
+            &mut self,
+            payment: Bucket,
+            skip: Option<i64>,
+            cdp_id: NonFungibleLocalId,
+        ) -> (Bucket, Option<Bucket>, Bucket) {
+            self.liquidate_without_marker(payment, skip, cdp_id, true)
+        }
 
-            let cdp_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
-            let marker_data: CdpMarker = self
-                .cdp_marker_manager
-                .get_non_fungible_data(&NonFungibleLocalId::integer(cdp_data.marker_id));
+        /// Fill (part of) the liquidation auction of a marked loan / CDP
+        ///
+        /// # Input
+        /// - `cdp_id`: The id of the marked loan
+        /// - `payment`: The STAB tokens to pay back
+        ///
+        /// # Output
+        /// - The collateral bought
+        /// - The leftover STAB
+        /// - A liquidation receipt
+        ///
+        /// # Logic
+        /// - Assert that liquidations are enabled, the collateral is in auction mode, the loan is marked and the liquidation delay has passed
+        /// - Get the newest collateral ratio, assert the loan is still liquidatable (if not, it should be saved through the normal liquidation methods)
+        /// - Get the current discount of the auction (see `auction_discount`)
+        /// - Calculate the STAB to repay, making sure a partially filled auction leaves at least the minimum mint
+        /// - Calculate collateral per STAB repaid: (1 + discount + protocol fine) STAB worth of collateral
+        ///    - If the loan doesn't have enough collateral for this, all collateral is auctioned pro rata
+        /// - Burn the repayment, update minted STAB
+        /// - Take the collateral for the bidder and treasury
+        /// - Update the CDP receipt
+        /// - If the complete debt is repaid, the loan is liquidated and the marker is used
+        ///    - Leftover collateral can be retrieved by the owner through `retrieve_leftover_collateral`
+        /// - Make the liquidation receipt
+        /// - Return the collateral, the leftover STAB and the liquidation receipt
+        pub fn fill_auction(
+            &mut self,
+            cdp_id: NonFungibleLocalId,
+            mut payment: Bucket,
+        ) -> (Bucket, Bucket, Bucket) {
+            let cdp_data: Cdp = self.accrue_stability_fee(&cdp_id);
+
+            assert!(
+                !self.parameters.stop_liquidations,
+                "Not allowed to liquidate loans right now."
+            );
+            self.assert_price_not_paused(cdp_data.parent_address);
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+            assert!(cdp_data.status == CdpStatus::Marked, "Loan not marked");
+            assert!(
+                self.collaterals
+                    .get(&cdp_data.parent_address)
+                    .unwrap()
+                    .liquidation_mode
+                    == LiquidationMode::Auction,
+                "This collateral is not liquidated through auctions."
+            );
 
             let marker_id: NonFungibleLocalId = NonFungibleLocalId::integer(cdp_data.marker_id);
+            let marker_data: CdpMarker = self.cdp_marker_manager.get_non_fungible_data(&marker_id);
 
-            self.try_liquidate(
-                payment,
-                cdp_data,
-                marker_data,
+            assert!(
+                Clock::current_time_is_at_or_after(
+                    marker_data
+                        .time_marked
+                        .add_minutes(self.parameters.liquidation_delay)
+                        .unwrap(),
+                    TimePrecision::Minute
+                ),
+                "Auction not started yet."
+            );
+
+            let real_collateral_amount: Decimal = self.pool_to_real(
+                cdp_data.collateral_amount,
+                cdp_data.collateral,
+                cdp_data.is_pool_unit_collateral,
+            );
+            let cr: Decimal = real_collateral_amount / cdp_data.minted_stab;
+
+            let mcr: Decimal = self.collaterals.get(&cdp_data.parent_address).unwrap().mcr;
+            let liq_cr: Decimal = self
+                .collaterals
+                .get(&cdp_data.parent_address)
+                .unwrap()
+                .liquidation_collateral_ratio;
+
+            assert!(
+                cr < liq_cr,
+                "Loan is healthy again. Save it through the normal liquidation methods."
+            );
+
+            let discount: Decimal = self.auction_discount(&marker_data);
+
+            let mut stab_to_repay: Decimal = payment.amount();
+            if stab_to_repay >= cdp_data.minted_stab {
+                stab_to_repay = cdp_data.minted_stab;
+            } else if cdp_data.minted_stab - stab_to_repay < self.parameters.minimum_mint {
+                stab_to_repay = cdp_data.minted_stab - self.parameters.minimum_mint;
+            }
+
+            assert!(
+                stab_to_repay > dec!(0),
+                "Not enough STAB to fill the auction."
+            );
+
+            //real collateral per repaid STAB, for the bidder and the protocol
+            //if the loan can't pay the discount and fine, all collateral is divided pro rata
+            let collateral_per_stab: Decimal = liq_cr / mcr;
+            let full_rate: Decimal =
+                collateral_per_stab * (dec!(1) + discount + self.parameters.stabilis_liquidation_fine);
+            let rate: Decimal = if full_rate > cr { cr } else { full_rate };
+            let bidder_rate: Decimal = rate * (dec!(1) + discount)
+                / (dec!(1) + discount + self.parameters.stabilis_liquidation_fine);
+
+            //collateral is taken in the loan's own collateral (which might be a pool unit), so the real amounts are converted
+            let bidder_amount: Decimal =
+                cdp_data.collateral_amount * stab_to_repay * bidder_rate / real_collateral_amount;
+            let treasury_amount: Decimal = cdp_data.collateral_amount
+                * stab_to_repay
+                * (rate - bidder_rate)
+                / real_collateral_amount;
+
+            payment.take(stab_to_repay).burn();
+
+            self.update_minted_stab(
+                false,
+                cdp_data.is_pool_unit_collateral,
+                false,
+                stab_to_repay,
+                cdp_data.parent_address,
+                cdp_data.collateral,
+            );
+
+            self.collaterals
+                .get_mut(&cdp_data.parent_address)
+                .unwrap()
+                .collateral_amount -= cdp_data.collateral_stab_ratio * stab_to_repay;
+
+            let bought_collateral: Bucket = self.take_collateral(
+                cdp_data.collateral,
+                cdp_data.is_pool_unit_collateral,
+                bidder_amount,
+            );
+
+            let treasury_payment: Option<Bucket> = if treasury_amount > dec!(0) {
+                Some(self.take_collateral(
+                    cdp_data.collateral,
+                    cdp_data.is_pool_unit_collateral,
+                    treasury_amount,
+                ))
+            } else {
+                None
+            };
+
+            let treasury_payment_taken: Decimal = treasury_payment
+                .as_ref()
+                .map_or(dec!(0), |payment_bucket| payment_bucket.amount());
+
+            if let Some(treasury_bucket) = treasury_payment {
+                self.put_collateral_in_treasury(
+                    cdp_data.collateral,
+                    cdp_data.is_pool_unit_collateral,
+                    treasury_bucket,
+                );
+            }
+
+            let new_collateral_amount: Decimal =
+                cdp_data.collateral_amount - bought_collateral.amount() - treasury_payment_taken;
+            let new_stab_amount: Decimal = cdp_data.minted_stab - stab_to_repay;
+            let completed: bool = new_stab_amount == dec!(0);
+
+            self.cdp_manager.update_non_fungible_data(
+                &cdp_id,
+                "collateral_amount",
+                new_collateral_amount,
+            );
+            self.cdp_manager
+                .update_non_fungible_data(&cdp_id, "minted_stab", new_stab_amount);
+
+            if completed {
+                self.marked_cdps.remove(&marker_data.marker_placing);
+                self.marked_cdps_active -= 1;
+                self.cdp_marker_manager
+                    .update_non_fungible_data(&marker_id, "used", true);
+                self.cdp_manager
+                    .update_non_fungible_data(&cdp_id, "status", CdpStatus::Liquidated);
+            } else {
+                //after a partial fill, the collateral ratio of the remaining loan is recalculated (and its share of the parent collateral amount with it)
+                let new_cr: Decimal = self.pool_to_real(
+                    new_collateral_amount,
+                    cdp_data.collateral,
+                    cdp_data.is_pool_unit_collateral,
+                ) / new_stab_amount;
+
+                self.collaterals
+                    .get_mut(&cdp_data.parent_address)
+                    .unwrap()
+                    .collateral_amount += (new_cr - cdp_data.collateral_stab_ratio) * new_stab_amount;
+
+                self.cdp_manager
+                    .update_non_fungible_data(&cdp_id, "collateral_stab_ratio", new_cr);
+            }
+
+            self.liquidation_counter += 1;
+
+            let liquidation_receipt = LiquidationReceipt {
+                collateral: cdp_data.collateral,
+                stab_paid: stab_to_repay,
+                percentage_owed: dec!(1) + discount,
+                percentage_received: bidder_rate / collateral_per_stab,
+                cdp_liquidated: cdp_id.clone(),
+                date_liquidated: Clock::current_time_rounded_to_minutes(),
+                partial: !completed,
+                remaining_stab: new_stab_amount,
+            };
+
+            let receipt: NonFungibleBucket = self
+                .liquidation_receipt_manager
+                .mint_non_fungible(
+                    &NonFungibleLocalId::integer(self.liquidation_counter),
+                    liquidation_receipt,
+                )
+                .as_non_fungible();
+
+            Runtime::emit_event(AuctionFilledEvent {
+                cdp_id,
                 marker_id,
-                self.parameters.liquidation_delay + self.parameters.unmarked_delay,
-            )
+                liquidation_receipt_id: NonFungibleLocalId::integer(self.liquidation_counter),
+                collateral: cdp_data.collateral,
+                stab_paid: stab_to_repay,
+                discount,
+                collateral_to_bidder: bought_collateral.amount(),
+                collateral_to_treasury: treasury_payment_taken,
+                remaining_stab: new_stab_amount,
+                remaining_collateral: new_collateral_amount,
+                completed,
+                collateral_price: self
+                    .collaterals
+                    .get(&cdp_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
+            (bought_collateral, payment, receipt.into())
+        }
+
+        /// Gets the current auction discount of a marked loan / CDP
+        pub fn get_auction_discount(&self, cdp_id: NonFungibleLocalId) -> Decimal {
+            let cdp_data: Cdp = self.cdp_manager.get_non_fungible_data(&cdp_id);
+            assert!(cdp_data.status == CdpStatus::Marked, "Loan not marked");
+            let marker_data: CdpMarker = self
+                .cdp_marker_manager
+                .get_non_fungible_data(&NonFungibleLocalId::integer(cdp_data.marker_id));
+            self.auction_discount(&marker_data)
         }
 
         /// Changes the price of a collateral, which will also update the liquidation collateral ratio
+        ///   - If the collateral has a circuit breaker, a price moving too much (per update or per hour) is held as pending instead
+        ///       - Openings and liquidations using the collateral are paused until governance confirms or rejects the pending price
         pub fn change_collateral_price(&mut self, collateral: ResourceAddress, new_price: Decimal) {
-            let mcr: Decimal = self.collaterals.get_mut(&collateral).unwrap().mcr;
-            self.collaterals.get_mut(&collateral).unwrap().usd_price = new_price;
-            self.collaterals
+            assert!(
+                self.shutdown.is_none(),
+                "Prices are frozen after an emergency shutdown."
+            );
+
+            if self.circuit_breakers.get(&collateral).is_some()
+                && !self.pass_circuit_breaker(collateral, new_price)
+            {
+                return;
+            }
+
+            self.set_collateral_price(collateral, new_price);
+            self.update_lp_prices_of_leg(collateral);
+        }
+
+        /// Set the circuit breaker of a collateral (None to remove it)
+        ///
+        /// # Input
+        /// - `collateral`: The collateral
+        /// - `limits`: The maximum relative price move per update and per hour (0.2 is 20%)
+        pub fn set_circuit_breaker(
+            &mut self,
+            collateral: ResourceAddress,
+            limits: Option<(Decimal, Decimal)>,
+        ) {
+            let price: Decimal = self
+                .collaterals
+                .get(&collateral)
+                .expect("Collateral not found.")
+                .usd_price;
+
+            match limits {
+                Some((max_update_move, max_hourly_move)) => {
+                    assert!(
+                        max_update_move > dec!(0) && max_hourly_move > dec!(0),
+                        "Maximum price moves must be positive."
+                    );
+                    self.circuit_breakers.insert(
+                        collateral,
+                        CircuitBreaker {
+                            max_update_move,
+                            max_hourly_move,
+                            hour_start: Clock::current_time_rounded_to_minutes(),
+                            hour_start_price: price,
+                            pending_price: None,
+                        },
+                    );
+                }
+                None => {
+                    self.circuit_breakers.remove(&collateral);
+                }
+            }
+        }
+
+        /// Confirm the pending price of a collateral held by its circuit breaker, applying it and resuming openings and liquidations
+        pub fn confirm_pending_price(&mut self, collateral: ResourceAddress) {
+            let pending_price: Decimal = {
+                let mut breaker = self
+                    .circuit_breakers
+                    .get_mut(&collateral)
+                    .expect("No circuit breaker for this collateral.");
+                let pending_price: Decimal = breaker.pending_price.take().expect("No pending price.");
+                breaker.hour_start = Clock::current_time_rounded_to_minutes();
+                breaker.hour_start_price = pending_price;
+                pending_price
+            };
+
+            self.set_collateral_price(collateral, pending_price);
+            self.update_lp_prices_of_leg(collateral);
+
+            Runtime::emit_event(PendingPriceResolvedEvent {
+                collateral,
+                pending_price,
+                confirmed: true,
+            });
+        }
+
+        /// Reject the pending price of a collateral held by its circuit breaker, keeping the current price and resuming openings and liquidations
+        pub fn reject_pending_price(&mut self, collateral: ResourceAddress) {
+            let pending_price: Decimal = self
+                .circuit_breakers
                 .get_mut(&collateral)
-                .unwrap()
-                .liquidation_collateral_ratio = mcr * (self.internal_stab_price / new_price);
+                .expect("No circuit breaker for this collateral.")
+                .pending_price
+                .take()
+                .expect("No pending price.");
+
+            Runtime::emit_event(PendingPriceResolvedEvent {
+                collateral,
+                pending_price,
+                confirmed: false,
+            });
         }
 
         /// Add a possible collateral to the protocol
@@ -1607,6 +2857,11 @@ mod stabilis_component {
                 minted_stab: dec!(0),
                 collateral_amount: dec!(0),
                 highest_cr: dec!(0),
+                liquidation_mode: LiquidationMode::FixedFine,
+                stability_fee: dec!(1),
+                debt_index: dec!(1),
+                last_fee_update: Clock::current_time_rounded_to_minutes(),
+                bad_debt: dec!(0),
             };
 
             self.collaterals.insert(address, info);
@@ -1617,6 +2872,7 @@ mod stabilis_component {
         ///       - the collateral amount is calculated when a loan is opened and interacted with, so not continuously updated
         ///          - this means that sometimes a loan can be liquidated, but when interacting with it, the collateral amount is updated so it can't be anymore
         ///             - this results in the loan being saved
+        ///       - `refresh_pool_unit_crs` re-prices these loans in batches, so the stored collateral ratios don't drift too far
         pub fn add_pool_collateral(
             &self,
             address: ResourceAddress,
@@ -1650,14 +2906,85 @@ mod stabilis_component {
                 accepted: initial_acceptance,
                 max_pool_share: dec!(1),
                 minted_stab: dec!(0),
+                refresh_cursor: 0,
+                loans: KeyValueStore::new(),
+                loan_positions: KeyValueStore::new(),
+                loan_count: 0,
             };
 
             self.pool_units.insert(address, info);
         }
 
+        /// Add a possible LP collateral to the protocol
+        ///   - LP collaterals are pool units of a TwoResourcePool or MultiResourcePool (like the STAB/XRD LP units of the StabilisPool)
+        ///       - they are a collateral of their own (with their own MCR, accounting and share cap), not a pool unit of a parent collateral
+        ///       - their price is the value of the legs one LP unit can be redeemed for, using the prices of the legs (STAB is valued at the internal price, see `lp_price`)
+        ///          - so every leg needs to be STAB or an added collateral, and the pool can't be empty
+        ///       - the price is updated whenever the price of a leg changes, or by calling `update_lp_price`
+        ///       - when liquidated, the LP units themselves are seized
+        pub fn add_lp_collateral(
+            &mut self,
+            address: ResourceAddress,
+            pool_address: ComponentAddress,
+            multi_resource: bool,
+            chosen_mcr: Decimal,
+            max_stab_share: Decimal,
+        ) {
+            assert!(
+                self.lp_units.get(&address).is_none() && self.pool_units.get(&address).is_none(),
+                "Collateral is already accepted."
+            );
+
+            let pool: LpPool = match multi_resource {
+                false => LpPool::TwoResource(Global::from(pool_address)),
+                true => LpPool::MultiResource(Global::from(pool_address)),
+            };
+
+            let legs: Vec<ResourceAddress> = pool.get_redemption_value(dec!(1)).keys().copied().collect();
+
+            for leg in legs.iter() {
+                assert!(
+                    *leg == self.stab_manager.address() || self.collaterals.get(leg).is_some(),
+                    "Every leg of the LP collateral must be STAB or an added collateral."
+                );
+                let mut lp_addresses: Vec<ResourceAddress> = self
+                    .lp_units_by_leg
+                    .get(leg)
+                    .map(|lp_addresses| lp_addresses.clone())
+                    .unwrap_or_default();
+                lp_addresses.push(address);
+                self.lp_units_by_leg.insert(*leg, lp_addresses);
+            }
+
+            self.lp_units.insert(address, LpUnitInfo { pool, legs });
+
+            let initial_price: Decimal = self.lp_price(address);
+            self.add_collateral(address, chosen_mcr, initial_price);
+            self.collaterals.get_mut(&address).unwrap().max_stab_share = max_stab_share;
+        }
+
+        /// Update the price of an LP collateral, as the amounts its units can be redeemed for change over time
+        pub fn update_lp_price(&mut self, lp_address: ResourceAddress) {
+            assert!(
+                self.shutdown.is_none(),
+                "Prices are frozen after an emergency shutdown."
+            );
+            assert!(
+                self.lp_units.get(&lp_address).is_some(),
+                "LP collateral not found."
+            );
+            let price: Decimal = self.lp_price(lp_address);
+            self.set_collateral_price(lp_address, price);
+        }
+
         /// Changes the internal price of the STAB token
         pub fn change_internal_price(&mut self, new_price: Decimal) {
+            assert!(
+                self.shutdown.is_none(),
+                "Prices are frozen after an emergency shutdown."
+            );
             self.internal_stab_price = new_price;
+            self.update_lp_prices_of_leg(self.stab_manager.address());
         }
 
         ///Emptying the treasury of a collateral, error_fallback exists if a pool unit is also in self.collaterals
@@ -1684,6 +3011,13 @@ mod stabilis_component {
             }
         }
 
+        /// Put collateral back in its treasury (in the pool unit treasury if the collateral is a pool unit)
+        pub fn return_collateral_to_treasury(&mut self, collateral_bucket: Bucket) {
+            let collateral: ResourceAddress = collateral_bucket.resource_address();
+            let pool: bool = self.pool_units.get(&collateral).is_some();
+            self.put_collateral_in_treasury(collateral, pool, collateral_bucket);
+        }
+
         /// Mint a controller badge
         pub fn mint_controller_badge(&self, amount: Decimal) -> Bucket {
             self.controller_badge_manager.mint(amount)
@@ -1732,6 +3066,10 @@ mod stabilis_component {
             force_mint: bool,
             force_liquidate: bool,
         ) {
+            assert!(
+                self.shutdown.is_none(),
+                "Can't change stops after an emergency shutdown."
+            );
             self.parameters.stop_closings = closings;
             self.parameters.stop_liquidations = liquidations;
             self.parameters.stop_openings = openings;
@@ -1744,6 +3082,23 @@ mod stabilis_component {
             self.parameters.max_vector_length = new_max_length;
         }
 
+        /// Set the deposit required to mark a loan (None to mark loans for free)
+        pub fn set_marker_deposit(&mut self, config: Option<MarkerDepositConfig>) {
+            if let Some(config) = &config {
+                assert!(
+                    config.token == self.stab_manager.address()
+                        || self.collaterals.get(&config.token).is_some(),
+                    "Marker deposit must be STAB or an accepted collateral."
+                );
+                assert!(config.amount > dec!(0), "Marker deposit must be positive.");
+                assert!(
+                    config.forfeit_share >= dec!(0) && config.forfeit_share <= dec!(1),
+                    "Forfeit share must be between 0 and 1."
+                );
+            }
+            self.parameters.marker_deposit = config;
+        }
+
         /// Set the minimum mintable amount of STAB (to prevent unprofitable liquidations)
         pub fn set_minimum_mint(&mut self, new_minimum_mint: Decimal) {
             self.parameters.minimum_mint = new_minimum_mint;
@@ -1756,375 +3111,3174 @@ mod stabilis_component {
             self.parameters.stabilis_liquidation_fine = stabilis_fine;
         }
 
-        /// Set the force mint multiplier
-        ///   - multiplier is used to calculate the minimum collateral ratio that will ever be reached through force minting
-        ///       - a multiplier of 2, and an mcr of 1.5 would mean the lowest collateralization ratio reached by forced minting would be 300%
-        pub fn set_force_mint_multiplier(&mut self, new_multiplier: Decimal) {
-            self.parameters.force_mint_cr_multiplier = new_multiplier;
-        }
-
-        /// Gets the internal price of the STAB token
-        pub fn return_internal_price(&self) -> Decimal {
-            self.internal_stab_price
+        /// Set availability, fee and maximum amount of loans / CDPs touched per call for redemptions
+        ///   - a fee of 0.005 would mean 0.5% of the redeemed collateral is put in the collateral treasury
+        pub fn set_redemption_parameters(
+            &mut self,
+            stop_redemptions: bool,
+            redemption_fee: Decimal,
+            max_redemption_cdps: u64,
+        ) {
+            assert!(
+                redemption_fee >= dec!(0) && redemption_fee < dec!(1),
+                "Redemption fee must be between 0 and 1."
+            );
+            self.parameters.stop_redemptions = stop_redemptions;
+            self.parameters.redemption_fee = redemption_fee;
+            self.parameters.max_redemption_cdps = max_redemption_cdps;
         }
 
-        /// Mints free STAB (used by the flash loan component, for instance)
-        pub fn free_stab(&mut self, amount: Decimal) -> Bucket {
-            self.stab_manager.mint(amount)
+        /// Set the buffer above the liquidation collateral ratio a partial liquidation restores a loan to
+        ///   - a buffer of 0.05 would mean a partially liquidated loan ends up at 1.05 times the liquidation collateral ratio
+        pub fn set_partial_liquidation_buffer(&mut self, new_buffer: Decimal) {
+            assert!(new_buffer >= dec!(0), "Buffer can't be negative.");
+            self.parameters.partial_liquidation_buffer = new_buffer;
         }
 
-        /// Burns STAB
-        pub fn burn_stab(&mut self, bucket: Bucket) {
+        /// Set the auction parameters
+        ///   - the discount starts at the start discount when the liquidation delay has passed, and increases by the discount increase every minute, until the max discount is reached
+        ///       - a start of 0, increase of 0.001 and max of 0.2 would mean the discount is 6% an hour after the auction started, and 20% after 200 minutes
+        pub fn set_auction_parameters(
+            &mut self,
+            start_discount: Decimal,
+            discount_increase: Decimal,
+            max_discount: Decimal,
+        ) {
             assert!(
-                bucket.resource_address() == self.stab_manager.address(),
-                "Can only burn STAB, not another token."
+                start_discount >= dec!(0)
+                    && discount_increase >= dec!(0)
+                    && max_discount >= start_discount,
+                "Invalid auction parameters."
             );
-            bucket.burn();
+            self.parameters.auction_start_discount = start_discount;
+            self.parameters.auction_discount_increase = discount_increase;
+            self.parameters.auction_max_discount = max_discount;
         }
 
-        /// Burns a used marker
-        pub fn burn_marker(&self, marker: Bucket) {
-            let data: CdpMarker = marker.as_non_fungible().non_fungible().data();
-            assert!(
-                self.cdp_marker_manager.address() == marker.resource_address(),
-                "Can only burn markers, not another token."
-            );
-            assert!(data.used, "Only used markers can be burned!");
-            marker.burn();
+        /// Set the liquidation mode of a collateral (fixed fines or auctions)
+        pub fn set_liquidation_mode(&mut self, collateral: ResourceAddress, mode: LiquidationMode) {
+            self.collaterals
+                .get_mut(&collateral)
+                .expect("Collateral not found.")
+                .liquidation_mode = mode;
         }
 
-        /// Burns a used loan receipt (has to be liquidated, closed or force liquidated, and have no collateral left)
-        pub fn burn_loan_receipt(&self, receipt: Bucket) {
-            let data: Cdp = receipt.as_non_fungible().non_fungible().data();
-            assert!(
-                self.cdp_manager.address() == receipt.resource_address(),
-                "Can only burn loan receipts, not another token."
-            );
+        /// Set the stability fee of a collateral, as interest per minute
+        ///   - a fee of 1.0000001 would mean debt grows by roughly 5.4% a year
+        ///   - the debt index is updated first, so the old fee applies until now
+        pub fn set_stability_fee(&mut self, collateral: ResourceAddress, new_fee: Decimal) {
+            assert!(new_fee >= dec!(1), "Stability fee can't be negative.");
             assert!(
-                data.status == CdpStatus::Liquidated
-                    || data.status == CdpStatus::ForceLiquidated
-                    || data.status == CdpStatus::Closed,
-                "Loan not closed or liquidated"
+                self.collaterals.get(&collateral).is_some(),
+                "Collateral not found."
             );
+            self.update_debt_index(collateral);
+            self.collaterals
+                .get_mut(&collateral)
+                .expect("Collateral not found.")
+                .stability_fee = new_fee;
+        }
+
+        /// Retrieve STAB from the surplus vault (collected stability fees)
+        pub fn retrieve_stab_surplus(&mut self, amount: Decimal) -> Bucket {
+            self.stab_surplus.take(amount)
+        }
+
+        /// Deposit STAB into the surplus buffer, used to cancel bad debt
+        ///   - funded by flash loan interest and liquidation fines sold for STAB
+        pub fn deposit_surplus(&mut self, stab: Bucket) {
             assert!(
-                data.collateral_amount == dec!(0),
-                "Retrieve all collateral before burning!"
+                stab.resource_address() == self.stab_manager.address(),
+                "Invalid STAB deposit."
             );
-            receipt.burn();
+
+            Runtime::emit_event(SurplusDepositedEvent {
+                amount: stab.amount(),
+                surplus_buffer: self.surplus_buffer.amount() + stab.amount(),
+            });
+
+            self.surplus_buffer.put(stab);
         }
 
-        //HELPER METHODS
+        /// Cancel bad debt by burning STAB from the surplus buffer
+        ///
+        /// # Input
+        /// - `amount`: The amount of bad debt to cancel (capped by the bad debt and the surplus buffer)
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Burn STAB from the surplus buffer
+        /// - Lower the bad debt and circulating STAB by the burned amount
+        pub fn cancel_bad_debt(&mut self, amount: Decimal) {
+            let amount: Decimal = amount.min(self.bad_debt).min(self.surplus_buffer.amount());
+            assert!(amount > dec!(0), "No bad debt to cancel, or the surplus buffer is empty.");
 
-        /// Try to liquidate a CDP / loan
+            self.surplus_buffer.take(amount).burn();
+            self.bad_debt -= amount;
+            self.circulating_stab -= amount;
+
+            Runtime::emit_event(BadDebtCancelledEvent {
+                amount,
+                bad_debt: self.bad_debt,
+                surplus_buffer: self.surplus_buffer.amount(),
+            });
+        }
+
+        /// Start a debt auction, selling tokens (newly minted ILIS) for STAB to cancel bad debt
         ///
         /// # Input
-        /// - `payment`: The STAB tokens to pay back
-        /// - `cdp_data`: The CDP data
-        /// - `marker_data`: The marker data
-        /// - `marker_id`: The marker receipt id
-        /// - `delay`: The delay until the loan can be liquidated from when it was marked
+        /// - `tokens`: The tokens to sell
+        /// - `starting_price`: The starting price of the tokens (in STAB)
+        /// - `price_decay`: The factor the price is multiplied by every minute (so 0.999 lowers the price by 0.1% per minute)
+        /// - `minimum_price`: The price under which the tokens are never sold (in STAB)
         ///
-        /// # Output, depends on outcome:
-        /// 1: liquidation successful
-        /// - The collateral reward
-        /// - The leftover STAB
-        /// - A liquidation receipt
-        /// 2: liquidation unsuccessful (because the loan was saved):
-        /// - The STAB tokens
+        /// # Output
         /// - None
-        /// - A liquidation receipt (with saved status)
         ///
         /// # Logic
-        /// - Get the liquidation collateral ratio
-        /// - Assert that liquidation is currently enabled, the marker is valid, the payment is sufficient, the time has passed, and the loan is marked
-        /// - Get the newest collateral ratio for the CDP
-        /// - Check whether the collateral ratio is sufficient, liquidate if not, save if it is
-        fn try_liquidate(
+        /// - Check no auction is running, there is bad debt, and the surplus buffer is empty
+        /// - Check the auction parameters
+        /// - Store the tokens, and start the auction
+        pub fn start_debt_auction(
             &mut self,
-            payment: Bucket,
-            cdp_data: Cdp,
-            marker_data: CdpMarker,
-            marker_id: NonFungibleLocalId,
-            delay: i64,
-        ) -> (Bucket, Option<Bucket>, Bucket) {
-            let liquidation_collateral_ratio = self
-                .collaterals
-                .get(&cdp_data.parent_address)
-                .unwrap()
-                .liquidation_collateral_ratio;
-
-            assert!(
-                !self.parameters.stop_liquidations,
-                "Not allowed to liquidate loans right now."
-            );
+            tokens: Bucket,
+            starting_price: Decimal,
+            price_decay: Decimal,
+            minimum_price: Decimal,
+        ) {
+            assert!(self.debt_auction.is_none(), "A debt auction is already running.");
+            assert!(self.bad_debt > dec!(0), "No bad debt to cover.");
             assert!(
-                !marker_data.used && marker_data.mark_type == CdpUpdate::Marked,
-                "Non-valid marker."
+                self.surplus_buffer.is_empty(),
+                "Surplus buffer not empty. Cancel bad debt with the buffer first."
             );
             assert!(
-                payment.amount() >= cdp_data.minted_stab,
-                "not enough STAB supplied to close completely"
+                price_decay > dec!(0) && price_decay <= dec!(1),
+                "Price decay must be between 0 and 1."
             );
-
             assert!(
-                Clock::current_time_is_at_or_after(
-                    marker_data.time_marked.add_minutes(delay).unwrap(),
-                    TimePrecision::Minute
-                ),
-                "Not yet able to liquidate, time now: {}, time marked: {}.",
-                Clock::current_time_rounded_to_minutes().seconds_since_unix_epoch,
-                marker_data.time_marked.seconds_since_unix_epoch
+                minimum_price > dec!(0) && starting_price >= minimum_price,
+                "Invalid auction prices."
             );
 
-            assert!(cdp_data.status == CdpStatus::Marked, "Loan not marked");
-
-            let cr: Decimal = self.pool_to_real(
-                cdp_data.collateral_amount,
-                cdp_data.collateral,
-                cdp_data.is_pool_unit_collateral,
-            ) / cdp_data.minted_stab;
+            let token_address: ResourceAddress = tokens.resource_address();
+            let tokens_amount: Decimal = tokens.amount();
 
-            if cr < liquidation_collateral_ratio {
-                let (liquidation_payment, remainder, receipt): (Bucket, Bucket, Bucket) =
-                    self.liquidate(payment, marker_data, marker_id, cdp_data, cr);
-                (liquidation_payment, Some(remainder), receipt)
-            } else {
-                let marker_receipt: Bucket = self.save(marker_data, cdp_data, cr);
-                (payment, None, marker_receipt)
+            match self.debt_auction_tokens.as_mut() {
+                Some(vault) => {
+                    assert!(
+                        vault.resource_address() == token_address,
+                        "Debt auctions always sell the same token."
+                    );
+                    vault.put(tokens);
+                }
+                None => self.debt_auction_tokens = Some(Vault::with_bucket(tokens)),
             }
+
+            self.debt_auction = Some(DebtAuction {
+                start_time: Clock::current_time_rounded_to_minutes(),
+                starting_price,
+                price_decay,
+                minimum_price,
+                stab_raised: dec!(0),
+            });
+
+            Runtime::emit_event(DebtAuctionStartedEvent {
+                token: token_address,
+                tokens_amount,
+                starting_price,
+                price_decay,
+                minimum_price,
+                bad_debt: self.bad_debt,
+            });
         }
 
-        /// Liquidate a loan / CDP
+        /// Buy tokens in the running debt auction
         ///
         /// # Input
-        /// - `payment`: The STAB tokens to pay back
-        /// - `marker_data`: The marker data
-        /// - `marker_id`: The marker receipt id
-        /// - `cdp_data`: The CDP data
-        /// - `cr`: The collateral ratio
+        /// - `payment`: The STAB to pay
         ///
         /// # Output
-        /// - The collateral reward
+        /// - The bought tokens
         /// - The leftover STAB
-        /// - A liquidation receipt
         ///
         /// # Logic
-        /// - Update minted STAB
-        /// - Update collateral amount of the parent address
-        /// - Calculate the minimum collateral ratio and the liquidation collateral ratio
-        /// - Create the liquidation receipt data
-        /// - Update the marker and CDP receipts
-        /// - Take the payment, check whether it's enough, and burn it
-        /// - Calculate the liquidations according to the cr
-        ///    - for calculation details, see code
-        /// - Make the liquidation receipt
-        /// - Handle calculated liquidations
-        /// - Update liquidated cdp
-        /// - Return the collateral reward, the leftover STAB and the liquidation receipt
-        fn liquidate(
-            &mut self,
-            mut payment: Bucket,
-            marker_data: CdpMarker,
-            marker_id: NonFungibleLocalId,
-            cdp_data: Cdp,
-            cr: Decimal,
-        ) -> (Bucket, Bucket, Bucket) {
-            self.update_minted_stab(
-                false,
-                cdp_data.is_pool_unit_collateral,
-                false,
-                cdp_data.minted_stab,
-                cdp_data.parent_address,
-                cdp_data.collateral,
+        /// - Calculate the current price
+        /// - Use as much of the payment as the bad debt and available tokens allow
+        /// - Burn the used STAB, lowering the bad debt and circulating STAB
+        /// - Return the bought tokens and leftover STAB
+        pub fn bid_debt_auction(&mut self, mut payment: Bucket) -> (Bucket, Bucket) {
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
             );
+            assert!(self.bad_debt > dec!(0), "No bad debt left to cover.");
 
-            self.collaterals
-                .get_mut(&cdp_data.parent_address)
-                .unwrap()
-                .collateral_amount -= cdp_data.collateral_stab_ratio * cdp_data.minted_stab;
-
-            let mcr: Decimal = self.collaterals.get(&cdp_data.parent_address).unwrap().mcr;
-            let liq_cr: Decimal = self
-                .collaterals
-                .get(&cdp_data.parent_address)
-                .unwrap()
-                .liquidation_collateral_ratio;
-            let mut treasury_payment_amount: Option<Decimal> = None;
-            let liquidation_payment_amount;
-            let mut liquidation_receipt = LiquidationReceipt {
-                collateral: cdp_data.collateral,
-                stab_paid: cdp_data.minted_stab,
-                percentage_owed: dec!(1) + self.parameters.liquidation_liquidation_fine,
-                percentage_received: dec!(1) + self.parameters.liquidation_liquidation_fine,
-                cdp_liquidated: marker_data.marked_id.clone(),
-                date_liquidated: Clock::current_time_rounded_to_minutes(),
-            };
+            let price: Decimal = self.debt_auction_price();
+            let tokens: &mut Vault = self.debt_auction_tokens.as_mut().unwrap();
 
-            self.liquidation_counter += 1;
+            let stab_used: Decimal = payment
+                .amount()
+                .min(self.bad_debt)
+                .min(tokens.amount() * price);
+            assert!(stab_used > dec!(0), "Nothing left to buy.");
 
-            self.marked_cdps.remove(&marker_data.marker_placing);
-            self.marked_cdps_active -= 1;
-            self.cdp_marker_manager
-                .update_non_fungible_data(&marker_id, "used", true);
-            self.cdp_manager.update_non_fungible_data(
-                &marker_data.marked_id,
-                "status",
-                CdpStatus::Liquidated,
+            let bought_tokens: Bucket = tokens.take_advanced(
+                stab_used / price,
+                WithdrawStrategy::Rounded(RoundingMode::ToZero),
             );
 
-            assert!(
-                cdp_data.minted_stab <= payment.amount(),
-                "Not enough STAB to liquidate."
-            );
-            let repayment: Bucket = payment.take(cdp_data.minted_stab);
-            repayment.burn();
+            payment.take(stab_used).burn();
+            self.bad_debt -= stab_used;
+            self.circulating_stab -= stab_used;
+            self.debt_auction.as_mut().unwrap().stab_raised += stab_used;
 
-            //calculate the cr percentage, just the cr in percentage of the minted stab value
-            //example: collateral value is $100, minted stab value is $80 -> cr = 100/80 = 1.25
-            let cr_percentage: Decimal = mcr * cr / liq_cr;
+            Runtime::emit_event(DebtAuctionBidEvent {
+                stab_paid: stab_used,
+                tokens_bought: bought_tokens.amount(),
+                price,
+                bad_debt: self.bad_debt,
+            });
 
-            //calculate liquidations depending on cr
-            //sit 1: cr > 1 + liquidation fine + stabilis fine   -> everyone can receive complete fines
-            //sit 2: cr > 1 + liquidation fine                   -> liquidator receives whole fine, stabilis a partial fine
-            //sit 3: cr <= 1                                     -> liquidator receives whole collateral, which might be less than minted stab
+            (bought_tokens, payment)
+        }
 
-            if cr_percentage
-                > dec!(1)
-                    + self.parameters.liquidation_liquidation_fine
-                    + self.parameters.stabilis_liquidation_fine
-            {
-                if self.parameters.stabilis_liquidation_fine > dec!(0) {
-                    treasury_payment_amount = Some(
-                        (self.parameters.stabilis_liquidation_fine)
-                            * (cdp_data.collateral_amount / cr_percentage),
-                    );
-                }
-                liquidation_payment_amount = (dec!(1)
-                    + self.parameters.liquidation_liquidation_fine)
-                    * (cdp_data.collateral_amount / cr_percentage);
-            } else if cr_percentage > dec!(1) + self.parameters.liquidation_liquidation_fine {
-                liquidation_payment_amount = (dec!(1)
-                    + self.parameters.liquidation_liquidation_fine)
-                    * (cdp_data.collateral_amount / cr_percentage);
+        /// End the running debt auction, returning the unsold tokens
+        pub fn end_debt_auction(&mut self) -> Bucket {
+            let auction: DebtAuction = self
+                .debt_auction
+                .take()
+                .expect("No debt auction is running.");
+            let unsold_tokens: Bucket = self.debt_auction_tokens.as_mut().unwrap().take_all();
+
+            Runtime::emit_event(DebtAuctionEndedEvent {
+                stab_raised: auction.stab_raised,
+                tokens_returned: unsold_tokens.amount(),
+                bad_debt: self.bad_debt,
+            });
+
+            unsold_tokens
+        }
 
-                treasury_payment_amount =
-                    Some(cdp_data.collateral_amount - liquidation_payment_amount);
-            } else {
-                liquidation_receipt.percentage_received = cr_percentage;
-                liquidation_payment_amount = cdp_data.collateral_amount;
-            }
+        /// Set the force mint multiplier
+        ///   - multiplier is used to calculate the minimum collateral ratio that will ever be reached through force minting
+        ///       - a multiplier of 2, and an mcr of 1.5 would mean the lowest collateralization ratio reached by forced minting would be 300%
+        pub fn set_force_mint_multiplier(&mut self, new_multiplier: Decimal) {
+            self.parameters.force_mint_cr_multiplier = new_multiplier;
+        }
 
-            let receipt: NonFungibleBucket = self
-                .liquidation_receipt_manager
-                .mint_non_fungible(
-                    &NonFungibleLocalId::integer(self.liquidation_counter),
-                    liquidation_receipt,
-                )
-                .as_non_fungible();
+        /// Gets the internal price of the STAB token
+        pub fn return_internal_price(&self) -> Decimal {
+            self.internal_stab_price
+        }
 
-            let treasury_payment = if let Some(payment_amount) = treasury_payment_amount {
-                Some(self.take_collateral(
-                    cdp_data.collateral,
-                    cdp_data.is_pool_unit_collateral,
-                    payment_amount,
-                ))
+        /// Gets the information of a collateral
+        pub fn get_collateral_info(&self, collateral: ResourceAddress) -> CollateralInfoView {
+            let info = self
+                .collaterals
+                .get(&collateral)
+                .expect("Collateral not found.");
+
+            let stab_share: Decimal = if self.circulating_stab > dec!(0) {
+                info.minted_stab / self.circulating_stab
             } else {
-                None
+                dec!(0)
             };
 
-            let liquidation_payment = self.take_collateral(
-                cdp_data.collateral,
-                cdp_data.is_pool_unit_collateral,
-                liquidation_payment_amount,
+            CollateralInfoView {
+                resource_address: info.resource_address,
+                usd_price: info.usd_price,
+                mcr: info.mcr,
+                liquidation_collateral_ratio: info.liquidation_collateral_ratio,
+                accepted: info.accepted,
+                liquidation_mode: info.liquidation_mode,
+                stability_fee: info.stability_fee,
+                debt_index: self.current_debt_index(collateral),
+                minted_stab: info.minted_stab,
+                stab_share,
+                max_stab_share: info.max_stab_share,
+                collateral_amount: info.collateral_amount,
+                vault_amount: info.vault.amount(),
+                treasury_amount: info.treasury.amount(),
+                highest_cr: info.highest_cr,
+                bad_debt: info.bad_debt,
+            }
+        }
+
+        /// Checks whether a collateral is a pool unit collateral
+        pub fn is_pool_unit(&self, collateral: ResourceAddress) -> bool {
+            self.pool_units.get(&collateral).is_some()
+        }
+
+        /// Gets the information of a pool unit collateral
+        pub fn get_pool_unit_info(&self, pool_unit: ResourceAddress) -> PoolUnitInfoView {
+            let info = self
+                .pool_units
+                .get(&pool_unit)
+                .expect("Pool unit not found.");
+
+            let redemption_value_per_unit: Decimal = self.pool_to_real(dec!(1), pool_unit, true);
+            let parent_minted_stab: Decimal = self
+                .collaterals
+                .get(&info.parent_address)
+                .unwrap()
+                .minted_stab;
+
+            let pool_address: Option<ComponentAddress> = match info.lsu {
+                true => info.validator.map(|validator| validator.address()),
+                false => info.one_resource_pool.map(|pool| pool.address()),
+            };
+
+            let pool_share: Decimal = if parent_minted_stab > dec!(0) {
+                info.minted_stab / parent_minted_stab
+            } else {
+                dec!(0)
+            };
+
+            PoolUnitInfoView {
+                address: info.address,
+                parent_address: info.parent_address,
+                lsu: info.lsu,
+                pool_address,
+                accepted: info.accepted,
+                minted_stab: info.minted_stab,
+                pool_share,
+                max_pool_share: info.max_pool_share,
+                vault_amount: info.vault.amount(),
+                treasury_amount: info.treasury.amount(),
+                redemption_value_per_unit,
+                indexed_loans: info.loan_count,
+            }
+        }
+
+        /// Gets the information of an LP collateral
+        pub fn get_lp_unit_info(&self, lp_address: ResourceAddress) -> LpUnitInfoView {
+            let info = self
+                .lp_units
+                .get(&lp_address)
+                .expect("LP collateral not found.");
+
+            let (pool_address, multi_resource): (ComponentAddress, bool) = match &info.pool {
+                LpPool::TwoResource(pool) => (pool.address(), false),
+                LpPool::MultiResource(pool) => (pool.address(), true),
+            };
+
+            LpUnitInfoView {
+                address: lp_address,
+                pool_address,
+                multi_resource,
+                redemption_value_per_unit: info.pool.get_redemption_value(dec!(1)),
+                usd_price: self.collaterals.get(&lp_address).unwrap().usd_price,
+            }
+        }
+
+        /// Gets the circuit breaker of a collateral (None if it has none)
+        pub fn get_circuit_breaker(&self, collateral: ResourceAddress) -> Option<CircuitBreaker> {
+            self.circuit_breakers
+                .get(&collateral)
+                .map(|breaker| breaker.clone())
+        }
+
+        /// Gets the protocol parameters
+        pub fn get_protocol_parameters(&self) -> ProtocolParameters {
+            self.parameters.clone()
+        }
+
+        /// Gets the loans / CDPs with the lowest collateral ratios for a collateral
+        ///
+        /// # Input
+        /// - `collateral`: The (parent) collateral to get the loans for
+        /// - `amount`: The maximum amount of loans to return
+        ///
+        /// # Output
+        /// - The collateral ratios and ids of the loans, sorted from lowest to highest collateral ratio
+        pub fn get_lowest_crs(
+            &self,
+            collateral: ResourceAddress,
+            amount: u64,
+        ) -> Vec<(Decimal, NonFungibleLocalId)> {
+            let mut lowest_crs: Vec<(Decimal, NonFungibleLocalId)> = Vec::new();
+
+            if let Some(collateral_ratios) = self.collateral_ratios.get(&collateral) {
+                'outer_loop: for (cr, collateral_ids, _next_key) in
+                    collateral_ratios.range(dec!(0)..)
+                {
+                    for collateral_id in collateral_ids.iter() {
+                        if lowest_crs.len() as u64 >= amount {
+                            break 'outer_loop;
+                        }
+                        lowest_crs.push((cr, collateral_id.clone()));
+                    }
+                }
+            }
+
+            lowest_crs
+        }
+
+        /// Gets the marked loans / CDPs, in order of marking
+        ///
+        /// # Input
+        /// - `amount`: The maximum amount of marked loans to return
+        ///
+        /// # Output
+        /// - The number of active marked loans
+        /// - The marker placings and ids of the marked loans
+        pub fn get_marked_cdps(&self, amount: u64) -> (u64, Vec<(Decimal, NonFungibleLocalId)>) {
+            let mut marked_cdps: Vec<(Decimal, NonFungibleLocalId)> = Vec::new();
+
+            for (marker_placing, collateral_id, _next_key) in self.marked_cdps.range(dec!(0)..) {
+                if marked_cdps.len() as u64 >= amount {
+                    break;
+                }
+                marked_cdps.push((marker_placing, collateral_id.clone()));
+            }
+
+            (self.marked_cdps_active, marked_cdps)
+        }
+
+        /// Gets a health summary of a loan / CDP
+        ///
+        /// # Input
+        /// - `cdp_id`: The id of the loan
+        ///
+        /// # Output
+        /// - The health summary of the loan
+        ///
+        /// # Logic
+        /// - Get the CDP data and calculate the current collateral ratio (as pool units aren't always up to date)
+        /// - Calculate the collateral value / minted STAB value
+        /// - Calculate the liquidation price, the parent collateral price at which the loan's collateral ratio equals the liquidation collateral ratio
+        pub fn get_cdp_health(&self, cdp_id: NonFungibleLocalId) -> CdpHealth {
+            let data: Cdp = self.cdp_manager.get_non_fungible_data(&cdp_id);
+
+            let pending_fee: Decimal = if data.status == CdpStatus::Healthy
+                || data.status == CdpStatus::Marked
+            {
+                data.minted_stab * self.current_debt_index(data.parent_address) / data.debt_index
+                    - data.minted_stab
+            } else {
+                dec!(0)
+            };
+
+            let real_collateral_amount: Decimal = self.pool_to_real(
+                data.collateral_amount,
+                data.collateral,
+                data.is_pool_unit_collateral,
+            );
+
+            let info = self.collaterals.get(&data.parent_address).unwrap();
+
+            let (current_cr, cr_percentage, liquidation_price): (Decimal, Decimal, Decimal) =
+                if data.minted_stab > dec!(0) && real_collateral_amount > dec!(0) {
+                    (
+                        real_collateral_amount / data.minted_stab,
+                        (real_collateral_amount * info.usd_price)
+                            / (data.minted_stab * self.internal_stab_price),
+                        (info.mcr * self.internal_stab_price * data.minted_stab)
+                            / real_collateral_amount,
+                    )
+                } else {
+                    (dec!(0), dec!(0), dec!(0))
+                };
+
+            let liquidatable: bool = (data.status == CdpStatus::Healthy
+                || data.status == CdpStatus::Marked)
+                && current_cr < info.liquidation_collateral_ratio;
+
+            CdpHealth {
+                cdp_id,
+                status: data.status,
+                collateral: data.collateral,
+                parent_address: data.parent_address,
+                collateral_amount: data.collateral_amount,
+                real_collateral_amount,
+                minted_stab: data.minted_stab,
+                pending_fee,
+                stored_cr: data.collateral_stab_ratio,
+                current_cr,
+                liquidation_collateral_ratio: info.liquidation_collateral_ratio,
+                cr_percentage,
+                liquidation_price,
+                liquidatable,
+            }
+        }
+
+        /// Gets the amount of STAB in the surplus vault (collected stability fees)
+        pub fn get_stab_surplus(&self) -> Decimal {
+            self.stab_surplus.amount()
+        }
+
+        /// Gets the bad debt that isn't cancelled yet
+        pub fn get_bad_debt(&self) -> Decimal {
+            self.bad_debt
+        }
+
+        /// Gets the amount of STAB in the surplus buffer
+        pub fn get_surplus_buffer(&self) -> Decimal {
+            self.surplus_buffer.amount()
+        }
+
+        /// Gets the state of the running debt auction (None if no auction is running)
+        pub fn get_debt_auction(&self) -> Option<DebtAuctionView> {
+            self.debt_auction.as_ref().map(|auction| DebtAuctionView {
+                token: self.debt_auction_tokens.as_ref().unwrap().resource_address(),
+                tokens_left: self.debt_auction_tokens.as_ref().unwrap().amount(),
+                start_time: auction.start_time,
+                current_price: self.debt_auction_price(),
+                minimum_price: auction.minimum_price,
+                stab_raised: auction.stab_raised,
+            })
+        }
+
+        /// Gets a health summary of a basket loan / CDP
+        ///
+        /// # Input
+        /// - `basket_id`: The id of the basket loan
+        ///
+        /// # Output
+        /// - The health summary of the basket loan (stability fees accrued since the last interaction aren't included yet)
+        pub fn get_basket_health(&self, basket_id: NonFungibleLocalId) -> BasketCdpHealth {
+            let data: BasketCdp = self.basket_manager.get_non_fungible_data(&basket_id);
+
+            let (collateral_value, borrowing_power): (Decimal, Decimal) =
+                self.basket_value(&data.collaterals);
+
+            let weighted_mcr: Decimal = if borrowing_power > dec!(0) {
+                collateral_value / borrowing_power
+            } else {
+                dec!(0)
+            };
+
+            let current_health: Decimal = self.basket_health(&data.collaterals, data.minted_stab);
+
+            let liquidatable: bool = (data.status == CdpStatus::Healthy
+                || data.status == CdpStatus::Marked)
+                && current_health < dec!(1);
+
+            BasketCdpHealth {
+                basket_id,
+                status: data.status,
+                collaterals: data.collaterals,
+                minted_stab: data.minted_stab,
+                collateral_value,
+                weighted_mcr,
+                stored_health: data.health,
+                current_health,
+                liquidatable,
+            }
+        }
+
+        /// Gets the basket loans / CDPs with the lowest stored health
+        ///
+        /// # Input
+        /// - `amount`: The maximum amount of basket loans to return
+        ///
+        /// # Output
+        /// - The stored healths and ids of the basket loans, sorted from lowest to highest health
+        ///    - Healths are stored at the last interaction, so prices might have changed the current ranking
+        pub fn get_lowest_basket_healths(&self, amount: u64) -> Vec<(Decimal, NonFungibleLocalId)> {
+            let mut lowest_healths: Vec<(Decimal, NonFungibleLocalId)> = Vec::new();
+
+            'outer_loop: for (health, basket_ids, _next_key) in
+                self.basket_healths.range(dec!(0)..)
+            {
+                for basket_id in basket_ids.iter() {
+                    if lowest_healths.len() as u64 >= amount {
+                        break 'outer_loop;
+                    }
+                    lowest_healths.push((health, basket_id.clone()));
+                }
+            }
+
+            lowest_healths
+        }
+
+        /// Gets the resource address of the basket loan receipts
+        pub fn get_basket_receipt_address(&self) -> ResourceAddress {
+            self.basket_manager.address()
+        }
+
+        /// Gets the state of the emergency shutdown (None if the protocol is running normally)
+        pub fn get_shutdown_state(&self) -> Option<ShutdownState> {
+            self.shutdown.clone()
+        }
+
+        /// Mints free STAB (used by the flash loan component, for instance)
+        pub fn free_stab(&mut self, amount: Decimal) -> Bucket {
+            self.stab_manager.mint(amount)
+        }
+
+        /// Mints STAB for the peg stability module, counting toward circulating STAB
+        pub fn psm_mint(&mut self, amount: Decimal) -> Bucket {
+            assert!(
+                self.shutdown.is_none(),
+                "Not allowed to mint STAB after an emergency shutdown."
+            );
+            self.psm_stab += amount;
+            self.circulating_stab += amount;
+            self.stab_manager.mint(amount)
+        }
+
+        /// Burns STAB redeemed through the peg stability module, reducing circulating STAB
+        pub fn psm_burn(&mut self, bucket: Bucket) {
+            assert!(
+                bucket.resource_address() == self.stab_manager.address(),
+                "Can only burn STAB, not another token."
+            );
+            assert!(
+                self.shutdown.as_ref().map_or(true, |shutdown| !shutdown.finalized),
+                "Not allowed to burn STAB after an emergency shutdown is finalized."
+            );
+            let amount: Decimal = bucket.amount();
+            assert!(
+                amount <= self.psm_stab,
+                "Can't burn more STAB than was minted by the peg stability module."
+            );
+            self.psm_stab -= amount;
+            self.circulating_stab -= amount;
+            bucket.burn();
+        }
+
+        /// Burns STAB
+        pub fn burn_stab(&mut self, bucket: Bucket) {
+            assert!(
+                bucket.resource_address() == self.stab_manager.address(),
+                "Can only burn STAB, not another token."
+            );
+            bucket.burn();
+        }
+
+        /// Burns a used marker
+        pub fn burn_marker(&self, marker: Bucket) {
+            let data: CdpMarker = marker.as_non_fungible().non_fungible().data();
+            assert!(
+                self.cdp_marker_manager.address() == marker.resource_address(),
+                "Can only burn markers, not another token."
+            );
+            assert!(data.used, "Only used markers can be burned!");
+            assert!(
+                data.deposit.map_or(true, |deposit| deposit.refunded),
+                "Retrieve the marker deposit before burning the marker!"
+            );
+            marker.burn();
+        }
+
+        /// Refund the deposit paid when marking a loan
+        ///
+        /// # Input
+        /// - `marker_id`: The id of the marker receipt (the proof is checked by the Proxy component)
+        ///
+        /// # Output
+        /// - The refunded deposit (minus the forfeited part, if the marked loan was saved)
+        ///
+        /// # Logic
+        /// - Check the marker has a deposit that hasn't been refunded yet
+        /// - Check the mark is settled: the loan was liquidated (or topped up / closed), or this is a saved marker
+        /// - Record the refund on the marker and return the deposit
+        pub fn retrieve_marker_deposit(&mut self, marker_id: NonFungibleLocalId) -> Bucket {
+            let data: CdpMarker = self.cdp_marker_manager.get_non_fungible_data(&marker_id);
+            let mut deposit: MarkerDeposit = data.deposit.expect("No deposit paid for this marker.");
+            assert!(!deposit.refunded, "Marker deposit already refunded.");
+            assert!(
+                data.used || data.mark_type == CdpUpdate::Saved,
+                "Marked loan not liquidated yet."
+            );
+
+            deposit.refunded = true;
+            let refund: Bucket = self
+                .marker_deposits
+                .get_mut(&deposit.token)
+                .unwrap()
+                .take(deposit.amount - deposit.forfeited);
+
+            Runtime::emit_event(MarkerDepositRefundedEvent {
+                marker_id: marker_id.clone(),
+                token: deposit.token,
+                refunded: refund.amount(),
+            });
+
+            self.cdp_marker_manager
+                .update_non_fungible_data(&marker_id, "deposit", Some(deposit));
+
+            refund
+        }
+
+        /// Burns a used loan receipt (has to be liquidated, closed, force liquidated, redeemed or settled, and have no collateral left)
+        pub fn burn_loan_receipt(&self, receipt: Bucket) {
+            let data: Cdp = receipt.as_non_fungible().non_fungible().data();
+            assert!(
+                self.cdp_manager.address() == receipt.resource_address(),
+                "Can only burn loan receipts, not another token."
+            );
+            assert!(
+                data.status == CdpStatus::Liquidated
+                    || data.status == CdpStatus::ForceLiquidated
+                    || data.status == CdpStatus::Closed
+                    || data.status == CdpStatus::Redeemed
+                    || data.status == CdpStatus::Settled,
+                "Loan not closed, liquidated, redeemed or settled"
+            );
+            assert!(
+                data.collateral_amount == dec!(0),
+                "Retrieve all collateral before burning!"
+            );
+            receipt.burn();
+        }
+
+        /// Borrow STAB by opening a basket loan / CDP, backed by several collaterals at once
+        ///
+        /// # Input
+        /// - `collaterals`: The collaterals to be used, one bucket per collateral
+        /// - `stab_to_mint`: The amount of STAB to mint
+        ///
+        /// # Output
+        /// - The minted STAB in a `Bucket`
+        /// - The basket receipt in a `Bucket`
+        ///
+        /// # Logic
+        /// - Check whether amount to mint > minimum mint and opening loans is allowed right now
+        /// - Check whether all collaterals are accepted and each collateral is only supplied once
+        /// - Check whether the basket is healthy: the summed collateral value must be >= minted STAB value * weighted MCR
+        /// - Insert the health into the basket AvlTree
+        /// - Attribute the debt to the collaterals pro rata by value, and check their max STAB shares
+        /// - Mint the basket receipt
+        /// - Store the collaterals in the correct vaults
+        /// - Return the minted STAB and the basket receipt
+        pub fn open_basket_cdp(
+            &mut self,
+            collaterals: Vec<Bucket>,
+            stab_to_mint: Decimal,
+        ) -> (Bucket, Bucket) {
+            assert!(
+                stab_to_mint >= self.parameters.minimum_mint,
+                "Minted STAB is less than the minimum required amount."
+            );
+            assert!(
+                !self.parameters.stop_openings,
+                "Not allowed to open loans right now."
+            );
+            assert!(!collaterals.is_empty(), "No collateral supplied.");
+
+            let mut amounts: IndexMap<ResourceAddress, Decimal> = index_map_new();
+
+            for collateral in collaterals.iter() {
+                self.check_basket_collateral(collateral.resource_address());
+                self.assert_price_not_paused(
+                    self.basket_parent_address(collateral.resource_address()),
+                );
+                assert!(
+                    amounts
+                        .insert(collateral.resource_address(), collateral.amount())
+                        .is_none(),
+                    "Supply each collateral in a single bucket."
+                );
+            }
+
+            let health: Decimal = self.basket_health(&amounts, stab_to_mint);
+            assert!(health >= dec!(1), "Collateral value too low.");
+
+            self.basket_counter += 1;
+            let basket_id: NonFungibleLocalId = NonFungibleLocalId::integer(self.basket_counter);
+
+            self.insert_basket_health(health, basket_id.clone());
+
+            let debt_attribution: IndexMap<ResourceAddress, (Decimal, Decimal)> =
+                self.attribute_basket_debt(&index_map_new(), &amounts, stab_to_mint, true);
+
+            let basket = BasketCdp {
+                collaterals: amounts.clone(),
+                minted_stab: stab_to_mint,
+                health,
+                status: CdpStatus::Healthy,
+                time_marked: None,
+                debt_indexes: self.basket_debt_indexes(&amounts),
+                debt_attribution,
+            };
+
+            let stab_tokens: Bucket = self.stab_manager.mint(stab_to_mint);
+
+            let basket_receipt: Bucket = self.basket_manager.mint_non_fungible(&basket_id, basket);
+
+            Runtime::emit_event(BasketCdpOpenedEvent {
+                basket_id,
+                collaterals: amounts,
+                minted_stab: stab_to_mint,
+                health,
+                internal_price: self.internal_stab_price,
+            });
+
+            for collateral in collaterals {
+                let pool: bool = self.pool_units.get(&collateral.resource_address()).is_some();
+                self.put_collateral(collateral.resource_address(), pool, collateral);
+            }
+
+            (stab_tokens, basket_receipt)
+        }
+
+        /// Add collateral to a basket loan / CDP
+        ///
+        /// # Input
+        /// - `basket_id`: The basket receipt
+        /// - `collaterals`: The collaterals to add (can be empty, to save a marked basket that's healthy again through price changes)
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Add accrued stability fees
+        /// - Check if the basket is healthy or marked
+        /// - Check if the collaterals are accepted, add them to the basket and store them in the correct vaults
+        /// - Calculate the new health
+        /// - If the basket was healthy, move it in the AvlTree
+        /// - If the basket was marked and is healthy now, it is saved and inserted into the AvlTree again
+        /// - Attribute the debt to the collaterals again
+        /// - Update the basket receipt
+        pub fn top_up_basket_cdp(&mut self, basket_id: NonFungibleLocalId, collaterals: Vec<Bucket>) {
+            let data: BasketCdp = self.accrue_basket_fee(&basket_id);
+
+            assert!(
+                data.status == CdpStatus::Healthy || data.status == CdpStatus::Marked,
+                "Basket loan not active."
+            );
+
+            let mut amounts: IndexMap<ResourceAddress, Decimal> = data.collaterals.clone();
+            let mut added: IndexMap<ResourceAddress, Decimal> = index_map_new();
+
+            for collateral in collaterals {
+                let address: ResourceAddress = collateral.resource_address();
+                self.check_basket_collateral(address);
+                *amounts.entry(address).or_insert(dec!(0)) += collateral.amount();
+                *added.entry(address).or_insert(dec!(0)) += collateral.amount();
+                let pool: bool = self.pool_units.get(&address).is_some();
+                self.put_collateral(address, pool, collateral);
+            }
+
+            let new_health: Decimal = self.basket_health(&amounts, data.minted_stab);
+            let was_marked: bool = data.status == CdpStatus::Marked;
+
+            if !was_marked {
+                self.remove_basket_health(data.health, basket_id.clone());
+                self.insert_basket_health(new_health, basket_id.clone());
+            } else if new_health >= dec!(1) {
+                self.insert_basket_health(new_health, basket_id.clone());
+                self.basket_manager
+                    .update_non_fungible_data(&basket_id, "status", CdpStatus::Healthy);
+                self.basket_manager.update_non_fungible_data(
+                    &basket_id,
+                    "time_marked",
+                    None::<Instant>,
+                );
+            }
+
+            let debt_attribution: IndexMap<ResourceAddress, (Decimal, Decimal)> = self
+                .attribute_basket_debt(&data.debt_attribution, &amounts, data.minted_stab, false);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "debt_attribution", debt_attribution);
+
+            let debt_indexes = self.basket_debt_indexes(&amounts);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "debt_indexes", debt_indexes);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "collaterals", amounts);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "health", new_health);
+
+            Runtime::emit_event(BasketCdpToppedUpEvent {
+                basket_id,
+                collaterals_added: added,
+                old_health: data.health,
+                new_health,
+                was_marked,
+            });
+        }
+
+        /// Remove collateral from a basket loan / CDP
+        ///
+        /// # Input
+        /// - `basket_id`: The basket receipt
+        /// - `collateral`: The collateral to remove
+        /// - `amount`: The amount of collateral to remove
+        ///
+        /// # Output
+        /// - The removed collateral
+        ///
+        /// # Logic
+        /// - Add accrued stability fees
+        /// - Check if the basket is healthy, closing is allowed and the basket holds enough of the collateral
+        /// - Check if the basket is still healthy after removing the collateral
+        /// - Move the basket in the AvlTree
+        /// - Attribute the debt to the remaining collaterals, and check their max STAB shares
+        /// - Update the basket receipt
+        /// - Return the removed collateral
+        pub fn remove_basket_collateral(
+            &mut self,
+            basket_id: NonFungibleLocalId,
+            collateral: ResourceAddress,
+            amount: Decimal,
+        ) -> Bucket {
+            let data: BasketCdp = self.accrue_basket_fee(&basket_id);
+
+            assert!(
+                !self.parameters.stop_closings,
+                "Not allowed to remove collateral right now."
+            );
+            assert!(
+                data.status == CdpStatus::Healthy,
+                "Basket loan not healthy. Can't remove collateral right now."
+            );
+
+            let mut amounts: IndexMap<ResourceAddress, Decimal> = data.collaterals.clone();
+            let held: Decimal = amounts.get(&collateral).copied().unwrap_or(dec!(0));
+
+            assert!(
+                amount > dec!(0) && amount <= held,
+                "Not enough of this collateral in the basket."
+            );
+
+            if amount == held {
+                amounts.shift_remove(&collateral);
+            } else {
+                amounts.insert(collateral, held - amount);
+            }
+
+            let new_health: Decimal = self.basket_health(&amounts, data.minted_stab);
+            assert!(new_health >= dec!(1), "Collateral value too low.");
+
+            self.remove_basket_health(data.health, basket_id.clone());
+            self.insert_basket_health(new_health, basket_id.clone());
+
+            let debt_attribution: IndexMap<ResourceAddress, (Decimal, Decimal)> = self
+                .attribute_basket_debt(&data.debt_attribution, &amounts, data.minted_stab, true);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "debt_attribution", debt_attribution);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "collaterals", amounts);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "health", new_health);
+
+            Runtime::emit_event(BasketCdpCollateralRemovedEvent {
+                basket_id,
+                collateral,
+                collateral_removed: amount,
+                old_health: data.health,
+                new_health,
+            });
+
+            let pool: bool = self.pool_units.get(&collateral).is_some();
+            self.take_collateral(collateral, pool, amount)
+        }
+
+        /// Borrow more STAB against a basket loan / CDP
+        ///
+        /// # Input
+        /// - `basket_id`: The basket receipt
+        /// - `amount`: The amount of extra STAB to borrow
+        ///
+        /// # Output
+        /// - The borrowed STAB
+        ///
+        /// # Logic
+        /// - Add accrued stability fees
+        /// - Check if the basket is healthy and opening loans is allowed right now
+        /// - Check none of the collaterals is paused by its circuit breaker
+        /// - Check if the basket is still healthy with the extra debt
+        /// - Move the basket in the AvlTree
+        /// - Attribute the new debt to the collaterals, and check their max STAB shares
+        /// - Update the basket receipt
+        /// - Mint and return the STAB
+        pub fn borrow_more_basket(&mut self, basket_id: NonFungibleLocalId, amount: Decimal) -> Bucket {
+            let data: BasketCdp = self.accrue_basket_fee(&basket_id);
+
+            assert!(
+                data.status == CdpStatus::Healthy,
+                "Basket loan not healthy. Can't borrow more right now."
+            );
+            assert!(
+                !self.parameters.stop_openings,
+                "Not allowed to open loans right now."
+            );
+            for address in data.collaterals.keys() {
+                self.assert_price_not_paused(self.basket_parent_address(*address));
+            }
+
+            let new_minted: Decimal = data.minted_stab + amount;
+            let new_health: Decimal = self.basket_health(&data.collaterals, new_minted);
+            assert!(new_health >= dec!(1), "Collateral value too low.");
+
+            self.remove_basket_health(data.health, basket_id.clone());
+            self.insert_basket_health(new_health, basket_id.clone());
+
+            let debt_attribution: IndexMap<ResourceAddress, (Decimal, Decimal)> = self
+                .attribute_basket_debt(&data.debt_attribution, &data.collaterals, new_minted, true);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "debt_attribution", debt_attribution);
+
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "minted_stab", new_minted);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "health", new_health);
+
+            Runtime::emit_event(BasketCdpBorrowedMoreEvent {
+                basket_id,
+                stab_borrowed: amount,
+                minted_stab: new_minted,
+                old_health: data.health,
+                new_health,
+                internal_price: self.internal_stab_price,
+            });
+
+            self.stab_manager.mint(amount)
+        }
+
+        /// Close a basket loan / CDP, by paying off the debt
+        ///
+        /// # Input
+        /// - `basket_id`: The basket receipt
+        /// - `stab_payment`: The STAB tokens to pay back
+        ///
+        /// # Output
+        /// - The collaterals returned
+        /// - The leftover STAB
+        ///
+        /// # Logic
+        /// - Add accrued stability fees
+        /// - Check if the basket is healthy, closing is allowed and the STAB payment is valid and large enough
+        /// - Burn the paid back STAB, and remove the debt attributed to the collaterals
+        /// - Remove the basket from the AvlTree
+        /// - Take all collaterals out of their vaults
+        /// - Update the basket receipt
+        /// - Return the collaterals and the leftover STAB
+        pub fn close_basket_cdp(
+            &mut self,
+            basket_id: NonFungibleLocalId,
+            mut stab_payment: Bucket,
+        ) -> (Vec<Bucket>, Bucket) {
+            let data: BasketCdp = self.accrue_basket_fee(&basket_id);
+
+            assert!(
+                !self.parameters.stop_closings,
+                "Not allowed to close loans right now."
+            );
+            assert!(
+                data.status == CdpStatus::Healthy,
+                "Basket loan not healthy. Can't close right now. In case of liquidation, retrieve collateral. Else, add collateral to save."
+            );
+            assert!(
+                stab_payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+            assert!(
+                stab_payment.amount() >= data.minted_stab,
+                "not enough STAB supplied to close completely"
+            );
+
+            stab_payment.take(data.minted_stab).burn();
+            self.attribute_basket_debt(&data.debt_attribution, &index_map_new(), dec!(0), false);
+
+            self.remove_basket_health(data.health, basket_id.clone());
+
+            let collaterals: Vec<Bucket> = self.take_basket_collaterals(&data.collaterals);
+
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "status", CdpStatus::Closed);
+            self.basket_manager.update_non_fungible_data(
+                &basket_id,
+                "collaterals",
+                index_map_new::<ResourceAddress, Decimal>(),
+            );
+            self.basket_manager.update_non_fungible_data(
+                &basket_id,
+                "debt_attribution",
+                index_map_new::<ResourceAddress, (Decimal, Decimal)>(),
+            );
+
+            Runtime::emit_event(BasketCdpClosedEvent {
+                basket_id,
+                collaterals_returned: data.collaterals,
+                stab_repaid: data.minted_stab,
+                internal_price: self.internal_stab_price,
+            });
+
+            (collaterals, stab_payment)
+        }
+
+        /// Mark a basket loan / CDP for liquidation
+        ///
+        /// # Input
+        /// - `basket_id`: The basket to mark, or None to take the basket with the lowest stored health
+        ///
+        /// # Output
+        /// - Whether the basket was marked
+        ///
+        /// # Logic
+        /// - Add accrued stability fees
+        /// - Check if the basket is healthy (not marked yet)
+        /// - Calculate the health using the latest prices, as prices of the separate collaterals move the basket through the AvlTree
+        /// - Remove the basket from the AvlTree
+        /// - If the health is below 1, mark the basket, starting the liquidation delay
+        /// - Otherwise, insert it into the AvlTree again using its latest health, so the AvlTree ranking is refreshed
+        pub fn mark_basket_for_liquidation(&mut self, basket_id: Option<NonFungibleLocalId>) -> bool {
+            let basket_id: NonFungibleLocalId = match basket_id {
+                Some(basket_id) => basket_id,
+                None => {
+                    let (_first_health, basket_ids, _next_key) = self
+                        .basket_healths
+                        .range(dec!(0)..)
+                        .next()
+                        .expect("No basket loans to mark.");
+                    basket_ids[0].clone()
+                }
+            };
+
+            let data: BasketCdp = self.accrue_basket_fee(&basket_id);
+
+            assert!(
+                data.status == CdpStatus::Healthy,
+                "Basket loan not active or already marked."
+            );
+            for address in data.collaterals.keys() {
+                self.assert_price_not_paused(self.basket_parent_address(*address));
+            }
+
+            let new_health: Decimal = self.basket_health(&data.collaterals, data.minted_stab);
+            let marked: bool = new_health < dec!(1);
+
+            self.remove_basket_health(data.health, basket_id.clone());
+
+            if marked {
+                self.basket_manager
+                    .update_non_fungible_data(&basket_id, "status", CdpStatus::Marked);
+                self.basket_manager.update_non_fungible_data(
+                    &basket_id,
+                    "time_marked",
+                    Some(Clock::current_time_rounded_to_minutes()),
+                );
+            } else {
+                self.insert_basket_health(new_health, basket_id.clone());
+            }
+
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "health", new_health);
+
+            Runtime::emit_event(BasketCdpMarkedEvent {
+                basket_id,
+                old_health: data.health,
+                new_health,
+                marked,
+                internal_price: self.internal_stab_price,
+            });
+
+            marked
+        }
+
+        /// Liquidate a marked basket loan / CDP, seizing each collateral pro rata
+        ///
+        /// # Input
+        /// - `basket_id`: The marked basket
+        /// - `payment`: The STAB tokens to pay back the debt (the complete debt, or the collateral value minus the liquidation fine if the basket is worth less)
+        ///
+        /// # Output
+        /// - The collaterals received by the liquidator
+        /// - The leftover STAB
+        ///
+        /// # Logic
+        /// - Add accrued stability fees
+        /// - Check if liquidations are allowed, the basket is marked, the liquidation delay has passed and the payment is valid
+        /// - Check if the basket is still unhealthy using the latest prices
+        /// - Calculate the share of every collateral for the liquidator and the treasury, using the summed collateral value
+        ///    - The liquidator receives debt value * (1 + liquidation fine), the treasury debt value * stabilis fine, if the collateral value allows
+        ///    - The same share of every collateral is taken, so the basket composition stays the same
+        /// - Burn the STAB debt, and remove the debt attributed to the collaterals
+        ///    - If the collaterals are worth less than the debt, only their value minus the liquidation fine is paid, the rest is recorded as bad debt
+        /// - Take the collaterals, putting the treasury part in the treasuries
+        /// - Update the basket receipt, so the borrower can retrieve the leftover collateral
+        /// - Return the collaterals and the leftover STAB
+        pub fn liquidate_basket(
+            &mut self,
+            basket_id: NonFungibleLocalId,
+            mut payment: Bucket,
+        ) -> (Vec<Bucket>, Bucket) {
+            let data: BasketCdp = self.accrue_basket_fee(&basket_id);
+
+            assert!(
+                !self.parameters.stop_liquidations,
+                "Not allowed to liquidate loans right now."
+            );
+            for address in data.collaterals.keys() {
+                self.assert_price_not_paused(self.basket_parent_address(*address));
+            }
+            assert!(data.status == CdpStatus::Marked, "Basket loan not marked");
+            assert!(
+                Clock::current_time_is_at_or_after(
+                    data.time_marked
+                        .unwrap()
+                        .add_minutes(self.parameters.liquidation_delay)
+                        .unwrap(),
+                    TimePrecision::Minute
+                ),
+                "Not yet able to liquidate, time now: {}, time marked: {}.",
+                Clock::current_time_rounded_to_minutes().seconds_since_unix_epoch,
+                data.time_marked.unwrap().seconds_since_unix_epoch
+            );
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+
+            let (collateral_value, borrowing_power): (Decimal, Decimal) =
+                self.basket_value(&data.collaterals);
+            let debt_value: Decimal = data.minted_stab * self.internal_stab_price;
+            let health: Decimal = borrowing_power / debt_value;
+
+            let stab_to_pay: Decimal = if collateral_value < debt_value {
+                collateral_value
+                    / self.internal_stab_price
+                    / (dec!(1) + self.parameters.liquidation_liquidation_fine)
+            } else {
+                data.minted_stab
+            };
+            assert!(
+                payment.amount() >= stab_to_pay,
+                "Not enough STAB to liquidate."
+            );
+
+            assert!(
+                health < dec!(1),
+                "Basket loan is healthy again. Top it up to save it."
+            );
+
+            let (liquidator_share, treasury_share): (Decimal, Decimal) =
+                if collateral_value > dec!(0) {
+                    let liquidator_share: Decimal = (debt_value
+                        * (dec!(1) + self.parameters.liquidation_liquidation_fine)
+                        / collateral_value)
+                        .min(dec!(1));
+                    let treasury_share: Decimal = (debt_value
+                        * self.parameters.stabilis_liquidation_fine
+                        / collateral_value)
+                        .min(dec!(1) - liquidator_share);
+                    (liquidator_share, treasury_share)
+                } else {
+                    (dec!(1), dec!(0))
+                };
+
+            payment.take(stab_to_pay).burn();
+            self.attribute_basket_debt(&data.debt_attribution, &index_map_new(), dec!(0), false);
+
+            if collateral_value < debt_value {
+                let shortfall: Decimal = data.minted_stab - stab_to_pay;
+                let collateral_count: Decimal = Decimal::from(data.collaterals.len() as u64);
+
+                for (address, amount) in data.collaterals.iter() {
+                    let parent_address: ResourceAddress = self.basket_parent_address(*address);
+                    let share: Decimal = if collateral_value > dec!(0) {
+                        let pool: bool = self.pool_units.get(address).is_some();
+                        self.pool_to_real(*amount, *address, pool)
+                            * self.collaterals.get(&parent_address).unwrap().usd_price
+                            / collateral_value
+                    } else {
+                        dec!(1) / collateral_count
+                    };
+                    self.record_bad_debt(parent_address, shortfall * share);
+                }
+            }
+
+            let mut liquidator_collaterals: Vec<Bucket> = Vec::new();
+            let mut to_liquidator: IndexMap<ResourceAddress, Decimal> = index_map_new();
+            let mut to_treasury: IndexMap<ResourceAddress, Decimal> = index_map_new();
+            let mut leftover: IndexMap<ResourceAddress, Decimal> = index_map_new();
+
+            for (address, amount) in data.collaterals.iter() {
+                let pool: bool = self.pool_units.get(address).is_some();
+
+                let liquidator_payment: Bucket =
+                    self.take_collateral(*address, pool, *amount * liquidator_share);
+                let treasury_payment: Bucket =
+                    self.take_collateral(*address, pool, *amount * treasury_share);
+
+                let leftover_amount: Decimal =
+                    *amount - liquidator_payment.amount() - treasury_payment.amount();
+
+                to_liquidator.insert(*address, liquidator_payment.amount());
+                to_treasury.insert(*address, treasury_payment.amount());
+                if leftover_amount > dec!(0) {
+                    leftover.insert(*address, leftover_amount);
+                }
+
+                self.put_collateral_in_treasury(*address, pool, treasury_payment);
+                liquidator_collaterals.push(liquidator_payment);
+            }
+
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "status", CdpStatus::Liquidated);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "time_marked", None::<Instant>);
+            self.basket_manager.update_non_fungible_data(
+                &basket_id,
+                "debt_attribution",
+                index_map_new::<ResourceAddress, (Decimal, Decimal)>(),
+            );
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "collaterals", leftover.clone());
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "health", health);
+
+            Runtime::emit_event(BasketCdpLiquidatedEvent {
+                basket_id,
+                stab_paid: stab_to_pay,
+                collateral_to_liquidator: to_liquidator,
+                collateral_to_treasury: to_treasury,
+                leftover_collateral: leftover,
+                health,
+                internal_price: self.internal_stab_price,
+            });
+
+            (liquidator_collaterals, payment)
+        }
+
+        /// Retrieve leftover collateral from a liquidated or settled basket loan / CDP
+        ///
+        /// # Input
+        /// - `basket_id`: The basket receipt
+        ///
+        /// # Output
+        /// - The leftover collaterals
+        ///
+        /// # Logic
+        /// - Check if the basket is liquidated or settled, has leftover collateral and closing is allowed right now
+        /// - Update the basket receipt to hold no collateral
+        /// - Return the leftover collaterals
+        pub fn retrieve_leftover_basket_collateral(
+            &mut self,
+            basket_id: NonFungibleLocalId,
+        ) -> Vec<Bucket> {
+            let data: BasketCdp = self.basket_manager.get_non_fungible_data(&basket_id);
+
+            assert!(
+                data.status == CdpStatus::Liquidated || data.status == CdpStatus::Settled,
+                "Basket loan not liquidated or settled"
+            );
+            assert!(!data.collaterals.is_empty(), "No collateral leftover");
+            assert!(
+                !self.parameters.stop_closings || data.status == CdpStatus::Settled,
+                "Not allowed to close loans right now."
+            );
+
+            self.basket_manager.update_non_fungible_data(
+                &basket_id,
+                "collaterals",
+                index_map_new::<ResourceAddress, Decimal>(),
+            );
+
+            self.take_basket_collaterals(&data.collaterals)
+        }
+
+        /// Trigger an emergency shutdown, starting the orderly wind-down of the protocol
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check the protocol isn't shut down already
+        /// - Freeze the internal price and collateral prices (they can't be changed anymore), and stop stability fees from accruing
+        /// - Stop openings, liquidations, force mints, force liquidations and redemptions (closing loans stays possible)
+        /// - Store the shutdown state, after which loans can be settled
+        pub fn emergency_shutdown(&mut self) {
+            assert!(self.shutdown.is_none(), "Protocol is shut down already.");
+
+            self.parameters.stop_openings = true;
+            self.parameters.stop_liquidations = true;
+            self.parameters.stop_force_mint = true;
+            self.parameters.stop_force_liquidate = true;
+            self.parameters.stop_redemptions = true;
+
+            self.shutdown = Some(ShutdownState {
+                time: Clock::current_time_rounded_to_minutes(),
+                finalized: false,
+                settlement_collateral: index_map_new(),
+                settlement_reserves: index_map_new(),
+                outstanding_stab: dec!(0),
+            });
+
+            Runtime::emit_event(EmergencyShutdownEvent {
+                time: Clock::current_time_rounded_to_minutes(),
+                internal_price: self.internal_stab_price,
+                circulating_stab: self.circulating_stab,
+            });
+        }
+
+        /// Settle a loan / CDP after an emergency shutdown (can be done for any loan, by anyone)
+        ///
+        /// # Input
+        /// - `cdp_id`: The loan to settle
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check the protocol is shut down and not finalized yet
+        /// - Add stability fees accrued until the shutdown
+        /// - Check if the loan is healthy or marked, and remove it from the AvlTree or the marked loans
+        /// - Calculate the collateral worth the debt, using the frozen prices
+        ///    - If the collateral is worth less than the debt, all collateral is taken
+        /// - Reserve that collateral for STAB holders, and remove the debt from circulating STAB
+        /// - Update the CDP receipt, so the borrower can retrieve the excess collateral using `retrieve_leftover_collateral`
+        pub fn settle_cdp(&mut self, cdp_id: NonFungibleLocalId) {
+            assert!(
+                self.shutdown.as_ref().is_some_and(|shutdown| !shutdown.finalized),
+                "Loans can only be settled after an emergency shutdown, before it is finalized."
+            );
+
+            let data: Cdp = self.accrue_stability_fee(&cdp_id);
+
+            if data.status == CdpStatus::Healthy {
+                self.remove_cr(
+                    data.parent_address,
+                    data.collateral_stab_ratio,
+                    cdp_id.clone(),
+                );
+            } else if data.status == CdpStatus::Marked {
+                let marker_id: NonFungibleLocalId = NonFungibleLocalId::integer(data.marker_id);
+                let marker_data: CdpMarker =
+                    self.cdp_marker_manager.get_non_fungible_data(&marker_id);
+                self.marked_cdps.remove(&marker_data.marker_placing);
+                self.marked_cdps_active -= 1;
+                self.cdp_marker_manager
+                    .update_non_fungible_data(&marker_id, "used", true);
+            } else {
+                panic!("Loan not active.");
+            }
+
+            let real_collateral_amount: Decimal = self.pool_to_real(
+                data.collateral_amount,
+                data.collateral,
+                data.is_pool_unit_collateral,
+            );
+            let collateral_price: Decimal = self
+                .collaterals
+                .get(&data.parent_address)
+                .unwrap()
+                .usd_price;
+            let debt_in_collateral: Decimal =
+                data.minted_stab * self.internal_stab_price / collateral_price;
+
+            let settled_collateral: Decimal = if real_collateral_amount > debt_in_collateral {
+                debt_in_collateral * data.collateral_amount / real_collateral_amount
+            } else {
+                data.collateral_amount
+            };
+            let leftover_collateral: Decimal = data.collateral_amount - settled_collateral;
+
+            self.collaterals
+                .get_mut(&data.parent_address)
+                .unwrap()
+                .collateral_amount -= data.collateral_stab_ratio * data.minted_stab;
+
+            self.update_minted_stab(
+                false,
+                data.is_pool_unit_collateral,
+                false,
+                data.minted_stab,
+                data.parent_address,
+                data.collateral,
+            );
+
+            *self
+                .shutdown
+                .as_mut()
+                .unwrap()
+                .settlement_collateral
+                .entry(data.collateral)
+                .or_insert(dec!(0)) += settled_collateral;
+
+            self.cdp_manager
+                .update_non_fungible_data(&cdp_id, "status", CdpStatus::Settled);
+            self.cdp_manager
+                .update_non_fungible_data(&cdp_id, "collateral_amount", leftover_collateral);
+
+            Runtime::emit_event(CdpSettledEvent {
+                cdp_id,
+                collateral: data.collateral,
+                stab_settled: data.minted_stab,
+                collateral_settled: settled_collateral,
+                leftover_collateral,
+                collateral_price,
+                internal_price: self.internal_stab_price,
+            });
+        }
+
+        /// Settle a basket loan / CDP after an emergency shutdown (can be done for any basket, by anyone)
+        ///
+        /// # Input
+        /// - `basket_id`: The basket to settle
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check the protocol is shut down and not finalized yet
+        /// - Add stability fees accrued until the shutdown
+        /// - Check if the basket is healthy or marked, and remove it from the basket AvlTree
+        /// - Reserve the same share of every collateral for STAB holders, so that the reserved collateral is worth the debt (or all collateral, if the basket is worth less)
+        /// - Remove the debt attributed to the collaterals (and so from circulating STAB)
+        /// - Update the basket receipt, so the borrower can retrieve the excess collateral using `retrieve_leftover_basket_collateral`
+        pub fn settle_basket_cdp(&mut self, basket_id: NonFungibleLocalId) {
+            assert!(
+                self.shutdown.as_ref().is_some_and(|shutdown| !shutdown.finalized),
+                "Loans can only be settled after an emergency shutdown, before it is finalized."
+            );
+
+            let data: BasketCdp = self.accrue_basket_fee(&basket_id);
+
+            assert!(
+                data.status == CdpStatus::Healthy || data.status == CdpStatus::Marked,
+                "Basket loan not active."
+            );
+
+            if data.status == CdpStatus::Healthy {
+                self.remove_basket_health(data.health, basket_id.clone());
+            }
+
+            let (collateral_value, _borrowing_power): (Decimal, Decimal) =
+                self.basket_value(&data.collaterals);
+            let debt_value: Decimal = data.minted_stab * self.internal_stab_price;
+
+            let settled_share: Decimal = if collateral_value > debt_value {
+                debt_value / collateral_value
+            } else {
+                dec!(1)
+            };
+
+            let mut settled: IndexMap<ResourceAddress, Decimal> = index_map_new();
+            let mut leftover: IndexMap<ResourceAddress, Decimal> = index_map_new();
+
+            for (address, amount) in data.collaterals.iter() {
+                let settled_amount: Decimal = *amount * settled_share;
+                *self
+                    .shutdown
+                    .as_mut()
+                    .unwrap()
+                    .settlement_collateral
+                    .entry(*address)
+                    .or_insert(dec!(0)) += settled_amount;
+                settled.insert(*address, settled_amount);
+                if *amount > settled_amount {
+                    leftover.insert(*address, *amount - settled_amount);
+                }
+            }
+
+            self.attribute_basket_debt(&data.debt_attribution, &index_map_new(), dec!(0), false);
+
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "status", CdpStatus::Settled);
+            self.basket_manager.update_non_fungible_data(
+                &basket_id,
+                "debt_attribution",
+                index_map_new::<ResourceAddress, (Decimal, Decimal)>(),
+            );
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "time_marked", None::<Instant>);
+            self.basket_manager
+                .update_non_fungible_data(&basket_id, "collaterals", leftover.clone());
+
+            Runtime::emit_event(BasketCdpSettledEvent {
+                basket_id,
+                stab_settled: data.minted_stab,
+                collateral_settled: settled,
+                leftover_collateral: leftover,
+                internal_price: self.internal_stab_price,
+            });
+        }
+
+        /// Finalize an emergency shutdown, after which STAB can be redeemed for the reserved collateral
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check the protocol is shut down and not finalized yet
+        /// - Check all loans are settled (no circulating STAB debt is left, except STAB minted by the peg stability module and bad debt, allowing for rounding dust)
+        /// - Burn the STAB surplus and surplus buffer, as the protocol doesn't redeem its own STAB
+        /// - Store the STAB supply, which can be redeemed pro rata for the reserved collateral and peg stability module reserves
+        pub fn finalize_shutdown(&mut self) {
+            assert!(
+                self.shutdown.as_ref().is_some_and(|shutdown| !shutdown.finalized),
+                "Protocol is not shut down, or the shutdown is finalized already."
+            );
+            assert!(
+                (self.circulating_stab - self.psm_stab - self.bad_debt)
+                    .checked_abs()
+                    .unwrap()
+                    <= dec!("0.000001"),
+                "Not all loans are settled yet."
+            );
+
+            self.stab_surplus.take_all().burn();
+            self.surplus_buffer.take_all().burn();
+
+            let outstanding_stab: Decimal = self.stab_manager.total_supply().unwrap();
+            let shutdown: &mut ShutdownState = self.shutdown.as_mut().unwrap();
+            shutdown.finalized = true;
+            shutdown.outstanding_stab = outstanding_stab;
+
+            Runtime::emit_event(ShutdownFinalizedEvent {
+                outstanding_stab,
+                settlement_collateral: shutdown.settlement_collateral.clone(),
+                settlement_reserves: shutdown.settlement_reserves.clone(),
+            });
+        }
+
+        /// Take over a reserve of the peg stability module during an emergency shutdown, so STAB minted by the PSM is backed by its reserves when redeeming
+        ///
+        /// # Input
+        /// - `reserve`: The stable assets of a PSM reserve
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check the protocol is shut down and not finalized yet
+        /// - Store the reserve, and add it to the reserves STAB can be redeemed for
+        pub fn settle_psm_reserve(&mut self, reserve: Bucket) {
+            assert!(
+                self.shutdown.as_ref().is_some_and(|shutdown| !shutdown.finalized),
+                "Reserves can only be settled after an emergency shutdown, before it is finalized."
+            );
+
+            let address: ResourceAddress = reserve.resource_address();
+            *self
+                .shutdown
+                .as_mut()
+                .unwrap()
+                .settlement_reserves
+                .entry(address)
+                .or_insert(dec!(0)) += reserve.amount();
+
+            if self.psm_reserves.get(&address).is_some() {
+                self.psm_reserves.get_mut(&address).unwrap().put(reserve);
+            } else {
+                self.psm_reserves.insert(address, Vault::with_bucket(reserve));
+            }
+        }
+
+        /// Redeem STAB for the collateral reserved during an emergency shutdown
+        ///
+        /// # Input
+        /// - `payment`: The STAB to redeem
+        ///
+        /// # Output
+        /// - The collaterals received, one bucket per reserved collateral
+        ///
+        /// # Logic
+        /// - Check the shutdown is finalized and the payment is valid
+        /// - For every reserved collateral and peg stability module reserve, take payment / outstanding STAB of what's left
+        /// - Update the reserved collateral, reserves and outstanding STAB, and burn the payment
+        /// - Return the collaterals, followed by the reserves
+        pub fn redeem_settlement(&mut self, payment: Bucket) -> Vec<Bucket> {
+            assert!(
+                self.shutdown.as_ref().is_some_and(|shutdown| shutdown.finalized),
+                "Emergency shutdown not finalized."
+            );
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+
+            let shutdown: ShutdownState = self.shutdown.clone().unwrap();
+            let share: Decimal = payment.amount() / shutdown.outstanding_stab;
+
+            let mut collaterals: Vec<Bucket> = Vec::new();
+            let mut new_settlement_collateral: IndexMap<ResourceAddress, Decimal> = index_map_new();
+            let mut returned: IndexMap<ResourceAddress, Decimal> = index_map_new();
+
+            for (address, amount) in shutdown.settlement_collateral.iter() {
+                let pool: bool = self.pool_units.get(address).is_some();
+                let collateral: Bucket = self.take_collateral(*address, pool, *amount * share);
+                new_settlement_collateral.insert(*address, *amount - collateral.amount());
+                returned.insert(*address, collateral.amount());
+                collaterals.push(collateral);
+            }
+
+            let mut new_settlement_reserves: IndexMap<ResourceAddress, Decimal> = index_map_new();
+
+            for (address, amount) in shutdown.settlement_reserves.iter() {
+                let reserve: Bucket = self.psm_reserves.get_mut(address).unwrap().take_advanced(
+                    *amount * share,
+                    WithdrawStrategy::Rounded(RoundingMode::ToZero),
+                );
+                new_settlement_reserves.insert(*address, *amount - reserve.amount());
+                *returned.entry(*address).or_insert(dec!(0)) += reserve.amount();
+                collaterals.push(reserve);
+            }
+
+            let stab_redeemed: Decimal = payment.amount();
+            payment.burn();
+
+            let shutdown_state: &mut ShutdownState = self.shutdown.as_mut().unwrap();
+            shutdown_state.settlement_collateral = new_settlement_collateral;
+            shutdown_state.settlement_reserves = new_settlement_reserves;
+            shutdown_state.outstanding_stab -= stab_redeemed;
+
+            Runtime::emit_event(SettlementRedemptionEvent {
+                stab_redeemed,
+                collateral_returned: returned,
+            });
+
+            collaterals
+        }
+
+        //HELPER METHODS
+
+        /// Liquidate a marked loan / CDP using a marker receipt, completely or partially
+        fn liquidate_with_marker(
+            &mut self,
+            marker_id: NonFungibleLocalId,
+            payment: Bucket,
+            partial: bool,
+        ) -> (Bucket, Option<Bucket>, Bucket) {
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+            let marker_data: CdpMarker = self.cdp_marker_manager.get_non_fungible_data(&marker_id);
+
+            let cdp_data: Cdp = self.accrue_stability_fee(&marker_data.marked_id);
+
+            self.try_liquidate(
+                payment,
+                cdp_data,
+                marker_data,
+                marker_id,
+                self.parameters.liquidation_delay,
+                partial,
+            )
+        }
+
+        /// Liquidate a marked loan / CDP without a marker receipt, completely or partially
+        fn liquidate_without_marker(
+            &mut self,
+            payment: Bucket,
+            skip: Option<i64>,
+            cdp_id: NonFungibleLocalId,
+            partial: bool,
+        ) -> (Bucket, Option<Bucket>, Bucket) {
+            assert!(
+                payment.resource_address() == self.stab_manager.address(),
+                "Invalid STAB payment."
+            );
+            let mut collateral_id: NonFungibleLocalId = cdp_id;
+            let mut skip_counter: i64 = 0;
+            let mut found: bool = false;
+
+            if let Some(skip) = skip {
+                for (_identifier, found_collateral_id, _next_key) in
+                    self.marked_cdps.range(dec!(0)..)
+                {
+                    collateral_id = found_collateral_id.clone();
+                    skip_counter += 1;
+                    if (skip_counter - 1) == skip {
+                        found = true;
+                        break;
+                    }
+                }
+                if skip_counter == 0 {
+                    panic!("No loans available to liquidate.");
+                } else if !found {
+                    panic!(
+                        "Too many skipped. Skip a maximum of {} loans.",
+                        skip_counter - 1
+                    );
+                }
+            }
+
+            let cdp_data: Cdp = self.accrue_stability_fee(&collateral_id);
+            let marker_data: CdpMarker = self
+                .cdp_marker_manager
+                .get_non_fungible_data(&NonFungibleLocalId::integer(cdp_data.marker_id));
+
+            let marker_id: NonFungibleLocalId = NonFungibleLocalId::integer(cdp_data.marker_id);
+
+            self.try_liquidate(
+                payment,
+                cdp_data,
+                marker_data,
+                marker_id,
+                self.parameters.liquidation_delay + self.parameters.unmarked_delay,
+                partial,
+            )
+        }
+
+        /// Try to liquidate a CDP / loan
+        ///
+        /// # Input
+        /// - `payment`: The STAB tokens to pay back
+        /// - `cdp_data`: The CDP data
+        /// - `marker_data`: The marker data
+        /// - `marker_id`: The marker receipt id
+        /// - `delay`: The delay until the loan can be liquidated from when it was marked
+        /// - `partial`: Whether to only liquidate the part of the loan needed to restore it to a healthy collateral ratio
+        ///
+        /// # Output, depends on outcome:
+        /// 1: liquidation successful
+        /// - The collateral reward
+        /// - The leftover STAB
+        /// - A liquidation receipt
+        /// 2: liquidation unsuccessful (because the loan was saved):
+        /// - The STAB tokens
+        /// - None
+        /// - A liquidation receipt (with saved status)
+        ///
+        /// # Logic
+        /// - Get the liquidation collateral ratio
+        /// - Assert that liquidation is currently enabled, the marker is valid, the payment is sufficient, the time has passed, and the loan is marked
+        /// - Get the newest collateral ratio for the CDP
+        /// - Check whether the collateral ratio is sufficient, liquidate if not, save if it is
+        ///    - If partial, calculate the STAB to repay to restore the loan, and liquidate partially if possible, completely if not
+        fn try_liquidate(
+            &mut self,
+            payment: Bucket,
+            cdp_data: Cdp,
+            marker_data: CdpMarker,
+            marker_id: NonFungibleLocalId,
+            delay: i64,
+            partial: bool,
+        ) -> (Bucket, Option<Bucket>, Bucket) {
+            let liquidation_collateral_ratio = self
+                .collaterals
+                .get(&cdp_data.parent_address)
+                .unwrap()
+                .liquidation_collateral_ratio;
+
+            assert!(
+                !self.parameters.stop_liquidations,
+                "Not allowed to liquidate loans right now."
+            );
+            self.assert_price_not_paused(cdp_data.parent_address);
+            assert!(
+                !marker_data.used && marker_data.mark_type == CdpUpdate::Marked,
+                "Non-valid marker."
+            );
+            if !partial {
+                assert!(
+                    payment.amount() >= cdp_data.minted_stab,
+                    "not enough STAB supplied to close completely"
+                );
+            }
+
+            assert!(
+                Clock::current_time_is_at_or_after(
+                    marker_data.time_marked.add_minutes(delay).unwrap(),
+                    TimePrecision::Minute
+                ),
+                "Not yet able to liquidate, time now: {}, time marked: {}.",
+                Clock::current_time_rounded_to_minutes().seconds_since_unix_epoch,
+                marker_data.time_marked.seconds_since_unix_epoch
+            );
+
+            assert!(cdp_data.status == CdpStatus::Marked, "Loan not marked");
+
+            let cr: Decimal = self.pool_to_real(
+                cdp_data.collateral_amount,
+                cdp_data.collateral,
+                cdp_data.is_pool_unit_collateral,
+            ) / cdp_data.minted_stab;
+
+            if cr < liquidation_collateral_ratio {
+                assert!(
+                    self.collaterals
+                        .get(&cdp_data.parent_address)
+                        .unwrap()
+                        .liquidation_mode
+                        == LiquidationMode::FixedFine,
+                    "This collateral is liquidated through auctions. Fill the auction instead."
+                );
+
+                let partial_stab: Option<Decimal> = match partial {
+                    true => self.partial_liquidation_amount(&cdp_data, cr),
+                    false => None,
+                };
+
+                let (liquidation_payment, remainder, receipt): (Bucket, Bucket, Bucket) =
+                    if let Some(stab_to_repay) = partial_stab {
+                        self.partial_liquidate(
+                            payment,
+                            marker_data,
+                            marker_id,
+                            cdp_data,
+                            cr,
+                            stab_to_repay,
+                        )
+                    } else {
+                        assert!(
+                            payment.amount() >= cdp_data.minted_stab,
+                            "Partial liquidation not possible, not enough STAB supplied to liquidate completely"
+                        );
+                        self.liquidate(payment, marker_data, marker_id, cdp_data, cr)
+                    };
+                (liquidation_payment, Some(remainder), receipt)
+            } else {
+                let marker_receipt: Bucket = self.save(marker_data, cdp_data, cr, false);
+                (payment, None, marker_receipt)
+            }
+        }
+
+        /// Liquidate a loan / CDP
+        ///
+        /// # Input
+        /// - `payment`: The STAB tokens to pay back
+        /// - `marker_data`: The marker data
+        /// - `marker_id`: The marker receipt id
+        /// - `cdp_data`: The CDP data
+        /// - `cr`: The collateral ratio
+        ///
+        /// # Output
+        /// - The collateral reward
+        /// - The leftover STAB
+        /// - A liquidation receipt
+        ///
+        /// # Logic
+        /// - Update minted STAB
+        /// - Update collateral amount of the parent address
+        /// - Calculate the minimum collateral ratio and the liquidation collateral ratio
+        /// - Create the liquidation receipt data
+        /// - Update the marker and CDP receipts
+        /// - Take the payment, check whether it's enough, and burn it
+        ///    - If the collateral is worth less than the debt, only its value minus the liquidation fine is paid, the rest is recorded as bad debt
+        /// - Calculate the liquidations according to the cr
+        ///    - for calculation details, see code
+        /// - Make the liquidation receipt
+        /// - Handle calculated liquidations
+        /// - Update liquidated cdp
+        /// - Return the collateral reward, the leftover STAB and the liquidation receipt
+        fn liquidate(
+            &mut self,
+            mut payment: Bucket,
+            marker_data: CdpMarker,
+            marker_id: NonFungibleLocalId,
+            cdp_data: Cdp,
+            cr: Decimal,
+        ) -> (Bucket, Bucket, Bucket) {
+            self.update_minted_stab(
+                false,
+                cdp_data.is_pool_unit_collateral,
+                false,
+                cdp_data.minted_stab,
+                cdp_data.parent_address,
+                cdp_data.collateral,
+            );
+
+            self.collaterals
+                .get_mut(&cdp_data.parent_address)
+                .unwrap()
+                .collateral_amount -= cdp_data.collateral_stab_ratio * cdp_data.minted_stab;
+
+            let mcr: Decimal = self.collaterals.get(&cdp_data.parent_address).unwrap().mcr;
+            let liq_cr: Decimal = self
+                .collaterals
+                .get(&cdp_data.parent_address)
+                .unwrap()
+                .liquidation_collateral_ratio;
+            let mut treasury_payment_amount: Option<Decimal> = None;
+            let liquidation_payment_amount;
+            let mut liquidation_receipt = LiquidationReceipt {
+                collateral: cdp_data.collateral,
+                stab_paid: cdp_data.minted_stab,
+                percentage_owed: dec!(1) + self.parameters.liquidation_liquidation_fine,
+                percentage_received: dec!(1) + self.parameters.liquidation_liquidation_fine,
+                cdp_liquidated: marker_data.marked_id.clone(),
+                date_liquidated: Clock::current_time_rounded_to_minutes(),
+                partial: false,
+                remaining_stab: dec!(0),
+            };
+
+            self.liquidation_counter += 1;
+
+            self.marked_cdps.remove(&marker_data.marker_placing);
+            self.marked_cdps_active -= 1;
+            self.cdp_marker_manager
+                .update_non_fungible_data(&marker_id, "used", true);
+            self.cdp_manager.update_non_fungible_data(
+                &marker_data.marked_id,
+                "status",
+                CdpStatus::Liquidated,
+            );
+
+            //calculate the cr percentage, just the cr in percentage of the minted stab value
+            //example: collateral value is $100, minted stab value is $80 -> cr = 100/80 = 1.25
+            let cr_percentage: Decimal = mcr * cr / liq_cr;
+
+            //if the collateral is worth less than the debt, the liquidator pays the collateral value minus the liquidation fine
+            //the rest of the debt isn't paid by the liquidator, but stays in circulation as bad debt
+            let stab_to_pay: Decimal = if cr_percentage < dec!(1) {
+                cdp_data.minted_stab * cr_percentage
+                    / (dec!(1) + self.parameters.liquidation_liquidation_fine)
+            } else {
+                cdp_data.minted_stab
+            };
+            liquidation_receipt.stab_paid = stab_to_pay;
+
+            assert!(
+                stab_to_pay <= payment.amount(),
+                "Not enough STAB to liquidate."
+            );
+            let repayment: Bucket = payment.take(stab_to_pay);
+            repayment.burn();
+
+            //calculate liquidations depending on cr
+            //sit 1: cr > 1 + liquidation fine + stabilis fine   -> everyone can receive complete fines
+            //sit 2: cr > 1 + liquidation fine                   -> liquidator receives whole fine, stabilis a partial fine
+            //sit 3: cr <= 1 + liquidation fine                  -> liquidator receives whole collateral, which might be less than minted stab
+            //                                                      (below a cr of 1 the liquidator pays less, so it still receives the complete fine)
+
+            if cr_percentage
+                > dec!(1)
+                    + self.parameters.liquidation_liquidation_fine
+                    + self.parameters.stabilis_liquidation_fine
+            {
+                if self.parameters.stabilis_liquidation_fine > dec!(0) {
+                    treasury_payment_amount = Some(
+                        (self.parameters.stabilis_liquidation_fine)
+                            * (cdp_data.collateral_amount / cr_percentage),
+                    );
+                }
+                liquidation_payment_amount = (dec!(1)
+                    + self.parameters.liquidation_liquidation_fine)
+                    * (cdp_data.collateral_amount / cr_percentage);
+            } else if cr_percentage > dec!(1) + self.parameters.liquidation_liquidation_fine {
+                liquidation_payment_amount = (dec!(1)
+                    + self.parameters.liquidation_liquidation_fine)
+                    * (cdp_data.collateral_amount / cr_percentage);
+
+                treasury_payment_amount =
+                    Some(cdp_data.collateral_amount - liquidation_payment_amount);
+            } else {
+                liquidation_payment_amount = cdp_data.collateral_amount;
+
+                if cr_percentage >= dec!(1) {
+                    liquidation_receipt.percentage_received = cr_percentage;
+                } else {
+                    self.record_bad_debt(
+                        cdp_data.parent_address,
+                        cdp_data.minted_stab - stab_to_pay,
+                    );
+                }
+            }
+
+            let receipt: NonFungibleBucket = self
+                .liquidation_receipt_manager
+                .mint_non_fungible(
+                    &NonFungibleLocalId::integer(self.liquidation_counter),
+                    liquidation_receipt,
+                )
+                .as_non_fungible();
+
+            let treasury_payment = if let Some(payment_amount) = treasury_payment_amount {
+                Some(self.take_collateral(
+                    cdp_data.collateral,
+                    cdp_data.is_pool_unit_collateral,
+                    payment_amount,
+                ))
+            } else {
+                None
+            };
+
+            let liquidation_payment = self.take_collateral(
+                cdp_data.collateral,
+                cdp_data.is_pool_unit_collateral,
+                liquidation_payment_amount,
+            );
+
+            let leftover_collateral: Decimal = cdp_data.collateral_amount
+                - liquidation_payment.amount()
+                - treasury_payment
+                    .as_ref()
+                    .map_or(dec!(0), |payment_bucket| payment_bucket.amount());
+
+            self.cdp_manager.update_non_fungible_data(
+                &marker_data.marked_id,
+                "collateral_amount",
+                leftover_collateral,
+            );
+
+            Runtime::emit_event(CdpLiquidatedEvent {
+                cdp_id: marker_data.marked_id.clone(),
+                marker_id: marker_id.clone(),
+                liquidation_receipt_id: NonFungibleLocalId::integer(self.liquidation_counter),
+                collateral: cdp_data.collateral,
+                stab_paid: stab_to_pay,
+                collateral_to_liquidator: liquidation_payment.amount(),
+                collateral_to_treasury: treasury_payment
+                    .as_ref()
+                    .map_or(dec!(0), |payment_bucket| payment_bucket.amount()),
+                leftover_collateral,
+                cr,
+                partial: false,
+                collateral_price: self
+                    .collaterals
+                    .get(&cdp_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
+            if let Some(payment) = treasury_payment {
+                self.put_collateral_in_treasury(
+                    cdp_data.collateral,
+                    cdp_data.is_pool_unit_collateral,
+                    payment,
+                );
+            }
+
+            (liquidation_payment, payment, receipt.into())
+        }
+
+        /// Calculate the STAB that has to be repaid to restore a loan / CDP to the liquidation collateral ratio plus a buffer
+        ///
+        /// # Input
+        /// - `cdp_data`: The CDP data
+        /// - `cr`: The latest collateral ratio of the loan
+        ///
+        /// # Output
+        /// - The STAB to repay, or None if a partial liquidation isn't possible
+        ///
+        /// # Logic
+        /// - For repaying x STAB, the liquidator and protocol receive x * (1 + fines) STAB worth of collateral
+        /// - 1 STAB is worth LCR / MCR collateral, so the collateral taken is x * (1 + fines) * LCR / MCR
+        /// - Solving (collateral - collateral taken) / (debt - x) = target CR gives:
+        ///    - x = (target CR * debt - collateral) / (target CR - (1 + fines) * LCR / MCR)
+        /// - A partial liquidation isn't possible if the loan can't pay the complete fines, if the target can't be reached, or if the leftover debt would be below the minimum mint
+        fn partial_liquidation_amount(&self, cdp_data: &Cdp, cr: Decimal) -> Option<Decimal> {
+            let mcr: Decimal = self.collaterals.get(&cdp_data.parent_address).unwrap().mcr;
+            let liq_cr: Decimal = self
+                .collaterals
+                .get(&cdp_data.parent_address)
+                .unwrap()
+                .liquidation_collateral_ratio;
+
+            let fines: Decimal = dec!(1)
+                + self.parameters.liquidation_liquidation_fine
+                + self.parameters.stabilis_liquidation_fine;
+            let collateral_per_stab: Decimal = liq_cr / mcr;
+            let target_cr: Decimal = liq_cr * (dec!(1) + self.parameters.partial_liquidation_buffer);
+            let cr_percentage: Decimal = mcr * cr / liq_cr;
+
+            if cr_percentage <= fines || target_cr <= collateral_per_stab * fines {
+                return None;
+            }
+
+            let stab_to_repay: Decimal = (target_cr * cdp_data.minted_stab
+                - cr * cdp_data.minted_stab)
+                / (target_cr - collateral_per_stab * fines);
+
+            if stab_to_repay <= dec!(0)
+                || cdp_data.minted_stab - stab_to_repay < self.parameters.minimum_mint
+            {
+                None
+            } else {
+                Some(stab_to_repay)
+            }
+        }
+
+        /// Partially liquidate a loan / CDP
+        ///
+        /// # Input
+        /// - `payment`: The STAB tokens to pay back
+        /// - `marker_data`: The marker data
+        /// - `marker_id`: The marker receipt id
+        /// - `cdp_data`: The CDP data
+        /// - `cr`: The collateral ratio
+        /// - `stab_to_repay`: The STAB to repay, calculated by `partial_liquidation_amount`
+        ///
+        /// # Output
+        /// - The collateral reward
+        /// - The leftover STAB
+        /// - A liquidation receipt (with partial status)
+        ///
+        /// # Logic
+        /// - Take the repayment and burn it, update minted STAB
+        /// - Calculate the collateral for the liquidator and the treasury (repaid STAB's value plus fines)
+        /// - Update the marker receipt to used and remove the loan from the marked loans
+        /// - Take the collateral
+        /// - Update the CDP to a healthy state, with the new debt, collateral and collateral ratio
+        /// - Insert the new collateral ratio into the AvlTree
+        /// - Make the liquidation receipt
+        /// - Return the collateral reward, the leftover STAB and the liquidation receipt
+        fn partial_liquidate(
+            &mut self,
+            mut payment: Bucket,
+            marker_data: CdpMarker,
+            marker_id: NonFungibleLocalId,
+            cdp_data: Cdp,
+            cr: Decimal,
+            stab_to_repay: Decimal,
+        ) -> (Bucket, Bucket, Bucket) {
+            assert!(
+                stab_to_repay <= payment.amount(),
+                "Not enough STAB to partially liquidate."
+            );
+
+            let mcr: Decimal = self.collaterals.get(&cdp_data.parent_address).unwrap().mcr;
+            let liq_cr: Decimal = self
+                .collaterals
+                .get(&cdp_data.parent_address)
+                .unwrap()
+                .liquidation_collateral_ratio;
+            let collateral_per_stab: Decimal = liq_cr / mcr;
+            let real_collateral_amount: Decimal = cr * cdp_data.minted_stab;
+
+            payment.take(stab_to_repay).burn();
+
+            self.update_minted_stab(
+                false,
+                cdp_data.is_pool_unit_collateral,
+                false,
+                stab_to_repay,
+                cdp_data.parent_address,
+                cdp_data.collateral,
+            );
+
+            //collateral is taken in the loan's own collateral (which might be a pool unit), so the real amounts are converted
+            let liquidation_payment_amount: Decimal = cdp_data.collateral_amount
+                * (stab_to_repay
+                    * collateral_per_stab
+                    * (dec!(1) + self.parameters.liquidation_liquidation_fine))
+                / real_collateral_amount;
+            let treasury_payment_amount: Decimal = cdp_data.collateral_amount
+                * (stab_to_repay * collateral_per_stab * self.parameters.stabilis_liquidation_fine)
+                / real_collateral_amount;
+
+            self.liquidation_counter += 1;
+
+            self.marked_cdps.remove(&marker_data.marker_placing);
+            self.marked_cdps_active -= 1;
+            self.cdp_marker_manager
+                .update_non_fungible_data(&marker_id, "used", true);
+
+            let liquidation_payment: Bucket = self.take_collateral(
+                cdp_data.collateral,
+                cdp_data.is_pool_unit_collateral,
+                liquidation_payment_amount,
+            );
+
+            let treasury_payment: Option<Bucket> = if treasury_payment_amount > dec!(0) {
+                Some(self.take_collateral(
+                    cdp_data.collateral,
+                    cdp_data.is_pool_unit_collateral,
+                    treasury_payment_amount,
+                ))
+            } else {
+                None
+            };
+
+            let treasury_payment_taken: Decimal = treasury_payment
+                .as_ref()
+                .map_or(dec!(0), |payment_bucket| payment_bucket.amount());
+
+            let new_collateral_amount: Decimal =
+                cdp_data.collateral_amount - liquidation_payment.amount() - treasury_payment_taken;
+            let new_stab_amount: Decimal = cdp_data.minted_stab - stab_to_repay;
+
+            let new_cr: Decimal = self.pool_to_real(
+                new_collateral_amount,
+                cdp_data.collateral,
+                cdp_data.is_pool_unit_collateral,
+            ) / new_stab_amount;
+
+            assert!(
+                new_cr >= liq_cr,
+                "Partial liquidation would not restore the loan."
+            );
+
+            self.collaterals
+                .get_mut(&cdp_data.parent_address)
+                .unwrap()
+                .collateral_amount +=
+                new_cr * new_stab_amount - cdp_data.collateral_stab_ratio * cdp_data.minted_stab;
+
+            self.insert_cr(
+                cdp_data.parent_address,
+                new_cr,
+                marker_data.marked_id.clone(),
             );
 
-            let leftover_collateral: Decimal = cdp_data.collateral_amount
-                - liquidation_payment.amount()
-                - treasury_payment
-                    .as_ref()
-                    .map_or(dec!(0), |payment_bucket| payment_bucket.amount());
+            self.cdp_manager.update_non_fungible_data(
+                &marker_data.marked_id,
+                "status",
+                CdpStatus::Healthy,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &marker_data.marked_id,
+                "collateral_amount",
+                new_collateral_amount,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &marker_data.marked_id,
+                "minted_stab",
+                new_stab_amount,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &marker_data.marked_id,
+                "collateral_stab_ratio",
+                new_cr,
+            );
+
+            let liquidation_receipt = LiquidationReceipt {
+                collateral: cdp_data.collateral,
+                stab_paid: stab_to_repay,
+                percentage_owed: dec!(1) + self.parameters.liquidation_liquidation_fine,
+                percentage_received: dec!(1) + self.parameters.liquidation_liquidation_fine,
+                cdp_liquidated: marker_data.marked_id.clone(),
+                date_liquidated: Clock::current_time_rounded_to_minutes(),
+                partial: true,
+                remaining_stab: new_stab_amount,
+            };
+
+            let receipt: NonFungibleBucket = self
+                .liquidation_receipt_manager
+                .mint_non_fungible(
+                    &NonFungibleLocalId::integer(self.liquidation_counter),
+                    liquidation_receipt,
+                )
+                .as_non_fungible();
+
+            Runtime::emit_event(CdpLiquidatedEvent {
+                cdp_id: marker_data.marked_id.clone(),
+                marker_id: marker_id.clone(),
+                liquidation_receipt_id: NonFungibleLocalId::integer(self.liquidation_counter),
+                collateral: cdp_data.collateral,
+                stab_paid: stab_to_repay,
+                collateral_to_liquidator: liquidation_payment.amount(),
+                collateral_to_treasury: treasury_payment_taken,
+                leftover_collateral: new_collateral_amount,
+                cr,
+                partial: true,
+                collateral_price: self
+                    .collaterals
+                    .get(&cdp_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
+            if let Some(payment) = treasury_payment {
+                self.put_collateral_in_treasury(
+                    cdp_data.collateral,
+                    cdp_data.is_pool_unit_collateral,
+                    payment,
+                );
+            }
+
+            (liquidation_payment, payment, receipt.into())
+        }
+
+        /// Calculate the current discount of a liquidation auction
+        ///   - the auction starts when the liquidation delay has passed since marking
+        ///   - the discount then increases every minute, until the maximum discount is reached
+        fn auction_discount(&self, marker_data: &CdpMarker) -> Decimal {
+            let minutes_since_start: i64 = (Clock::current_time_rounded_to_minutes()
+                .seconds_since_unix_epoch
+                - marker_data.time_marked.seconds_since_unix_epoch)
+                / 60
+                - self.parameters.liquidation_delay;
+
+            if minutes_since_start <= 0 {
+                return self.parameters.auction_start_discount;
+            }
+
+            let discount: Decimal = self.parameters.auction_start_discount
+                + self.parameters.auction_discount_increase * Decimal::from(minutes_since_start);
+
+            if discount > self.parameters.auction_max_discount {
+                self.parameters.auction_max_discount
+            } else {
+                discount
+            }
+        }
+
+        /// Mark a loan for liquidation, saving it immediately if its recalculated collateral ratio is healthy
+        ///
+        /// # Input
+        /// - `collateral_id`: The id of the loan to mark
+        /// - `data`: The data of the loan, with the stability fee accrued
+        /// - `cr`: The recalculated collateral ratio of the loan
+        /// - `deposit`: The marker deposit, if governance requires one
+        ///
+        /// # Output
+        /// - The marker receipt in a `Bucket` (a saved marker receipt if the loan was saved)
+        fn mark(
+            &mut self,
+            collateral_id: NonFungibleLocalId,
+            data: Cdp,
+            cr: Decimal,
+            deposit: Option<Bucket>,
+        ) -> Bucket {
+            let collateral: ResourceAddress = data.parent_address;
+            self.assert_price_not_paused(collateral);
+            let marker_deposit: Option<MarkerDeposit> = self.take_marker_deposit(deposit);
+
+            self.collaterals
+                .get_mut(&data.parent_address)
+                .unwrap()
+                .collateral_amount += (cr - data.collateral_stab_ratio) * data.minted_stab;
+
+            self.marker_placing_counter += dec!(1);
+            self.cdp_marker_counter += 1;
+            let id: Decimal = self.marker_placing_counter;
+
+            let marker = CdpMarker {
+                mark_type: CdpUpdate::Marked,
+                time_marked: Clock::current_time_rounded_to_minutes(),
+                marked_id: collateral_id.clone(),
+                marker_placing: self.marker_placing_counter,
+                saved_by_own_mark: false,
+                used: false,
+                deposit: marker_deposit,
+            };
+
+            self.marked_cdps.insert(id, collateral_id.clone());
+            self.marked_cdps_active += 1;
+
+            self.remove_cr(
+                data.parent_address,
+                data.collateral_stab_ratio,
+                collateral_id.clone(),
+            );
+
+            let marker_receipt_success: NonFungibleBucket = self
+                .cdp_marker_manager
+                .mint_non_fungible(
+                    &NonFungibleLocalId::integer(self.cdp_marker_counter),
+                    marker,
+                )
+                .as_non_fungible();
+
+            self.cdp_manager.update_non_fungible_data(
+                &collateral_id,
+                "marker_id",
+                self.cdp_marker_counter,
+            );
+            self.cdp_manager
+                .update_non_fungible_data(&collateral_id, "status", CdpStatus::Marked);
+
+            let mut cdp_ids: Vec<NonFungibleLocalId> = Vec::new();
+
+            if self
+                .collateral_ratios
+                .get_mut(&collateral)
+                .unwrap()
+                .get_mut(&cr)
+                .is_some()
+            {
+                cdp_ids = self
+                    .collateral_ratios
+                    .get_mut(&collateral)
+                    .unwrap()
+                    .get_mut(&cr)
+                    .unwrap()
+                    .to_vec();
+            }
+
+            if (cr
+                > self
+                    .collaterals
+                    .get(&collateral)
+                    .unwrap()
+                    .liquidation_collateral_ratio)
+                && cdp_ids.len() < self.parameters.max_vector_length.try_into().unwrap()
+            {
+                let marker_data: CdpMarker = marker_receipt_success.non_fungible().data();
+                marker_receipt_success.burn();
+                let cdp_data: Cdp = self.cdp_manager.get_non_fungible_data(&collateral_id);
+                self.save(marker_data, cdp_data, cr, true)
+            } else {
+                //only a loan that stays marked emits a marked event, a loan saved right away only emits a saved event
+                Runtime::emit_event(CdpMarkedEvent {
+                    cdp_id: collateral_id.clone(),
+                    marker_id: NonFungibleLocalId::integer(self.cdp_marker_counter),
+                    collateral: data.collateral,
+                    old_cr: data.collateral_stab_ratio,
+                    new_cr: cr,
+                    collateral_price: self.collaterals.get(&collateral).unwrap().usd_price,
+                    internal_price: self.internal_stab_price,
+                });
+
+                marker_receipt_success.into()
+            }
+        }
+
+        /// Save a loan / CDP
+        ///
+        /// # Input
+        /// - `marker_data`: The marker data
+        /// - `cdp_data`: The CDP data
+        /// - `cr`: The collateral ratio
+        /// - `deposit_to_saved_marker`: Whether the marker deposit moves to the saved marker (when the marker itself is saving, as its marker receipt is burned), which is then flagged as saved by its own mark
+        ///
+        /// # Output
+        /// - A bucket with the liquidation receipt (a receipt specifying the loan was saved)
+        ///
+        /// # Logic
+        /// - Update the collateral amount of the parent address
+        /// - Forfeit part of the marker deposit to the treasury
+        /// - Create a marker for the savior
+        /// - Create a liquidation receipt with saved status
+        /// - Update the CDP to a healthy state and collateral ratio
+        /// - Update the marker receipt to used
+        /// - Insert the healthy CDP again
+        /// - Return the liquidation receipt
+        fn save(
+            &mut self,
+            marker_data: CdpMarker,
+            cdp_data: Cdp,
+            cr: Decimal,
+            deposit_to_saved_marker: bool,
+        ) -> Bucket {
+            self.collaterals
+                .get_mut(&cdp_data.parent_address)
+                .unwrap()
+                .collateral_amount += (cr - cdp_data.collateral_stab_ratio) * cdp_data.minted_stab;
+
+            let deposit: Option<MarkerDeposit> = marker_data
+                .deposit
+                .clone()
+                .map(|deposit| self.forfeit_marker_deposit(deposit, cdp_data.marker_id));
+
+            self.marker_placing_counter += dec!(1);
+            self.cdp_marker_counter += 1;
+
+            let marker = CdpMarker {
+                mark_type: CdpUpdate::Saved,
+                time_marked: Clock::current_time_rounded_to_minutes(),
+                marked_id: marker_data.marked_id.clone(),
+                marker_placing: self.marker_placing_counter,
+                saved_by_own_mark: deposit_to_saved_marker,
+                used: false,
+                deposit: match deposit_to_saved_marker {
+                    true => deposit.clone(),
+                    false => None,
+                },
+            };
+
+            let marker_receipt: NonFungibleBucket = self
+                .cdp_marker_manager
+                .mint_non_fungible(
+                    &NonFungibleLocalId::integer(self.cdp_marker_counter),
+                    marker,
+                )
+                .as_non_fungible();
 
+            self.marked_cdps.remove(&marker_data.marker_placing);
+            self.marked_cdps_active -= 1;
             self.cdp_manager.update_non_fungible_data(
                 &marker_data.marked_id,
-                "collateral_amount",
-                leftover_collateral,
+                "status",
+                CdpStatus::Healthy,
+            );
+            self.cdp_manager.update_non_fungible_data(
+                &marker_data.marked_id,
+                "collateral_stab_ratio",
+                cr,
             );
+            self.cdp_marker_manager.update_non_fungible_data(
+                &NonFungibleLocalId::integer(cdp_data.marker_id),
+                "used",
+                true,
+            );
+            if deposit.is_some() && !deposit_to_saved_marker {
+                self.cdp_marker_manager.update_non_fungible_data(
+                    &NonFungibleLocalId::integer(cdp_data.marker_id),
+                    "deposit",
+                    deposit,
+                );
+            }
 
-            if let Some(payment) = treasury_payment {
-                self.put_collateral_in_treasury(
-                    cdp_data.collateral,
-                    cdp_data.is_pool_unit_collateral,
-                    payment,
+            self.insert_cr(cdp_data.parent_address, cr, marker_data.marked_id.clone());
+
+            Runtime::emit_event(CdpSavedEvent {
+                cdp_id: marker_data.marked_id,
+                marker_id: NonFungibleLocalId::integer(cdp_data.marker_id),
+                saved_marker_id: NonFungibleLocalId::integer(self.cdp_marker_counter),
+                collateral: cdp_data.collateral,
+                old_cr: cdp_data.collateral_stab_ratio,
+                new_cr: cr,
+                collateral_price: self
+                    .collaterals
+                    .get(&cdp_data.parent_address)
+                    .unwrap()
+                    .usd_price,
+                internal_price: self.internal_stab_price,
+            });
+
+            marker_receipt.into()
+        }
+
+        /// Take the marker deposit required by governance, returning the deposit data to store on the marker
+        fn take_marker_deposit(&mut self, deposit: Option<Bucket>) -> Option<MarkerDeposit> {
+            let config: MarkerDepositConfig = match (&self.parameters.marker_deposit, &deposit) {
+                (None, None) => return None,
+                (None, Some(_)) => panic!("No marker deposit required."),
+                (Some(_), None) => panic!("Marker deposit required."),
+                (Some(config), Some(_)) => config.clone(),
+            };
+
+            let deposit: Bucket = deposit.unwrap();
+            assert!(
+                deposit.resource_address() == config.token,
+                "Invalid marker deposit token."
+            );
+            assert!(deposit.amount() >= config.amount, "Marker deposit too small.");
+
+            let amount: Decimal = deposit.amount();
+            if self.marker_deposits.get(&config.token).is_some() {
+                self.marker_deposits
+                    .get_mut(&config.token)
+                    .unwrap()
+                    .put(deposit);
+            } else {
+                self.marker_deposits
+                    .insert(config.token, Vault::with_bucket(deposit));
+            }
+
+            Some(MarkerDeposit {
+                token: config.token,
+                amount,
+                forfeited: dec!(0),
+                refunded: false,
+            })
+        }
+
+        /// Forfeit a share of a marker deposit to the treasury (STAB to the STAB surplus, collateral to its treasury), because the marked loan was saved
+        fn forfeit_marker_deposit(&mut self, mut deposit: MarkerDeposit, marker_id: u64) -> MarkerDeposit {
+            let forfeit_share: Decimal = self
+                .parameters
+                .marker_deposit
+                .as_ref()
+                .map_or(dec!(0), |config| config.forfeit_share);
+            let forfeited: Bucket = self
+                .marker_deposits
+                .get_mut(&deposit.token)
+                .unwrap()
+                .take_advanced(
+                    deposit.amount * forfeit_share,
+                    WithdrawStrategy::Rounded(RoundingMode::ToZero),
+                );
+            deposit.forfeited = forfeited.amount();
+
+            Runtime::emit_event(MarkerDepositForfeitedEvent {
+                marker_id: NonFungibleLocalId::integer(marker_id),
+                token: deposit.token,
+                deposit: deposit.amount,
+                forfeited: deposit.forfeited,
+            });
+
+            if deposit.token == self.stab_manager.address() {
+                self.stab_surplus.put(forfeited);
+            } else {
+                self.put_collateral_in_treasury(deposit.token, false, forfeited);
+            }
+
+            deposit
+        }
+
+        /// Calculate the debt index of a collateral at the current time, without storing it
+        fn current_debt_index(&self, collateral: ResourceAddress) -> Decimal {
+            let info = self.collaterals.get(&collateral).unwrap();
+            let passed_minutes: Decimal = Decimal::from(
+                (self.fee_time().seconds_since_unix_epoch
+                    - info.last_fee_update.seconds_since_unix_epoch)
+                    / 60,
+            );
+
+            if passed_minutes <= dec!(0) || info.stability_fee == dec!(1) {
+                info.debt_index
+            } else {
+                info.debt_index * info.stability_fee.pow(passed_minutes).unwrap()
+            }
+        }
+
+        /// Update the debt index of a collateral to the current time
+        fn update_debt_index(&mut self, collateral: ResourceAddress) {
+            let new_index: Decimal = self.current_debt_index(collateral);
+            let fee_time: Instant = self.fee_time();
+            let mut info = self.collaterals.get_mut(&collateral).unwrap();
+            info.debt_index = new_index;
+            info.last_fee_update = fee_time;
+        }
+
+        /// Get the time up to which stability fees are charged (frozen at the time of an emergency shutdown)
+        fn fee_time(&self) -> Instant {
+            match &self.shutdown {
+                Some(shutdown) => shutdown.time,
+                None => Clock::current_time_rounded_to_minutes(),
+            }
+        }
+
+        /// Add accrued stability fees to the debt of a loan / CDP
+        ///
+        /// # Input
+        /// - `cdp_id`: The id of the loan
+        ///
+        /// # Output
+        /// - The updated CDP data
+        ///
+        /// # Logic
+        /// - Update the debt index of the collateral
+        /// - If the loan is active, calculate the new debt using the growth of the debt index since the loan's last interaction
+        /// - Mint the fee as STAB into the surplus vault, and update minted STAB
+        /// - Keep the stored collateral (collateral ratio * minted STAB) equal, so the new collateral ratio is lower
+        ///     - If the loan is healthy, move it in the AvlTree
+        /// - Update the CDP receipt
+        fn accrue_stability_fee(&mut self, cdp_id: &NonFungibleLocalId) -> Cdp {
+            let data: Cdp = self.cdp_manager.get_non_fungible_data(cdp_id);
+
+            if data.status != CdpStatus::Healthy && data.status != CdpStatus::Marked {
+                return data;
+            }
+
+            self.update_debt_index(data.parent_address);
+            let debt_index: Decimal = self.collaterals.get(&data.parent_address).unwrap().debt_index;
+
+            if debt_index == data.debt_index {
+                return data;
+            }
+
+            let new_stab_amount: Decimal = data.minted_stab * debt_index / data.debt_index;
+            let fee: Decimal = new_stab_amount - data.minted_stab;
+
+            self.cdp_manager
+                .update_non_fungible_data(cdp_id, "debt_index", debt_index);
+
+            if fee <= dec!(0) {
+                return self.cdp_manager.get_non_fungible_data(cdp_id);
+            }
+
+            let new_cr: Decimal = data.collateral_stab_ratio * data.minted_stab / new_stab_amount;
+
+            if data.status == CdpStatus::Healthy {
+                self.remove_cr(
+                    data.parent_address,
+                    data.collateral_stab_ratio,
+                    cdp_id.clone(),
+                );
+                self.insert_cr(data.parent_address, new_cr, cdp_id.clone());
+            }
+
+            self.update_minted_stab(
+                true,
+                data.is_pool_unit_collateral,
+                false,
+                fee,
+                data.parent_address,
+                data.collateral,
+            );
+            self.stab_surplus.put(self.stab_manager.mint(fee));
+
+            self.cdp_manager
+                .update_non_fungible_data(cdp_id, "minted_stab", new_stab_amount);
+            self.cdp_manager
+                .update_non_fungible_data(cdp_id, "collateral_stab_ratio", new_cr);
+
+            Runtime::emit_event(StabilityFeeAccruedEvent {
+                cdp_id: cdp_id.clone(),
+                collateral: data.collateral,
+                fee,
+                minted_stab: new_stab_amount,
+                debt_index,
+            });
+
+            self.cdp_manager.get_non_fungible_data(cdp_id)
+        }
+
+        /// Check whether a collateral (or pool unit) is accepted to be used in a basket loan / CDP
+        fn check_basket_collateral(&self, collateral: ResourceAddress) {
+            let accepted: bool = match self.pool_units.get(&collateral) {
+                Some(pool_unit) => pool_unit.accepted,
+                None => self
+                    .collaterals
+                    .get(&collateral)
+                    .map(|c| c.accepted)
+                    .unwrap_or(false),
+            };
+            assert!(accepted, "This collateral is not accepted");
+        }
+
+        /// Get the parent address of a collateral (only differs from the collateral in the case of a pool unit)
+        fn basket_parent_address(&self, collateral: ResourceAddress) -> ResourceAddress {
+            match self.pool_units.get(&collateral) {
+                Some(pool_unit) => pool_unit.parent_address,
+                None => collateral,
+            }
+        }
+
+        /// Add a loan to the index of loans using a pool unit (walked by `refresh_pool_unit_crs`), if it isn't indexed yet
+        fn index_pool_unit_loan(&mut self, pool_unit: ResourceAddress, cdp_id: NonFungibleLocalId) {
+            let mut info = self.pool_units.get_mut(&pool_unit).unwrap();
+            if info.loan_positions.get(&cdp_id).is_some() {
+                return;
+            }
+
+            info.loan_count += 1;
+            let position: u64 = info.loan_count;
+            info.loans.insert(position, cdp_id.clone());
+            info.loan_positions.insert(cdp_id, position);
+        }
+
+        /// Remove a loan from the index of loans using a pool unit, moving the last indexed loan to its position
+        fn unindex_pool_unit_loan(&mut self, pool_unit: ResourceAddress, cdp_id: NonFungibleLocalId) {
+            let mut info = self.pool_units.get_mut(&pool_unit).unwrap();
+            let position: u64 = match info.loan_positions.get(&cdp_id) {
+                Some(position) => *position,
+                None => return,
+            };
+
+            let last_position: u64 = info.loan_count;
+            let last_id: NonFungibleLocalId = info.loans.get(&last_position).unwrap().clone();
+
+            if position != last_position {
+                info.loans.insert(position, last_id.clone());
+                info.loan_positions.insert(last_id, position);
+            }
+
+            info.loans.remove(&last_position);
+            info.loan_positions.remove(&cdp_id);
+            info.loan_count -= 1;
+        }
+
+        /// Calculate the value of the collaterals of a basket loan / CDP
+        ///
+        /// # Output
+        /// - The summed USD value of all collaterals
+        /// - The borrowing power: the sum of each collateral's USD value / MCR
+        ///    - So the weighted MCR of the basket is collateral value / borrowing power
+        fn basket_value(&self, collaterals: &IndexMap<ResourceAddress, Decimal>) -> (Decimal, Decimal) {
+            let mut collateral_value: Decimal = dec!(0);
+            let mut borrowing_power: Decimal = dec!(0);
+
+            for (address, amount) in collaterals.iter() {
+                let pool: bool = self.pool_units.get(address).is_some();
+                let real_amount: Decimal = self.pool_to_real(*amount, *address, pool);
+                let info = self
+                    .collaterals
+                    .get(&self.basket_parent_address(*address))
+                    .unwrap();
+                let value: Decimal = real_amount * info.usd_price;
+
+                collateral_value += value;
+                borrowing_power += value / info.mcr;
+            }
+
+            (collateral_value, borrowing_power)
+        }
+
+        /// Calculate the health of a basket loan / CDP: borrowing power / minted STAB value (the basket can be liquidated if < 1)
+        fn basket_health(
+            &self,
+            collaterals: &IndexMap<ResourceAddress, Decimal>,
+            minted_stab: Decimal,
+        ) -> Decimal {
+            let (_collateral_value, borrowing_power): (Decimal, Decimal) =
+                self.basket_value(collaterals);
+            borrowing_power / (minted_stab * self.internal_stab_price)
+        }
+
+        /// Update and get the debt indexes of the (parent) collaterals of a basket loan / CDP
+        fn basket_debt_indexes(
+            &mut self,
+            collaterals: &IndexMap<ResourceAddress, Decimal>,
+        ) -> IndexMap<ResourceAddress, Decimal> {
+            let mut debt_indexes: IndexMap<ResourceAddress, Decimal> = index_map_new();
+
+            for address in collaterals.keys() {
+                let parent_address: ResourceAddress = self.basket_parent_address(*address);
+                if !debt_indexes.contains_key(&parent_address) {
+                    self.update_debt_index(parent_address);
+                    debt_indexes.insert(
+                        parent_address,
+                        self.collaterals.get(&parent_address).unwrap().debt_index,
+                    );
+                }
+            }
+
+            debt_indexes
+        }
+
+        /// Add accrued stability fees to the debt of a basket loan / CDP
+        ///
+        /// # Input
+        /// - `basket_id`: The id of the basket loan
+        ///
+        /// # Output
+        /// - The updated basket data
+        ///
+        /// # Logic
+        /// - Update the debt indexes of the collaterals
+        /// - If the basket is active, grow its debt by the growth of each collateral's debt index, weighted by collateral value
+        /// - Mint the fee as STAB into the surplus vault, and attribute the new debt to the collaterals
+        /// - If the basket is healthy, move it in the AvlTree using its new health
+        /// - Update the basket receipt
+        fn accrue_basket_fee(&mut self, basket_id: &NonFungibleLocalId) -> BasketCdp {
+            let data: BasketCdp = self.basket_manager.get_non_fungible_data(basket_id);
+
+            if data.status != CdpStatus::Healthy && data.status != CdpStatus::Marked {
+                return data;
+            }
+
+            let debt_indexes: IndexMap<ResourceAddress, Decimal> =
+                self.basket_debt_indexes(&data.collaterals);
+
+            let mut collateral_value: Decimal = dec!(0);
+            let mut weighted_growth: Decimal = dec!(0);
+
+            for (address, amount) in data.collaterals.iter() {
+                let mut single_collateral: IndexMap<ResourceAddress, Decimal> = index_map_new();
+                single_collateral.insert(*address, *amount);
+                let (value, _borrowing_power): (Decimal, Decimal) =
+                    self.basket_value(&single_collateral);
+
+                let parent_address: ResourceAddress = self.basket_parent_address(*address);
+                let debt_index: Decimal = *debt_indexes.get(&parent_address).unwrap();
+                let last_debt_index: Decimal = data
+                    .debt_indexes
+                    .get(&parent_address)
+                    .copied()
+                    .unwrap_or(debt_index);
+
+                collateral_value += value;
+                weighted_growth += value * debt_index / last_debt_index;
+            }
+
+            self.basket_manager
+                .update_non_fungible_data(basket_id, "debt_indexes", debt_indexes);
+
+            if collateral_value == dec!(0) {
+                return self.basket_manager.get_non_fungible_data(basket_id);
+            }
+
+            let new_stab_amount: Decimal = data.minted_stab * weighted_growth / collateral_value;
+            let fee: Decimal = new_stab_amount - data.minted_stab;
+
+            if fee <= dec!(0) {
+                return self.basket_manager.get_non_fungible_data(basket_id);
+            }
+
+            let new_health: Decimal = self.basket_health(&data.collaterals, new_stab_amount);
+
+            if data.status == CdpStatus::Healthy {
+                self.remove_basket_health(data.health, basket_id.clone());
+                self.insert_basket_health(new_health, basket_id.clone());
+            }
+
+            let debt_attribution: IndexMap<ResourceAddress, (Decimal, Decimal)> = self
+                .attribute_basket_debt(
+                    &data.debt_attribution,
+                    &data.collaterals,
+                    new_stab_amount,
+                    false,
                 );
+            self.stab_surplus.put(self.stab_manager.mint(fee));
+
+            self.basket_manager
+                .update_non_fungible_data(basket_id, "debt_attribution", debt_attribution);
+            self.basket_manager
+                .update_non_fungible_data(basket_id, "minted_stab", new_stab_amount);
+            self.basket_manager
+                .update_non_fungible_data(basket_id, "health", new_health);
+
+            Runtime::emit_event(BasketStabilityFeeAccruedEvent {
+                basket_id: basket_id.clone(),
+                fee,
+                minted_stab: new_stab_amount,
+            });
+
+            self.basket_manager.get_non_fungible_data(basket_id)
+        }
+
+        /// Attribute the debt of a basket loan / CDP to its collaterals, replacing its previous attribution
+        ///
+        /// # Input
+        /// - `previous`: The previous attribution of the basket (empty for a new basket)
+        /// - `collaterals`: The collaterals of the basket (empty if the basket's debt is gone)
+        /// - `minted_stab`: The debt of the basket
+        /// - `check_share`: Whether to check the max STAB share (and max pool share) of the collaterals
+        ///
+        /// # Output
+        /// - The new attribution: the debt and (real) collateral amount counted in every collateral's totals
+        ///
+        /// # Logic
+        /// - Remove the previous attribution from the minted STAB and collateral amount of the collaterals (and from circulating STAB)
+        /// - Split the debt over the collaterals pro rata by value (evenly if they're worthless), the last collateral receiving the rounding remainder
+        /// - Add the new attribution to the minted STAB and collateral amount of the collaterals (and to circulating STAB)
+        /// - Check the shares of the collaterals that received debt, if needed
+        fn attribute_basket_debt(
+            &mut self,
+            previous: &IndexMap<ResourceAddress, (Decimal, Decimal)>,
+            collaterals: &IndexMap<ResourceAddress, Decimal>,
+            minted_stab: Decimal,
+            check_share: bool,
+        ) -> IndexMap<ResourceAddress, (Decimal, Decimal)> {
+            for (address, (stab, amount)) in previous.iter() {
+                let parent_address: ResourceAddress = self.basket_parent_address(*address);
+                let pool: bool = self.pool_units.get(address).is_some();
+                self.update_minted_stab(false, pool, false, *stab, parent_address, *address);
+                self.collaterals
+                    .get_mut(&parent_address)
+                    .unwrap()
+                    .collateral_amount -= *amount;
+            }
+
+            let (collateral_value, _borrowing_power): (Decimal, Decimal) =
+                self.basket_value(collaterals);
+            let collateral_count: Decimal = Decimal::from(collaterals.len() as u64);
+            let mut attribution: IndexMap<ResourceAddress, (Decimal, Decimal)> = index_map_new();
+            let mut attributed_stab: Decimal = dec!(0);
+
+            for (index, (address, amount)) in collaterals.iter().enumerate() {
+                let parent_address: ResourceAddress = self.basket_parent_address(*address);
+                let pool: bool = self.pool_units.get(address).is_some();
+                let real_amount: Decimal = self.pool_to_real(*amount, *address, pool);
+
+                let stab: Decimal = if index + 1 == collaterals.len() {
+                    minted_stab - attributed_stab
+                } else if collateral_value > dec!(0) {
+                    minted_stab * real_amount
+                        * self.collaterals.get(&parent_address).unwrap().usd_price
+                        / collateral_value
+                } else {
+                    minted_stab / collateral_count
+                };
+                attributed_stab += stab;
+
+                self.update_minted_stab(true, pool, false, stab, parent_address, *address);
+                self.collaterals
+                    .get_mut(&parent_address)
+                    .unwrap()
+                    .collateral_amount += real_amount;
+                attribution.insert(*address, (stab, real_amount));
+            }
+
+            if check_share {
+                for (address, (stab, _amount)) in attribution.iter() {
+                    if *stab > dec!(0) {
+                        let pool: bool = self.pool_units.get(address).is_some();
+                        self.check_share(self.basket_parent_address(*address), pool, *address);
+                    }
+                }
             }
 
-            (liquidation_payment, payment, receipt.into())
+            attribution
         }
 
-        /// Save a loan / CDP
-        ///
-        /// # Input
-        /// - `marker_data`: The marker data
-        /// - `cdp_data`: The CDP data
-        /// - `cr`: The collateral ratio
-        ///
-        /// # Output
-        /// - A bucket with the liquidation receipt (a receipt specifying the loan was saved)
-        ///
-        /// # Logic
-        /// - Update the collateral amount of the parent address
-        /// - Create a marker for the savior
-        /// - Create a liquidation receipt with saved status
-        /// - Update the CDP to a healthy state and collateral ratio
-        /// - Update the marker receipt to used
-        /// - Insert the healthy CDP again
-        /// - Return the liquidation receipt
-        fn save(&mut self, marker_data: CdpMarker, cdp_data: Cdp, cr: Decimal) -> Bucket {
-            self.collaterals
-                .get_mut(&cdp_data.parent_address)
-                .unwrap()
-                .collateral_amount += (cr - cdp_data.collateral_stab_ratio) * cdp_data.minted_stab;
+        /// Take all collaterals of a basket loan / CDP out of their vaults
+        fn take_basket_collaterals(
+            &mut self,
+            collaterals: &IndexMap<ResourceAddress, Decimal>,
+        ) -> Vec<Bucket> {
+            let mut buckets: Vec<Bucket> = Vec::new();
 
-            self.marker_placing_counter += dec!(1);
-            self.cdp_marker_counter += 1;
+            for (address, amount) in collaterals.iter() {
+                let pool: bool = self.pool_units.get(address).is_some();
+                buckets.push(self.take_collateral(*address, pool, *amount));
+            }
 
-            let marker = CdpMarker {
-                mark_type: CdpUpdate::Saved,
-                time_marked: Clock::current_time_rounded_to_minutes(),
-                marked_id: marker_data.marked_id.clone(),
-                marker_placing: self.marker_placing_counter,
-                used: false,
-            };
+            buckets
+        }
 
-            let marker_receipt: NonFungibleBucket = self
-                .cdp_marker_manager
-                .mint_non_fungible(
-                    &NonFungibleLocalId::integer(self.cdp_marker_counter),
-                    marker,
-                )
-                .as_non_fungible();
+        /// Insert a basket health into the basket AvlTree
+        fn insert_basket_health(&mut self, health: Decimal, basket_id: NonFungibleLocalId) {
+            let mut basket_ids: Vec<NonFungibleLocalId> = self
+                .basket_healths
+                .get_mut(&health)
+                .map(|basket_ids| basket_ids.to_vec())
+                .unwrap_or_default();
 
-            self.marked_cdps.remove(&marker_data.marker_placing);
-            self.marked_cdps_active -= 1;
-            self.cdp_manager.update_non_fungible_data(
-                &marker_data.marked_id,
-                "status",
-                CdpStatus::Healthy,
-            );
-            self.cdp_manager.update_non_fungible_data(
-                &marker_data.marked_id,
-                "collateral_stab_ratio",
-                cr,
-            );
-            self.cdp_marker_manager.update_non_fungible_data(
-                &NonFungibleLocalId::integer(cdp_data.marker_id),
-                "used",
-                true,
+            assert!(
+                basket_ids.len() < self.parameters.max_vector_length.try_into().unwrap(),
+                "Health vector is full..."
             );
 
-            self.insert_cr(cdp_data.parent_address, cr, marker_data.marked_id.clone());
+            basket_ids.push(basket_id);
+            self.basket_healths.insert(health, basket_ids);
+        }
 
-            marker_receipt.into()
+        /// Remove a basket health from the basket AvlTree
+        fn remove_basket_health(&mut self, health: Decimal, basket_id: NonFungibleLocalId) {
+            let mut basket_ids: Vec<NonFungibleLocalId> =
+                self.basket_healths.get_mut(&health).unwrap().to_vec();
+
+            basket_ids.retain(|id| id != &basket_id);
+
+            if basket_ids.is_empty() {
+                self.basket_healths.remove(&health);
+            } else {
+                self.basket_healths.insert(health, basket_ids);
+            }
+        }
+
+        /// Get the lowest collateral ratio stored in the AvlTree of a collateral (None if no loans use it)
+        fn lowest_cr(&self, collateral: ResourceAddress) -> Option<Decimal> {
+            if let Some(collateral_ratios) = self.collateral_ratios.get(&collateral) {
+                if let Some((cr, _collateral_ids, _next_key)) =
+                    collateral_ratios.range(dec!(0)..).next()
+                {
+                    return Some(cr);
+                }
+            }
+
+            None
+        }
+
+        /// Record bad debt caused by a loan using a (parent) collateral
+        ///
+        /// The liquidated loan's debt is already removed from the circulating STAB, but the unpaid part stays in circulation, so it's added back until it's cancelled.
+        fn record_bad_debt(&mut self, parent_address: ResourceAddress, amount: Decimal) {
+            self.collaterals.get_mut(&parent_address).unwrap().bad_debt += amount;
+            self.bad_debt += amount;
+            self.circulating_stab += amount;
+
+            Runtime::emit_event(BadDebtRecordedEvent {
+                collateral: parent_address,
+                amount,
+                bad_debt: self.bad_debt,
+            });
+        }
+
+        /// Calculate the current price of the running debt auction: starting price * decay ^ passed minutes, but never below the minimum price
+        fn debt_auction_price(&self) -> Decimal {
+            let auction: &DebtAuction = self
+                .debt_auction
+                .as_ref()
+                .expect("No debt auction is running.");
+            let passed_minutes: Decimal = (Clock::current_time_rounded_to_minutes()
+                .seconds_since_unix_epoch
+                - auction.start_time.seconds_since_unix_epoch)
+                / dec!(60);
+
+            (auction.starting_price * auction.price_decay.pow(passed_minutes).unwrap())
+                .max(auction.minimum_price)
+        }
+
+        /// Insert a collateral ratio into the AvlTree, creating the AvlTree if this is the first loan using the collateral
+        fn initialize_or_insert_cr(
+            &mut self,
+            parent_address: ResourceAddress,
+            cr: Decimal,
+            cdp_id: NonFungibleLocalId,
+        ) {
+            if self.collaterals.get(&parent_address).unwrap().initialized {
+                self.insert_cr(parent_address, cr, cdp_id);
+            } else {
+                let mut avl_tree: AvlTree<Decimal, Vec<NonFungibleLocalId>> = AvlTree::new();
+                let cdp_ids: Vec<NonFungibleLocalId> = vec![cdp_id];
+                avl_tree.insert(cr, cdp_ids);
+                self.collateral_ratios.insert(parent_address, avl_tree);
+                self.collaterals
+                    .get_mut(&parent_address)
+                    .unwrap()
+                    .initialized = true;
+                self.collaterals.get_mut(&parent_address).unwrap().highest_cr = cr;
+            }
         }
 
         /// Insert a collateral ratio into the AvlTree
@@ -2204,25 +6358,154 @@ mod stabilis_component {
             }
         }
 
+        /// Check a new price against the circuit breaker of a collateral
+        ///
+        /// # Output
+        /// - Whether the price can be applied
+        ///
+        /// # Logic
+        /// - If a price is already pending, replace the pending price (the collateral stays paused)
+        /// - Start a new hour window if the last one is over
+        /// - If the price moves more than allowed since the last update or since the start of the hour window, hold it as pending and emit an event
+        fn pass_circuit_breaker(&mut self, collateral: ResourceAddress, new_price: Decimal) -> bool {
+            let current_price: Decimal = self.collaterals.get(&collateral).unwrap().usd_price;
+            let mut breaker = self.circuit_breakers.get_mut(&collateral).unwrap();
+
+            if breaker.pending_price.is_some() {
+                breaker.pending_price = Some(new_price);
+                return false;
+            }
+
+            if Clock::current_time_is_at_or_after(
+                breaker.hour_start.add_minutes(60).unwrap(),
+                TimePrecision::Minute,
+            ) {
+                breaker.hour_start = Clock::current_time_rounded_to_minutes();
+                breaker.hour_start_price = current_price;
+            }
+
+            let update_move: Decimal =
+                (new_price - current_price).checked_abs().unwrap() / current_price;
+            let hourly_move: Decimal = (new_price - breaker.hour_start_price).checked_abs().unwrap()
+                / breaker.hour_start_price;
+
+            if update_move <= breaker.max_update_move && hourly_move <= breaker.max_hourly_move {
+                return true;
+            }
+
+            breaker.pending_price = Some(new_price);
+
+            Runtime::emit_event(CircuitBreakerTrippedEvent {
+                collateral,
+                current_price,
+                pending_price: new_price,
+                update_move,
+                hourly_move,
+            });
+
+            false
+        }
+
+        /// Whether openings and liquidations using a (parent) collateral are paused, because its circuit breaker holds a pending price
+        ///    - An LP collateral is paused as well while one of its legs is paused, as its price depends on the legs' prices
+        fn price_paused(&self, collateral: ResourceAddress) -> bool {
+            if self
+                .circuit_breakers
+                .get(&collateral)
+                .map_or(false, |breaker| breaker.pending_price.is_some())
+            {
+                return true;
+            }
+
+            let legs: Vec<ResourceAddress> = self
+                .lp_units
+                .get(&collateral)
+                .map(|lp_unit| lp_unit.legs.clone())
+                .unwrap_or_default();
+
+            legs.iter().any(|leg| self.price_paused(*leg))
+        }
+
+        /// Assert openings and liquidations using a (parent) collateral aren't paused by its circuit breaker
+        fn assert_price_not_paused(&self, collateral: ResourceAddress) {
+            assert!(
+                !self.price_paused(collateral),
+                "Collateral paused by its circuit breaker, until its pending price is confirmed or rejected."
+            );
+        }
+
+        /// Set the price of a collateral and recalculate its liquidation collateral ratio
+        fn set_collateral_price(&mut self, collateral: ResourceAddress, new_price: Decimal) {
+            let mcr: Decimal = self.collaterals.get_mut(&collateral).unwrap().mcr;
+            self.collaterals.get_mut(&collateral).unwrap().usd_price = new_price;
+            self.collaterals
+                .get_mut(&collateral)
+                .unwrap()
+                .liquidation_collateral_ratio = mcr * (self.internal_stab_price / new_price);
+        }
+
+        /// Update the prices of all LP collaterals that have a resource as one of their legs
+        fn update_lp_prices_of_leg(&mut self, leg: ResourceAddress) {
+            let lp_addresses: Vec<ResourceAddress> = match self.lp_units_by_leg.get(&leg) {
+                Some(lp_addresses) => lp_addresses.clone(),
+                None => return,
+            };
+
+            for lp_address in lp_addresses {
+                let price: Decimal = self.lp_price(lp_address);
+                self.set_collateral_price(lp_address, price);
+            }
+        }
+
+        /// Calculate the price of one LP unit from the value of the legs it can be redeemed for
+        ///    - For a TwoResourcePool, the fair value 2 * sqrt(value leg 1 * value leg 2) is used, so skewing the reserves with a swap can't inflate the price
+        ///    - For a MultiResourcePool, the same fair value for n legs is used: n * (value leg 1 * ... * value leg n) ^ (1 / n)
+        ///       - calculated through logarithms, so the product of many small leg values can't round down to 0
+        fn lp_price(&self, lp_address: ResourceAddress) -> Decimal {
+            let pool: LpPool = self.lp_units.get(&lp_address).unwrap().pool.clone();
+            let leg_values: Vec<Decimal> = pool
+                .get_redemption_value(dec!(1))
+                .iter()
+                .map(|(leg, amount)| {
+                    let leg_price: Decimal = if *leg == self.stab_manager.address() {
+                        self.internal_stab_price
+                    } else {
+                        self.collaterals.get(leg).unwrap().usd_price
+                    };
+                    *amount * leg_price
+                })
+                .collect();
+
+            match pool {
+                LpPool::TwoResource(_) => dec!(2) * (leg_values[0] * leg_values[1]).sqrt().unwrap(),
+                LpPool::MultiResource(_) => {
+                    if leg_values.iter().any(|value| *value <= dec!(0)) {
+                        return dec!(0);
+                    }
+                    let legs: Decimal = Decimal::from(leg_values.len());
+                    let mean_ln: Decimal = leg_values
+                        .iter()
+                        .fold(dec!(0), |total, value| total + value.ln().unwrap())
+                        / legs;
+                    legs * mean_ln.exp().unwrap()
+                }
+            }
+        }
+
         /// Calculate the real value of a pool collateral, if it is a pool unit
         ///    - Example: a resource is an LSU, 1 LSU = 1.1 XRD. If the collateral amount is 10 LSU, 11 XRD is returned.
-        fn pool_to_real(
-            &mut self,
-            amount: Decimal,
-            collateral: ResourceAddress,
-            pool: bool,
-        ) -> Decimal {
+        fn pool_to_real(&self, amount: Decimal, collateral: ResourceAddress, pool: bool) -> Decimal {
             if pool {
-                if self.pool_units.get_mut(&collateral).unwrap().lsu {
+                if self.pool_units.get(&collateral).unwrap().lsu {
                     self.pool_units
-                        .get_mut(&collateral)
+                        .get(&collateral)
                         .unwrap()
                         .validator
                         .unwrap()
                         .get_redemption_value(amount)
                 } else {
                     self.pool_units
-                        .get_mut(&collateral)
+                        .get(&collateral)
                         .unwrap()
                         .one_resource_pool
                         .unwrap()
@@ -2390,6 +6673,11 @@ pub struct CollateralInfo {
     pub minted_stab: Decimal,
     pub collateral_amount: Decimal,
     pub highest_cr: Decimal,
+    pub liquidation_mode: LiquidationMode,
+    pub stability_fee: Decimal,
+    pub debt_index: Decimal,
+    pub last_fee_update: Instant,
+    pub bad_debt: Decimal,
 }
 
 #[derive(ScryptoSbor)]
@@ -2404,9 +6692,39 @@ pub struct PoolUnitInfo {
     pub accepted: bool,
     pub minted_stab: Decimal,
     pub max_pool_share: Decimal,
+    pub refresh_cursor: u64,
+    /// loans using the pool unit as collateral, by position (walked by `refresh_pool_unit_crs`)
+    pub loans: KeyValueStore<u64, NonFungibleLocalId>,
+    /// position of every indexed loan in `loans`
+    pub loan_positions: KeyValueStore<NonFungibleLocalId, u64>,
+    /// amount of indexed loans
+    pub loan_count: u64,
+}
+
+/// Pool of an LP collateral
+#[derive(ScryptoSbor, Clone)]
+pub enum LpPool {
+    TwoResource(Global<TwoResourcePool>),
+    MultiResource(Global<MultiResourcePool>),
+}
+
+impl LpPool {
+    /// Get the amounts of every leg an amount of LP units can be redeemed for
+    pub fn get_redemption_value(&self, amount: Decimal) -> IndexMap<ResourceAddress, Decimal> {
+        match self {
+            LpPool::TwoResource(pool) => pool.get_redemption_value(amount),
+            LpPool::MultiResource(pool) => pool.get_redemption_value(amount),
+        }
+    }
 }
 
 #[derive(ScryptoSbor)]
+pub struct LpUnitInfo {
+    pub pool: LpPool,
+    pub legs: Vec<ResourceAddress>,
+}
+
+#[derive(ScryptoSbor, Clone)]
 pub struct ProtocolParameters {
     pub minimum_mint: Decimal,
     pub max_vector_length: u64,
@@ -2420,4 +6738,511 @@ pub struct ProtocolParameters {
     pub stop_force_mint: bool,
     pub stop_force_liquidate: bool,
     pub force_mint_cr_multiplier: Decimal,
+    pub stop_redemptions: bool,
+    pub redemption_fee: Decimal,
+    pub max_redemption_cdps: u64,
+    pub partial_liquidation_buffer: Decimal,
+    pub auction_start_discount: Decimal,
+    pub auction_discount_increase: Decimal,
+    pub auction_max_discount: Decimal,
+    pub marker_deposit: Option<MarkerDepositConfig>,
+}
+
+/// State of an emergency shutdown
+#[derive(ScryptoSbor, Clone)]
+pub struct ShutdownState {
+    /// time of the shutdown, at which prices and stability fees were frozen
+    pub time: Instant,
+    /// whether all loans have been settled, so STAB can be redeemed
+    pub finalized: bool,
+    /// collateral (or pool units) reserved for STAB holders by settling loans, which is still left
+    pub settlement_collateral: IndexMap<ResourceAddress, Decimal>,
+    /// peg stability module reserves handed over for STAB holders, which are still left
+    pub settlement_reserves: IndexMap<ResourceAddress, Decimal>,
+    /// STAB that can still be redeemed for the reserved collateral (known once finalized)
+    pub outstanding_stab: Decimal,
+}
+
+/// State of a running debt auction
+#[derive(ScryptoSbor, Clone)]
+pub struct DebtAuction {
+    /// time the auction started, from which the price decays
+    pub start_time: Instant,
+    /// starting price of the sold tokens (in STAB)
+    pub starting_price: Decimal,
+    /// factor the price is multiplied by every minute
+    pub price_decay: Decimal,
+    /// price under which the tokens are never sold (in STAB)
+    pub minimum_price: Decimal,
+    /// STAB raised (and burned) by the auction
+    pub stab_raised: Decimal,
+}
+
+/// Circuit breaker on the price updates of a collateral
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct CircuitBreaker {
+    /// maximum relative price move per update (0.2 is 20%)
+    pub max_update_move: Decimal,
+    /// maximum relative price move within an hour
+    pub max_hourly_move: Decimal,
+    /// start of the current hour window
+    pub hour_start: Instant,
+    /// price at the start of the current hour window
+    pub hour_start_price: Decimal,
+    /// price held by the breaker, pausing openings and liquidations until governance confirms or rejects it
+    pub pending_price: Option<Decimal>,
+}
+
+/// Read-only view of a running debt auction
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct DebtAuctionView {
+    /// address of the sold tokens
+    pub token: ResourceAddress,
+    /// tokens left to sell
+    pub tokens_left: Decimal,
+    /// time the auction started
+    pub start_time: Instant,
+    /// current price of the tokens (in STAB)
+    pub current_price: Decimal,
+    /// price under which the tokens are never sold (in STAB)
+    pub minimum_price: Decimal,
+    /// STAB raised (and burned) by the auction
+    pub stab_raised: Decimal,
+}
+
+/// Event emitted when a loan / CDP is opened
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpOpenedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub collateral_amount: Decimal,
+    pub minted_stab: Decimal,
+    pub new_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a loan / CDP is closed by paying off the debt
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpClosedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub collateral_returned: Decimal,
+    pub stab_repaid: Decimal,
+    pub old_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when part of the debt of a loan / CDP is paid off
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpPartiallyClosedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub stab_repaid: Decimal,
+    pub minted_stab: Decimal,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when more STAB is borrowed against a loan / CDP
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpBorrowedMoreEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub stab_borrowed: Decimal,
+    pub minted_stab: Decimal,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when collateral is added to a loan / CDP (which saves it if it was marked)
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpToppedUpEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub collateral_added: Decimal,
+    pub collateral_amount: Decimal,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub was_marked: bool,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when collateral is removed from a loan / CDP
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpCollateralRemovedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub collateral_removed: Decimal,
+    pub collateral_amount: Decimal,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when the collateral of a loan / CDP is swapped for another collateral
+///   - `collateral_price` is the price of the (parent of the) new collateral
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpCollateralSwappedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub old_collateral: ResourceAddress,
+    pub new_collateral: ResourceAddress,
+    pub collateral_returned: Decimal,
+    pub collateral_added: Decimal,
+    pub minted_stab: Decimal,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a loan / CDP is marked for liquidation
+///   - `new_cr` is the collateral ratio after the pool unit conversion, so it can differ from `old_cr` for pool units
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpMarkedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub marker_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a marked loan / CDP is liquidated
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpLiquidatedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub marker_id: NonFungibleLocalId,
+    pub liquidation_receipt_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub stab_paid: Decimal,
+    pub collateral_to_liquidator: Decimal,
+    pub collateral_to_treasury: Decimal,
+    pub leftover_collateral: Decimal,
+    pub cr: Decimal,
+    pub partial: bool,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a marked loan / CDP turns out to be healthy and is saved
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpSavedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub marker_id: NonFungibleLocalId,
+    pub saved_marker_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a loan / CDP is (partially) force liquidated
+///   - `new_cr` is 0 if the loan was liquidated completely
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpForceLiquidatedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub stab_paid: Decimal,
+    pub collateral_taken: Decimal,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub fully_liquidated: bool,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when STAB is force minted against a loan / CDP
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpForceMintedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub collateral_added: Decimal,
+    pub stab_minted: Decimal,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when (part of) the debt of a loan / CDP is redeemed
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpRedeemedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub stab_redeemed: Decimal,
+    pub collateral_taken: Decimal,
+    pub old_cr: Decimal,
+    pub new_cr: Decimal,
+    pub fully_redeemed: bool,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when STAB is redeemed for collateral
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct RedemptionEvent {
+    pub collateral: ResourceAddress,
+    pub stab_redeemed: Decimal,
+    pub collateral_returned: Decimal,
+    pub fee: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when (part of) a liquidation auction is filled
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct AuctionFilledEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub marker_id: NonFungibleLocalId,
+    pub liquidation_receipt_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub stab_paid: Decimal,
+    pub discount: Decimal,
+    pub collateral_to_bidder: Decimal,
+    pub collateral_to_treasury: Decimal,
+    pub remaining_stab: Decimal,
+    pub remaining_collateral: Decimal,
+    pub completed: bool,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when stability fees are added to the debt of a loan / CDP
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct StabilityFeeAccruedEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub fee: Decimal,
+    pub minted_stab: Decimal,
+    pub debt_index: Decimal,
+}
+
+/// Event emitted when a basket loan / CDP is opened
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketCdpOpenedEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub collaterals: IndexMap<ResourceAddress, Decimal>,
+    pub minted_stab: Decimal,
+    pub health: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a basket loan / CDP is closed by paying off the debt
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketCdpClosedEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub collaterals_returned: IndexMap<ResourceAddress, Decimal>,
+    pub stab_repaid: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when collateral is added to a basket loan / CDP (which saves it if it was marked and is healthy again)
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketCdpToppedUpEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub collaterals_added: IndexMap<ResourceAddress, Decimal>,
+    pub old_health: Decimal,
+    pub new_health: Decimal,
+    pub was_marked: bool,
+}
+
+/// Event emitted when collateral is removed from a basket loan / CDP
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketCdpCollateralRemovedEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub collateral_removed: Decimal,
+    pub old_health: Decimal,
+    pub new_health: Decimal,
+}
+
+/// Event emitted when more STAB is borrowed against a basket loan / CDP
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketCdpBorrowedMoreEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub stab_borrowed: Decimal,
+    pub minted_stab: Decimal,
+    pub old_health: Decimal,
+    pub new_health: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a basket loan / CDP is checked for liquidation
+///   - `marked` is false if the basket turned out to be healthy, in which case only its stored health was refreshed
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketCdpMarkedEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub old_health: Decimal,
+    pub new_health: Decimal,
+    pub marked: bool,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a marked basket loan / CDP is liquidated
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketCdpLiquidatedEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub stab_paid: Decimal,
+    pub collateral_to_liquidator: IndexMap<ResourceAddress, Decimal>,
+    pub collateral_to_treasury: IndexMap<ResourceAddress, Decimal>,
+    pub leftover_collateral: IndexMap<ResourceAddress, Decimal>,
+    pub health: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when stability fees are added to the debt of a basket loan / CDP
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketStabilityFeeAccruedEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub fee: Decimal,
+    pub minted_stab: Decimal,
+}
+
+/// Event emitted when the protocol is shut down
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct EmergencyShutdownEvent {
+    pub time: Instant,
+    pub internal_price: Decimal,
+    pub circulating_stab: Decimal,
+}
+
+/// Event emitted when a loan / CDP is settled after an emergency shutdown
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CdpSettledEvent {
+    pub cdp_id: NonFungibleLocalId,
+    pub collateral: ResourceAddress,
+    pub stab_settled: Decimal,
+    pub collateral_settled: Decimal,
+    pub leftover_collateral: Decimal,
+    pub collateral_price: Decimal,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when a basket loan / CDP is settled after an emergency shutdown
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BasketCdpSettledEvent {
+    pub basket_id: NonFungibleLocalId,
+    pub stab_settled: Decimal,
+    pub collateral_settled: IndexMap<ResourceAddress, Decimal>,
+    pub leftover_collateral: IndexMap<ResourceAddress, Decimal>,
+    pub internal_price: Decimal,
+}
+
+/// Event emitted when an emergency shutdown is finalized, after which STAB can be redeemed
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct ShutdownFinalizedEvent {
+    pub outstanding_stab: Decimal,
+    pub settlement_collateral: IndexMap<ResourceAddress, Decimal>,
+    pub settlement_reserves: IndexMap<ResourceAddress, Decimal>,
+}
+
+/// Event emitted when STAB is redeemed for the collateral reserved during an emergency shutdown
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SettlementRedemptionEvent {
+    pub stab_redeemed: Decimal,
+    pub collateral_returned: IndexMap<ResourceAddress, Decimal>,
+}
+
+/// Event emitted when bad debt is recorded, because a liquidated loan's collateral was worth less than its debt
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BadDebtRecordedEvent {
+    pub collateral: ResourceAddress,
+    pub amount: Decimal,
+    pub bad_debt: Decimal,
+}
+
+/// Event emitted when STAB is deposited into the surplus buffer
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SurplusDepositedEvent {
+    pub amount: Decimal,
+    pub surplus_buffer: Decimal,
+}
+
+/// Event emitted when bad debt is cancelled against the surplus buffer
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct BadDebtCancelledEvent {
+    pub amount: Decimal,
+    pub bad_debt: Decimal,
+    pub surplus_buffer: Decimal,
+}
+
+/// Event emitted when a debt auction is started
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct DebtAuctionStartedEvent {
+    pub token: ResourceAddress,
+    pub tokens_amount: Decimal,
+    pub starting_price: Decimal,
+    pub price_decay: Decimal,
+    pub minimum_price: Decimal,
+    pub bad_debt: Decimal,
+}
+
+/// Event emitted when tokens are bought in a debt auction
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct DebtAuctionBidEvent {
+    pub stab_paid: Decimal,
+    pub tokens_bought: Decimal,
+    pub price: Decimal,
+    pub bad_debt: Decimal,
+}
+
+/// Event emitted when a debt auction is ended
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct DebtAuctionEndedEvent {
+    pub stab_raised: Decimal,
+    pub tokens_returned: Decimal,
+    pub bad_debt: Decimal,
+}
+
+/// Event emitted when part of a marker deposit is forfeited, because the marked loan was saved
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct MarkerDepositForfeitedEvent {
+    pub marker_id: NonFungibleLocalId,
+    pub token: ResourceAddress,
+    pub deposit: Decimal,
+    pub forfeited: Decimal,
+}
+
+/// Event emitted when a marker deposit is refunded
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct MarkerDepositRefundedEvent {
+    pub marker_id: NonFungibleLocalId,
+    pub token: ResourceAddress,
+    pub refunded: Decimal,
+}
+
+/// Event emitted when loans using a pool unit collateral are re-priced
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PoolUnitCrsRefreshedEvent {
+    pub pool_unit: ResourceAddress,
+    pub refreshed: u64,
+    pub cursor: u64,
+}
+
+/// Event emitted when a collateral's circuit breaker holds a price update, pausing openings and liquidations
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct CircuitBreakerTrippedEvent {
+    pub collateral: ResourceAddress,
+    pub current_price: Decimal,
+    pub pending_price: Decimal,
+    pub update_move: Decimal,
+    pub hourly_move: Decimal,
+}
+
+/// Event emitted when governance confirms or rejects a pending collateral price
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct PendingPriceResolvedEvent {
+    pub collateral: ResourceAddress,
+    pub pending_price: Decimal,
+    pub confirmed: bool,
 }
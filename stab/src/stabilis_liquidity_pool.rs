@@ -1,6 +1,9 @@
 //! # Stabilis Liquidity Pool Blueprint
 //!
 //! This blueprint instantiates a liquidity pool for the Stabilis protocol. The pool is a native STAB/XRD liquidity pool, and is used to determine the price of STAB tokens.
+//!
+//! Besides the spot price, the pool keeps a cumulative price (price * minutes), updated before every swap and liquidity change, and stores it once per minute.
+//! The time weighted average price (`get_twap`) follows from two of these observations, and can't be moved by a price spike within a single transaction.
 
 use scrypto::prelude::*;
 
@@ -11,6 +14,8 @@ mod stabilis_liquidity_pool {
             add_liquidity => PUBLIC;
             remove_liquidity => PUBLIC;
             get_stab_price => PUBLIC;
+            get_twap => PUBLIC;
+            get_pool_address => PUBLIC;
             swap => PUBLIC;
             set_fee => restrict_to: [OWNER];
         }
@@ -21,6 +26,18 @@ mod stabilis_liquidity_pool {
         pool_component: Global<TwoResourcePool>,
         /// The fee charged for swaps
         fee: Decimal,
+        /// The cumulative price (price * minutes) at the last observation
+        cumulative_price: Decimal,
+        /// The price after the last swap or liquidity change, counted in the cumulative price from then on
+        last_price: Decimal,
+        /// The time of the last observation
+        last_observation: Instant,
+        /// KVS storing the observations (time and cumulative price) as a ring buffer, at most one per minute
+        observations: KeyValueStore<u64, (Instant, Decimal)>,
+        /// The key of the latest observation
+        observation_index: u64,
+        /// The maximum amount of stored observations
+        max_observations: u64,
     }
 
     impl StabilisPool {
@@ -56,9 +73,18 @@ mod stabilis_liquidity_pool {
                 None,
             );
 
+            let observations: KeyValueStore<u64, (Instant, Decimal)> = KeyValueStore::new();
+            observations.insert(0, (Clock::current_time_rounded_to_minutes(), dec!(0)));
+
             Self {
                 pool_component,
                 fee,
+                cumulative_price: dec!(0),
+                last_price: dec!(0),
+                last_observation: Clock::current_time_rounded_to_minutes(),
+                observations,
+                observation_index: 0,
+                max_observations: 1440,
             }
             .instantiate()
             .prepare_to_globalize(owner_role)
@@ -77,13 +103,17 @@ mod stabilis_liquidity_pool {
         /// - The leftover resource, if any
         ///
         /// # Logic
+        /// - Updates the cumulative price
         /// - Contributes the resources to the pool and returns them
         pub fn add_liquidity(
             &mut self,
             resource1: Bucket,
             resource2: Bucket,
         ) -> (Bucket, Option<Bucket>) {
-            self.pool_component.contribute((resource1, resource2))
+            self.observe();
+            let result = self.pool_component.contribute((resource1, resource2));
+            self.update_last_price();
+            result
         }
 
         /// Removes liquidity from the pool
@@ -96,9 +126,13 @@ mod stabilis_liquidity_pool {
         /// - The resource2 received
         ///
         /// # Logic
+        /// - Updates the cumulative price
         /// - Redeems and returns the pool units
         pub fn remove_liquidity(&mut self, pool_units: Bucket) -> (Bucket, Bucket) {
-            self.pool_component.redeem(pool_units)
+            self.observe();
+            let result = self.pool_component.redeem(pool_units);
+            self.update_last_price();
+            result
         }
 
         /// Swaps one resource for another
@@ -110,11 +144,14 @@ mod stabilis_liquidity_pool {
         /// - The resulting tokens
         ///
         /// # Logic
+        /// - Updates the cumulative price
         /// - Checks the token reserves for the pool
         /// - Calculates the output amount for the input amount
         /// - Deposits the input bucket
         /// - Withdraws and returns the output bucket
         pub fn swap(&mut self, input_bucket: Bucket) -> Bucket {
+            self.observe();
+
             let mut reserves = self.vault_reserves();
 
             let input_reserves = reserves
@@ -129,7 +166,9 @@ mod stabilis_liquidity_pool {
 
             self.deposit(input_bucket);
 
-            self.withdraw(output_resource_address, output_amount)
+            let output_bucket: Bucket = self.withdraw(output_resource_address, output_amount);
+            self.update_last_price();
+            output_bucket
         }
 
         /// Gets the price of STAB tokens (or, if you've instantiated a different pool, the price of the first resource in the pool)
@@ -147,6 +186,55 @@ mod stabilis_liquidity_pool {
             last_amount / first_amount
         }
 
+        /// Gets the address of the TwoResourcePool component (to add its LP units as collateral)
+        pub fn get_pool_address(&self) -> ComponentAddress {
+            self.pool_component.address()
+        }
+
+        /// Gets the time weighted average price of STAB tokens (see `get_stab_price`)
+        ///
+        /// # Input
+        /// - `window_minutes`: The amount of minutes to average the price over
+        ///
+        /// # Output
+        /// - The time weighted average price
+        ///
+        /// # Logic
+        /// - Calculates the current cumulative price, counting the last price since the last observation
+        /// - Finds the latest observation at least `window_minutes` old (or the oldest stored observation, if none is old enough)
+        /// - Returns the growth of the cumulative price divided by the passed minutes
+        ///     - Prices set in the current minute aren't counted yet, so a price spike within a transaction doesn't move the result
+        pub fn get_twap(&self, window_minutes: i64) -> Decimal {
+            let now: Instant = Clock::current_time_rounded_to_minutes();
+            let cumulative_now: Decimal =
+                self.cumulative_price + self.last_price * minutes_between(self.last_observation, now);
+            let target: i64 = now.seconds_since_unix_epoch - window_minutes * 60;
+
+            let mut index: u64 = self.observation_index;
+            let mut observation: (Instant, Decimal) = *self.observations.get(&index).unwrap();
+
+            for _ in 1..self.max_observations {
+                if observation.0.seconds_since_unix_epoch <= target {
+                    break;
+                }
+                index = match index {
+                    0 => self.max_observations - 1,
+                    _ => index - 1,
+                };
+                match self.observations.get(&index) {
+                    Some(older_observation) => observation = *older_observation,
+                    None => break,
+                }
+            }
+
+            let passed_minutes: Decimal = minutes_between(observation.0, now);
+            if passed_minutes == dec!(0) {
+                return self.last_price;
+            }
+
+            (cumulative_now - observation.1) / passed_minutes
+        }
+
         /// Sets the fee charged for swaps
         pub fn set_fee(&mut self, fee: Decimal) {
             self.fee = fee;
@@ -157,6 +245,31 @@ mod stabilis_liquidity_pool {
             self.pool_component.get_vault_amounts()
         }
 
+        /// Adds the last price to the cumulative price for the minutes passed since the last observation, and stores a new observation (at most one per minute)
+        fn observe(&mut self) {
+            let now: Instant = Clock::current_time_rounded_to_minutes();
+            let passed_minutes: Decimal = minutes_between(self.last_observation, now);
+
+            if passed_minutes == dec!(0) {
+                return;
+            }
+
+            self.cumulative_price += self.last_price * passed_minutes;
+            self.last_observation = now;
+            self.observation_index = (self.observation_index + 1) % self.max_observations;
+            self.observations
+                .insert(self.observation_index, (now, self.cumulative_price));
+        }
+
+        /// Stores the price after a swap or liquidity change (0 if the pool is empty)
+        fn update_last_price(&mut self) {
+            let reserves = self.vault_reserves();
+            self.last_price = match reserves.first().map(|(_, v)| *v) {
+                Some(first_amount) if first_amount > dec!(0) => self.get_stab_price(),
+                _ => dec!(0),
+            };
+        }
+
         /// Deposits a bucket into the pool (using the TwoResourcePool component's logic)
         fn deposit(&mut self, bucket: Bucket) {
             self.pool_component.protected_deposit(bucket)
@@ -172,3 +285,8 @@ mod stabilis_liquidity_pool {
         }
     }
 }
+
+/// Minutes passed between two instants
+fn minutes_between(from: Instant, to: Instant) -> Decimal {
+    Decimal::from(to.seconds_since_unix_epoch - from.seconds_since_unix_epoch) / dec!(60)
+}
@@ -0,0 +1,513 @@
+//! # Stability Pool Blueprint
+//!
+//! The stability pool holds STAB deposited by users, which is used to liquidate marked loans / CDPs when no external liquidator steps in.
+//! The collateral received from these liquidations (including the liquidator's fine) is shared among the depositors, pro rata to their deposits.
+//! The STAB used to liquidate is taken from all depositors, also pro rata to their deposits.
+//!
+//! To do this in constant time, Liquity-style product / sum accounting is used:
+//! - `p` is a running product, such that a deposit made when the product was `p_0` is now worth `deposit * p / p_0`
+//! - `s` is a running sum per collateral, such that a deposit made when the sum was `s_0` has gained `deposit * (s - s_0) / p_0` collateral
+//! - When a liquidation empties the pool completely, a new epoch is started (all older deposits are worth 0)
+//! - When `p` gets too small to be precise, it is scaled up by a factor of 1e9 and a new scale is started
+//!
+//! Depositors receive a receipt NFT, storing their deposit and snapshots of `p` and `s`.
+//! All methods are called through the Proxy component, which checks the receipt proofs.
+
+use scrypto::prelude::*;
+
+/// Factor `p` is scaled up with when it gets too small
+fn scale_factor() -> PreciseDecimal {
+    pdec!("1000000000")
+}
+
+/// A receipt for a deposit into the stability pool
+#[derive(ScryptoSbor, NonFungibleData)]
+pub struct StabilityPoolReceipt {
+    /// the STAB deposited, at the time of the snapshots
+    #[mutable]
+    pub deposit: Decimal,
+    /// snapshot of the product `p`
+    #[mutable]
+    pub p_snapshot: PreciseDecimal,
+    /// snapshot of the epoch
+    #[mutable]
+    pub epoch: u64,
+    /// snapshot of the scale
+    #[mutable]
+    pub scale: u64,
+    /// snapshots of the sum `s` for every collateral
+    #[mutable]
+    pub s_snapshots: HashMap<ResourceAddress, PreciseDecimal>,
+}
+
+/// Event emitted when STAB is deposited into the stability pool
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct StabilityPoolDepositEvent {
+    pub receipt_id: NonFungibleLocalId,
+    pub amount: Decimal,
+    pub total_deposits: Decimal,
+}
+
+/// Event emitted when STAB is withdrawn from the stability pool
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct StabilityPoolWithdrawEvent {
+    pub receipt_id: NonFungibleLocalId,
+    pub amount: Decimal,
+    pub total_deposits: Decimal,
+}
+
+/// Event emitted when the stability pool absorbs a liquidation
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct StabilityPoolLiquidationEvent {
+    pub collateral: ResourceAddress,
+    pub stab_used: Decimal,
+    pub collateral_received: Decimal,
+    pub total_deposits: Decimal,
+    pub epoch: u64,
+    pub scale: u64,
+}
+
+#[blueprint]
+#[events(
+    StabilityPoolDepositEvent,
+    StabilityPoolWithdrawEvent,
+    StabilityPoolLiquidationEvent
+)]
+mod stability_pool {
+    enable_method_auth! {
+        methods {
+            deposit => restrict_to: [OWNER];
+            withdraw => restrict_to: [OWNER];
+            claim_collateral => restrict_to: [OWNER];
+            provide_stab => restrict_to: [OWNER];
+            return_stab => restrict_to: [OWNER];
+            absorb_liquidation => restrict_to: [OWNER];
+            get_deposit => PUBLIC;
+            get_total_deposits => PUBLIC;
+            get_receipt_address => PUBLIC;
+        }
+    }
+
+    struct StabilityPool {
+        /// The vault holding the deposited STAB
+        stab_vault: Vault,
+        /// The vaults holding the collateral gained from liquidations
+        collateral_vaults: KeyValueStore<ResourceAddress, Vault>,
+        /// All collaterals the pool has ever received
+        collaterals: Vec<ResourceAddress>,
+        /// The resource manager for the deposit receipts
+        receipt_manager: ResourceManager,
+        /// The counter for the deposit receipts
+        receipt_counter: u64,
+        /// The total amount of STAB deposited (after liquidations)
+        total_deposits: Decimal,
+        /// The running product `p`
+        p: PreciseDecimal,
+        /// The current epoch
+        current_epoch: u64,
+        /// The current scale
+        current_scale: u64,
+        /// The running sums `s` per epoch, scale and collateral
+        epoch_to_scale_to_sum: KeyValueStore<(u64, u64, ResourceAddress), PreciseDecimal>,
+        /// The amount of STAB currently provided for a liquidation
+        stab_provided: Decimal,
+    }
+
+    impl StabilityPool {
+        /// Instantiates the StabilityPool component
+        ///
+        /// # Input
+        /// - `controller_address`: The address of the controller badge of the Stabilis component
+        /// - `stab_address`: The address of the STAB token
+        ///
+        /// # Output
+        /// - The global instance of the StabilityPool component
+        ///
+        /// # Logic
+        /// - Creates a ResourceManager for the deposit receipts
+        /// - Instantiates the StabilityPool component
+        pub fn instantiate(
+            controller_address: ResourceAddress,
+            stab_address: ResourceAddress,
+        ) -> Global<StabilityPool> {
+            let (address_reservation, component_address) =
+                Runtime::allocate_component_address(StabilityPool::blueprint_id());
+
+            let receipt_manager: ResourceManager =
+                ResourceBuilder::new_integer_non_fungible::<StabilityPoolReceipt>(OwnerRole::Fixed(
+                    rule!(require_amount(dec!("0.75"), controller_address)),
+                ))
+                .metadata(metadata!(
+                    init {
+                        "name" => "STAB Stability Pool Receipt", locked;
+                        "symbol" => "stabSP", locked;
+                        "description" => "A receipt for your STAB stability pool deposit", locked;
+                        "info_url" => "https://stabilis.finance", updatable;
+                    }
+                ))
+                .non_fungible_data_update_roles(non_fungible_data_update_roles!(
+                    non_fungible_data_updater => rule!(require(global_caller(component_address)));
+                    non_fungible_data_updater_updater => rule!(deny_all);
+                ))
+                .mint_roles(mint_roles!(
+                    minter => rule!(require(global_caller(component_address)));
+                    minter_updater => rule!(deny_all);
+                ))
+                .burn_roles(burn_roles!(
+                    burner => rule!(require(global_caller(component_address)));
+                    burner_updater => rule!(deny_all);
+                ))
+                .create_with_no_initial_supply();
+
+            Self {
+                stab_vault: Vault::new(stab_address),
+                collateral_vaults: KeyValueStore::new(),
+                collaterals: Vec::new(),
+                receipt_manager,
+                receipt_counter: 0,
+                total_deposits: dec!(0),
+                p: pdec!(1),
+                current_epoch: 0,
+                current_scale: 0,
+                epoch_to_scale_to_sum: KeyValueStore::new(),
+                stab_provided: dec!(0),
+            }
+            .instantiate()
+            .prepare_to_globalize(OwnerRole::Fixed(rule!(require(controller_address))))
+            .with_address(address_reservation)
+            .globalize()
+        }
+
+        /// Deposit STAB into the stability pool
+        ///
+        /// # Input
+        /// - `stab`: The STAB to deposit
+        ///
+        /// # Output
+        /// - The deposit receipt
+        ///
+        /// # Logic
+        /// - Put the STAB in the vault and update the total deposits
+        /// - Mint a receipt with snapshots of the current product and sums
+        pub fn deposit(&mut self, stab: Bucket) -> Bucket {
+            assert!(
+                stab.resource_address() == self.stab_vault.resource_address(),
+                "Invalid STAB deposit."
+            );
+            let amount: Decimal = stab.amount();
+            assert!(amount > dec!(0), "Deposit must be positive.");
+
+            self.stab_vault.put(stab);
+            self.total_deposits += amount;
+            self.receipt_counter += 1;
+
+            let receipt = StabilityPoolReceipt {
+                deposit: amount,
+                p_snapshot: self.p,
+                epoch: self.current_epoch,
+                scale: self.current_scale,
+                s_snapshots: self.current_sums(),
+            };
+
+            let receipt_id: NonFungibleLocalId = NonFungibleLocalId::integer(self.receipt_counter);
+
+            Runtime::emit_event(StabilityPoolDepositEvent {
+                receipt_id: receipt_id.clone(),
+                amount,
+                total_deposits: self.total_deposits,
+            });
+
+            self.receipt_manager.mint_non_fungible(&receipt_id, receipt)
+        }
+
+        /// Withdraw STAB from the stability pool, claiming all collateral gained
+        ///
+        /// # Input
+        /// - `receipt_id`: The id of the deposit receipt
+        /// - `amount`: The amount of STAB to withdraw (capped at the compounded deposit)
+        ///
+        /// # Output
+        /// - The withdrawn STAB
+        /// - The collateral gained
+        ///
+        /// # Logic
+        /// - Claim the collateral, which also updates the receipt to the compounded deposit
+        /// - Take the STAB and update the receipt and total deposits
+        pub fn withdraw(
+            &mut self,
+            receipt_id: NonFungibleLocalId,
+            amount: Decimal,
+        ) -> (Bucket, Vec<Bucket>) {
+            let collateral: Vec<Bucket> = self.claim_collateral(receipt_id.clone());
+
+            let receipt: StabilityPoolReceipt =
+                self.receipt_manager.get_non_fungible_data(&receipt_id);
+
+            let mut withdraw_amount: Decimal = amount;
+            if withdraw_amount > receipt.deposit {
+                withdraw_amount = receipt.deposit;
+            }
+            if withdraw_amount > self.stab_vault.amount() {
+                withdraw_amount = self.stab_vault.amount();
+            }
+
+            self.receipt_manager.update_non_fungible_data(
+                &receipt_id,
+                "deposit",
+                receipt.deposit - withdraw_amount,
+            );
+
+            self.total_deposits -= withdraw_amount;
+            if self.total_deposits < dec!(0) {
+                self.total_deposits = dec!(0);
+            }
+
+            Runtime::emit_event(StabilityPoolWithdrawEvent {
+                receipt_id,
+                amount: withdraw_amount,
+                total_deposits: self.total_deposits,
+            });
+
+            (self.stab_vault.take(withdraw_amount), collateral)
+        }
+
+        /// Claim all collateral gained by a deposit
+        ///
+        /// # Input
+        /// - `receipt_id`: The id of the deposit receipt
+        ///
+        /// # Output
+        /// - The collateral gained, one bucket per collateral
+        ///
+        /// # Logic
+        /// - Calculate the collateral gained per collateral and take it from the vaults
+        /// - Update the receipt to the compounded deposit and the current snapshots
+        pub fn claim_collateral(&mut self, receipt_id: NonFungibleLocalId) -> Vec<Bucket> {
+            let receipt: StabilityPoolReceipt =
+                self.receipt_manager.get_non_fungible_data(&receipt_id);
+
+            let mut collateral_buckets: Vec<Bucket> = Vec::new();
+
+            for collateral in self.collaterals.clone() {
+                let mut gain: Decimal = self.collateral_gain(&receipt, collateral);
+                let mut vault = self.collateral_vaults.get_mut(&collateral).unwrap();
+                if gain > vault.amount() {
+                    gain = vault.amount();
+                }
+                if gain > dec!(0) {
+                    collateral_buckets.push(vault.take_advanced(
+                        gain,
+                        WithdrawStrategy::Rounded(RoundingMode::ToZero),
+                    ));
+                }
+            }
+
+            let compounded_deposit: Decimal = self.compounded_deposit(&receipt);
+
+            self.receipt_manager.update_non_fungible_data(
+                &receipt_id,
+                "deposit",
+                compounded_deposit,
+            );
+            self.receipt_manager
+                .update_non_fungible_data(&receipt_id, "p_snapshot", self.p);
+            self.receipt_manager
+                .update_non_fungible_data(&receipt_id, "epoch", self.current_epoch);
+            self.receipt_manager
+                .update_non_fungible_data(&receipt_id, "scale", self.current_scale);
+            self.receipt_manager.update_non_fungible_data(
+                &receipt_id,
+                "s_snapshots",
+                self.current_sums(),
+            );
+
+            collateral_buckets
+        }
+
+        /// Provide STAB to liquidate a loan with
+        ///   - the STAB has to be returned through `absorb_liquidation` or `return_stab` in the same transaction
+        pub fn provide_stab(&mut self, amount: Decimal) -> Bucket {
+            assert!(
+                self.stab_provided == dec!(0),
+                "STAB already provided for a liquidation."
+            );
+            assert!(
+                amount <= self.total_deposits && amount <= self.stab_vault.amount(),
+                "Not enough STAB in the stability pool."
+            );
+            self.stab_provided = amount;
+            self.stab_vault.take(amount)
+        }
+
+        /// Return provided STAB that wasn't used (because the loan was saved)
+        pub fn return_stab(&mut self, stab: Bucket) {
+            assert!(
+                stab.amount() == self.stab_provided,
+                "Return all provided STAB."
+            );
+            self.stab_provided = dec!(0);
+            self.stab_vault.put(stab);
+        }
+
+        /// Absorb a liquidation that used the provided STAB
+        ///
+        /// # Input
+        /// - `collateral`: The collateral received from the liquidation
+        /// - `leftover_stab`: The provided STAB that wasn't used
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Calculate the STAB used, put the leftover STAB and collateral in their vaults
+        /// - Calculate the collateral gained and STAB lost per deposited STAB
+        /// - Increase the running sum of the collateral by the gain per STAB times the product
+        /// - If the pool is emptied, start a new epoch
+        /// - Else, decrease the product by the loss per STAB, scaling it up if it gets too small
+        pub fn absorb_liquidation(&mut self, collateral: Bucket, leftover_stab: Bucket) {
+            let stab_used: Decimal = self.stab_provided - leftover_stab.amount();
+            self.stab_provided = dec!(0);
+            self.stab_vault.put(leftover_stab);
+
+            let collateral_address: ResourceAddress = collateral.resource_address();
+            let collateral_received: Decimal = collateral.amount();
+
+            if self.collateral_vaults.get(&collateral_address).is_none() {
+                self.collateral_vaults
+                    .insert(collateral_address, Vault::new(collateral_address));
+                self.collaterals.push(collateral_address);
+            }
+            self.collateral_vaults
+                .get_mut(&collateral_address)
+                .unwrap()
+                .put(collateral);
+
+            let total: PreciseDecimal = PreciseDecimal::from(self.total_deposits);
+            let gain_per_stab: PreciseDecimal = PreciseDecimal::from(collateral_received) / total;
+            let loss_per_stab: PreciseDecimal = PreciseDecimal::from(stab_used) / total;
+
+            let key = (self.current_epoch, self.current_scale, collateral_address);
+            let current_sum: PreciseDecimal = self.get_sum(key);
+            self.epoch_to_scale_to_sum
+                .insert(key, current_sum + gain_per_stab * self.p);
+
+            if stab_used >= self.total_deposits {
+                self.current_epoch += 1;
+                self.current_scale = 0;
+                self.p = pdec!(1);
+                self.total_deposits = dec!(0);
+            } else {
+                let mut new_p: PreciseDecimal = self.p * (pdec!(1) - loss_per_stab);
+                if new_p < pdec!(1) / scale_factor() {
+                    new_p *= scale_factor();
+                    self.current_scale += 1;
+                }
+                self.p = new_p;
+                self.total_deposits -= stab_used;
+            }
+
+            Runtime::emit_event(StabilityPoolLiquidationEvent {
+                collateral: collateral_address,
+                stab_used,
+                collateral_received,
+                total_deposits: self.total_deposits,
+                epoch: self.current_epoch,
+                scale: self.current_scale,
+            });
+        }
+
+        /// Gets the compounded deposit and collateral gains of a deposit receipt
+        pub fn get_deposit(
+            &self,
+            receipt_id: NonFungibleLocalId,
+        ) -> (Decimal, Vec<(ResourceAddress, Decimal)>) {
+            let receipt: StabilityPoolReceipt =
+                self.receipt_manager.get_non_fungible_data(&receipt_id);
+
+            let gains: Vec<(ResourceAddress, Decimal)> = self
+                .collaterals
+                .iter()
+                .map(|collateral| (*collateral, self.collateral_gain(&receipt, *collateral)))
+                .collect();
+
+            (self.compounded_deposit(&receipt), gains)
+        }
+
+        /// Gets the total STAB deposited
+        pub fn get_total_deposits(&self) -> Decimal {
+            self.total_deposits
+        }
+
+        /// Gets the address of the deposit receipts
+        pub fn get_receipt_address(&self) -> ResourceAddress {
+            self.receipt_manager.address()
+        }
+
+        //HELPER METHODS
+
+        /// Get the running sum for an epoch, scale and collateral
+        fn get_sum(&self, key: (u64, u64, ResourceAddress)) -> PreciseDecimal {
+            self.epoch_to_scale_to_sum
+                .get(&key)
+                .map_or(pdec!(0), |sum| *sum)
+        }
+
+        /// Get the current running sums for all collaterals
+        fn current_sums(&self) -> HashMap<ResourceAddress, PreciseDecimal> {
+            self.collaterals
+                .iter()
+                .map(|collateral| {
+                    (
+                        *collateral,
+                        self.get_sum((self.current_epoch, self.current_scale, *collateral)),
+                    )
+                })
+                .collect()
+        }
+
+        /// Calculate the compounded deposit of a receipt
+        ///   - if the epoch changed since the snapshot, the deposit was completely used
+        ///   - if the scale changed by more than 1, the deposit is negligible
+        fn compounded_deposit(&self, receipt: &StabilityPoolReceipt) -> Decimal {
+            if receipt.epoch < self.current_epoch || receipt.deposit == dec!(0) {
+                return dec!(0);
+            }
+
+            let compounded: PreciseDecimal = match self.current_scale - receipt.scale {
+                0 => PreciseDecimal::from(receipt.deposit) * self.p / receipt.p_snapshot,
+                1 => {
+                    PreciseDecimal::from(receipt.deposit) * self.p
+                        / receipt.p_snapshot
+                        / scale_factor()
+                }
+                _ => pdec!(0),
+            };
+
+            compounded.checked_truncate(RoundingMode::ToZero).unwrap()
+        }
+
+        /// Calculate the collateral gained by a receipt
+        ///   - the gain is the difference between the current sum and the snapshot, plus the sum of the next scale (scaled down)
+        fn collateral_gain(&self, receipt: &StabilityPoolReceipt, collateral: ResourceAddress) -> Decimal {
+            if receipt.deposit == dec!(0) {
+                return dec!(0);
+            }
+
+            let snapshot: PreciseDecimal = receipt
+                .s_snapshots
+                .get(&collateral)
+                .copied()
+                .unwrap_or(pdec!(0));
+
+            let first_portion: PreciseDecimal =
+                self.get_sum((receipt.epoch, receipt.scale, collateral)) - snapshot;
+            let second_portion: PreciseDecimal =
+                self.get_sum((receipt.epoch, receipt.scale + 1, collateral)) / scale_factor();
+
+            (PreciseDecimal::from(receipt.deposit) * (first_portion + second_portion)
+                / receipt.p_snapshot)
+                .checked_truncate(RoundingMode::ToZero)
+                .unwrap()
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! # Surplus Auction Blueprint
+//!
+//! Liquidation fines and redemption fees pile up in the collateral treasuries of the Stabilis component.
+//! The surplus auction sells these treasuries without needing a governance proposal for every sale:
+//! - Governance sets an auction configuration per collateral: a threshold, the token to pay with (STAB or ILIS), and the price curve
+//! - A cancelled auction returns the unsold collateral to the treasury it was taken from
+//! - Once a treasury holds more than its threshold, anyone can start an auction for it (`start_auction`)
+//! - The price starts high and decays every minute (a Dutch auction), until someone buys (part of) the collateral (`bid`)
+//! - The proceeds are burned, or sent to the DAO vaults
+//!
+//! All methods are called through the Proxy component.
+
+use crate::stabilis_component::stabilis_component::*;
+use scrypto::prelude::*;
+use scrypto_math::*;
+
+/// Auction configuration of a collateral, set by governance
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct SurplusAuctionConfig {
+    /// amount of collateral in the treasury above which an auction can be started
+    pub threshold: Decimal,
+    /// token the collateral is sold for (STAB or ILIS)
+    pub payment_token: ResourceAddress,
+    /// starting price of the collateral (in payment tokens)
+    pub starting_price: Decimal,
+    /// factor the price is multiplied by every minute (so 0.999 lowers the price by 0.1% per minute)
+    pub price_decay: Decimal,
+    /// price under which the collateral is never sold (in payment tokens)
+    pub minimum_price: Decimal,
+    /// whether the proceeds are burned (otherwise they are sent to the DAO)
+    pub burn_proceeds: bool,
+}
+
+/// All info about the auctions of a collateral
+#[derive(ScryptoSbor)]
+pub struct SurplusAuction {
+    /// the auction configuration
+    pub config: SurplusAuctionConfig,
+    /// the collateral being sold
+    pub lot: Vault,
+    /// time the running auction started (None if no auction is running)
+    pub start_time: Option<Instant>,
+    /// payment tokens raised by the running auction
+    pub raised: Decimal,
+}
+
+/// Read-only view of the auctions of a collateral
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct SurplusAuctionView {
+    /// address of the collateral
+    pub collateral: ResourceAddress,
+    /// the auction configuration
+    pub config: SurplusAuctionConfig,
+    /// collateral left to sell in the running auction
+    pub lot: Decimal,
+    /// time the running auction started (None if no auction is running)
+    pub start_time: Option<Instant>,
+    /// current price of the collateral (in payment tokens, 0 if no auction is running)
+    pub current_price: Decimal,
+    /// payment tokens raised by the running auction
+    pub raised: Decimal,
+}
+
+/// Event emitted when a surplus auction is started
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SurplusAuctionStartedEvent {
+    pub collateral: ResourceAddress,
+    pub lot: Decimal,
+    pub payment_token: ResourceAddress,
+    pub starting_price: Decimal,
+}
+
+/// Event emitted when collateral is bought in a surplus auction
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SurplusAuctionBidEvent {
+    pub collateral: ResourceAddress,
+    pub collateral_bought: Decimal,
+    pub payment: Decimal,
+    pub price: Decimal,
+    pub burned: bool,
+}
+
+/// Event emitted when a surplus auction ends, because the lot is sold or the auction is cancelled
+#[derive(ScryptoSbor, ScryptoEvent)]
+pub struct SurplusAuctionEndedEvent {
+    pub collateral: ResourceAddress,
+    pub raised: Decimal,
+    pub collateral_returned: Decimal,
+}
+
+#[blueprint]
+#[events(SurplusAuctionStartedEvent, SurplusAuctionBidEvent, SurplusAuctionEndedEvent)]
+mod surplus_auction {
+    enable_method_auth! {
+        methods {
+            start_auction => restrict_to: [OWNER];
+            bid => restrict_to: [OWNER];
+            set_config => restrict_to: [OWNER];
+            set_dao => restrict_to: [OWNER];
+            cancel_auction => restrict_to: [OWNER];
+            get_auction => PUBLIC;
+        }
+    }
+
+    struct SurplusAuctions {
+        /// The vault for the controller badge, used to authorize emptying treasuries and burning STAB
+        badge_vault: FungibleVault,
+        /// The global instance of the Stabilis component
+        stabilis: Global<Stabilis>,
+        /// The resource address of STAB
+        stab_address: ResourceAddress,
+        /// KVS storing the auctions of every configured collateral
+        auctions: KeyValueStore<ResourceAddress, SurplusAuction>,
+        /// The DAO component, receiving the proceeds of auctions that don't burn them
+        dao: Option<Global<AnyComponent>>,
+        /// The resource address of ILIS, the DAO's token (None until the DAO is set)
+        ilis_address: Option<ResourceAddress>,
+    }
+
+    impl SurplusAuctions {
+        /// Instantiates the SurplusAuctions component
+        ///
+        /// # Input
+        /// - `controller_badge`: The controller badge of the Stabilis component
+        /// - `stabilis_address`: The address of the Stabilis component
+        /// - `stab_address`: The resource address of STAB
+        ///
+        /// # Output
+        /// - The global instance of the SurplusAuctions component
+        pub fn instantiate(
+            controller_badge: Bucket,
+            stabilis_address: ComponentAddress,
+            stab_address: ResourceAddress,
+        ) -> Global<SurplusAuctions> {
+            let controller_address: ResourceAddress = controller_badge.resource_address();
+
+            Self {
+                badge_vault: FungibleVault::with_bucket(controller_badge.as_fungible()),
+                stabilis: Global::from(stabilis_address),
+                stab_address,
+                auctions: KeyValueStore::new(),
+                dao: None,
+                ilis_address: None,
+            }
+            .instantiate()
+            .prepare_to_globalize(OwnerRole::Fixed(rule!(require(controller_address))))
+            .globalize()
+        }
+
+        /// Start an auction for the treasury of a collateral
+        ///
+        /// # Input
+        /// - `collateral`: The collateral to auction
+        ///
+        /// # Output
+        /// - None
+        ///
+        /// # Logic
+        /// - Check the collateral is configured, and no auction is running for it
+        /// - Check the treasury holds more than the threshold
+        /// - Take the complete treasury from the Stabilis component as the lot, and start the auction
+        pub fn start_auction(&mut self, collateral: ResourceAddress) {
+            let config: SurplusAuctionConfig = {
+                let auction = self
+                    .auctions
+                    .get(&collateral)
+                    .expect("No surplus auction configured for this collateral.");
+                assert!(auction.start_time.is_none(), "Auction already running.");
+                auction.config.clone()
+            };
+
+            let treasury_amount: Decimal = if self.stabilis.is_pool_unit(collateral) {
+                self.stabilis.get_pool_unit_info(collateral).treasury_amount
+            } else {
+                self.stabilis.get_collateral_info(collateral).treasury_amount
+            };
+            assert!(
+                treasury_amount >= config.threshold && treasury_amount > dec!(0),
+                "Treasury below the auction threshold."
+            );
+
+            let lot: Bucket = self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis
+                    .empty_collateral_treasury(treasury_amount, collateral, false)
+            });
+
+            Runtime::emit_event(SurplusAuctionStartedEvent {
+                collateral,
+                lot: lot.amount(),
+                payment_token: config.payment_token,
+                starting_price: config.starting_price,
+            });
+
+            let mut auction = self.auctions.get_mut(&collateral).unwrap();
+            auction.lot.put(lot);
+            auction.start_time = Some(Clock::current_time_rounded_to_minutes());
+            auction.raised = dec!(0);
+        }
+
+        /// Buy collateral in a running auction
+        ///
+        /// # Input
+        /// - `collateral`: The collateral to buy
+        /// - `payment`: The payment tokens
+        ///
+        /// # Output
+        /// - The bought collateral
+        /// - The leftover payment
+        ///
+        /// # Logic
+        /// - Calculate the current price
+        /// - Buy as much collateral as the payment and lot allow
+        /// - Burn the used payment, or send it to the DAO
+        /// - End the auction if the lot is sold
+        pub fn bid(&mut self, collateral: ResourceAddress, mut payment: Bucket) -> (Bucket, Bucket) {
+            let price: Decimal = Self::auction_price(
+                &self
+                    .auctions
+                    .get(&collateral)
+                    .expect("No surplus auction configured for this collateral."),
+            );
+
+            let (bought_collateral, proceeds, burn_proceeds, sold_out, raised): (
+                Bucket,
+                Bucket,
+                bool,
+                bool,
+                Decimal,
+            ) = {
+                let mut auction = self.auctions.get_mut(&collateral).unwrap();
+                assert!(
+                    payment.resource_address() == auction.config.payment_token,
+                    "Invalid payment token."
+                );
+
+                let collateral_amount: Decimal = (payment.amount() / price).min(auction.lot.amount());
+                assert!(collateral_amount > dec!(0), "Nothing to buy.");
+
+                let proceeds: Bucket = payment.take_advanced(
+                    (collateral_amount * price).min(payment.amount()),
+                    WithdrawStrategy::Rounded(RoundingMode::AwayFromZero),
+                );
+                let bought_collateral: Bucket = auction.lot.take_advanced(
+                    collateral_amount,
+                    WithdrawStrategy::Rounded(RoundingMode::ToZero),
+                );
+                auction.raised += proceeds.amount();
+
+                let sold_out: bool = auction.lot.is_empty();
+                if sold_out {
+                    auction.start_time = None;
+                }
+
+                (
+                    bought_collateral,
+                    proceeds,
+                    auction.config.burn_proceeds,
+                    sold_out,
+                    auction.raised,
+                )
+            };
+
+            Runtime::emit_event(SurplusAuctionBidEvent {
+                collateral,
+                collateral_bought: bought_collateral.amount(),
+                payment: proceeds.amount(),
+                price,
+                burned: burn_proceeds,
+            });
+
+            if burn_proceeds {
+                if proceeds.resource_address() == self.stab_address {
+                    self.badge_vault
+                        .authorize_with_amount(dec!("0.75"), || self.stabilis.burn_stab(proceeds));
+                } else {
+                    proceeds.burn();
+                }
+            } else {
+                self.dao
+                    .as_ref()
+                    .expect("No DAO set to receive the proceeds.")
+                    .call_raw::<()>("put_tokens", scrypto_args!(proceeds));
+            }
+
+            if sold_out {
+                Runtime::emit_event(SurplusAuctionEndedEvent {
+                    collateral,
+                    raised,
+                    collateral_returned: dec!(0),
+                });
+            }
+
+            (bought_collateral, payment)
+        }
+
+        /// Set the auction configuration of a collateral (can't be changed while an auction is running)
+        pub fn set_config(&mut self, collateral: ResourceAddress, config: SurplusAuctionConfig) {
+            assert!(
+                config.payment_token == self.stab_address
+                    || Some(config.payment_token) == self.ilis_address,
+                "Payment token must be STAB or ILIS."
+            );
+            assert!(
+                config.price_decay > dec!(0) && config.price_decay <= dec!(1),
+                "Price decay must be between 0 and 1."
+            );
+            assert!(
+                config.minimum_price > dec!(0) && config.starting_price >= config.minimum_price,
+                "Invalid auction prices."
+            );
+
+            if self.auctions.get(&collateral).is_some() {
+                let mut auction = self.auctions.get_mut(&collateral).unwrap();
+                assert!(
+                    auction.start_time.is_none(),
+                    "Can't change the configuration while an auction is running."
+                );
+                auction.config = config;
+            } else {
+                self.auctions.insert(
+                    collateral,
+                    SurplusAuction {
+                        config,
+                        lot: Vault::new(collateral),
+                        start_time: None,
+                        raised: dec!(0),
+                    },
+                );
+            }
+        }
+
+        /// Set the DAO component receiving the proceeds of auctions that don't burn them, and its ILIS token
+        pub fn set_dao(&mut self, dao_address: ComponentAddress, ilis_address: ResourceAddress) {
+            self.dao = Some(Global::from(dao_address));
+            self.ilis_address = Some(ilis_address);
+        }
+
+        /// Cancel a running auction, returning the unsold collateral to the treasury of the Stabilis component
+        pub fn cancel_auction(&mut self, collateral: ResourceAddress) {
+            let (unsold, raised): (Bucket, Decimal) = {
+                let mut auction = self.auctions.get_mut(&collateral).unwrap();
+                assert!(auction.start_time.is_some(), "No auction running.");
+                auction.start_time = None;
+                (auction.lot.take_all(), auction.raised)
+            };
+
+            Runtime::emit_event(SurplusAuctionEndedEvent {
+                collateral,
+                raised,
+                collateral_returned: unsold.amount(),
+            });
+
+            self.badge_vault.authorize_with_amount(dec!("0.75"), || {
+                self.stabilis.return_collateral_to_treasury(unsold)
+            });
+        }
+
+        /// Gets the auction info of a collateral
+        pub fn get_auction(&self, collateral: ResourceAddress) -> SurplusAuctionView {
+            let auction = self.auctions.get(&collateral).unwrap();
+            let current_price: Decimal = if auction.start_time.is_some() {
+                Self::auction_price(&auction)
+            } else {
+                dec!(0)
+            };
+
+            SurplusAuctionView {
+                collateral,
+                config: auction.config.clone(),
+                lot: auction.lot.amount(),
+                start_time: auction.start_time,
+                current_price,
+                raised: auction.raised,
+            }
+        }
+
+        /// Calculate the current price of a running auction: starting price * decay ^ passed minutes, but never below the minimum price
+        fn auction_price(auction: &SurplusAuction) -> Decimal {
+            let start_time: Instant = auction.start_time.expect("No auction running.");
+            let passed_minutes: Decimal = (Clock::current_time_rounded_to_minutes()
+                .seconds_since_unix_epoch
+                - start_time.seconds_since_unix_epoch)
+                / dec!(60);
+
+            (auction.config.starting_price * auction.config.price_decay.pow(passed_minutes).unwrap())
+                .max(auction.config.minimum_price)
+        }
+    }
+}
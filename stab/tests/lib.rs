@@ -1,5 +1,24 @@
 use scrypto_test::prelude::*;
 use stab_module::stabilis_component::stabilis_component_test::*;
+use stab_module::stability_pool::stability_pool_test::*;
+use stab_module::peg_stability_module::peg_stability_module_test::*;
+use stab_module::surplus_auction::surplus_auction_test::*;
+use stab_module::surplus_auction::SurplusAuctionConfig;
+use stab_module::keeper_rewards::keeper_rewards_test::*;
+use stab_module::keeper_rewards::KeeperRewardConfig;
+use stab_module::price_aggregator::price_aggregator_test::*;
+use stab_module::price_aggregator::{PriceStatus, ReportSignature};
+use stab_module::oracle::oracle_test::*;
+use stab_module::proxy::proxy_test::*;
+use stab_module::proxy::InterestRateStrategy;
+use stab_module::stabilis_liquidity_pool::stabilis_liquidity_pool_test::*;
+use stab_module::shared_structs::{CdpStatus, LiquidationMode, MarkerDepositConfig};
+use stab_module::stabilis_component::{
+    CdpBorrowedMoreEvent, CdpClosedEvent, CdpCollateralRemovedEvent, CdpForceLiquidatedEvent,
+    CdpForceMintedEvent, CdpLiquidatedEvent, CdpMarkedEvent, CdpOpenedEvent,
+    AuctionFilledEvent, CdpPartiallyClosedEvent, CdpRedeemedEvent, CdpSavedEvent, CdpToppedUpEvent,
+    RedemptionEvent, BasketCdpOpenedEvent, MarkerDepositForfeitedEvent, CircuitBreakerTrippedEvent,
+};
 
 // Generic setup
 pub fn publish_and_setup() -> Result<
@@ -34,6 +53,23 @@ pub fn publish_and_setup() -> Result<
     Ok((env, stab_comp, a_bucket, controller_badge))
 }
 
+// Collect all events with the given name that have been emitted in the test environment so far
+pub fn emitted_events<T: ScryptoDecode>(
+    env: &mut TestEnvironment<InMemorySubstateDatabase>,
+    event_name: &str,
+) -> Vec<T> {
+    env.with_kernel_mut(|kernel| {
+        kernel
+            .kernel_get_system()
+            .modules
+            .events()
+            .iter()
+            .filter(|event| event.type_identifier.1 == event_name)
+            .map(|event| scrypto_decode::<T>(&event.payload).unwrap())
+            .collect()
+    })
+}
+
 // Individual tests
 #[test]
 fn deploys() -> Result<(), RuntimeError> {
@@ -316,7 +352,7 @@ fn can_mark_for_liquidation() -> Result<(), RuntimeError> {
         &mut env,
     );
 
-    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
 
     assert!(marker.amount(&mut env)? > dec!(0));
 
@@ -354,7 +390,7 @@ fn can_liquidate_with_marker() -> Result<(), RuntimeError> {
     );
 
     //mark loan
-    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
     let marker_ids = marker.non_fungible_local_ids(&mut env)?;
     let marker_id = marker_ids.first().unwrap();
 
@@ -402,7 +438,7 @@ fn can_liquidate_without_marker_by_id() -> Result<(), RuntimeError> {
     );
 
     //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
 
     //liq without marker
     let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
@@ -449,7 +485,7 @@ fn can_liquidate_without_marker_automatic() -> Result<(), RuntimeError> {
     );
 
     //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
 
     //liq without marker
     let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
@@ -510,7 +546,7 @@ fn correct_liquidation_fines_over_115_cr() -> Result<(), RuntimeError> {
     //liquidator receives 880, stabilis receives 40 and 80 is left in the cdp
 
     //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
 
     //liq without marker
     let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
@@ -585,7 +621,7 @@ fn correct_liquidation_fines_between_110_115_cr() -> Result<(), RuntimeError> {
     //liquidator receives 4400, stabilis receives 100 and 0 is left in the cdp
 
     //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
 
     //liq without marker
     let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
@@ -658,7 +694,7 @@ fn correct_liquidation_fines_below_110_cr() -> Result<(), RuntimeError> {
     //liquidator receives 2100, stabilis receives 0 and 0 is left in the cdp
 
     //mark loan
-    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, &mut env)?;
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
 
     //liq without marker
     let (collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
@@ -821,3 +857,2135 @@ fn fail_force_mint_invalid_collateral() -> Result<(), RuntimeError> {
 
     Ok(())
 }
+
+// Opening a CDP emits an event with the loan details
+#[test]
+fn open_cdp_emits_event() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    let events: Vec<CdpOpenedEvent> = emitted_events(&mut env, "CdpOpenedEvent");
+    assert_eq!(events.len(), 1);
+    let event = events.last().unwrap();
+    assert_eq!(&event.cdp_id, cdp_id);
+    assert_eq!(event.collateral, a_bucket.resource_address(&mut env)?);
+    assert_eq!(event.collateral_amount, dec!(1000));
+    assert_eq!(event.minted_stab, dec!(500));
+    assert_eq!(event.new_cr, dec!(2));
+    assert_eq!(event.collateral_price, dec!(1));
+    assert_eq!(event.internal_price, dec!(1));
+
+    Ok(())
+}
+
+// Closing and partially closing a CDP emit events with the repaid amounts
+#[test]
+fn close_and_partial_close_emit_events() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    stab_comp.partial_close_cdp(cdp_id.clone(), stab.take(dec!(100), &mut env)?, &mut env)?;
+
+    let events: Vec<CdpPartiallyClosedEvent> =
+        emitted_events(&mut env, "CdpPartiallyClosedEvent");
+    let event = events.last().unwrap();
+    assert_eq!(&event.cdp_id, cdp_id);
+    assert_eq!(event.stab_repaid, dec!(100));
+    assert_eq!(event.minted_stab, dec!(400));
+    assert_eq!(event.old_cr, dec!(2));
+    assert_eq!(event.new_cr, dec!("2.5"));
+
+    let (_collateral, _leftover_stab) = stab_comp.close_cdp(cdp_id.clone(), stab, &mut env)?;
+
+    let events: Vec<CdpClosedEvent> = emitted_events(&mut env, "CdpClosedEvent");
+    let event = events.last().unwrap();
+    assert_eq!(&event.cdp_id, cdp_id);
+    assert_eq!(event.collateral_returned, dec!(1000));
+    assert_eq!(event.stab_repaid, dec!(400));
+    assert_eq!(event.old_cr, dec!("2.5"));
+
+    Ok(())
+}
+
+// Borrowing more, topping up and removing collateral emit events with old and new CRs
+#[test]
+fn cdp_adjustments_emit_events() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    let _additional_stab = stab_comp.borrow_more(cdp_id.clone(), dec!(125), &mut env)?;
+
+    let events: Vec<CdpBorrowedMoreEvent> = emitted_events(&mut env, "CdpBorrowedMoreEvent");
+    let event = events.last().unwrap();
+    assert_eq!(event.stab_borrowed, dec!(125));
+    assert_eq!(event.minted_stab, dec!(625));
+    assert_eq!(event.old_cr, dec!(2));
+    assert_eq!(event.new_cr, dec!("1.6"));
+
+    stab_comp.top_up_cdp(cdp_id.clone(), a_bucket.take(dec!(600), &mut env)?, &mut env)?;
+
+    let events: Vec<CdpToppedUpEvent> = emitted_events(&mut env, "CdpToppedUpEvent");
+    let event = events.last().unwrap();
+    assert_eq!(event.collateral_added, dec!(600));
+    assert_eq!(event.collateral_amount, dec!(1600));
+    assert_eq!(event.old_cr, dec!("1.6"));
+    assert_eq!(event.new_cr, dec!("2.56"));
+    assert!(!event.was_marked);
+
+    let _removed = stab_comp.remove_collateral(cdp_id.clone(), dec!(200), &mut env)?;
+
+    let events: Vec<CdpCollateralRemovedEvent> =
+        emitted_events(&mut env, "CdpCollateralRemovedEvent");
+    let event = events.last().unwrap();
+    assert_eq!(event.collateral_removed, dec!(200));
+    assert_eq!(event.collateral_amount, dec!(1400));
+    assert_eq!(event.old_cr, dec!("2.56"));
+    assert_eq!(event.new_cr, dec!("2.24"));
+
+    Ok(())
+}
+
+// Marking and liquidating a CDP emit events with the fines paid out
+#[test]
+fn mark_and_liquidate_emit_events() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+    let _col_price =
+        stab_comp.change_collateral_price(a_bucket.resource_address(&mut env)?, dec!(1), &mut env);
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    let events: Vec<CdpMarkedEvent> = emitted_events(&mut env, "CdpMarkedEvent");
+    let event = events.last().unwrap();
+    assert_eq!(&event.cdp_id, cdp_id);
+    assert_eq!(&event.marker_id, marker_id);
+    assert_eq!(event.old_cr, dec!("2.5"));
+    assert_eq!(event.new_cr, dec!("2.5"));
+    assert_eq!(event.collateral_price, dec!(1));
+    assert_eq!(event.internal_price, dec!(2));
+
+    let (_collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_with_marker(
+            marker_id.clone(),
+            free_stab.take(dec!(500), &mut env)?,
+            &mut env,
+        )?;
+
+    let events: Vec<CdpLiquidatedEvent> = emitted_events(&mut env, "CdpLiquidatedEvent");
+    let event = events.last().unwrap();
+    assert_eq!(&event.cdp_id, cdp_id);
+    assert_eq!(&event.marker_id, marker_id);
+    assert_eq!(event.stab_paid, dec!(400));
+    assert_eq!(event.collateral_to_liquidator, dec!(880));
+    assert_eq!(event.collateral_to_treasury, dec!(40));
+    assert_eq!(event.leftover_collateral, dec!(80));
+    assert_eq!(event.cr, dec!("2.5"));
+
+    Ok(())
+}
+
+// Liquidating an undercollateralized CDP records bad debt, which is cancelled by the surplus buffer and a debt auction
+#[test]
+fn bad_debt_is_recorded_and_cancelled() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let stab_address = stab.resource_address(&mut env)?;
+
+    let free_stab =
+        BucketFactory::create_fungible_bucket(stab_address, dec!(100000), Mock, &mut env)?;
+
+    // collateral is worth 330, debt 400
+    stab_comp.change_collateral_price(a_address, dec!("0.33"), &mut env)?;
+
+    let marker = stab_comp.mark_for_liquidation(a_address, None, &mut env)?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let (collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_with_marker(marker_id, free_stab.take(dec!(400), &mut env)?, &mut env)?;
+
+    // the liquidator pays 330 / 1.1 = 300 STAB for all collateral, the other 100 stay in circulation as bad debt
+    assert_eq!(collateral_reward.amount(&mut env)?, dec!(1000));
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(100));
+    assert_eq!(stab_comp.get_bad_debt(&mut env)?, dec!(100));
+    let info = stab_comp.get_collateral_info(a_address, &mut env)?;
+    assert_eq!(info.bad_debt, dec!(100));
+
+    // the surplus buffer can only cancel what it holds
+    stab_comp.deposit_surplus(free_stab.take(dec!(70), &mut env)?, &mut env)?;
+    stab_comp.cancel_bad_debt(dec!(100), &mut env)?;
+    assert_eq!(stab_comp.get_bad_debt(&mut env)?, dec!(30));
+    assert_eq!(stab_comp.get_surplus_buffer(&mut env)?, dec!(0));
+
+    let ilis_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(100, &mut env)?;
+    stab_comp.start_debt_auction(ilis_bucket, dec!(1), dec!("0.5"), dec!("0.25"), &mut env)?;
+
+    let new_time = env.get_current_time().add_minutes(1).unwrap();
+    env.set_current_time(new_time);
+
+    let auction = stab_comp.get_debt_auction(&mut env)?.unwrap();
+    assert!((auction.current_price - dec!("0.5")).checked_abs().unwrap() < dec!("0.000001"));
+
+    let (ilis, leftover_stab) =
+        stab_comp.bid_debt_auction(free_stab.take(dec!(100), &mut env)?, &mut env)?;
+    assert!((ilis.amount(&mut env)? - dec!(60)).checked_abs().unwrap() < dec!("0.0001"));
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(70));
+    assert_eq!(stab_comp.get_bad_debt(&mut env)?, dec!(0));
+
+    let unsold = stab_comp.end_debt_auction(&mut env)?;
+    assert!((unsold.amount(&mut env)? - dec!(40)).checked_abs().unwrap() < dec!("0.0001"));
+    assert!(stab_comp.get_debt_auction(&mut env)?.is_none());
+
+    Ok(())
+}
+
+// Marking and liquidating in batches handles every liquidatable CDP, and stops at the first healthy one
+#[test]
+fn can_mark_and_liquidate_in_batches() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(2000), &mut env)?, dec!(400), &mut env)?;
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(10000),
+        Mock,
+        &mut env,
+    )?;
+
+    // liquidation collateral ratio becomes 3, so only the loans with a cr of 2 and 2.5 can be marked
+    stab_comp.change_internal_price(dec!(2), &mut env)?;
+
+    let (markers, _deposit) =
+        stab_comp.mark_for_liquidation_batch(a_address, 10, None, &mut env)?;
+    assert_eq!(markers.len(), 2);
+    assert!(stab_comp.mark_for_liquidation_batch(a_address, 10, None, &mut env).is_err());
+
+    let (rewards, leftover_stab, receipts) =
+        stab_comp.liquidate_batch(free_stab.take(dec!(1000), &mut env)?, 10, &mut env)?;
+    assert_eq!(rewards.len(), 2);
+    assert_eq!(receipts.len(), 2);
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(100));
+
+    let (active, _marked) = stab_comp.get_marked_cdps(10, &mut env)?;
+    assert_eq!(active, 0);
+
+    Ok(())
+}
+
+// Any undercollateralized CDP can be marked by id, not just the one with the lowest CR
+#[test]
+fn can_mark_cdp_by_id() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, _lowest_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let (_stab, healthy_cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(2000), &mut env)?, dec!(400), &mut env)?;
+
+    let cdp = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let healthy_cdp = healthy_cdp
+        .non_fungible_local_ids(&mut env)?
+        .first()
+        .unwrap()
+        .clone();
+
+    // liquidation collateral ratio becomes 3, so the loans with a cr of 2 and 2.5 can be marked
+    stab_comp.change_internal_price(dec!(2), &mut env)?;
+
+    assert!(stab_comp.mark_cdp(healthy_cdp, None, &mut env).is_err());
+
+    let marker = stab_comp.mark_cdp(cdp.clone(), None, &mut env)?;
+    assert_eq!(marker.amount(&mut env)?, dec!(1));
+
+    let events = emitted_events::<CdpMarkedEvent>(&mut env, "CdpMarkedEvent");
+    assert_eq!(events.last().unwrap().cdp_id, cdp);
+
+    // an already marked loan can't be marked again
+    assert!(stab_comp.mark_cdp(cdp, None, &mut env).is_err());
+
+    let (active, _marked) = stab_comp.get_marked_cdps(10, &mut env)?;
+    assert_eq!(active, 1);
+
+    Ok(())
+}
+
+// Liquidating a CDP that has become healthy again emits a saved event
+#[test]
+fn save_emits_event() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdps = cdp.non_fungible_local_ids(&mut env)?;
+    let cdp_id = cdps.first().unwrap();
+
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(0.5),
+        &mut env,
+    );
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
+    let marker_ids = marker.non_fungible_local_ids(&mut env)?;
+    let marker_id = marker_ids.first().unwrap();
+
+    //price recovers before the liquidation, so the loan is saved
+    let _ = stab_comp.change_collateral_price(
+        a_bucket.resource_address(&mut env)?,
+        dec!(1),
+        &mut env,
+    );
+
+    let (returned_stab, _none, saved_marker) = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        stab.take(dec!(500), &mut env)?,
+        &mut env,
+    )?;
+    assert_eq!(returned_stab.amount(&mut env)?, dec!(500));
+
+    let saved_marker_ids = saved_marker.non_fungible_local_ids(&mut env)?;
+
+    let events: Vec<CdpSavedEvent> = emitted_events(&mut env, "CdpSavedEvent");
+    let event = events.last().unwrap();
+    assert_eq!(&event.cdp_id, cdp_id);
+    assert_eq!(&event.marker_id, marker_id);
+    assert_eq!(&event.saved_marker_id, saved_marker_ids.first().unwrap());
+    assert_eq!(event.old_cr, dec!(2));
+    assert_eq!(event.new_cr, dec!(2));
+    assert_eq!(event.collateral_price, dec!(1));
+
+    Ok(())
+}
+
+// Marking requires a deposit when set, which is partly forfeited when the marked loan is saved
+#[test]
+fn can_forfeit_and_refund_marker_deposit() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    stab_comp.set_marker_deposit(
+        Some(MarkerDepositConfig {
+            token: a_address,
+            amount: dec!(10),
+            forfeit_share: dec!("0.5"),
+        }),
+        &mut env,
+    )?;
+
+    let _ = stab_comp.change_collateral_price(a_address, dec!(0.5), &mut env);
+
+    // marking without a deposit, or with a too small one, fails
+    assert!(stab_comp.mark_for_liquidation(a_address, None, &mut env).is_err());
+    assert!(stab_comp
+        .mark_for_liquidation(a_address, Some(a_bucket.take(dec!(5), &mut env)?), &mut env)
+        .is_err());
+
+    let marker = stab_comp.mark_for_liquidation(
+        a_address,
+        Some(a_bucket.take(dec!(10), &mut env)?),
+        &mut env,
+    )?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    // deposit can't be retrieved before the mark is settled
+    assert!(stab_comp.retrieve_marker_deposit(marker_id.clone(), &mut env).is_err());
+
+    //price recovers before the liquidation, so the loan is saved
+    let _ = stab_comp.change_collateral_price(a_address, dec!(1), &mut env);
+    let (_returned_stab, _none, _saved_marker) = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        stab.take(dec!(500), &mut env)?,
+        &mut env,
+    )?;
+
+    let events: Vec<MarkerDepositForfeitedEvent> =
+        emitted_events(&mut env, "MarkerDepositForfeitedEvent");
+    assert_eq!(events.last().unwrap().forfeited, dec!(5));
+
+    let refund = stab_comp.retrieve_marker_deposit(marker_id.clone(), &mut env)?;
+    assert_eq!(refund.amount(&mut env)?, dec!(5));
+    assert!(stab_comp.retrieve_marker_deposit(marker_id, &mut env).is_err());
+
+    // a liquidated loan refunds the whole deposit
+    let _ = stab_comp.change_collateral_price(a_address, dec!(0.5), &mut env);
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(1000),
+        Mock,
+        &mut env,
+    )?;
+    let marker = stab_comp.mark_for_liquidation(
+        a_address,
+        Some(a_bucket.take(dec!(10), &mut env)?),
+        &mut env,
+    )?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let (_collateral, _leftover_stab, _receipt) = stab_comp.liquidate_position_with_marker(
+        marker_id.clone(),
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    )?;
+
+    let refund = stab_comp.retrieve_marker_deposit(marker_id, &mut env)?;
+    assert_eq!(refund.amount(&mut env)?, dec!(10));
+
+    Ok(())
+}
+
+// Force liquidating and force minting emit events
+#[test]
+fn force_actions_emit_events() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
+
+    let (_minted_stab, _leftover_collateral) = stab_comp.force_mint(
+        a_bucket.resource_address(&mut env)?,
+        a_bucket.take(dec!(100), &mut env)?,
+        dec!(1),
+        &mut env,
+    )?;
+
+    let events: Vec<CdpForceMintedEvent> = emitted_events(&mut env, "CdpForceMintedEvent");
+    let event = events.last().unwrap();
+    assert_eq!(event.collateral_added, dec!(100));
+    assert_eq!(event.stab_minted, dec!(100));
+    assert_eq!(event.old_cr, dec!(10));
+    assert_eq!(event.new_cr, dec!("5.5"));
+
+    let (_returned_collateral, _leftover_stab) = stab_comp.force_liquidate(
+        a_bucket.resource_address(&mut env)?,
+        stab.take(dec!(10), &mut env)?,
+        dec!(1),
+        false,
+        &mut env,
+    )?;
+
+    let events: Vec<CdpForceLiquidatedEvent> =
+        emitted_events(&mut env, "CdpForceLiquidatedEvent");
+    let event = events.last().unwrap();
+    assert_eq!(event.stab_paid, dec!(10));
+    assert_eq!(event.collateral_taken, dec!(10));
+    assert!(!event.fully_liquidated);
+
+    Ok(())
+}
+
+// The read-only query methods return the state of collaterals, loans and parameters
+#[test]
+fn can_query_collateral_and_cdps() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let (_stab_2, cdp_2) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let cdp_id_2 = cdp_2.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let info = stab_comp.get_collateral_info(a_bucket.resource_address(&mut env)?, &mut env)?;
+    assert_eq!(info.minted_stab, dec!(900));
+    assert_eq!(info.collateral_amount, dec!(2000));
+    assert_eq!(info.vault_amount, dec!(2000));
+    assert_eq!(info.stab_share, dec!(1));
+    assert_eq!(info.mcr, dec!("1.5"));
+    assert_eq!(info.highest_cr, dec!("2.5"));
+
+    let lowest_crs =
+        stab_comp.get_lowest_crs(a_bucket.resource_address(&mut env)?, 1, &mut env)?;
+    assert_eq!(lowest_crs, vec![(dec!(2), cdp_id_2.clone())]);
+
+    let lowest_crs =
+        stab_comp.get_lowest_crs(a_bucket.resource_address(&mut env)?, 10, &mut env)?;
+    assert_eq!(
+        lowest_crs,
+        vec![(dec!(2), cdp_id_2), (dec!("2.5"), cdp_id.clone())]
+    );
+
+    let health = stab_comp.get_cdp_health(cdp_id, &mut env)?;
+    assert!(health.status == CdpStatus::Healthy);
+    assert_eq!(health.current_cr, dec!("2.5"));
+    assert_eq!(health.cr_percentage, dec!("2.5"));
+    assert_eq!(health.liquidation_price, dec!("0.6"));
+    assert!(!health.liquidatable);
+
+    let parameters = stab_comp.get_protocol_parameters(&mut env)?;
+    assert_eq!(parameters.liquidation_delay, 0);
+
+    Ok(())
+}
+
+// Marked loans show up in the marked queue and are reported as liquidatable
+#[test]
+fn can_query_marked_cdps() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+
+    let health = stab_comp.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert!(health.liquidatable);
+    assert_eq!(health.liquidation_price, dec!("1.2"));
+
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
+
+    let (active, marked_cdps) = stab_comp.get_marked_cdps(10, &mut env)?;
+    assert_eq!(active, 1);
+    assert_eq!(marked_cdps.len(), 1);
+    assert_eq!(marked_cdps[0].1, cdp_id.clone());
+
+    let health = stab_comp.get_cdp_health(cdp_id, &mut env)?;
+    assert!(health.status == CdpStatus::Marked);
+
+    Ok(())
+}
+
+// Redeeming STAB takes collateral from the loans with the lowest collateral ratio first
+#[test]
+fn can_redeem_against_lowest_crs() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (_stab_2, cdp_2) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let cdp_id_2 = cdp_2.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let (collateral, leftover_stab) = stab_comp.redeem(
+        a_bucket.resource_address(&mut env)?,
+        free_stab.take(dec!(600), &mut env)?,
+        &mut env,
+    )?;
+
+    assert_eq!(collateral.amount(&mut env)?, dec!(597));
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
+
+    let events: Vec<RedemptionEvent> = emitted_events(&mut env, "RedemptionEvent");
+    let event = events.last().unwrap();
+    assert_eq!(event.stab_redeemed, dec!(600));
+    assert_eq!(event.fee, dec!(3));
+
+    let events: Vec<CdpRedeemedEvent> = emitted_events(&mut env, "CdpRedeemedEvent");
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].cdp_id, cdp_id.clone());
+    assert!(events[0].fully_redeemed);
+    assert_eq!(events[1].cdp_id, cdp_id_2.clone());
+    assert_eq!(events[1].new_cr, dec!(3));
+
+    let info = stab_comp.get_collateral_info(a_bucket.resource_address(&mut env)?, &mut env)?;
+    assert_eq!(info.minted_stab, dec!(300));
+    assert_eq!(info.treasury_amount, dec!(3));
+
+    let lowest_crs =
+        stab_comp.get_lowest_crs(a_bucket.resource_address(&mut env)?, 10, &mut env)?;
+    assert_eq!(lowest_crs, vec![(dec!(3), cdp_id_2)]);
+
+    let health = stab_comp.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert!(health.status == CdpStatus::Redeemed);
+
+    let leftover_collateral = stab_comp.retrieve_leftover_collateral(cdp_id, &mut env)?;
+    assert_eq!(leftover_collateral.amount(&mut env)?, dec!(500));
+
+    Ok(())
+}
+
+// Redemptions can be stopped
+#[test]
+fn cant_redeem_when_stopped() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    stab_comp.set_redemption_parameters(true, dec!("0.005"), 10, &mut env)?;
+
+    let result = stab_comp.redeem(
+        a_bucket.resource_address(&mut env)?,
+        stab.take(dec!(100), &mut env)?,
+        &mut env,
+    );
+
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+// A partial liquidation only repays enough debt to restore the loan above the liquidation collateral ratio plus buffer
+#[test]
+fn can_partially_liquidate() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let (_collateral_reward, leftover_stab, _liquidation_receipt) = stab_comp
+        .partial_liquidate_position_with_marker(
+            marker_id,
+            free_stab.take(dec!(400), &mut env)?,
+            &mut env,
+        )?;
+
+    let events: Vec<CdpLiquidatedEvent> = emitted_events(&mut env, "CdpLiquidatedEvent");
+    let event = events.last().unwrap();
+    assert!(event.partial);
+    assert!(event.stab_paid < dec!(400));
+    assert_eq!(
+        leftover_stab.unwrap().amount(&mut env)?,
+        dec!(400) - event.stab_paid
+    );
+
+    let health = stab_comp.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert!(health.status == CdpStatus::Healthy);
+    assert_eq!(health.minted_stab, dec!(400) - event.stab_paid);
+    assert!((health.current_cr - dec!("3.15")).checked_abs().unwrap() < dec!("0.000001"));
+    assert!(!health.liquidatable);
+
+    let lowest_crs =
+        stab_comp.get_lowest_crs(a_bucket.resource_address(&mut env)?, 1, &mut env)?;
+    assert_eq!(lowest_crs[0].1, cdp_id);
+
+    Ok(())
+}
+
+// If the loan can't pay the complete fines, a partial liquidation falls back to a complete liquidation
+#[test]
+fn partial_liquidation_falls_back_to_complete() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _stab_price = stab_comp.change_internal_price(dec!("2.2"), &mut env);
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let (_collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .partial_liquidate_position_with_marker(
+            marker_id,
+            free_stab.take(dec!(500), &mut env)?,
+            &mut env,
+        )?;
+
+    let events: Vec<CdpLiquidatedEvent> = emitted_events(&mut env, "CdpLiquidatedEvent");
+    let event = events.last().unwrap();
+    assert!(!event.partial);
+    assert_eq!(event.stab_paid, dec!(400));
+
+    let health = stab_comp.get_cdp_health(cdp_id, &mut env)?;
+    assert!(health.status == CdpStatus::Liquidated);
+
+    Ok(())
+}
+
+// Marked loans of an auction mode collateral are sold at a discount increasing over time, leftovers go to the owner
+#[test]
+fn can_fill_liquidation_auction() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    stab_comp.set_liquidation_mode(
+        a_bucket.resource_address(&mut env)?,
+        LiquidationMode::Auction,
+        &mut env,
+    )?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+
+    let marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    assert_eq!(stab_comp.get_auction_discount(cdp_id.clone(), &mut env)?, dec!(0));
+
+    let result = stab_comp.liquidate_position_with_marker(
+        marker_id,
+        free_stab.take(dec!(400), &mut env)?,
+        &mut env,
+    );
+    assert!(result.is_err());
+
+    let new_time = env.get_current_time().add_minutes(60).unwrap();
+    env.set_current_time(new_time);
+
+    assert_eq!(
+        stab_comp.get_auction_discount(cdp_id.clone(), &mut env)?,
+        dec!("0.06")
+    );
+
+    let (bought_collateral, leftover_stab, _receipt) =
+        stab_comp.fill_auction(cdp_id.clone(), free_stab.take(dec!(200), &mut env)?, &mut env)?;
+
+    assert_eq!(bought_collateral.amount(&mut env)?, dec!(424));
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
+
+    let events: Vec<AuctionFilledEvent> = emitted_events(&mut env, "AuctionFilledEvent");
+    let event = events.last().unwrap();
+    assert_eq!(event.collateral_to_treasury, dec!(20));
+    assert_eq!(event.remaining_stab, dec!(200));
+    assert!(!event.completed);
+
+    // the stored collateral ratio follows the remaining loan: 556 / 200
+    let health = stab_comp.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert_eq!(health.stored_cr, dec!("2.78"));
+    assert_eq!(health.stored_cr, health.current_cr);
+
+    let (bought_collateral, leftover_stab, _receipt) =
+        stab_comp.fill_auction(cdp_id.clone(), free_stab.take(dec!(500), &mut env)?, &mut env)?;
+
+    assert_eq!(bought_collateral.amount(&mut env)?, dec!(424));
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(300));
+
+    let health = stab_comp.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert!(health.status == CdpStatus::Liquidated);
+
+    let leftover_collateral = stab_comp.retrieve_leftover_collateral(cdp_id, &mut env)?;
+    assert_eq!(leftover_collateral.amount(&mut env)?, dec!(112));
+
+    Ok(())
+}
+
+// The stability pool liquidates a marked loan and shares the collateral pro rata among depositors
+#[test]
+fn stability_pool_absorbs_liquidation() -> Result<(), RuntimeError> {
+    let mut env = TestEnvironment::new();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let (mut stab_comp, controller_badge) = Stabilis::instantiate(package, &mut env)?;
+
+    let a_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+
+    stab_comp.add_collateral(
+        a_bucket.resource_address(&mut env)?,
+        dec!("1.5"),
+        dec!("1"),
+        &mut env,
+    )?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let mut stability_pool = StabilityPool::instantiate(
+        controller_badge.resource_address(&mut env)?,
+        stab.resource_address(&mut env)?,
+        package,
+        &mut env,
+    )?;
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(100000),
+        Mock,
+        &mut env,
+    )?;
+
+    let receipt_a = stability_pool.deposit(free_stab.take(dec!(800), &mut env)?, &mut env)?;
+    let receipt_b = stability_pool.deposit(free_stab.take(dec!(200), &mut env)?, &mut env)?;
+    let receipt_a_id = receipt_a.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let receipt_b_id = receipt_b.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+    let _marker = stab_comp.mark_for_liquidation(a_bucket.resource_address(&mut env)?, None, &mut env)?;
+
+    let pool_stab = stability_pool.provide_stab(dec!(400), &mut env)?;
+    let (collateral, leftover_stab, _receipt) =
+        stab_comp.liquidate_position_without_marker(pool_stab, None, cdp_id, &mut env)?;
+    stability_pool.absorb_liquidation(collateral, leftover_stab.unwrap(), &mut env)?;
+
+    assert_eq!(stability_pool.get_total_deposits(&mut env)?, dec!(600));
+
+    let (deposit_b, gains_b) = stability_pool.get_deposit(receipt_b_id, &mut env)?;
+    assert_eq!(deposit_b, dec!(120));
+    assert_eq!(gains_b[0].1, dec!(176));
+
+    let (withdrawn_stab, collateral_gains) =
+        stability_pool.withdraw(receipt_a_id.clone(), dec!(1000), &mut env)?;
+    assert_eq!(withdrawn_stab.amount(&mut env)?, dec!(480));
+    assert_eq!(collateral_gains[0].amount(&mut env)?, dec!(704));
+
+    let (deposit_a, gains_a) = stability_pool.get_deposit(receipt_a_id, &mut env)?;
+    assert_eq!(deposit_a, dec!(0));
+    assert_eq!(gains_a[0].1, dec!(0));
+
+    Ok(())
+}
+
+// Stability fees are added to the debt on interaction, and minted into the surplus vault
+#[test]
+fn stability_fee_accrues_on_interaction() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+
+    stab_comp.set_stability_fee(a_bucket.resource_address(&mut env)?, dec!("1.001"), &mut env)?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let new_time = env.get_current_time().add_minutes(1).unwrap();
+    env.set_current_time(new_time);
+
+    let health = stab_comp.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert!((health.pending_fee - dec!("0.1")).checked_abs().unwrap() < dec!("0.000001"));
+    assert_eq!(health.minted_stab, dec!(100));
+
+    let _borrowed = stab_comp.borrow_more(cdp_id.clone(), dec!(10), &mut env)?;
+
+    let health = stab_comp.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert_eq!(health.pending_fee, dec!(0));
+    assert!((health.minted_stab - dec!("110.1")).checked_abs().unwrap() < dec!("0.000001"));
+    assert_eq!(
+        stab_comp.get_stab_surplus(&mut env)?,
+        health.minted_stab - dec!(110)
+    );
+
+    let info = stab_comp.get_collateral_info(a_bucket.resource_address(&mut env)?, &mut env)?;
+    assert_eq!(info.minted_stab, health.minted_stab);
+    assert!((info.debt_index - dec!("1.001")).checked_abs().unwrap() < dec!("0.000001"));
+
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(1000),
+        Mock,
+        &mut env,
+    )?;
+
+    let (_collateral, leftover_stab) =
+        stab_comp.close_cdp(cdp_id, free_stab.take(dec!(200), &mut env)?, &mut env)?;
+    assert_eq!(
+        leftover_stab.amount(&mut env)?,
+        dec!(200) - health.minted_stab
+    );
+
+    Ok(())
+}
+
+// Setup with a second collateral (mcr 2, price 1), to open basket loans
+fn add_second_collateral(
+    env: &mut TestEnvironment<InMemorySubstateDatabase>,
+    stab_comp: &mut Stabilis,
+) -> Result<Bucket, RuntimeError> {
+    let b_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, env)?;
+
+    stab_comp.add_collateral(b_bucket.resource_address(env)?, dec!(2), dec!(1), env)?;
+
+    Ok(b_bucket)
+}
+
+// Can swap the collateral of a loan, moving its debt to the new collateral
+#[test]
+fn can_swap_cdp_collateral() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let b_bucket = add_second_collateral(&mut env, &mut stab_comp)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+    let b_address = b_bucket.resource_address(&mut env)?;
+
+    let (_stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    // 500 STAB needs 1000 B at an mcr of 2
+    let result = stab_comp.swap_cdp_collateral(
+        cdp_id.clone(),
+        b_bucket.take(dec!(999), &mut env)?,
+        &mut env,
+    );
+    assert!(result.is_err());
+
+    let old_collateral = stab_comp.swap_cdp_collateral(
+        cdp_id.clone(),
+        b_bucket.take(dec!(1100), &mut env)?,
+        &mut env,
+    )?;
+    assert_eq!(old_collateral.resource_address(&mut env)?, a_address);
+    assert_eq!(old_collateral.amount(&mut env)?, dec!(1000));
+
+    let a_info = stab_comp.get_collateral_info(a_address, &mut env)?;
+    assert_eq!(a_info.minted_stab, dec!(0));
+    let b_info = stab_comp.get_collateral_info(b_address, &mut env)?;
+    assert_eq!(b_info.minted_stab, dec!(500));
+
+    let health = stab_comp.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert_eq!(health.collateral, b_address);
+    assert_eq!(health.collateral_amount, dec!(1100));
+    assert_eq!(health.minted_stab, dec!(500));
+    assert_eq!(health.stored_cr, dec!("2.2"));
+
+    let result = stab_comp.swap_cdp_collateral(
+        cdp_id,
+        b_bucket.take(dec!(1100), &mut env)?,
+        &mut env,
+    );
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+// Can open, manage and close a basket loan backed by two collaterals
+#[test]
+fn can_open_and_close_basket_cdp() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let b_bucket = add_second_collateral(&mut env, &mut stab_comp)?;
+
+    // borrowing power = 300 / 1.5 + 400 / 2 = 400
+    let result = stab_comp.open_basket_cdp(
+        vec![
+            a_bucket.take(dec!(300), &mut env)?,
+            b_bucket.take(dec!(400), &mut env)?,
+        ],
+        dec!(401),
+        &mut env,
+    );
+    assert!(result.is_err());
+
+    let (stab, basket) = stab_comp.open_basket_cdp(
+        vec![
+            a_bucket.take(dec!(300), &mut env)?,
+            b_bucket.take(dec!(400), &mut env)?,
+        ],
+        dec!(200),
+        &mut env,
+    )?;
+    let basket_id = basket.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let health = stab_comp.get_basket_health(basket_id.clone(), &mut env)?;
+    assert_eq!(health.current_health, dec!(2));
+    assert_eq!(health.collateral_value, dec!(700));
+    assert_eq!(health.weighted_mcr, dec!("1.75"));
+    assert!(!health.liquidatable);
+
+    let more_stab = stab_comp.borrow_more_basket(basket_id.clone(), dec!(100), &mut env)?;
+    assert_eq!(more_stab.amount(&mut env)?, dec!(100));
+
+    // removing 100 B lowers the borrowing power to 350, which is still enough for 300 STAB
+    let removed = stab_comp.remove_basket_collateral(
+        basket_id.clone(),
+        b_bucket.resource_address(&mut env)?,
+        dec!(100),
+        &mut env,
+    )?;
+    assert_eq!(removed.amount(&mut env)?, dec!(100));
+    assert!(stab_comp
+        .remove_basket_collateral(
+            basket_id.clone(),
+            a_bucket.resource_address(&mut env)?,
+            dec!(100),
+            &mut env,
+        )
+        .is_err());
+
+    let lowest = stab_comp.get_lowest_basket_healths(10, &mut env)?;
+    assert_eq!(lowest.len(), 1);
+    assert_eq!(lowest[0].1, basket_id);
+
+    // a healthy basket only gets its stored health refreshed when trying to mark it
+    assert!(!stab_comp.mark_basket_for_liquidation(None, &mut env)?);
+
+    stab.put(more_stab, &mut env)?;
+    let (collaterals, leftover_stab) = stab_comp.close_basket_cdp(basket_id.clone(), stab, &mut env)?;
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
+    assert_eq!(collaterals[0].amount(&mut env)?, dec!(300));
+    assert_eq!(collaterals[1].amount(&mut env)?, dec!(300));
+
+    let health = stab_comp.get_basket_health(basket_id, &mut env)?;
+    assert!(health.status == CdpStatus::Closed);
+    assert!(stab_comp.get_lowest_basket_healths(10, &mut env)?.is_empty());
+
+    let events: Vec<BasketCdpOpenedEvent> = emitted_events(&mut env, "BasketCdpOpenedEvent");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].health, dec!(2));
+
+    Ok(())
+}
+
+// A marked basket loan is liquidated by seizing the same share of every collateral
+#[test]
+fn basket_liquidation_seizes_collateral_pro_rata() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let b_bucket = add_second_collateral(&mut env, &mut stab_comp)?;
+
+    let (stab, basket) = stab_comp.open_basket_cdp(
+        vec![
+            a_bucket.take(dec!(300), &mut env)?,
+            b_bucket.take(dec!(400), &mut env)?,
+        ],
+        dec!(300),
+        &mut env,
+    )?;
+    let basket_id = basket.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    // health = 400 / (300 * 2) = 2/3
+    let _stab_price = stab_comp.change_internal_price(dec!(2), &mut env);
+    assert!(stab_comp.get_basket_health(basket_id.clone(), &mut env)?.liquidatable);
+    assert!(stab_comp.mark_basket_for_liquidation(Some(basket_id.clone()), &mut env)?);
+    let free_stab = BucketFactory::create_fungible_bucket(
+        stab.resource_address(&mut env)?,
+        dec!(1000),
+        Mock,
+        &mut env,
+    )?;
+    assert!(stab_comp
+        .close_basket_cdp(basket_id.clone(), free_stab, &mut env)
+        .is_err());
+
+    let (seized, leftover_stab) = stab_comp.liquidate_basket(basket_id.clone(), stab, &mut env)?;
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(0));
+
+    // debt value = 600, collateral value = 700: the liquidator receives 660 / 700 and the treasury 30 / 700 of every collateral
+    let seized_a = seized[0].amount(&mut env)?;
+    let seized_b = seized[1].amount(&mut env)?;
+    assert!((seized_a - dec!(300) * dec!(660) / dec!(700)).checked_abs().unwrap() < dec!("0.000001"));
+    assert!((seized_b - dec!(400) * dec!(660) / dec!(700)).checked_abs().unwrap() < dec!("0.000001"));
+
+    let info = stab_comp.get_collateral_info(a_bucket.resource_address(&mut env)?, &mut env)?;
+    assert!((info.treasury_amount - dec!(300) * dec!(30) / dec!(700)).checked_abs().unwrap() < dec!("0.000001"));
+
+    let leftovers = stab_comp.retrieve_leftover_basket_collateral(basket_id.clone(), &mut env)?;
+    assert!((leftovers[0].amount(&mut env)? - dec!(300) / dec!(70)).checked_abs().unwrap() < dec!("0.000001"));
+    assert!((leftovers[1].amount(&mut env)? - dec!(400) / dec!(70)).checked_abs().unwrap() < dec!("0.000001"));
+
+    let health = stab_comp.get_basket_health(basket_id, &mut env)?;
+    assert!(health.status == CdpStatus::Liquidated);
+    assert!(health.collaterals.is_empty());
+
+    Ok(())
+}
+
+// A basket worth less than its debt still leaves the liquidator the liquidation fine, the rest is recorded as bad debt
+#[test]
+fn underwater_basket_liquidation_pays_the_liquidation_fine() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let b_bucket = add_second_collateral(&mut env, &mut stab_comp)?;
+
+    let (stab, basket) = stab_comp.open_basket_cdp(
+        vec![
+            a_bucket.take(dec!(330), &mut env)?,
+            b_bucket.take(dec!(440), &mut env)?,
+        ],
+        dec!(300),
+        &mut env,
+    )?;
+    let basket_id = basket.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    // collateral value = 770, debt value = 300 * 3.5 = 1050
+    stab_comp.change_internal_price(dec!("3.5"), &mut env)?;
+    assert!(stab_comp.mark_basket_for_liquidation(Some(basket_id.clone()), &mut env)?);
+
+    let (seized, leftover_stab) = stab_comp.liquidate_basket(basket_id, stab, &mut env)?;
+
+    // the liquidator pays 770 / 3.5 / 1.1 = 200 STAB (worth 700) for all collateral (worth 770)
+    assert_eq!(leftover_stab.amount(&mut env)?, dec!(100));
+    assert_eq!(seized[0].amount(&mut env)?, dec!(330));
+    assert_eq!(seized[1].amount(&mut env)?, dec!(440));
+    assert!((stab_comp.get_bad_debt(&mut env)? - dec!(100)).checked_abs().unwrap() < dec!("0.000001"));
+
+    Ok(())
+}
+
+// Basket debt is attributed to its collaterals by value, so it counts towards their max STAB shares
+#[test]
+fn basket_debt_counts_towards_collateral_shares() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let b_bucket = add_second_collateral(&mut env, &mut stab_comp)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+    let b_address = b_bucket.resource_address(&mut env)?;
+
+    // 300 A and 400 B are worth 700, so B carries 4/7 of the debt
+    let (stab, basket) = stab_comp.open_basket_cdp(
+        vec![
+            a_bucket.take(dec!(300), &mut env)?,
+            b_bucket.take(dec!(400), &mut env)?,
+        ],
+        dec!(140),
+        &mut env,
+    )?;
+    let basket_id = basket.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let a_info = stab_comp.get_collateral_info(a_address, &mut env)?;
+    let b_info = stab_comp.get_collateral_info(b_address, &mut env)?;
+    assert_eq!(a_info.minted_stab, dec!(60));
+    assert_eq!(b_info.minted_stab, dec!(80));
+    assert_eq!(a_info.collateral_amount, dec!(300));
+    assert_eq!(b_info.collateral_amount, dec!(400));
+
+    // with B capped at 60% of all STAB, borrowing within the cap works, but moving more debt onto B is refused
+    stab_comp.edit_collateral(b_address, dec!(2), true, dec!("0.6"), &mut env)?;
+    assert!(stab_comp
+        .borrow_more_basket(basket_id.clone(), dec!(10), &mut env)
+        .is_ok());
+    assert!(stab_comp
+        .remove_basket_collateral(basket_id.clone(), a_address, dec!(200), &mut env)
+        .is_err());
+    assert!(stab_comp
+        .open_basket_cdp(vec![b_bucket.take(dec!(1000), &mut env)?], dec!(100), &mut env)
+        .is_err());
+
+    // closing removes the attributed debt again
+    let (_collaterals, _leftover_stab) = stab_comp.close_basket_cdp(
+        basket_id,
+        BucketFactory::create_fungible_bucket(
+            stab.resource_address(&mut env)?,
+            dec!(150),
+            Mock,
+            &mut env,
+        )?,
+        &mut env,
+    )?;
+    let a_info = stab_comp.get_collateral_info(a_address, &mut env)?;
+    let b_info = stab_comp.get_collateral_info(b_address, &mut env)?;
+    assert_eq!(a_info.minted_stab, dec!(0));
+    assert_eq!(b_info.minted_stab, dec!(0));
+    assert_eq!(a_info.collateral_amount, dec!(0));
+    assert_eq!(b_info.collateral_amount, dec!(0));
+
+    Ok(())
+}
+
+// Emergency shutdown: loans are settled at frozen prices, and STAB is redeemed pro rata for the reserved collateral
+#[test]
+fn can_settle_after_emergency_shutdown() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let (stab_1, cdp_1) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let (stab_2, cdp_2) =
+        stab_comp.open_cdp(a_bucket.take(dec!(300), &mut env)?, dec!(190), &mut env)?;
+    let cdp_1_id = cdp_1.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let cdp_2_id = cdp_2.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    // STAB minted by the peg stability module is redeemed for its reserve
+    let psm_stab = stab_comp.psm_mint(dec!(100), &mut env)?;
+    let usd_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(100, &mut env)?;
+
+    // marked loans are settled as well
+    stab_comp.change_collateral_price(a_address, dec!("0.9"), &mut env)?;
+    let _marker = stab_comp.mark_for_liquidation(a_address, None, &mut env)?;
+
+    // closings being stopped at the shutdown doesn't block retrieving leftover collateral
+    stab_comp.set_stops(false, false, true, false, false, &mut env)?;
+    stab_comp.emergency_shutdown(&mut env)?;
+    assert!(stab_comp.change_collateral_price(a_address, dec!(1), &mut env).is_err());
+    assert!(stab_comp.get_shutdown_state(&mut env)?.is_some());
+
+    stab_comp.settle_cdp(cdp_1_id.clone(), &mut env)?;
+    assert!(stab_comp.finalize_shutdown(&mut env).is_err());
+    stab_comp.settle_cdp(cdp_2_id.clone(), &mut env)?;
+    assert!(stab_comp.settle_cdp(cdp_2_id.clone(), &mut env).is_err());
+    stab_comp.settle_psm_reserve(usd_bucket, &mut env)?;
+    stab_comp.finalize_shutdown(&mut env)?;
+
+    let shutdown = stab_comp.get_shutdown_state(&mut env)?.unwrap();
+    assert!(shutdown.finalized);
+    assert_eq!(shutdown.outstanding_stab, dec!(790));
+
+    // collateral worth the debt at the frozen price of 0.9 is reserved, the excess is left for the borrower
+    let leftover_1 = stab_comp.retrieve_leftover_collateral(cdp_1_id.clone(), &mut env)?;
+    assert!((leftover_1.amount(&mut env)? - (dec!(1000) - dec!(500) / dec!("0.9"))).checked_abs().unwrap() < dec!("0.000001"));
+    let leftover_2 = stab_comp.retrieve_leftover_collateral(cdp_2_id, &mut env)?;
+    assert!((leftover_2.amount(&mut env)? - (dec!(300) - dec!(190) / dec!("0.9"))).checked_abs().unwrap() < dec!("0.000001"));
+
+    let health = stab_comp.get_cdp_health(cdp_1_id, &mut env)?;
+    assert!(health.status == CdpStatus::Settled);
+
+    let reserved: Decimal = dec!(690) / dec!("0.9");
+
+    let collateral = stab_comp.redeem_settlement(stab_1.take(dec!(395), &mut env)?, &mut env)?;
+    assert!((collateral[0].amount(&mut env)? - reserved / dec!(2)).checked_abs().unwrap() < dec!("0.000001"));
+    assert_eq!(collateral[1].amount(&mut env)?, dec!(50));
+
+    stab_1.put(stab_2, &mut env)?;
+    stab_1.put(psm_stab, &mut env)?;
+    let collateral = stab_comp.redeem_settlement(stab_1, &mut env)?;
+    assert!((collateral[0].amount(&mut env)? - reserved / dec!(2)).checked_abs().unwrap() < dec!("0.000001"));
+    assert_eq!(collateral[1].amount(&mut env)?, dec!(50));
+
+    let shutdown = stab_comp.get_shutdown_state(&mut env)?.unwrap();
+    assert_eq!(shutdown.outstanding_stab, dec!(0));
+    assert!(*shutdown.settlement_collateral.get(&a_address).unwrap() < dec!("0.000001"));
+
+    Ok(())
+}
+
+// The peg stability module mints STAB for stable assets and redeems it, with fees and a debt ceiling
+#[test]
+fn can_swap_through_peg_stability_module() -> Result<(), RuntimeError> {
+    let mut env = TestEnvironment::new();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let (mut stab_comp, controller_badge) = Stabilis::instantiate(package, &mut env)?;
+
+    let a_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    let usd_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+    let usd_address = usd_bucket.resource_address(&mut env)?;
+
+    stab_comp.add_collateral(a_address, dec!("1.5"), dec!("1"), &mut env)?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    let mut psm = PegStabilityModule::instantiate(
+        controller_badge.take(dec!(1), &mut env)?,
+        ComponentAddress::try_from(stab_comp.0.as_bytes()).unwrap(),
+        stab.resource_address(&mut env)?,
+        package,
+        &mut env,
+    )?;
+
+    psm.add_asset(usd_address, dec!("0.001"), dec!("0.002"), dec!(1000), &mut env)?;
+
+    let minted = psm.mint(usd_bucket.take(dec!(500), &mut env)?, &mut env)?;
+    assert_eq!(minted.amount(&mut env)?, dec!("499.5"));
+    assert!(psm.mint(usd_bucket.take(dec!(600), &mut env)?, &mut env).is_err());
+
+    // STAB minted by the PSM counts toward circulating STAB
+    let info = stab_comp.get_collateral_info(a_address, &mut env)?;
+    assert_eq!(info.stab_share, dec!("0.5"));
+
+    let redeemed = psm.redeem(minted.take(dec!(100), &mut env)?, usd_address, &mut env)?;
+    assert_eq!(redeemed.amount(&mut env)?, dec!("99.8"));
+
+    let asset_info = psm.get_asset_info(usd_address, &mut env)?;
+    assert_eq!(asset_info.reserve, dec!("400.2"));
+    assert_eq!(asset_info.minted_stab, dec!("400.2"));
+    assert_eq!(psm.get_assets(&mut env)?.len(), 1);
+    assert_eq!(psm.get_fees(&mut env)?, dec!("0.7"));
+
+    let fees = psm.withdraw_fees(dec!("0.7"), &mut env)?;
+    assert_eq!(fees.amount(&mut env)?, dec!("0.7"));
+
+    // an asset's reserve can only be redeemed for the STAB minted against it
+    let other_usd_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    let other_usd_address = other_usd_bucket.resource_address(&mut env)?;
+    assert!(psm
+        .add_asset(other_usd_address, dec!(1), dec!(0), dec!(1000), &mut env)
+        .is_err());
+    psm.add_asset(other_usd_address, dec!(0), dec!(0), dec!(1000), &mut env)?;
+    let other_minted = psm.mint(other_usd_bucket.take(dec!(100), &mut env)?, &mut env)?;
+    assert!(psm
+        .redeem(minted.take(dec!(200), &mut env)?, other_usd_address, &mut env)
+        .is_err());
+    let redeemed = psm.redeem(other_minted, other_usd_address, &mut env)?;
+    assert_eq!(redeemed.amount(&mut env)?, dec!(100));
+
+    Ok(())
+}
+
+// A collateral treasury above its threshold can be sold in a surplus auction with a decaying price
+#[test]
+fn can_auction_collateral_treasury() -> Result<(), RuntimeError> {
+    let mut env = TestEnvironment::new();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let (mut stab_comp, controller_badge) = Stabilis::instantiate(package, &mut env)?;
+
+    let a_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+    stab_comp.add_collateral(a_address, dec!("1.5"), dec!("1"), &mut env)?;
+
+    let (stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let stab_address = stab.resource_address(&mut env)?;
+    let free_stab =
+        BucketFactory::create_fungible_bucket(stab_address, dec!(100000), Mock, &mut env)?;
+
+    // liquidating puts a fine of 40 A in the treasury
+    stab_comp.change_internal_price(dec!(2), &mut env)?;
+    let marker = stab_comp.mark_for_liquidation(a_address, None, &mut env)?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let (_collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_with_marker(marker_id, free_stab.take(dec!(500), &mut env)?, &mut env)?;
+    assert_eq!(
+        stab_comp.get_collateral_info(a_address, &mut env)?.treasury_amount,
+        dec!(40)
+    );
+
+    let mut auctions = SurplusAuctions::instantiate(
+        controller_badge.take(dec!(1), &mut env)?,
+        ComponentAddress::try_from(stab_comp.0.as_bytes()).unwrap(),
+        stab_address,
+        package,
+        &mut env,
+    )?;
+
+    // collateral can only be sold for STAB or ILIS
+    let mut config = SurplusAuctionConfig {
+        threshold: dec!(50),
+        payment_token: a_address,
+        starting_price: dec!(2),
+        price_decay: dec!("0.5"),
+        minimum_price: dec!("0.5"),
+        burn_proceeds: true,
+    };
+    assert!(auctions.set_config(a_address, config.clone(), &mut env).is_err());
+
+    config.payment_token = stab_address;
+    auctions.set_config(a_address, config.clone(), &mut env)?;
+    assert!(auctions.start_auction(a_address, &mut env).is_err());
+
+    config.threshold = dec!(30);
+    auctions.set_config(a_address, config, &mut env)?;
+    auctions.start_auction(a_address, &mut env)?;
+    assert_eq!(
+        stab_comp.get_collateral_info(a_address, &mut env)?.treasury_amount,
+        dec!(0)
+    );
+
+    let new_time = env.get_current_time().add_minutes(1).unwrap();
+    env.set_current_time(new_time);
+
+    let auction = auctions.get_auction(a_address, &mut env)?;
+    assert_eq!(auction.lot, dec!(40));
+    assert!((auction.current_price - dec!(1)).checked_abs().unwrap() < dec!("0.000001"));
+
+    let (bought, leftover) =
+        auctions.bid(a_address, free_stab.take(dec!(10), &mut env)?, &mut env)?;
+    assert!((bought.amount(&mut env)? - dec!(10)).checked_abs().unwrap() < dec!("0.0001"));
+    assert!(leftover.amount(&mut env)? < dec!("0.0001"));
+
+    // cancelling returns the unsold collateral to the treasury
+    auctions.cancel_auction(a_address, &mut env)?;
+    assert!(auctions.get_auction(a_address, &mut env)?.start_time.is_none());
+    assert!(
+        (stab_comp.get_collateral_info(a_address, &mut env)?.treasury_amount - dec!(30))
+            .checked_abs()
+            .unwrap()
+            < dec!("0.0001")
+    );
+
+    auctions.start_auction(a_address, &mut env)?;
+    let new_time = env.get_current_time().add_minutes(1).unwrap();
+    env.set_current_time(new_time);
+
+    let (bought, leftover) =
+        auctions.bid(a_address, free_stab.take(dec!(100), &mut env)?, &mut env)?;
+    assert!((bought.amount(&mut env)? - dec!(30)).checked_abs().unwrap() < dec!("0.0001"));
+    assert!((leftover.amount(&mut env)? - dec!(70)).checked_abs().unwrap() < dec!("0.0001"));
+
+    let auction = auctions.get_auction(a_address, &mut env)?;
+    assert!(auction.start_time.is_none());
+    assert_eq!(auction.lot, dec!(0));
+
+    Ok(())
+}
+
+// Keepers are rewarded once per marker, and at most once per update delay for updates
+#[test]
+fn can_reward_keepers() -> Result<(), RuntimeError> {
+    let mut env = TestEnvironment::new();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let (mut stab_comp, controller_badge) = Stabilis::instantiate(package, &mut env)?;
+
+    let a_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+    stab_comp.add_collateral(a_address, dec!("1.5"), dec!("1"), &mut env)?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let stab_address = stab.resource_address(&mut env)?;
+    let free_stab =
+        BucketFactory::create_fungible_bucket(stab_address, dec!(100000), Mock, &mut env)?;
+
+    stab_comp.change_internal_price(dec!(2), &mut env)?;
+    let marker = stab_comp.mark_for_liquidation(a_address, None, &mut env)?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let mut keeper_rewards = KeeperRewards::instantiate(
+        controller_badge.resource_address(&mut env)?,
+        cdp.resource_address(&mut env)?,
+        marker.resource_address(&mut env)?,
+        package,
+        &mut env,
+    )?;
+
+    // no rewards before they are configured
+    assert!(keeper_rewards.reward_update(0, &mut env)?.is_none());
+
+    keeper_rewards.set_config(
+        KeeperRewardConfig {
+            reward_token: stab_address,
+            mark_reward: dec!(5),
+            save_reward: dec!(3),
+            update_reward: dec!(2),
+        },
+        &mut env,
+    )?;
+    keeper_rewards.fund(stab.take(dec!(11), &mut env)?, &mut env)?;
+
+    // a mark is only rewarded once the loan is liquidated
+    assert!(keeper_rewards.reward_marker(marker_id.clone(), &mut env).is_err());
+    let (_collateral_reward, _leftover_stab, _liquidation_receipt) = stab_comp
+        .liquidate_position_with_marker(marker_id.clone(), free_stab.take(dec!(400), &mut env)?, &mut env)?;
+
+    let reward = keeper_rewards.reward_marker(marker_id.clone(), &mut env)?;
+    assert_eq!(reward.amount(&mut env)?, dec!(5));
+    assert!(keeper_rewards.reward_marker(marker_id, &mut env).is_err());
+
+    // a loan marked by someone else and saved after the price recovered earns the save reward
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(2000), &mut env)?, dec!(400), &mut env)?;
+    stab_comp.change_internal_price(dec!(4), &mut env)?;
+    let marker = stab_comp.mark_for_liquidation(a_address, None, &mut env)?;
+    let marker_id = marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    stab_comp.change_internal_price(dec!(2), &mut env)?;
+
+    let (_returned_stab, _none, saved_marker) = stab_comp
+        .liquidate_position_with_marker(marker_id.clone(), free_stab.take(dec!(400), &mut env)?, &mut env)?;
+    let saved_marker_id = saved_marker.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    assert!(keeper_rewards.reward_marker(marker_id, &mut env).is_err());
+    let reward = keeper_rewards.reward_marker(saved_marker_id, &mut env)?;
+    assert_eq!(reward.amount(&mut env)?, dec!(3));
+
+    // updates are only rewarded once the update delay has passed
+    assert!(keeper_rewards.reward_update(10, &mut env)?.is_none());
+    let new_time = env.get_current_time().add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+    let reward = keeper_rewards.reward_update(10, &mut env)?.unwrap();
+    assert_eq!(reward.amount(&mut env)?, dec!(2));
+    assert!(keeper_rewards.reward_update(10, &mut env)?.is_none());
+
+    // an empty reward vault pays out what is left
+    let new_time = env.get_current_time().add_minutes(10).unwrap();
+    env.set_current_time(new_time);
+    let reward = keeper_rewards.reward_update(10, &mut env)?.unwrap();
+    assert_eq!(reward.amount(&mut env)?, dec!(1));
+    assert_eq!(
+        keeper_rewards.get_reward_funds(stab_address, &mut env)?,
+        dec!(0)
+    );
+
+    Ok(())
+}
+
+// Loans using a pool unit are re-priced when the pool unit's redemption value grows
+#[test]
+fn can_refresh_pool_unit_crs() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let mut pool = OneResourcePool::instantiate(
+        a_address,
+        OwnerRole::None,
+        rule!(allow_all),
+        None,
+        &mut env,
+    )?;
+    let pool_units = pool.contribute(a_bucket.take(dec!(1000), &mut env)?, &mut env)?;
+    let pool_unit_address = pool_units.resource_address(&mut env)?;
+
+    stab_comp.add_pool_collateral(
+        pool_unit_address,
+        a_address,
+        ComponentAddress::new_or_panic(pool.0 .0),
+        false,
+        true,
+        &mut env,
+    )?;
+
+    let (_stab, cdp) = stab_comp.open_cdp(pool_units, dec!(500), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    assert_eq!(stab_comp.get_lowest_crs(a_address, 1, &mut env)?[0].0, dec!(2));
+    assert_eq!(stab_comp.get_pool_unit_info(pool_unit_address, &mut env)?.indexed_loans, 1);
+
+    // the redemption value of the pool units doubles
+    pool.protected_deposit(a_bucket.take(dec!(1000), &mut env)?, &mut env)?;
+
+    assert_eq!(stab_comp.refresh_pool_unit_crs(pool_unit_address, 10, &mut env)?, 1);
+    assert_eq!(stab_comp.get_lowest_crs(a_address, 1, &mut env)?[0].0, dec!(4));
+    assert_eq!(
+        stab_comp.get_collateral_info(a_address, &mut env)?.collateral_amount,
+        dec!(2000)
+    );
+
+    // nothing changed since the last refresh
+    assert_eq!(stab_comp.refresh_pool_unit_crs(pool_unit_address, 10, &mut env)?, 0);
+
+    // a loan that stops using the pool unit is dropped from its index by the next refresh
+    stab_comp.swap_cdp_collateral(cdp_id, a_bucket.take(dec!(1000), &mut env)?, &mut env)?;
+    assert_eq!(stab_comp.refresh_pool_unit_crs(pool_unit_address, 10, &mut env)?, 0);
+    assert_eq!(stab_comp.get_pool_unit_info(pool_unit_address, &mut env)?.indexed_loans, 0);
+
+    Ok(())
+}
+
+// LP units of a two resource pool are a collateral of their own, priced at the fair value of their legs
+#[test]
+fn can_use_lp_units_as_collateral() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let b_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    let b_address = b_bucket.resource_address(&mut env)?;
+    stab_comp.add_collateral(b_address, dec!("1.5"), dec!(4), &mut env)?;
+
+    let mut pool = TwoResourcePool::instantiate(
+        OwnerRole::None,
+        rule!(allow_all),
+        (a_address, b_address),
+        None,
+        &mut env,
+    )?;
+    let (lp_units, _change) = pool.contribute(
+        (
+            a_bucket.take(dec!(100), &mut env)?,
+            b_bucket.take(dec!(25), &mut env)?,
+        ),
+        &mut env,
+    )?;
+    let lp_address = lp_units.resource_address(&mut env)?;
+    let lp_amount = lp_units.amount(&mut env)?;
+
+    stab_comp.add_lp_collateral(
+        lp_address,
+        ComponentAddress::new_or_panic(pool.0 .0),
+        false,
+        dec!(2),
+        dec!("0.5"),
+        &mut env,
+    )?;
+
+    // both legs are worth 100, so all LP units are worth 200
+    let lp_value = stab_comp.get_collateral_info(lp_address, &mut env)?.usd_price * lp_amount;
+    assert!((lp_value - dec!(200)).checked_abs().unwrap() < dec!("0.000001"));
+
+    // a leg's price change moves the LP price, using the fair value 2 * sqrt(100 * 400) instead of 500
+    stab_comp.change_collateral_price(b_address, dec!(16), &mut env)?;
+    let lp_value = stab_comp.get_collateral_info(lp_address, &mut env)?.usd_price * lp_amount;
+    assert!((lp_value - dec!(400)).checked_abs().unwrap() < dec!("0.000001"));
+
+    // the LP collateral's share cap of 50% needs other loans to be open
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let spare_lp_units = lp_units.take(lp_amount / dec!(10), &mut env)?;
+    let (stab, _cdp) = stab_comp.open_cdp(lp_units, dec!(100), &mut env)?;
+    assert_eq!(stab.amount(&mut env)?, dec!(100));
+    assert_eq!(
+        stab_comp.get_collateral_info(lp_address, &mut env)?.minted_stab,
+        dec!(100)
+    );
+
+    // the LP collateral is paused while the price of one of its legs is held by a circuit breaker
+    stab_comp.set_circuit_breaker(b_address, Some((dec!("0.2"), dec!("0.25"))), &mut env)?;
+    stab_comp.change_collateral_price(b_address, dec!(1), &mut env)?;
+    assert!(stab_comp
+        .open_cdp(spare_lp_units.take(lp_amount / dec!(20), &mut env)?, dec!(9), &mut env)
+        .is_err());
+
+    stab_comp.reject_pending_price(b_address, &mut env)?;
+    stab_comp.open_cdp(spare_lp_units.take(lp_amount / dec!(20), &mut env)?, dec!(9), &mut env)?;
+
+    Ok(())
+}
+
+// The aggregated price is the median of fresh sources close to the median, with a status if there aren't enough
+#[test]
+fn can_aggregate_prices() -> Result<(), RuntimeError> {
+    let mut env = TestEnvironment::new();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let controller = ResourceBuilder::new_fungible(OwnerRole::None)
+        .mint_initial_supply(1, &mut env)?;
+    let mut aggregator =
+        PriceAggregator::instantiate(controller.resource_address(&mut env)?, package, &mut env)?;
+
+    aggregator.add_resource(XRD, 60, dec!("0.05"), 2, &mut env)?;
+
+    let mut reporters: Vec<Bucket> = Vec::new();
+    for _ in 0..3 {
+        let reporter = ResourceBuilder::new_fungible(OwnerRole::None)
+            .mint_initial_supply(1, &mut env)?;
+        aggregator.add_reporter_source(XRD, reporter.resource_address(&mut env)?, &mut env)?;
+        reporters.push(reporter);
+    }
+
+    // no prices reported yet
+    assert_eq!(aggregator.get_price(XRD, &mut env)?.status, PriceStatus::Stale);
+
+    for (reporter, price) in reporters.iter().zip([dec!(1), dec!("1.02"), dec!(2)]) {
+        let proof = reporter.create_proof_of_all(&mut env)?;
+        aggregator.report_price(proof, XRD, price, &mut env)?;
+    }
+
+    // the price of 2 deviates too much from the median of 1.02
+    let price = aggregator.get_price(XRD, &mut env)?;
+    assert_eq!(price.status, PriceStatus::Valid);
+    assert_eq!(price.accepted_sources, 2);
+    assert_eq!(price.price, Some(dec!("1.01")));
+
+    // with a minimum of 3 sources, the deviating one blocks the price
+    aggregator.set_resource_parameters(XRD, 60, dec!("0.05"), 3, &mut env)?;
+    let price = aggregator.get_price(XRD, &mut env)?;
+    assert_eq!(price.status, PriceStatus::Deviating);
+    assert_eq!(price.price, None);
+
+    // after the heartbeat, all prices are stale
+    aggregator.set_resource_parameters(XRD, 60, dec!("0.05"), 2, &mut env)?;
+    let new_time = env.get_current_time().add_minutes(61).unwrap();
+    env.set_current_time(new_time);
+    assert_eq!(aggregator.get_price(XRD, &mut env)?.status, PriceStatus::Stale);
+    assert!(aggregator.get_prices(&mut env)?.is_empty());
+
+    Ok(())
+}
+
+// Price reports signed by a quorum of reporter keys can be relayed by anyone
+#[test]
+fn can_submit_signed_price_reports() -> Result<(), RuntimeError> {
+    let mut env = TestEnvironment::new();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let controller = ResourceBuilder::new_fungible(OwnerRole::None)
+        .mint_initial_supply(1, &mut env)?;
+    let mut aggregator =
+        PriceAggregator::instantiate(controller.resource_address(&mut env)?, package, &mut env)?;
+
+    aggregator.add_resource(XRD, 60, dec!("0.05"), 1, &mut env)?;
+    aggregator.add_signed_report_source(XRD, &mut env)?;
+
+    // reporters can use Secp256k1 and Ed25519 keys
+    let secp256k1_keys: Vec<Secp256k1PrivateKey> = (1..=2)
+        .map(|seed| Secp256k1PrivateKey::from_u64(seed).unwrap())
+        .collect();
+    let ed25519_key = Ed25519PrivateKey::from_u64(3).unwrap();
+    let mut reporter_keys: Vec<PublicKey> = secp256k1_keys
+        .iter()
+        .map(|key| key.public_key().into())
+        .collect();
+    reporter_keys.push(ed25519_key.public_key().into());
+    aggregator.set_reporter_keys(reporter_keys, 2, &mut env)?;
+
+    let timestamp = env.get_current_time().seconds_since_unix_epoch;
+    let message = aggregator.get_report_message(XRD, dec!("0.05"), timestamp, &mut env)?;
+    let message_hash = hash(&message);
+    let mut signatures: Vec<(PublicKey, ReportSignature)> = secp256k1_keys
+        .iter()
+        .map(|key| {
+            (
+                key.public_key().into(),
+                ReportSignature::Secp256k1(key.sign(&message_hash)),
+            )
+        })
+        .collect();
+    signatures.push((
+        ed25519_key.public_key().into(),
+        ReportSignature::Ed25519(ed25519_key.sign(&message_hash)),
+    ));
+
+    // one signature doesn't reach the quorum, the same key twice doesn't either
+    assert!(aggregator
+        .submit_signed_report(XRD, dec!("0.05"), timestamp, signatures[..1].to_vec(), &mut env)
+        .is_err());
+    assert!(aggregator
+        .submit_signed_report(
+            XRD,
+            dec!("0.05"),
+            timestamp,
+            vec![signatures[0].clone(), signatures[0].clone()],
+            &mut env
+        )
+        .is_err());
+
+    // a signature for another price is invalid, and so is a signature of another scheme than the key
+    assert!(aggregator
+        .submit_signed_report(XRD, dec!("0.06"), timestamp, signatures[..2].to_vec(), &mut env)
+        .is_err());
+    assert!(aggregator
+        .submit_signed_report(
+            XRD,
+            dec!("0.05"),
+            timestamp,
+            vec![
+                signatures[0].clone(),
+                (signatures[2].0, signatures[1].1.clone()),
+            ],
+            &mut env
+        )
+        .is_err());
+
+    // a Secp256k1 and an Ed25519 signature reach the quorum together
+    aggregator.submit_signed_report(XRD, dec!("0.05"), timestamp, signatures[1..].to_vec(), &mut env)?;
+    assert_eq!(aggregator.get_price(XRD, &mut env)?.price, Some(dec!("0.05")));
+
+    // the same report can't be replayed
+    assert!(aggregator
+        .submit_signed_report(XRD, dec!("0.05"), timestamp, signatures[..2].to_vec(), &mut env)
+        .is_err());
+
+    Ok(())
+}
+
+// A price spike within one transaction doesn't move the TWAP, and only moves it by its duration afterwards
+#[test]
+fn twap_resists_single_transaction_spikes() -> Result<(), RuntimeError> {
+    let mut env = TestEnvironment::new();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let stab_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    let xrd_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+
+    let mut pool = StabilisPool::new(
+        OwnerRole::None,
+        stab_bucket.resource_address(&mut env)?,
+        xrd_bucket.resource_address(&mut env)?,
+        dec!(0),
+        package,
+        &mut env,
+    )?;
+    pool.add_liquidity(
+        stab_bucket.take(dec!(1000), &mut env)?,
+        xrd_bucket.take(dec!(1000), &mut env)?,
+        &mut env,
+    )?;
+
+    let new_time = env.get_current_time().add_minutes(60).unwrap();
+    env.set_current_time(new_time);
+
+    // dumping STAB crashes the spot price to 0.25, but the TWAP is still 1
+    pool.swap(stab_bucket.take(dec!(1000), &mut env)?, &mut env)?;
+    assert_eq!(pool.get_stab_price(&mut env)?, dec!("0.25"));
+    assert_eq!(pool.get_twap(60, &mut env)?, dec!(1));
+
+    // a minute later, the crashed price is counted for one of 61 minutes
+    let new_time = env.get_current_time().add_minutes(1).unwrap();
+    env.set_current_time(new_time);
+    let twap = pool.get_twap(60, &mut env)?;
+    assert_eq!(twap, (dec!(60) + dec!("0.25")) / dec!(61));
+    assert!(twap > dec!("0.98"));
+
+    Ok(())
+}
+
+// A price move beyond the circuit breaker's limits is held as pending, pausing openings and liquidations
+#[test]
+fn circuit_breaker_holds_extreme_prices() -> Result<(), RuntimeError> {
+    let (mut env, mut stab_comp, a_bucket, _control_bucket) = publish_and_setup()?;
+    let a_address = a_bucket.resource_address(&mut env)?;
+
+    let (_stab, _cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+
+    stab_comp.set_circuit_breaker(a_address, Some((dec!("0.2"), dec!("0.25"))), &mut env)?;
+    let (_basket_stab, basket) =
+        stab_comp.open_basket_cdp(vec![a_bucket.take(dec!(1000), &mut env)?], dec!(100), &mut env)?;
+    let basket_id = basket.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    // a 10% move is applied
+    stab_comp.change_collateral_price(a_address, dec!("0.9"), &mut env)?;
+    assert_eq!(stab_comp.get_collateral_info(a_address, &mut env)?.usd_price, dec!("0.9"));
+
+    // another 18% move stays within the update limit, but not within the hourly one
+    stab_comp.change_collateral_price(a_address, dec!("0.738"), &mut env)?;
+    assert_eq!(stab_comp.get_collateral_info(a_address, &mut env)?.usd_price, dec!("0.9"));
+    let events: Vec<CircuitBreakerTrippedEvent> =
+        emitted_events(&mut env, "CircuitBreakerTrippedEvent");
+    assert_eq!(events.last().unwrap().pending_price, dec!("0.738"));
+
+    // a crash to near zero stays pending, so loans can't be opened, marked or borrowed more against
+    stab_comp.change_collateral_price(a_address, dec!("0.01"), &mut env)?;
+    assert_eq!(
+        stab_comp.get_circuit_breaker(a_address, &mut env)?.unwrap().pending_price,
+        Some(dec!("0.01"))
+    );
+    assert!(stab_comp
+        .open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)
+        .is_err());
+    assert!(stab_comp.mark_for_liquidation(a_address, None, &mut env).is_err());
+    assert!(stab_comp.borrow_more_basket(basket_id.clone(), dec!(10), &mut env).is_err());
+
+    // rejecting keeps the old price and resumes openings
+    stab_comp.reject_pending_price(a_address, &mut env)?;
+    assert_eq!(stab_comp.get_collateral_info(a_address, &mut env)?.usd_price, dec!("0.9"));
+    stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(100), &mut env)?;
+    stab_comp.borrow_more_basket(basket_id, dec!(10), &mut env)?;
+
+    // a confirmed pending price is applied
+    stab_comp.change_collateral_price(a_address, dec!("0.5"), &mut env)?;
+    stab_comp.confirm_pending_price(a_address, &mut env)?;
+    assert_eq!(stab_comp.get_collateral_info(a_address, &mut env)?.usd_price, dec!("0.5"));
+    assert!(stab_comp.get_circuit_breaker(a_address, &mut env)?.unwrap().pending_price.is_none());
+
+    Ok(())
+}
+
+// Instantiates a Proxy on top of a Stabilis component, with a STAB/mock XRD pool trading at 1 and an XRD price of 1
+fn publish_and_setup_proxy() -> Result<
+    (TestEnvironment<InMemorySubstateDatabase>, Proxy, Oracle),
+    RuntimeError,
+> {
+    let mut env = TestEnvironment::new();
+    env.disable_auth_module();
+    let package =
+        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
+
+    let (mut stab_comp, controller_badge) = Stabilis::instantiate(package, &mut env)?;
+    let controller_address = controller_badge.resource_address(&mut env)?;
+
+    let a_bucket = ResourceBuilder::new_fungible(OwnerRole::None)
+        .divisibility(18)
+        .mint_initial_supply(10000, &mut env)?;
+    let xrd_bucket = BucketFactory::create_fungible_bucket(XRD, dec!(10000), Mock, &mut env)?;
+
+    stab_comp.add_collateral(a_bucket.resource_address(&mut env)?, dec!("1.5"), dec!("1"), &mut env)?;
+    stab_comp.add_collateral(XRD, dec!("1.5"), dec!("1"), &mut env)?;
+
+    let (stab, cdp) =
+        stab_comp.open_cdp(a_bucket.take(dec!(1000), &mut env)?, dec!(500), &mut env)?;
+    let cdp_receipt_address = cdp.resource_address(&mut env)?;
+
+    let mut oracle = Oracle::instantiate_oracle(controller_address, package, &mut env)?;
+    oracle.set_xrd_price(dec!(1), &mut env)?;
+
+    // markers aren't used in the simulations, so the receipt address is passed for them as well
+    let (proxy, _lp_tokens, _leftover) = Proxy::new(
+        xrd_bucket.take(dec!(100), &mut env)?,
+        stab.take(dec!(100), &mut env)?,
+        controller_badge,
+        cdp_receipt_address,
+        cdp_receipt_address,
+        ComponentAddress::try_from(oracle.0.as_bytes()).unwrap(),
+        ComponentAddress::try_from(stab_comp.0.as_bytes()).unwrap(),
+        package,
+        &mut env,
+    )?;
+
+    Ok((env, proxy, oracle))
+}
+
+// Runs an update every hour, with STAB trading at the scripted market prices, and returns the resulting interest rates
+fn simulate_market(
+    env: &mut TestEnvironment<InMemorySubstateDatabase>,
+    proxy: &mut Proxy,
+    oracle: &mut Oracle,
+    market_prices: &[Decimal],
+) -> Result<Vec<Decimal>, RuntimeError> {
+    let mut interest_rates: Vec<Decimal> = vec![];
+
+    for market_price in market_prices {
+        oracle.set_xrd_price(*market_price, env)?;
+        let new_time = env.get_current_time().add_minutes(60).unwrap();
+        env.set_current_time(new_time);
+        proxy.update(env)?;
+        interest_rates.push(proxy.get_interest_rate_state(env)?.interest_rate);
+    }
+
+    Ok(interest_rates)
+}
+
+// Every interest rate strategy reacts to scripted market prices, while the price error cache keeps being filled
+#[test]
+fn interest_rate_strategies_follow_scripted_prices() -> Result<(), RuntimeError> {
+    let (mut env, mut proxy, mut oracle) = publish_and_setup_proxy()?;
+
+    // the PI controller increases the interest rate while STAB trades below its peg
+    let rates = simulate_market(&mut env, &mut proxy, &mut oracle, &[dec!("0.98"); 3])?;
+    assert!(rates[0] > dec!(1));
+    assert!(rates[2] > rates[0]);
+    let state = proxy.get_interest_rate_state(&mut env)?;
+    assert_eq!(state.cached_price_errors, 3);
+    assert!(state.price_errors_total < dec!(0));
+
+    // switching strategies leaves the error cache untouched
+    proxy.set_fixed_interest_rate(dec!("1.0000001"), &mut env)?;
+    proxy.set_interest_strategy(InterestRateStrategy::FixedRate, &mut env)?;
+    assert_eq!(
+        proxy.get_interest_rate_state(&mut env)?.price_errors_total,
+        state.price_errors_total
+    );
+
+    // a fixed rate ignores the market, but errors are still cached
+    let price_before = proxy.get_internal_price(&mut env)?;
+    let rates = simulate_market(&mut env, &mut proxy, &mut oracle, &[dec!("1.05")])?;
+    assert_eq!(rates[0], dec!("1.0000001"));
+    assert!(proxy.get_internal_price(&mut env)? > price_before);
+    assert_eq!(
+        proxy.get_interest_rate_state(&mut env)?.cached_price_errors,
+        4
+    );
+
+    // the schedule picks the rate of the highest threshold below the relative price error
+    proxy.set_interest_schedule(
+        vec![
+            (dec!(-1), dec!("1.0000005")),
+            (dec!("-0.01"), dec!(1)),
+            (dec!("0.01"), dec!("0.9999995")),
+        ],
+        &mut env,
+    )?;
+    proxy.set_interest_strategy(InterestRateStrategy::Schedule, &mut env)?;
+    let rates = simulate_market(
+        &mut env,
+        &mut proxy,
+        &mut oracle,
+        &[dec!("0.98"), dec!("1.05"), dec!(1)],
+    )?;
+    assert_eq!(rates, vec![dec!("1.0000005"), dec!("0.9999995"), dec!(1)]);
+
+    // the PID controller's integral is bounded while STAB stays below its peg
+    proxy.set_pid_parameters(
+        dec!("0.00001"),
+        dec!("0.0000001"),
+        dec!("0.001"),
+        dec!(2),
+        &mut env,
+    )?;
+    proxy.set_interest_strategy(InterestRateStrategy::Pid, &mut env)?;
+    let rates = simulate_market(&mut env, &mut proxy, &mut oracle, &[dec!("0.98"); 5])?;
+    assert!(rates.iter().all(|rate| *rate > dec!(1)));
+    let pid = proxy.get_interest_rate_state(&mut env)?.controllers.pid;
+    assert_eq!(pid.integral, dec!(-2));
+
+    // back at the peg, only the bounded integral and the damping derivative remain
+    let rates = simulate_market(&mut env, &mut proxy, &mut oracle, &[dec!(1)])?;
+    assert!(rates[0] < dec!("1.0000002"));
+    let rates = simulate_market(&mut env, &mut proxy, &mut oracle, &[dec!(1)])?;
+    assert_eq!(rates[0], dec!("1.0000002"));
+
+    assert_eq!(
+        proxy.get_interest_rate_state(&mut env)?.cached_price_errors,
+        14
+    );
+
+    Ok(())
+}
+
+// Leveraged XRD loans are opened and (partially) deleveraged through flash loans and the StabilisPool
+#[test]
+fn can_open_and_deleverage_leveraged_cdp() -> Result<(), RuntimeError> {
+    let (mut env, mut proxy, _oracle) = publish_and_setup_proxy()?;
+    let xrd_bucket = BucketFactory::create_fungible_bucket(XRD, dec!(100), Mock, &mut env)?;
+
+    // at a target of 200%, 10 STAB is borrowed against 10 XRD, which buys ~9.08 XRD from the 100 XRD / 100 STAB pool
+    let (cdp, _leftover_stab) =
+        proxy.open_leveraged_cdp(xrd_bucket.take(dec!(10), &mut env)?, dec!(2), dec!("0.1"), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+
+    let health = proxy.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert_eq!(health.minted_stab, dec!(10));
+    assert!(health.collateral_amount > dec!("19.08") && health.collateral_amount < dec!("19.09"));
+    let collateral_before: Decimal = health.collateral_amount;
+
+    // a partial deleverage sells collateral worth the repaid debt at the internal price, plus the maximum slippage
+    let proof = NonFungibleProof(cdp.create_proof_of_all(&mut env)?);
+    let (collateral, leftover_stab) = proxy.deleverage_cdp(proof, dec!(4), dec!("0.1"), &mut env)?;
+    assert!(collateral.is_none());
+    assert!(leftover_stab.amount(&mut env)? > dec!(0));
+
+    let health = proxy.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert_eq!(health.minted_stab, dec!(6));
+    assert_eq!(health.collateral_amount, collateral_before - dec!(4) / dec!("0.9"));
+
+    // repaying more than the debt closes the loan and returns the collateral that wasn't sold
+    let proof = NonFungibleProof(cdp.create_proof_of_all(&mut env)?);
+    let (collateral, _leftover_stab) = proxy.deleverage_cdp(proof, dec!(100), dec!("0.1"), &mut env)?;
+    let collateral_amount: Decimal = collateral.unwrap().amount(&mut env)?;
+    assert_eq!(collateral_amount, collateral_before - dec!(4) / dec!("0.9") - dec!(6) / dec!("0.9"));
+
+    let health = proxy.get_cdp_health(cdp_id, &mut env)?;
+    assert!(health.status == CdpStatus::Closed);
+
+    // the slippage is measured against the internal price, so swapping 10 STAB for ~9.4 XRD is rejected at a maximum of 5%
+    assert!(proxy
+        .open_leveraged_cdp(xrd_bucket.take(dec!(10), &mut env)?, dec!(2), dec!("0.05"), &mut env)
+        .is_err());
+    assert!(proxy
+        .open_leveraged_cdp(xrd_bucket.take(dec!(10), &mut env)?, dec!(1), dec!("0.1"), &mut env)
+        .is_err());
+
+    Ok(())
+}
+
+// A STAB price crash within the transaction of an update moves the interest rate when using the spot price, but not when using the TWAP
+#[test]
+fn twap_window_ignores_same_transaction_spikes() -> Result<(), RuntimeError> {
+    let mut price_errors: Vec<Decimal> = vec![];
+    let mut rate_changes: Vec<Decimal> = vec![];
+
+    for twap_window in [None, Some(60)] {
+        let (mut env, mut proxy, _oracle) = publish_and_setup_proxy()?;
+        proxy.set_twap_window(twap_window, &mut env)?;
+        let xrd_bucket = BucketFactory::create_fungible_bucket(XRD, dec!(100), Mock, &mut env)?;
+
+        let new_time = env.get_current_time().add_minutes(60).unwrap();
+        env.set_current_time(new_time);
+        let rate_before = proxy.get_interest_rate_state(&mut env)?.interest_rate;
+
+        // swapping 50 STAB into the 100 XRD / 100 STAB pool crashes the spot price to ~0.44 XRD, right before updating
+        let (_cdp, _leftover_stab) =
+            proxy.open_leveraged_cdp(xrd_bucket, dec!(3), dec!("0.5"), &mut env)?;
+        proxy.update(&mut env)?;
+
+        let state = proxy.get_interest_rate_state(&mut env)?;
+        price_errors.push(state.price_errors_total);
+        rate_changes.push(state.interest_rate - rate_before);
+    }
+
+    // the spot price sees the crash as a large price error and raises the interest rate
+    assert!(price_errors[0] < dec!("-0.5"));
+    assert!(rate_changes[0] > dec!(0));
+
+    // the TWAP doesn't count the price of the current minute yet, so nothing changes
+    assert_eq!(price_errors[1], dec!(0));
+    assert_eq!(rate_changes[1], dec!(0));
+
+    Ok(())
+}
+
+// The stability pool fills the complete auction of a loan using a collateral that is liquidated through auctions
+#[test]
+fn stability_pool_fills_liquidation_auctions() -> Result<(), RuntimeError> {
+    let (mut env, mut proxy, mut oracle) = publish_and_setup_proxy()?;
+    let xrd_bucket = BucketFactory::create_fungible_bucket(XRD, dec!(10000), Mock, &mut env)?;
+    proxy.set_liquidation_mode(XRD, LiquidationMode::Auction, &mut env)?;
+
+    let (_stab, cdp) = proxy.open_cdp(xrd_bucket.take(dec!(1000), &mut env)?, dec!(400), &mut env)?;
+    let cdp_id = cdp.non_fungible_local_ids(&mut env)?.first().unwrap().clone();
+    let (stab, _cdp) = proxy.open_cdp(xrd_bucket.take(dec!(3000), &mut env)?, dec!(800), &mut env)?;
+    let _deposit = proxy.stability_pool_deposit(stab, &mut env)?;
+
+    // at an XRD price of 0.5, loans below a collateral ratio of 3 can be liquidated
+    oracle.set_xrd_price(dec!("0.5"), &mut env)?;
+    proxy.update(&mut env)?;
+    let _marker = proxy.mark_for_liquidation(XRD, None, &mut env)?;
+
+    let _receipt = proxy.liquidate_with_stability_pool(cdp_id.clone(), &mut env)?;
+    let health = proxy.get_cdp_health(cdp_id.clone(), &mut env)?;
+    assert!(health.status == CdpStatus::Liquidated);
+
+    let events: Vec<AuctionFilledEvent> = emitted_events(&mut env, "AuctionFilledEvent");
+    let event = events.last().unwrap();
+    assert_eq!(event.cdp_id, cdp_id);
+    assert_eq!(event.stab_paid, dec!(400));
+
+    Ok(())
+}